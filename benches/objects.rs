@@ -0,0 +1,288 @@
+use codecrafters_git::object::{
+    calculate_sha_hash, encode_object, serialize_tree, Object, ObjectType,
+};
+use codecrafters_git::odb::{FsBackend, Repository};
+use codecrafters_git::pack::PackWriter;
+use codecrafters_git::parallel;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use std::fs;
+use std::hint::black_box;
+use std::path::{Path, PathBuf};
+
+const BLOB_SIZES: [usize; 4] = [1 << 10, 16 << 10, 256 << 10, 4 << 20];
+
+/// Entries in each benchmarked tree, whether spread flat or over nested levels.
+const TREE_ENTRIES: usize = 4096;
+const DEEP_TREE_FANOUT: usize = 64;
+
+/// Files in the work tree whose blobs `blob_write` stores, as `add .` would.
+const WORK_TREE_FILES: usize = 2048;
+const WORK_TREE_FILE_SIZE: usize = 16 << 10;
+
+/// A throwaway repository in the system temp directory, removed on drop.
+struct Scratch {
+    git_dir: PathBuf,
+}
+
+impl Scratch {
+    fn new(name: &str) -> Scratch {
+        let root = std::env::temp_dir().join(format!(
+            "codecrafters-git-bench-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let git_dir = root.join(".git");
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        Scratch { git_dir }
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        if let Some(root) = self.git_dir.parent() {
+            let _ = fs::remove_dir_all(root);
+        }
+    }
+}
+
+/// Deterministic content that compresses about as well as source code, so
+/// inflate isn't measured on all-zero or incompressible input.
+fn blob_content(size: usize) -> Vec<u8> {
+    let words: [&[u8]; 8] = [
+        b"fn ", b"let ", b"object", b"tree ", b"(&self)", b";\n", b"    ", b"hash",
+    ];
+    let mut state: u32 = 0x2545_f491;
+    let mut content = Vec::with_capacity(size + 16);
+    while content.len() < size {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        content.extend_from_slice(words[(state % 8) as usize]);
+        content.push(b'a' + (state >> 8) as u8 % 26);
+    }
+    content.truncate(size);
+    content
+}
+
+fn loose_read(c: &mut Criterion) {
+    let scratch = Scratch::new("read");
+    let mut repository = Repository::open(&scratch.git_dir);
+    let mut group = c.benchmark_group("loose_read");
+
+    for size in BLOB_SIZES {
+        let id = repository
+            .write(ObjectType::Blob, &blob_content(size))
+            .unwrap();
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &id, |b, id| {
+            b.iter(|| repository.read(black_box(id)).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+/// Stores blobs in a single pack with a version 1 index, like an unpacked
+/// clone would have them, and returns their ids.
+fn write_packed_blobs(git_dir: &Path, contents: &[Vec<u8>]) -> Vec<String> {
+    let mut pack = Vec::new();
+    let mut writer = PackWriter::new(&mut pack, contents.len() as u32).unwrap();
+    let mut entries = Vec::new();
+    for content in contents {
+        let offset = writer.add(ObjectType::Blob, content).unwrap();
+        entries.push((calculate_sha_hash(&encode_object("blob", content)), offset));
+    }
+    let checksum = writer.finish().unwrap();
+    let ids = entries.iter().map(|(id, _)| hex::encode(id)).collect();
+
+    // Version 1: a fanout table, then (offset, id) sorted by id, then checksums.
+    entries.sort();
+    let mut idx = Vec::new();
+    for byte in 0..=255u8 {
+        let count = entries.iter().filter(|(id, _)| id[0] <= byte).count() as u32;
+        idx.extend_from_slice(&count.to_be_bytes());
+    }
+    for (id, offset) in &entries {
+        idx.extend_from_slice(&(*offset as u32).to_be_bytes());
+        idx.extend_from_slice(id);
+    }
+    idx.extend_from_slice(&hex::decode(&checksum).unwrap());
+    let idx_checksum = calculate_sha_hash(&idx);
+    idx.extend_from_slice(&idx_checksum);
+
+    let pack_dir = git_dir.join("objects/pack");
+    fs::create_dir_all(&pack_dir).unwrap();
+    fs::write(pack_dir.join(format!("pack-{}.pack", checksum)), pack).unwrap();
+    fs::write(pack_dir.join(format!("pack-{}.idx", checksum)), idx).unwrap();
+    ids
+}
+
+/// Type and size lookups, as `cat-file --batch-check` does them, against
+/// reading the whole object.
+fn header_read(c: &mut Criterion) {
+    let loose = Scratch::new("header-loose");
+    let packed = Scratch::new("header-packed");
+    let contents: Vec<_> = BLOB_SIZES.iter().map(|&size| blob_content(size)).collect();
+    let packed_ids = write_packed_blobs(&packed.git_dir, &contents);
+    let mut loose = Repository::open(&loose.git_dir);
+    let packed = Repository::open(&packed.git_dir);
+    let mut group = c.benchmark_group("header_read");
+
+    for ((size, content), packed_id) in BLOB_SIZES.iter().zip(&contents).zip(&packed_ids) {
+        let loose_id = loose.write(ObjectType::Blob, content).unwrap();
+        let cases = [
+            ("loose_header", &loose, &loose_id, true),
+            ("loose_full", &loose, &loose_id, false),
+            ("packed_header", &packed, packed_id, true),
+            ("packed_full", &packed, packed_id, false),
+        ];
+        for (name, repository, id, header_only) in cases {
+            group.bench_with_input(BenchmarkId::new(name, size), id, |b, id| {
+                b.iter(|| match header_only {
+                    true => repository.read_header(black_box(id)).unwrap().1,
+                    false => repository.read(black_box(id)).unwrap().1.len() as u64,
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn hash_object(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hash_object");
+
+    for size in BLOB_SIZES {
+        let content = blob_content(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &content, |b, content| {
+            b.iter(|| calculate_sha_hash(&encode_object("blob", black_box(content))))
+        });
+    }
+
+    group.finish();
+}
+
+/// Writes one tree holding `TREE_ENTRIES` blobs and returns its id.
+fn write_wide_tree(repository: &mut Repository<FsBackend>, blob: &[u8]) -> String {
+    let entries: Vec<_> = (0..TREE_ENTRIES)
+        .map(|i| ("100644".to_string(), format!("file{:05}", i), blob.to_vec()))
+        .collect();
+    repository
+        .write(ObjectType::Tree, &serialize_tree(&entries))
+        .unwrap()
+}
+
+/// Writes a chain of trees, each holding `DEEP_TREE_FANOUT - 1` blobs and the
+/// next level, until `TREE_ENTRIES` entries are used up; returns the root id.
+fn write_deep_tree(repository: &mut Repository<FsBackend>, blob: &[u8]) -> String {
+    let levels = TREE_ENTRIES / DEEP_TREE_FANOUT;
+    let mut child: Option<Vec<u8>> = None;
+
+    for _ in 0..levels {
+        let mut entries: Vec<_> = (0..DEEP_TREE_FANOUT - 1)
+            .map(|i| ("100644".to_string(), format!("file{:02}", i), blob.to_vec()))
+            .collect();
+        // Files sort before "sub" since they start with "f".
+        if let Some(child) = child.take() {
+            entries.push(("40000".to_string(), "sub".to_string(), child));
+        }
+        let id = repository
+            .write(ObjectType::Tree, &serialize_tree(&entries))
+            .unwrap();
+        child = Some(hex::decode(id).unwrap());
+    }
+
+    hex::encode(child.unwrap())
+}
+
+/// Loads a tree and all of its subtrees, returning how many entries were parsed.
+fn parse_tree(repository: &Repository<FsBackend>, id: &str) -> usize {
+    let Ok(Object::Tree(tree)) = repository.object(id) else {
+        panic!("{} is not a tree", id);
+    };
+
+    let mut entries = tree.elements.len();
+    for element in &tree.elements {
+        if element.mode == "40000" {
+            entries += parse_tree(repository, &hex::encode(&element.hash));
+        }
+    }
+    entries
+}
+
+fn tree_parse(c: &mut Criterion) {
+    let scratch = Scratch::new("tree");
+    let mut repository = Repository::open(&scratch.git_dir);
+    let blob = hex::decode(repository.write(ObjectType::Blob, b"content\n").unwrap()).unwrap();
+    let mut group = c.benchmark_group("tree_parse");
+
+    let trees = [
+        ("wide", write_wide_tree(&mut repository, &blob)),
+        ("deep", write_deep_tree(&mut repository, &blob)),
+    ];
+    for (shape, id) in &trees {
+        let entries = parse_tree(&repository, id);
+        group.throughput(Throughput::Elements(entries as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(shape), id, |b, id| {
+            b.iter(|| parse_tree(&repository, black_box(id)))
+        });
+    }
+
+    group.finish();
+}
+
+/// Hashes, compresses and writes a big work tree's worth of new blobs, one
+/// at a time and on all cores, into a fresh repository each time.
+fn blob_write(c: &mut Criterion) {
+    let contents: Vec<Vec<u8>> = (0..WORK_TREE_FILES)
+        .map(|i| {
+            let mut content = format!("file {}\n", i).into_bytes();
+            content.extend(blob_content(WORK_TREE_FILE_SIZE));
+            content
+        })
+        .collect();
+    let mut group = c.benchmark_group("blob_write");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(WORK_TREE_FILES as u64));
+
+    group.bench_function("serial", |b| {
+        b.iter_batched(
+            || Scratch::new("blob-write"),
+            |scratch| {
+                let mut repository = Repository::open(&scratch.git_dir);
+                for content in &contents {
+                    repository.write(ObjectType::Blob, content).unwrap();
+                }
+                scratch
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function("parallel", |b| {
+        b.iter_batched(
+            || Scratch::new("blob-write"),
+            |scratch| {
+                parallel::map(&contents, |content| {
+                    Repository::open(&scratch.git_dir)
+                        .write(ObjectType::Blob, content)
+                        .unwrap()
+                });
+                scratch
+            },
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    loose_read,
+    header_read,
+    hash_object,
+    tree_parse,
+    blob_write
+);
+criterion_main!(benches);
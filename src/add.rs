@@ -0,0 +1,271 @@
+use crate::ignore::{self, IgnoreRules};
+use crate::index::{Index, IndexEntry};
+use crate::platform::{self, Filesystem};
+use crate::{
+    git_dir_at, git_path, index_path_of, parallel, resolve_ref, status, work_tree_relative_path,
+    ObjectType, Repository,
+};
+use anyhow::{anyhow, Context};
+use std::collections::HashSet;
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+
+/// Stages `paths` like `git add`: files are hashed into blobs and recorded
+/// with their stat data, directories are walked for the files under them
+/// that aren't ignored, and tracked files that are gone are unstaged.
+///
+/// A directory that is a repository of its own is staged as a gitlink to
+/// its checked out commit, as `write-tree --work-tree` stores it.
+///
+/// With `no_filters`, files are stored exactly as on disk, without the
+/// `.gitattributes` conversions.
+pub fn add(
+    git_dir: &Path,
+    work_tree: &Path,
+    paths: &[PathBuf],
+    no_filters: bool,
+) -> anyhow::Result<()> {
+    let index_path = git_path(git_dir, "index");
+    let mut index = Index::load(&index_path)?;
+
+    // Files named one by one are staged together, before anything else
+    // changes the index, so they're hashed in parallel but in order.
+    let mut files = Vec::new();
+    for file_path in paths {
+        let metadata = match fs::symlink_metadata(file_path) {
+            Result::Ok(metadata) => metadata,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                stage(
+                    git_dir,
+                    work_tree,
+                    &mut index,
+                    &std::mem::take(&mut files),
+                    no_filters,
+                )?;
+                let path = index_path_of(work_tree, file_path)?;
+                if !remove_under(&mut index, &path) {
+                    return Err(anyhow!("pathspec '{}' did not match any files", path));
+                }
+                continue;
+            }
+            Err(err) => return Err(err).with_context(|| format!("Stat {}.", file_path.display())),
+        };
+
+        if !metadata.is_dir() {
+            let path = index_path_of(work_tree, file_path)?;
+            let tracked = index.entries.iter().any(|x| x.path == path);
+            if !tracked && is_ignored(work_tree, git_dir, &path, false) {
+                return Err(anyhow!(
+                    "The path '{}' is ignored by one of your .gitignore files.",
+                    path
+                ));
+            }
+            files.push((path, metadata));
+            continue;
+        }
+
+        stage(
+            git_dir,
+            work_tree,
+            &mut index,
+            &std::mem::take(&mut files),
+            no_filters,
+        )?;
+        let path = work_tree_relative_path(work_tree, file_path)
+            .ok_or_else(|| anyhow!("{}: is outside repository.", file_path.display()))?;
+        if path.is_empty() {
+            add_dir(
+                git_dir,
+                work_tree,
+                &mut index,
+                "",
+                &IgnoreRules::load(work_tree, git_dir),
+                no_filters,
+            )?;
+            continue;
+        }
+        if is_ignored(work_tree, git_dir, &path, true) {
+            return Err(anyhow!(
+                "The path '{}' is ignored by one of your .gitignore files.",
+                path
+            ));
+        }
+        if file_path.join(".git").exists() {
+            files.push((path, metadata));
+            continue;
+        }
+        let dir = format!("{}/", path);
+        let rules = rules_for(work_tree, git_dir, &path)
+            .unwrap_or_default()
+            .for_subdir(work_tree, &dir);
+        add_dir(git_dir, work_tree, &mut index, &dir, &rules, no_filters)?;
+    }
+
+    stage(git_dir, work_tree, &mut index, &files, no_filters)?;
+    index.write(&index_path)
+}
+
+/// Stages everything under `dir` (`/` terminated, or "" for the work tree
+/// root) that isn't ignored, along with changes to the tracked files under
+/// it, ignored or not.
+fn add_dir(
+    git_dir: &Path,
+    work_tree: &Path,
+    index: &mut Index,
+    dir: &str,
+    ignore: &IgnoreRules,
+    no_filters: bool,
+) -> anyhow::Result<()> {
+    // Unstage what's gone first, so a file may replace a tracked directory
+    // of the same name and the other way around.
+    let tracked: Vec<String> = index
+        .entries
+        .iter()
+        .filter(|x| x.path.starts_with(dir))
+        .map(|x| x.path.clone())
+        .collect();
+    for path in &tracked {
+        let full_path = work_tree.join(path);
+        let exists = fs::symlink_metadata(&full_path)
+            .is_ok_and(|x| !x.is_dir() || full_path.join(".git").exists());
+        if !exists {
+            index.remove(path);
+        }
+    }
+
+    let mut files = Vec::new();
+    add_untracked_and_modified(work_tree, dir, ignore, &mut files)?;
+
+    // Tracked files stay tracked even when ignored.
+    let staged: HashSet<String> = files.iter().map(|x| x.0.clone()).collect();
+    for path in tracked.iter().filter(|x| !staged.contains(*x)) {
+        if let Result::Ok(metadata) = fs::symlink_metadata(work_tree.join(path)) {
+            if index.entries.iter().any(|x| x.path == *path) {
+                files.push((path.clone(), metadata));
+            }
+        }
+    }
+    stage(git_dir, work_tree, index, &files, no_filters)
+}
+
+/// Lists the files and nested repositories under `dir` that aren't ignored,
+/// to be staged.
+fn add_untracked_and_modified(
+    work_tree: &Path,
+    dir: &str,
+    ignore: &IgnoreRules,
+    files: &mut Vec<(String, Metadata)>,
+) -> anyhow::Result<()> {
+    for entry in fs::read_dir(work_tree.join(dir))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name == ".git" {
+            continue;
+        }
+        let path = format!("{}{}", dir, name);
+        let metadata = fs::symlink_metadata(entry.path())?;
+        if ignore.is_ignored(&path, metadata.is_dir()) {
+            continue;
+        }
+
+        if metadata.is_dir() && !entry.path().join(".git").exists() {
+            let subdir = format!("{}/", path);
+            let rules = ignore.for_subdir(work_tree, &subdir);
+            add_untracked_and_modified(work_tree, &subdir, &rules, files)?;
+            continue;
+        }
+        files.push((path, metadata));
+    }
+    Ok(())
+}
+
+/// Records the current content of `files`: a blob for a file or symlink, a
+/// gitlink for a nested repository. The blobs are hashed and written on
+/// several threads, and the entries added in the order given.
+fn stage(
+    git_dir: &Path,
+    work_tree: &Path,
+    index: &mut Index,
+    files: &[(String, Metadata)],
+    no_filters: bool,
+) -> anyhow::Result<()> {
+    let objects = parallel::map(files, |(path, metadata)| {
+        let staged = index.mode_of(path);
+        object_for(git_dir, work_tree, path, metadata, staged, no_filters)
+    });
+    for ((path, metadata), object) in files.iter().zip(objects) {
+        let (mode, object_id) = object?;
+        index.add(IndexEntry::from_metadata(
+            path,
+            mode,
+            hex::decode(object_id)?,
+            metadata,
+        ))?;
+    }
+    Ok(())
+}
+
+/// The mode and object id `path` is staged as, writing its blob; `staged`
+/// is the mode it is staged with now, if it is. With `no_filters`, a file's
+/// blob is its content as on disk.
+fn object_for(
+    git_dir: &Path,
+    work_tree: &Path,
+    path: &str,
+    metadata: &Metadata,
+    staged: Option<u32>,
+    no_filters: bool,
+) -> anyhow::Result<(u32, String)> {
+    Ok(if metadata.is_dir() {
+        let sub_git_dir = git_dir_at(&work_tree.join(path))?;
+        let commit = resolve_ref(&sub_git_dir, "HEAD")?
+            .ok_or_else(|| anyhow!("{}: repository has no commit checked out.", path))?;
+        (0o160000, commit)
+    } else {
+        let content = match no_filters && !metadata.file_type().is_symlink() {
+            true => fs::read(platform::work_tree_path(work_tree, path))?,
+            false => status::work_tree_content(git_dir, work_tree, path, metadata)?,
+        };
+        let blob = Repository::open(git_dir).write(ObjectType::Blob, &content)?;
+        let mode = platform::work_tree_mode(Filesystem::of(git_dir), metadata, staged);
+        (mode, blob)
+    })
+}
+
+/// Unstages `path` and everything under it, returning whether anything was.
+fn remove_under(index: &mut Index, path: &str) -> bool {
+    let dir = format!("{}/", path);
+    let doomed: Vec<String> = index
+        .entries
+        .iter()
+        .filter(|x| x.path == path || x.path.starts_with(&dir))
+        .map(|x| x.path.clone())
+        .collect();
+    for path in &doomed {
+        index.remove(path);
+    }
+    !doomed.is_empty()
+}
+
+/// The ignore rules in effect for `path`: those of the directories above it.
+/// `None` if one of those directories is ignored itself, which ignores
+/// everything under it.
+fn rules_for(work_tree: &Path, git_dir: &Path, path: &str) -> Option<IgnoreRules> {
+    let mut rules = IgnoreRules::load(work_tree, git_dir);
+    let mut dir = String::new();
+    let mut components: Vec<&str> = path.split('/').collect();
+    components.pop();
+    for component in components {
+        dir.push_str(component);
+        if rules.is_ignored(&dir, true) {
+            return None;
+        }
+        dir.push('/');
+        rules = rules.for_subdir(work_tree, &dir);
+    }
+    Some(rules)
+}
+
+fn is_ignored(work_tree: &Path, git_dir: &Path, path: &str, is_dir: bool) -> bool {
+    ignore::check_path(work_tree, git_dir, path, is_dir).is_some_and(|x| !x.negated)
+}
@@ -0,0 +1,387 @@
+use crate::date::{now, signature_time};
+use crate::log::read_commit;
+use crate::{resolve_rev, Object, ObjectType, Repository};
+use anyhow::anyhow;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+use std::io::Write;
+use std::path::Path;
+
+/// Size of a tar header and of the blocks contents are padded to.
+const TAR_BLOCK: usize = 512;
+/// tar archives are padded to a whole record of this size, as git does.
+const TAR_RECORD: usize = 20 * TAR_BLOCK;
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    Tar,
+    Zip,
+}
+
+/// One file or directory of the archived tree.
+struct Entry {
+    path: String,
+    /// Names the extended header of a path too long for a tar header.
+    id: String,
+    kind: Kind,
+}
+
+enum Kind {
+    /// Subtrees, and submodules, which are archived as empty directories.
+    Directory,
+    File {
+        executable: bool,
+        content: Vec<u8>,
+    },
+    Symlink {
+        target: Vec<u8>,
+    },
+}
+
+/// Writes the tree of `rev` as an archive, like `git archive`, every path
+/// under `prefix`.
+///
+/// Entries get the commit's committer date, or the current time for a bare
+/// tree, and git's modes: `0664` for files, `0775` for executables and
+/// directories. A tar archive records the commit id in a pax global
+/// header, and a zip archive in its comment, so `git get-tar-commit-id`
+/// can read it back.
+pub fn archive(
+    git_dir: &Path,
+    rev: &str,
+    format: Format,
+    prefix: &str,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    let object_id = resolve_rev(git_dir, rev)?;
+    let repository = Repository::open(git_dir);
+    let tree_id = repository
+        .peel_to(&object_id, ObjectType::Tree)
+        .map_err(|_| anyhow!("{} is not a tree-ish.", rev))?;
+    let (commit_id, time) = match repository.peel_to(&object_id, ObjectType::Commit) {
+        Result::Ok(commit_id) => {
+            let commit = read_commit(git_dir, &commit_id)?;
+            let time = signature_time(&commit.committer).map_or(0, |(seconds, _)| seconds);
+            (Some(commit_id), time)
+        }
+        Err(_) => (None, now()),
+    };
+
+    let mut entries = Vec::new();
+    if !prefix.is_empty() && prefix.ends_with('/') {
+        entries.push(Entry {
+            path: prefix.to_string(),
+            id: tree_id.clone(),
+            kind: Kind::Directory,
+        });
+    }
+    collect(git_dir, &tree_id, prefix, &mut entries)?;
+    match format {
+        Format::Tar => write_tar(out, &entries, commit_id.as_deref(), time),
+        Format::Zip => write_zip(out, &entries, commit_id.as_deref(), time),
+    }
+}
+
+fn collect(
+    git_dir: &Path,
+    tree_id: &str,
+    prefix: &str,
+    entries: &mut Vec<Entry>,
+) -> anyhow::Result<()> {
+    let repository = Repository::open(git_dir);
+    let Object::Tree(tree) = repository.object(tree_id)? else {
+        return Err(anyhow!("Object {} is not a tree.", tree_id));
+    };
+    for element in tree.elements {
+        let path = format!("{}{}", prefix, element.name);
+        let id = hex::encode(&element.hash);
+        match element.mode.as_str() {
+            "40000" => {
+                let path = format!("{}/", path);
+                entries.push(Entry {
+                    path: path.clone(),
+                    id: id.clone(),
+                    kind: Kind::Directory,
+                });
+                collect(git_dir, &id, &path, entries)?;
+            }
+            "160000" => entries.push(Entry {
+                path: format!("{}/", path),
+                id,
+                kind: Kind::Directory,
+            }),
+            mode => {
+                let (object_type, content) = repository.read(&id)?;
+                if object_type != ObjectType::Blob {
+                    return Err(anyhow!("{}: {} is not a blob.", path, id));
+                }
+                let kind = match mode {
+                    "120000" => Kind::Symlink { target: content },
+                    _ => Kind::File {
+                        executable: mode == "100755",
+                        content,
+                    },
+                };
+                entries.push(Entry { path, id, kind });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_tar(
+    out: &mut impl Write,
+    entries: &[Entry],
+    commit_id: Option<&str>,
+    time: i64,
+) -> anyhow::Result<()> {
+    let mut written = 0;
+    let mut write_block = |out: &mut dyn Write, data: &[u8]| -> anyhow::Result<()> {
+        out.write_all(data)?;
+        let padding = (TAR_BLOCK - data.len() % TAR_BLOCK) % TAR_BLOCK;
+        out.write_all(&vec![0; padding])?;
+        written += data.len() + padding;
+        Ok(())
+    };
+
+    if let Some(commit_id) = commit_id {
+        let records = pax_record("comment", commit_id);
+        let header = tar_header("pax_global_header", 0o666, records.len(), time, b'g', b"");
+        write_block(out, &header)?;
+        write_block(out, &records)?;
+    }
+    for entry in entries {
+        let (mode, typeflag, content, link): (u32, u8, &[u8], &[u8]) = match &entry.kind {
+            Kind::Directory => (0o775, b'5', b"", b""),
+            Kind::File {
+                executable,
+                content,
+            } => match executable {
+                true => (0o775, b'0', content, b""),
+                false => (0o664, b'0', content, b""),
+            },
+            Kind::Symlink { target } => (0o777, b'2', b"", target),
+        };
+        // Paths and link targets too long for the header go in an extended
+        // header before it, which the header names.
+        let mut records = Vec::new();
+        let (prefix, name) = match split_tar_name(&entry.path) {
+            Some((prefix, name)) => (prefix, name.to_string()),
+            None => {
+                records.extend(pax_record("path", &entry.path));
+                ("", format!("{}.data", entry.id))
+            }
+        };
+        let link = match link.len() > 100 {
+            true => {
+                records.extend(pax_record("linkpath", &String::from_utf8_lossy(link)));
+                format!("see {}.paxheader", entry.id).into_bytes()
+            }
+            false => link.to_vec(),
+        };
+        if !records.is_empty() {
+            let name = format!("{}.paxheader", entry.id);
+            let header = tar_header(&name, 0o666, records.len(), time, b'x', b"");
+            write_block(out, &header)?;
+            write_block(out, &records)?;
+        }
+        let mut header = tar_header(&name, mode, content.len(), time, typeflag, &link);
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        set_tar_checksum(&mut header);
+        write_block(out, &header)?;
+        if !content.is_empty() {
+            write_block(out, content)?;
+        }
+    }
+
+    write_block(out, &[0; 2 * TAR_BLOCK])?;
+    let padding = (TAR_RECORD - written % TAR_RECORD) % TAR_RECORD;
+    out.write_all(&vec![0; padding])?;
+    Ok(())
+}
+
+/// Splits a path into the ustar header's 155 byte prefix and 100 byte name,
+/// at the last `/` that fits the prefix, like git. `None` when it doesn't fit
+/// that way either.
+fn split_tar_name(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+    let end = path.len() - usize::from(path.ends_with('/'));
+    let split = path.as_bytes()[..end.min(155)]
+        .iter()
+        .rposition(|&x| x == b'/')?;
+    match path.len() - split - 1 <= 100 && split > 0 {
+        true => Some((&path[..split], &path[split + 1..])),
+        false => None,
+    }
+}
+
+/// A pax record, `<length> <key>=<value>\n`, whose length counts itself.
+fn pax_record(key: &str, value: &str) -> Vec<u8> {
+    let rest = format!(" {}={}\n", key, value);
+    let mut length = rest.len() + 1;
+    while (length.to_string().len() + rest.len()) != length {
+        length += 1;
+    }
+    format!("{}{}", length, rest).into_bytes()
+}
+
+fn tar_header(
+    name: &str,
+    mode: u32,
+    size: usize,
+    time: i64,
+    typeflag: u8,
+    link: &[u8],
+) -> [u8; TAR_BLOCK] {
+    let mut header = [0; TAR_BLOCK];
+    let mut put = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+    put(0, &name.as_bytes()[..name.len().min(100)]);
+    put(100, format!("{:07o}\0", mode).as_bytes());
+    put(108, b"0000000\0");
+    put(116, b"0000000\0");
+    put(124, format!("{:011o}\0", size).as_bytes());
+    put(136, format!("{:011o}\0", time.max(0)).as_bytes());
+    put(156, &[typeflag]);
+    put(157, link);
+    put(257, b"ustar\x0000");
+    put(265, b"root");
+    put(297, b"root");
+    put(329, b"0000000\0");
+    put(337, b"0000000\0");
+    set_tar_checksum(&mut header);
+    header
+}
+
+/// The checksum is the sum of the header's bytes, counting its own field as
+/// spaces.
+fn set_tar_checksum(header: &mut [u8; TAR_BLOCK]) {
+    header[148..156].copy_from_slice(b"        ");
+    let sum: u32 = header.iter().map(|&x| x as u32).sum();
+    header[148..156].copy_from_slice(format!("{:07o}\0", sum).as_bytes());
+}
+
+fn write_zip(
+    out: &mut impl Write,
+    entries: &[Entry],
+    commit_id: Option<&str>,
+    time: i64,
+) -> anyhow::Result<()> {
+    let (dos_time, dos_date) = dos_date_time(time);
+    let mut offset = 0u32;
+    let mut central = Vec::new();
+    for entry in entries {
+        let (mode, content): (u32, &[u8]) = match &entry.kind {
+            Kind::Directory => (0o40775, b""),
+            Kind::File {
+                executable: true,
+                content,
+            } => (0o100775, content),
+            Kind::File { content, .. } => (0o100664, content),
+            Kind::Symlink { target } => (0o120777, target),
+        };
+        let mut crc = Crc::new();
+        crc.update(content);
+        // Like git, only files are compressed, and only when that helps.
+        let compressed = match entry.kind {
+            Kind::File { .. } if !content.is_empty() => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(content)?;
+                Some(encoder.finish()?).filter(|x| x.len() < content.len())
+            }
+            _ => None,
+        };
+        let (method, data) = match &compressed {
+            Some(data) => (8u16, data.as_slice()),
+            None => (0u16, content),
+        };
+
+        let mut fields = Vec::new();
+        fields.extend(method.to_le_bytes());
+        fields.extend(dos_time.to_le_bytes());
+        fields.extend(dos_date.to_le_bytes());
+        fields.extend(crc.sum().to_le_bytes());
+        fields.extend((data.len() as u32).to_le_bytes());
+        fields.extend((content.len() as u32).to_le_bytes());
+        fields.extend((entry.path.len() as u16).to_le_bytes());
+        fields.extend(0u16.to_le_bytes());
+
+        // Version 1.0 suffices to extract stored entries; deflate needs 2.0.
+        let version: u16 = match method {
+            8 => 20,
+            _ => 10,
+        };
+        let mut local = Vec::new();
+        local.extend(0x04034b50u32.to_le_bytes());
+        local.extend(version.to_le_bytes());
+        local.extend(0u16.to_le_bytes());
+        local.extend(&fields);
+        local.extend(entry.path.as_bytes());
+        out.write_all(&local)?;
+        out.write_all(data)?;
+
+        // Made by Unix, so the mode in the external attributes counts.
+        central.extend(0x02014b50u32.to_le_bytes());
+        central.extend((0x0300 | 63u16).to_le_bytes());
+        central.extend(version.to_le_bytes());
+        central.extend(0u16.to_le_bytes());
+        central.extend(&fields);
+        central.extend(0u16.to_le_bytes());
+        central.extend(0u16.to_le_bytes());
+        central.extend(0u16.to_le_bytes());
+        let dos_attributes: u32 = match entry.kind {
+            Kind::Directory => 0x10,
+            _ => 0,
+        };
+        central.extend(((mode << 16) | dos_attributes).to_le_bytes());
+        central.extend(offset.to_le_bytes());
+        central.extend(entry.path.as_bytes());
+
+        offset += (local.len() + data.len()) as u32;
+    }
+    out.write_all(&central)?;
+
+    let comment = commit_id.unwrap_or_default();
+    let mut end = Vec::new();
+    end.extend(0x06054b50u32.to_le_bytes());
+    end.extend(0u16.to_le_bytes());
+    end.extend(0u16.to_le_bytes());
+    end.extend((entries.len() as u16).to_le_bytes());
+    end.extend((entries.len() as u16).to_le_bytes());
+    end.extend((central.len() as u32).to_le_bytes());
+    end.extend(offset.to_le_bytes());
+    end.extend((comment.len() as u16).to_le_bytes());
+    end.extend(comment.as_bytes());
+    out.write_all(&end)?;
+    Ok(())
+}
+
+/// MS-DOS time and date fields, which zip uses, for a Unix timestamp taken
+/// as UTC. They can't hold dates before 1980.
+fn dos_date_time(time: i64) -> (u16, u16) {
+    const DAY: i64 = 24 * 60 * 60;
+    let time = time.max(315532800);
+    let seconds = time.rem_euclid(DAY);
+    // Howard Hinnant's civil_from_days.
+    let days = time.div_euclid(DAY) + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    let dos_time = ((seconds / 3600) << 11) | ((seconds % 3600 / 60) << 5) | (seconds % 60 / 2);
+    let dos_date = ((year - 1980) << 9) | (month << 5) | day;
+    (dos_time as u16, dos_date as u16)
+}
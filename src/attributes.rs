@@ -0,0 +1,154 @@
+use crate::wildmatch::wildmatch;
+use std::fs;
+use std::path::Path;
+
+/// State of the `text` attribute for a path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Text {
+    Unspecified,
+    /// `text`: always normalize line endings.
+    Set,
+    /// `-text` or `binary`: never touch the content.
+    Unset,
+    /// `text=auto`: normalize only if the content looks like text.
+    Auto,
+}
+
+/// The attributes that affect how content is stored, resolved for one path.
+#[derive(Debug)]
+pub struct Attributes {
+    pub text: Text,
+    /// Whether an `eol` attribute is set, which implies `text`.
+    pub eol: bool,
+}
+
+struct Rule {
+    /// Directory of the attributes file, relative to the work tree ("" or "dir/").
+    base: String,
+    pattern: String,
+    text: Option<Text>,
+    eol: Option<bool>,
+}
+
+/// Resolves the attributes of `path` (relative to the work tree).
+///
+/// Reads `.gitattributes` in the work tree root and in every directory leading
+/// to `path`, then `$GIT_DIR/info/attributes`; later files and later lines win.
+pub fn attributes_for(work_tree: &Path, git_dir: &Path, path: &str) -> Attributes {
+    let mut rules = Vec::new();
+
+    read_rules(&work_tree.join(".gitattributes"), "", &mut rules);
+    if let Some((dirs, _)) = path.rsplit_once('/') {
+        let mut base = String::new();
+        for dir in dirs.split('/') {
+            base.push_str(dir);
+            base.push('/');
+            read_rules(
+                &work_tree.join(&base).join(".gitattributes"),
+                &base,
+                &mut rules,
+            );
+        }
+    }
+    read_rules(&git_dir.join("info").join("attributes"), "", &mut rules);
+
+    let mut attributes = Attributes {
+        text: Text::Unspecified,
+        eol: false,
+    };
+    for rule in rules.iter().filter(|x| rule_matches(x, path)) {
+        if let Some(text) = rule.text {
+            attributes.text = text;
+        }
+        if let Some(eol) = rule.eol {
+            attributes.eol = eol;
+        }
+    }
+
+    attributes
+}
+
+/// Whether `convert_to_git` may change content with these attributes; when
+/// it can't, content can be stored without being read whole first.
+pub fn may_convert(attributes: &Attributes) -> bool {
+    match attributes.text {
+        Text::Set | Text::Auto => true,
+        Text::Unset => false,
+        Text::Unspecified => attributes.eol,
+    }
+}
+
+/// Applies the "clean" conversion done when content enters the object store:
+/// CRLF line endings become LF for paths treated as text.
+pub fn convert_to_git(attributes: &Attributes, content: Vec<u8>) -> Vec<u8> {
+    let is_text = match attributes.text {
+        Text::Auto => !content.iter().take(8000).any(|&x| x == 0),
+        _ => may_convert(attributes),
+    };
+
+    if !is_text || !content.windows(2).any(|x| x == b"\r\n") {
+        return content;
+    }
+
+    let mut converted = Vec::with_capacity(content.len());
+    let mut iter = content.iter().peekable();
+    while let Some(&byte) = iter.next() {
+        if byte == b'\r' && iter.peek() == Some(&&b'\n') {
+            continue;
+        }
+        converted.push(byte);
+    }
+    converted
+}
+
+fn read_rules(path: &Path, base: &str, rules: &mut Vec<Rule>) {
+    let Some(content) = fs::read_to_string(path).ok() else {
+        return;
+    };
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut tokens = line.split_whitespace();
+        let Some(pattern) = tokens.next() else {
+            continue;
+        };
+
+        let mut rule = Rule {
+            base: base.to_string(),
+            pattern: pattern.to_string(),
+            text: None,
+            eol: None,
+        };
+        for token in tokens {
+            match token {
+                "text" => rule.text = Some(Text::Set),
+                "-text" | "binary" => rule.text = Some(Text::Unset),
+                "!text" => rule.text = Some(Text::Unspecified),
+                "text=auto" => rule.text = Some(Text::Auto),
+                "-eol" | "!eol" => rule.eol = Some(false),
+                token if token.starts_with("eol=") => rule.eol = Some(true),
+                _ => {}
+            }
+        }
+        rules.push(rule);
+    }
+}
+
+/// Patterns without a slash match the file name at any depth; others match the
+/// path relative to the directory of the attributes file.
+fn rule_matches(rule: &Rule, path: &str) -> bool {
+    let Some(relative) = path.strip_prefix(&rule.base) else {
+        return false;
+    };
+
+    if rule.pattern.contains('/') {
+        wildmatch(rule.pattern.trim_start_matches('/'), relative)
+    } else {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        wildmatch(&rule.pattern, name)
+    }
+}
@@ -0,0 +1,244 @@
+use crate::date::{format_iso_date, now, signature_time};
+use crate::diff::{self, diff_lines, split_lines, Edit};
+use crate::graph::CommitGraph;
+use crate::{resolve_ref, short_id, Object, ObjectType, Repository};
+use anyhow::anyhow;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// Where a line of the blamed file was last changed: in a commit, under the
+/// path the file had there, or in the work tree when `commit` is `None`.
+#[derive(Clone)]
+struct Origin {
+    commit: Option<String>,
+    path: String,
+}
+
+/// Lines of one version of the file still to be blamed, as
+/// `(line in the blamed file, line in this version)`.
+type Lines = Vec<(usize, usize)>;
+
+/// Prints who last changed each line of `path` in the work tree, like
+/// `git blame`: `<id> (<author> <date> <line number>) <line>`.
+///
+/// Starting from the work tree file, lines are passed from each commit to
+/// the parents that have them unchanged, following the file through
+/// renames, until they reach the commit that introduced them. Commits are
+/// visited highest generation first, so a commit has heard from all its
+/// children before it is blamed. Lines not in `HEAD` yet are shown as `Not
+/// Committed Yet`, and lines of root commits are marked with `^`.
+pub fn blame(git_dir: &Path, work_tree: &Path, path: &str) -> anyhow::Result<()> {
+    let head = resolve_ref(git_dir, "HEAD")?.ok_or_else(|| anyhow!("No commits yet."))?;
+    let head = Repository::open(git_dir).peel_to(&head, ObjectType::Commit)?;
+    let mut graph = CommitGraph::new(git_dir);
+    let head_blob = blob_at(git_dir, &graph.commit(&head)?.tree, path)?
+        .ok_or_else(|| anyhow!("No such path '{}' in HEAD.", path))?;
+
+    let content = match fs::read(work_tree.join(path)) {
+        Result::Ok(content) => String::from_utf8_lossy(&content).into_owned(),
+        // A deleted file is blamed as it is in HEAD.
+        Err(_) => read_blob(git_dir, &head_blob)?,
+    };
+    let lines = split_lines(&content);
+    let mut origins: Vec<Option<Origin>> = vec![None; lines.len()];
+
+    let head_content = read_blob(git_dir, &head_blob)?;
+    let head_lines = split_lines(&head_content);
+    let unchanged = matching_lines(&head_lines, &lines, (0..lines.len()).map(|x| (x, x)));
+    let committed: HashSet<usize> = unchanged.iter().map(|(x, _)| *x).collect();
+    for (line, origin) in origins.iter_mut().enumerate() {
+        if !committed.contains(&line) {
+            *origin = Some(Origin {
+                commit: None,
+                path: path.to_string(),
+            });
+        }
+    }
+
+    let mut pending: HashMap<(String, String), Lines> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    if !unchanged.is_empty() {
+        queue.push((graph.generation(&head)?, head.clone(), path.to_string()));
+        pending.insert((head, path.to_string()), unchanged);
+    }
+    while let Some((_, commit_id, commit_path)) = queue.pop() {
+        let Some(mut lines) = pending.remove(&(commit_id.clone(), commit_path.clone())) else {
+            continue;
+        };
+        let commit = graph.commit(&commit_id)?.clone();
+        let blob = blob_at(git_dir, &commit.tree, &commit_path)?
+            .ok_or_else(|| anyhow!("{} has no {}.", commit_id, commit_path))?;
+        let content = read_blob(git_dir, &blob)?;
+        let own_lines = split_lines(&content);
+
+        for parent in &commit.parents {
+            if lines.is_empty() {
+                break;
+            }
+            let parent_tree = graph.commit(parent)?.tree.clone();
+            let Some((parent_path, parent_blob)) =
+                find_in_parent(git_dir, &parent_tree, &commit.tree, &commit_path)?
+            else {
+                continue;
+            };
+            let passed = match parent_blob == blob {
+                true => std::mem::take(&mut lines),
+                false => {
+                    let parent_content = read_blob(git_dir, &parent_blob)?;
+                    let parent_lines = split_lines(&parent_content);
+                    let passed = matching_lines(&parent_lines, &own_lines, lines.iter().copied());
+                    let passed_lines: HashSet<usize> = passed.iter().map(|(x, _)| *x).collect();
+                    lines.retain(|(x, _)| !passed_lines.contains(x));
+                    passed
+                }
+            };
+            if passed.is_empty() {
+                continue;
+            }
+            let key = (parent.clone(), parent_path.clone());
+            if !pending.contains_key(&key) {
+                queue.push((graph.generation(parent)?, parent.clone(), parent_path));
+            }
+            pending.entry(key).or_default().extend(passed);
+        }
+
+        for (line, _) in lines {
+            origins[line] = Some(Origin {
+                commit: Some(commit_id.clone()),
+                path: commit_path.clone(),
+            });
+        }
+    }
+
+    print_blame(&mut graph, path, &lines, &origins)
+}
+
+/// Of `lines`, those whose line in `new` the diff from `old` kept, mapped to
+/// their line in `old`.
+fn matching_lines(
+    old: &[&str],
+    new: &[&str],
+    lines: impl Iterator<Item = (usize, usize)>,
+) -> Lines {
+    let mut old_line = vec![None; new.len()];
+    for edit in diff_lines(old, new) {
+        if let Edit::Equal(old, new) = edit {
+            old_line[new] = Some(old);
+        }
+    }
+    lines
+        .filter_map(|(line, local)| old_line[local].map(|old| (line, old)))
+        .collect()
+}
+
+/// The path and blob the file at `path` in `tree` had in `parent_tree`: at
+/// the same path, or where it was renamed from.
+fn find_in_parent(
+    git_dir: &Path,
+    parent_tree: &str,
+    tree: &str,
+    path: &str,
+) -> anyhow::Result<Option<(String, String)>> {
+    if let Some(blob) = blob_at(git_dir, parent_tree, path)? {
+        return Ok(Some((path.to_string(), blob)));
+    }
+    let changes = diff::diff_trees(git_dir, Some(parent_tree), Some(tree))?;
+    let changes = diff::detect_renames(git_dir, changes, Some(50))?;
+    let Some(rename) = changes
+        .into_iter()
+        .find(|x| x.path == path)
+        .and_then(|x| x.rename)
+    else {
+        return Ok(None);
+    };
+    Ok(blob_at(git_dir, parent_tree, &rename.from)?.map(|blob| (rename.from, blob)))
+}
+
+/// The id of the blob at `path` under `tree_id`, if there is one.
+fn blob_at(git_dir: &Path, tree_id: &str, path: &str) -> anyhow::Result<Option<String>> {
+    let mut tree_id = tree_id.to_string();
+    let mut components = path.split('/').peekable();
+    while let Some(name) = components.next() {
+        let Object::Tree(tree) = Repository::open(git_dir).object(&tree_id)? else {
+            return Err(anyhow!("Object {} is not a tree.", tree_id));
+        };
+        let Some(element) = tree.elements.into_iter().find(|x| x.name == name) else {
+            return Ok(None);
+        };
+        let is_last = components.peek().is_none();
+        match (element.object_type, is_last) {
+            (ObjectType::Blob, true) => return Ok(Some(hex::encode(&element.hash))),
+            (ObjectType::Tree, false) => tree_id = hex::encode(&element.hash),
+            _ => return Ok(None),
+        }
+    }
+    Ok(None)
+}
+
+fn read_blob(git_dir: &Path, blob_id: &str) -> anyhow::Result<String> {
+    let (_, content) = Repository::open(git_dir).read(blob_id)?;
+    Ok(String::from_utf8_lossy(&content).into_owned())
+}
+
+fn print_blame(
+    graph: &mut CommitGraph,
+    path: &str,
+    lines: &[&str],
+    origins: &[Option<Origin>],
+) -> anyhow::Result<()> {
+    let mut rows = Vec::new();
+    for origin in origins {
+        let origin = origin.clone().expect("every line is blamed");
+        let row = match &origin.commit {
+            Some(id) => {
+                let commit = graph.commit(id)?;
+                let shown = match commit.parents.is_empty() {
+                    true => format!("^{}", short_id(id)),
+                    false => id[..8].to_string(),
+                };
+                let author = &commit.author;
+                let name = author.split(" <").next().unwrap_or_default().to_string();
+                let date = match signature_time(author) {
+                    Some((seconds, offset)) => format_iso_date(seconds, offset),
+                    None => String::new(),
+                };
+                (shown, name, date)
+            }
+            None => (
+                "00000000".to_string(),
+                "Not Committed Yet".to_string(),
+                format_iso_date(now(), "+0000"),
+            ),
+        };
+        rows.push((row, origin.path));
+    }
+
+    // Like git, paths are only shown when the file was renamed.
+    let show_paths = rows.iter().any(|(_, x)| x != path);
+    let path_width = rows.iter().map(|(_, x)| x.len()).max().unwrap_or(0);
+    let name_width = rows
+        .iter()
+        .map(|((_, x, _), _)| x.chars().count())
+        .max()
+        .unwrap_or(0);
+    let number_width = lines.len().to_string().len();
+    for (number, (((id, name, date), origin_path), line)) in rows.iter().zip(lines).enumerate() {
+        let shown_path = match show_paths {
+            true => format!(" {:<path_width$}", origin_path),
+            false => String::new(),
+        };
+        let name_padding = name_width - name.chars().count();
+        println!(
+            "{}{} ({}{} {} {:>number_width$}) {}",
+            id,
+            shown_path,
+            name,
+            " ".repeat(name_padding),
+            date,
+            number + 1,
+            line.trim_end_matches('\n')
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,263 @@
+use crate::log::{self, Filter};
+use crate::pack_objects::missing_objects;
+use crate::transport::FetchStats;
+use crate::LockFile;
+use crate::{expand_ref, list_refs, pack, resolve_rev, ObjectType, Repository};
+use anyhow::{anyhow, Context};
+use sha1::{Digest, Sha1};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The first line of a version 2 bundle, the only version read or written.
+const SIGNATURE: &[u8] = b"# v2 git bundle\n";
+
+/// A bundle file, a fetch stored on disk: a header listing the commits the
+/// receiving repository must already have and the refs it carries, then
+/// a pack of every object those refs reach beyond the prerequisites.
+pub struct Bundle {
+    path: PathBuf,
+    /// `(commit id, subject)`; the subject is only a comment.
+    prerequisites: Vec<(String, String)>,
+    /// `(name, object id)`, in the order the bundle lists them.
+    refs: Vec<(String, String)>,
+    data: Vec<u8>,
+    pack_offset: usize,
+}
+
+/// Whether the file at `path` starts like a bundle.
+pub fn is_bundle(path: &Path) -> bool {
+    path.is_file() && fs::read(path).is_ok_and(|x| x.starts_with(SIGNATURE))
+}
+
+impl Bundle {
+    pub fn open(path: &Path) -> anyhow::Result<Bundle> {
+        let data = fs::read(path).with_context(|| format!("Read {}.", path.display()))?;
+        let Some(mut rest) = data.strip_prefix(SIGNATURE) else {
+            return Err(anyhow!(
+                "'{}' does not look like a v2 bundle file.",
+                path.display()
+            ));
+        };
+        let mut prerequisites = Vec::new();
+        let mut refs = Vec::new();
+        loop {
+            let end = rest
+                .iter()
+                .position(|x| *x == b'\n')
+                .ok_or_else(|| anyhow!("The bundle's header is cut short."))?;
+            let line = std::str::from_utf8(&rest[..end])
+                .map_err(|_| anyhow!("The bundle's header isn't UTF-8."))?;
+            rest = &rest[end + 1..];
+            if line.is_empty() {
+                break;
+            }
+            let (line, list) = match line.strip_prefix('-') {
+                Some(line) => (line, &mut prerequisites),
+                None => (line, &mut refs),
+            };
+            let (id, name) = line.split_once(' ').unwrap_or((line, ""));
+            if id.len() != 40 || !id.bytes().all(|x| x.is_ascii_hexdigit()) {
+                return Err(anyhow!("Invalid bundle header line '{}'.", line));
+            }
+            list.push((id.to_string(), name.to_string()));
+        }
+        let refs = refs.into_iter().map(|(id, name)| (name, id)).collect();
+        let pack_offset = data.len() - rest.len();
+        Ok(Bundle {
+            path: path.to_path_buf(),
+            prerequisites,
+            refs,
+            data,
+            pack_offset,
+        })
+    }
+
+    /// The refs the bundle carries as `(name, object id)`.
+    pub fn refs(&self) -> Vec<(String, String)> {
+        self.refs.clone()
+    }
+
+    /// The branch the bundle's `HEAD` names, if it carries `HEAD`: the first
+    /// branch at the same commit, there being no symbolic refs in a bundle.
+    pub fn head(&self) -> Option<String> {
+        let (_, head) = self.refs.iter().find(|(name, _)| name == "HEAD")?;
+        self.refs
+            .iter()
+            .find(|(name, id)| name.starts_with("refs/heads/") && id == head)
+            .map(|(name, _)| name.clone())
+    }
+
+    fn pack(&self) -> &[u8] {
+        &self.data[self.pack_offset..]
+    }
+
+    /// Fails unless `git_dir` has every prerequisite commit, naming those
+    /// it lacks.
+    fn check_prerequisites(&self, git_dir: &Path) -> anyhow::Result<()> {
+        let mut missing = String::new();
+        for (id, subject) in &self.prerequisites {
+            if !Repository::open(git_dir).exists(id)? {
+                missing.push_str(&format!("\n{} {}", id, subject));
+            }
+        }
+        match missing.is_empty() {
+            true => Ok(()),
+            false => Err(anyhow!(
+                "Repository lacks these prerequisite commits:{}",
+                missing
+            )),
+        }
+    }
+
+    /// Copies the bundle's objects into `git_dir` unless it has `wants`
+    /// already. A complete bundle's pack is stored as it is; one that needs
+    /// prerequisites may hold deltas against them, so its objects are
+    /// unpacked.
+    pub fn fetch(&self, git_dir: &Path, wants: &[String]) -> anyhow::Result<FetchStats> {
+        pack::require_sha1(git_dir)?;
+        self.check_prerequisites(git_dir)?;
+        let mut complete = true;
+        for want in wants {
+            complete &= Repository::open(git_dir).exists(want)?;
+        }
+        if complete {
+            return Ok(FetchStats::default());
+        }
+        if self.prerequisites.is_empty() {
+            let name = pack::store_pack(git_dir, self.pack())
+                .with_context(|| format!("Index the pack in {}.", self.path.display()))?;
+            info!("Copied the bundle's pack into {}.pack", name);
+            return Ok(FetchStats {
+                objects: 0,
+                packs: 1,
+            });
+        }
+        let objects = pack::unpack_objects(git_dir, self.pack())
+            .with_context(|| format!("Unpack the pack in {}.", self.path.display()))?;
+        Ok(FetchStats { objects, packs: 0 })
+    }
+}
+
+/// Writes a bundle of `revs` to `file`, like `git bundle create`: each rev
+/// that names a ref (or `HEAD`) is carried under that name, or with `all`
+/// every ref is. `^<rev>` and `<a>..<b>` leave out what the excluded commits
+/// reach; the excluded commits the bundled history starts from become its
+/// prerequisites.
+pub fn create(git_dir: &Path, file: &Path, revs: &[String], all: bool) -> anyhow::Result<()> {
+    pack::require_sha1(git_dir)?;
+    let or_head = |rev: &str| if rev.is_empty() { "HEAD" } else { rev }.to_string();
+    let mut refs = Vec::new();
+    let mut hidden = Vec::new();
+    if all {
+        refs.extend(list_refs(git_dir)?);
+    }
+    for rev in revs {
+        let positive = if let Some((from, to)) = rev.split_once("..") {
+            hidden.push(resolve_rev(git_dir, &or_head(from))?);
+            or_head(to)
+        } else if let Some(rev) = rev.strip_prefix('^') {
+            hidden.push(resolve_rev(git_dir, rev)?);
+            continue;
+        } else {
+            rev.clone()
+        };
+        let id = resolve_rev(git_dir, &positive)?;
+        let name = match positive.as_str() {
+            "HEAD" => Some(positive.clone()),
+            _ => expand_ref(git_dir, &positive)?,
+        };
+        match name {
+            Some(name) if !refs.iter().any(|(x, _)| *x == name) => refs.push((name, id)),
+            Some(_) => {}
+            None => info!(
+                "warning: '{}' isn't a ref, so the bundle won't carry it.",
+                rev
+            ),
+        }
+    }
+    if refs.is_empty() {
+        return Err(anyhow!("Refusing to create empty bundle."));
+    }
+
+    let mut starts = Vec::new();
+    let repository = Repository::open(git_dir);
+    for (_, id) in &refs {
+        if let Result::Ok(commit) = repository.peel_to(id, ObjectType::Commit) {
+            starts.push(commit);
+        }
+    }
+    let mut excluded = Vec::new();
+    for id in &hidden {
+        excluded.push(repository.peel_to(id, ObjectType::Commit)?);
+    }
+    let commits = log::walk_range(git_dir, &starts, &excluded, &Filter::default())?;
+    let bundled: HashSet<&String> = commits.iter().map(|(id, _)| id).collect();
+    let mut prerequisites = Vec::new();
+    for (_, commit) in &commits {
+        for parent in &commit.parents {
+            if !bundled.contains(parent) && !prerequisites.contains(parent) {
+                prerequisites.push(parent.clone());
+            }
+        }
+    }
+
+    let mut content = SIGNATURE.to_vec();
+    for id in &prerequisites {
+        let commit = log::read_commit(git_dir, id)?;
+        let subject = commit.message.lines().next().unwrap_or_default();
+        content.extend_from_slice(format!("-{} {}\n", id, subject).as_bytes());
+    }
+    for (name, id) in &refs {
+        content.extend_from_slice(format!("{} {}\n", id, name).as_bytes());
+    }
+    content.push(b'\n');
+    let tips: Vec<String> = refs.iter().map(|(_, id)| id.clone()).collect();
+    let objects = missing_objects(git_dir, &tips, &prerequisites)?;
+    pack::write_pack(git_dir, &objects, &mut content).context("Write the pack.")?;
+    LockFile::acquire(file)?.commit(&content)
+}
+
+/// Checks that the bundle at `file` is intact and that `git_dir` has its
+/// prerequisites, and describes it, like `git bundle verify`.
+pub fn verify(git_dir: &Path, file: &Path) -> anyhow::Result<()> {
+    let bundle = Bundle::open(file)?;
+    let pack = bundle.pack();
+    if pack.len() < 32 || !pack.starts_with(b"PACK") {
+        return Err(anyhow!("The bundle holds no pack."));
+    }
+    let (body, checksum) = pack.split_at(pack.len() - 20);
+    if Sha1::digest(body).as_slice() != checksum {
+        return Err(anyhow!(
+            "The bundle's pack is corrupt: its checksum doesn't match."
+        ));
+    }
+    bundle.check_prerequisites(git_dir)?;
+
+    match bundle.refs.len() {
+        1 => println!("The bundle contains this ref:"),
+        n => println!("The bundle contains these {} refs:", n),
+    }
+    for (name, id) in &bundle.refs {
+        println!("{} {}", id, name);
+    }
+    match bundle.prerequisites.len() {
+        0 => println!("The bundle records a complete history."),
+        1 => println!("The bundle requires this ref:"),
+        n => println!("The bundle requires these {} refs:", n),
+    }
+    for (id, _) in &bundle.prerequisites {
+        println!("{} ", id);
+    }
+    println!("The bundle uses this hash algorithm: sha1");
+    info!("{} is okay", file.display());
+    Ok(())
+}
+
+/// Prints the refs the bundle at `file` carries, like `git bundle list-heads`.
+pub fn list_heads(file: &Path) -> anyhow::Result<()> {
+    for (name, id) in Bundle::open(file)?.refs {
+        println!("{} {}", id, name);
+    }
+    Ok(())
+}
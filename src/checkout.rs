@@ -0,0 +1,448 @@
+use crate::index::{Index, IndexEntry};
+use crate::platform::{self, Filesystem};
+use crate::refs::RefLock;
+use crate::{
+    canonical_mode, diff, git_path, hooks, leads_through_symlink, merge, read_symref,
+    record_ref_update, resolve_ref, resolve_rev, short_id, status, CommitObject, Object,
+    ObjectFormat, ObjectType, Progress, Repository,
+};
+use anyhow::{anyhow, Context};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// How deeply trees may nest before checkout gives up (git's `core.maxTreeDepth`).
+const MAX_TREE_DEPTH: usize = 2048;
+
+/// Index path to `(mode, id)`.
+pub type Entries = BTreeMap<String, (u32, Vec<u8>)>;
+
+/// Switches the work tree, index and `HEAD` to `rev`: a branch, which `HEAD`
+/// is then attached to, or any other commit, which detaches `HEAD`.
+///
+/// Files that differ between the two commits are rewritten and those the
+/// target doesn't have are deleted. Local changes to other files are kept,
+/// like git does; local changes to files the switch would touch, and
+/// untracked files in the way of the target's, make it fail before anything
+/// is written. The post-checkout hook runs once `HEAD` moved; its failure
+/// is the command's, though the switch stays done.
+pub fn checkout(git_dir: &Path, work_tree: &Path, rev: &str) -> anyhow::Result<()> {
+    let branch_ref = format!("refs/heads/{}", rev);
+    let branch = resolve_ref(git_dir, &branch_ref)?.map(|_| branch_ref);
+    let repository = Repository::open(git_dir);
+    let commit_id = repository.peel_to(&resolve_rev(git_dir, rev)?, ObjectType::Commit)?;
+    let (_, content) = repository.read(&commit_id)?;
+    let commit = CommitObject::from_content(&content)?;
+
+    let index_path = git_path(git_dir, "index");
+    let index = Index::load(&index_path)?;
+    if index.entries.iter().any(|x| x.stage() != 0) {
+        return Err(anyhow!("You need to resolve your current index first."));
+    }
+
+    let mut head = Entries::new();
+    if let Some(head_id) = resolve_ref(git_dir, "HEAD")? {
+        let head_tree = repository.peel_to(&head_id, ObjectType::Tree)?;
+        status::flatten_tree(git_dir, &head_tree, "", &mut head)?;
+    }
+    let mut target = Entries::new();
+    tree_entries(git_dir, &commit.tree, "", 0, &mut target)?;
+
+    // Local changes carry over untouched where both commits agree on the
+    // file; elsewhere they would be lost.
+    let changed: BTreeSet<String> = diff::diff_head_to_index(git_dir)?
+        .into_iter()
+        .chain(diff::diff_index_to_work_tree(
+            git_dir,
+            work_tree,
+            &mut Default::default(),
+        )?)
+        .map(|x| x.path)
+        .collect();
+    let overwritten: Vec<&String> = changed
+        .iter()
+        .filter(|x| head.get(*x) != target.get(*x))
+        .collect();
+    if !overwritten.is_empty() {
+        return Err(anyhow!(
+            "Your local changes to the following files would be overwritten by checkout:\n{}\nCommit them or discard them before you switch.",
+            list_paths(overwritten)
+        ));
+    }
+
+    let tracked: BTreeMap<&str, &IndexEntry> =
+        index.entries.iter().map(|x| (x.path.as_str(), x)).collect();
+    let in_the_way: Vec<&String> = target
+        .keys()
+        .filter(|x| !tracked.contains_key(x.as_str()) && !changed.contains(*x))
+        .filter(|x| untracked_in_the_way(work_tree, x, &tracked))
+        .collect();
+    if !in_the_way.is_empty() {
+        return Err(anyhow!(
+            "The following untracked work tree files would be overwritten by checkout:\n{}\nMove or remove them before you switch.",
+            list_paths(in_the_way)
+        ));
+    }
+
+    let mut entries = Vec::new();
+    for entry in &index.entries {
+        if changed.contains(&entry.path) {
+            entries.push(entry.clone());
+        } else if !target.contains_key(&entry.path) {
+            remove_work_tree_file(work_tree, &entry.path)?;
+        }
+    }
+    let mut progress = Progress::delayed("Updating files", Some(target.len() as u64));
+    for (path, (mode, hash)) in &target {
+        progress.tick();
+        if changed.contains(path) {
+            continue;
+        }
+        match tracked.get(path.as_str()) {
+            Some(entry) if entry.mode == *mode && entry.hash == *hash => {
+                entries.push((*entry).clone())
+            }
+            _ => entries.push(write_entry(git_dir, work_tree, path, *mode, hash)?),
+        }
+    }
+    progress.finish();
+    write_index(git_dir, entries)?;
+
+    let old_head = resolve_ref(git_dir, "HEAD")?;
+    let from = match read_symref(git_dir, "HEAD")? {
+        Some(name) => name
+            .strip_prefix("refs/heads/")
+            .unwrap_or(&name)
+            .to_string(),
+        None => old_head.clone().unwrap_or_default(),
+    };
+    let head_lock = RefLock::acquire(git_dir, "HEAD")?;
+    match &branch {
+        Some(branch) => {
+            head_lock.commit_symref(branch)?;
+            info!("Switched to branch '{}'", rev);
+        }
+        None => {
+            head_lock.commit(&commit_id)?;
+            let subject = commit.message.lines().next().unwrap_or_default();
+            info!("HEAD is now at {} {}", short_id(&commit_id), subject);
+        }
+    }
+    record_ref_update(
+        git_dir,
+        "HEAD",
+        old_head.as_deref(),
+        &commit_id,
+        &format!("checkout: moving from {} to {}", from, rev),
+    )?;
+    for path in &changed {
+        info!("M\t{}", path);
+    }
+    let old_head = old_head.unwrap_or_else(|| "0".repeat(40));
+    if !hooks::run(git_dir, "post-checkout", &[&old_head, &commit_id, "1"], &[])? {
+        return Err(anyhow!("The post-checkout hook failed."));
+    }
+    Ok(())
+}
+
+/// What `reset` brings in line with the commit it moves `HEAD` to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetMode {
+    /// Only the branch moves.
+    Soft,
+    /// The index is reset too, leaving the work tree's changes unstaged.
+    Mixed,
+    /// The index and the work tree are reset, discarding every change to
+    /// tracked files.
+    Hard,
+}
+
+/// Moves the branch `HEAD` is on (or `HEAD` itself when detached) to `rev`,
+/// like `git reset`, then resets as much of the index and work tree as
+/// `mode` asks for. Untracked files are left alone, unless a `Hard` reset
+/// needs their place for a file of the commit. With `quiet`, nothing is
+/// printed.
+pub fn reset(
+    git_dir: &Path,
+    work_tree: &Path,
+    rev: &str,
+    mode: ResetMode,
+    quiet: bool,
+) -> anyhow::Result<()> {
+    let repository = Repository::open(git_dir);
+    let commit_id = repository.peel_to(&resolve_rev(git_dir, rev)?, ObjectType::Commit)?;
+    let (_, content) = repository.read(&commit_id)?;
+    let commit = CommitObject::from_content(&content)?;
+
+    let index_path = git_path(git_dir, "index");
+    let index = Index::load(&index_path)?;
+    let unmerged = index.entries.iter().any(|x| x.stage() != 0);
+    if mode == ResetMode::Soft && unmerged {
+        return Err(anyhow!("Cannot do a soft reset in the middle of a merge."));
+    }
+
+    if mode != ResetMode::Soft {
+        let mut target = Entries::new();
+        tree_entries(git_dir, &commit.tree, "", 0, &mut target)?;
+        let changed: BTreeSet<String> = match mode {
+            ResetMode::Hard => {
+                diff::diff_index_to_work_tree(git_dir, work_tree, &mut Default::default())?
+                    .into_iter()
+                    .map(|x| x.path)
+                    .collect()
+            }
+            _ => BTreeSet::new(),
+        };
+
+        let tracked: BTreeMap<&str, &IndexEntry> = index
+            .entries
+            .iter()
+            .filter(|x| x.stage() == 0)
+            .map(|x| (x.path.as_str(), x))
+            .collect();
+        if mode == ResetMode::Hard {
+            for entry in &index.entries {
+                if !target.contains_key(&entry.path) {
+                    remove_work_tree_file(work_tree, &entry.path)?;
+                }
+            }
+        }
+        let mut entries = Vec::new();
+        for (path, (mode_bits, hash)) in &target {
+            match tracked.get(path.as_str()) {
+                // Unchanged entries keep their stat data, so they aren't
+                // rehashed later.
+                Some(entry)
+                    if entry.mode == *mode_bits
+                        && entry.hash == *hash
+                        && !changed.contains(path) =>
+                {
+                    entries.push((*entry).clone())
+                }
+                _ if mode == ResetMode::Hard => {
+                    entries.push(write_entry(git_dir, work_tree, path, *mode_bits, hash)?)
+                }
+                _ => entries.push(IndexEntry::new(path, *mode_bits, hash.clone(), 0)),
+            }
+        }
+        write_index(git_dir, entries)?;
+    }
+
+    let old_head = resolve_ref(git_dir, "HEAD")?;
+    let branch = read_symref(git_dir, "HEAD")?.unwrap_or_else(|| "HEAD".to_string());
+    RefLock::acquire(git_dir, &branch)?.commit(&commit_id)?;
+    record_ref_update(
+        git_dir,
+        &branch,
+        old_head.as_deref(),
+        &commit_id,
+        &format!("reset: moving to {}", rev),
+    )?;
+    merge::remove_merge_state(git_dir);
+
+    if quiet {
+        return Ok(());
+    }
+    match mode {
+        ResetMode::Hard => {
+            let subject = commit.message.lines().next().unwrap_or_default();
+            println!("HEAD is now at {} {}", short_id(&commit_id), subject);
+        }
+        ResetMode::Mixed => {
+            let unstaged =
+                diff::diff_index_to_work_tree(git_dir, work_tree, &mut Default::default())?;
+            if !unstaged.is_empty() {
+                println!("Unstaged changes after reset:");
+                for change in &unstaged {
+                    println!("{}\t{}", status::change_code(change), change.path);
+                }
+            }
+        }
+        ResetMode::Soft => {}
+    }
+    Ok(())
+}
+
+/// Writes the files of `tree` into an empty work tree and records them in a
+/// fresh index.
+///
+/// Entries named `.`, `..` or `.git`, trees nested too deeply, and paths that
+/// would be written through a symbolic link checked out earlier are refused,
+/// so a malicious tree can't write outside the work tree.
+pub fn checkout_tree(git_dir: &Path, work_tree: &Path, tree: &str) -> anyhow::Result<()> {
+    let mut target = Entries::new();
+    tree_entries(git_dir, tree, "", 0, &mut target)?;
+
+    let mut entries = Vec::new();
+    let mut progress = Progress::delayed("Updating files", Some(target.len() as u64));
+    for (path, (mode, hash)) in &target {
+        entries.push(write_entry(git_dir, work_tree, path, *mode, hash)?);
+        progress.tick();
+    }
+    progress.finish();
+    write_index(git_dir, entries)
+}
+
+pub fn write_index(git_dir: &Path, mut entries: Vec<IndexEntry>) -> anyhow::Result<()> {
+    entries.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+    let index = Index {
+        version: 2,
+        entries,
+        cache_tree: None,
+        format: ObjectFormat::of(git_dir)?,
+    };
+    index.write(&git_path(git_dir, "index"))
+}
+
+/// Collects the non-tree entries under a tree, refusing names that could
+/// escape the work tree once checked out.
+pub fn tree_entries(
+    git_dir: &Path,
+    tree_id: &str,
+    prefix: &str,
+    depth: usize,
+    entries: &mut Entries,
+) -> anyhow::Result<()> {
+    if depth > MAX_TREE_DEPTH {
+        return Err(anyhow!(
+            "{}: trees nest more than {} levels deep.",
+            prefix,
+            MAX_TREE_DEPTH
+        ));
+    }
+    let Object::Tree(tree) = Repository::open(git_dir).object(tree_id)? else {
+        return Err(anyhow!("Object {} is not a tree.", tree_id));
+    };
+
+    for element in tree.elements {
+        let path = format!("{}{}", prefix, element.name);
+        if !platform::is_valid_tree_name(&element.name) {
+            return Err(anyhow!("Refusing to check out invalid path '{}'.", path));
+        }
+        if element.mode == "40000" {
+            let subtree = hex::encode(&element.hash);
+            tree_entries(git_dir, &subtree, &format!("{}/", path), depth + 1, entries)?;
+            continue;
+        }
+        let mode = u32::from_str_radix(&element.mode, 8)
+            .with_context(|| format!("Invalid mode {} for {}.", element.mode, path))?;
+        entries.insert(path, (canonical_mode(mode), element.hash));
+    }
+    Ok(())
+}
+
+/// Writes one entry into the work tree, replacing what was there, and
+/// returns its index entry.
+pub fn write_entry(
+    git_dir: &Path,
+    work_tree: &Path,
+    path: &str,
+    mode: u32,
+    hash: &[u8],
+) -> anyhow::Result<IndexEntry> {
+    // Neither the entry nor a directory on the way to it may lead through a
+    // symlink, which could point anywhere.
+    let filesystem = Filesystem::of(git_dir);
+    platform::check_tree_path(path)?;
+    let full_path = platform::work_tree_path(work_tree, path);
+    if leads_through_symlink(work_tree, path) {
+        return Err(anyhow!(
+            "Refusing to check out '{}' through a symbolic link.",
+            path
+        ));
+    }
+    if let Some(parent) = full_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if let Result::Ok(metadata) = fs::symlink_metadata(&full_path) {
+        match metadata.is_dir() {
+            // Submodules aren't checked out; git leaves an empty directory in their place.
+            true if mode == 0o160000 => {}
+            true => fs::remove_dir(&full_path)
+                .with_context(|| format!("{}: a directory is in the way.", path))?,
+            false => fs::remove_file(&full_path)?,
+        }
+    }
+
+    if mode == 0o160000 {
+        fs::create_dir_all(&full_path)?;
+    } else {
+        let (_, content) = Repository::open(git_dir).read(&hex::encode(hash))?;
+        platform::write_file(filesystem, &full_path, mode, &content)?;
+    }
+    Ok(IndexEntry::from_metadata(
+        path,
+        mode,
+        hash.to_vec(),
+        &fs::symlink_metadata(&full_path)?,
+    ))
+}
+
+/// Deletes a tracked file, then the directories it leaves empty.
+pub fn remove_work_tree_file(work_tree: &Path, path: &str) -> anyhow::Result<()> {
+    let full_path = work_tree.join(path);
+    let removed = match fs::symlink_metadata(&full_path) {
+        Result::Ok(metadata) if metadata.is_dir() => fs::remove_dir(&full_path),
+        Result::Ok(_) => fs::remove_file(&full_path),
+        Err(_) => return Ok(()),
+    };
+    // A gitlink's directory stays when the submodule is checked out.
+    if removed.is_err() {
+        return Ok(());
+    }
+
+    let mut dir = full_path.parent();
+    while let Some(parent) = dir.filter(|x| *x != work_tree) {
+        if fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+    Ok(())
+}
+
+/// Whether writing `path` would clobber something untracked: a file or
+/// directory at `path` itself, or a file where one of its directories goes.
+pub fn untracked_in_the_way(
+    work_tree: &Path,
+    path: &str,
+    tracked: &BTreeMap<&str, &IndexEntry>,
+) -> bool {
+    match fs::symlink_metadata(work_tree.join(path)) {
+        // A directory of tracked files goes away with them.
+        Result::Ok(metadata) if metadata.is_dir() => {
+            return holds_untracked(work_tree, &format!("{}/", path), tracked)
+        }
+        Result::Ok(_) => return true,
+        Err(_) => {}
+    }
+    let mut dir = String::new();
+    for component in path.split('/').take(path.split('/').count() - 1) {
+        dir.push_str(component);
+        if fs::symlink_metadata(work_tree.join(&dir)).is_ok_and(|x| !x.is_dir())
+            && !tracked.contains_key(dir.as_str())
+        {
+            return true;
+        }
+        dir.push('/');
+    }
+    false
+}
+
+fn holds_untracked(work_tree: &Path, dir: &str, tracked: &BTreeMap<&str, &IndexEntry>) -> bool {
+    let Result::Ok(entries) = fs::read_dir(work_tree.join(dir)) else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        let path = format!("{}{}", dir, entry.file_name().to_string_lossy());
+        match entry.file_type().is_ok_and(|x| x.is_dir()) {
+            true => holds_untracked(work_tree, &format!("{}/", path), tracked),
+            false => !tracked.contains_key(path.as_str()),
+        }
+    })
+}
+
+pub fn list_paths(paths: Vec<&String>) -> String {
+    let lines: Vec<String> = paths.iter().map(|x| format!("\t{}", x)).collect();
+    lines.join("\n")
+}
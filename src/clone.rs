@@ -0,0 +1,231 @@
+use crate::{bundle, checkout, local, transport, Repository};
+use crate::{git_path, record_ref_update, write_ref, CommitObject};
+use anyhow::anyhow;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The name `clone` gives the remote it was cloned from.
+const REMOTE: &str = "origin";
+
+/// Default directory for a clone of `url`: its last path component without
+/// a trailing `.git`, or `.bundle` for a bundle file, like git picks. Mirrors
+/// are bare, so they keep the `.git`.
+pub fn default_directory(url: &str, mirror: bool) -> PathBuf {
+    let path = url.trim_end_matches('/').trim_end_matches("/.git");
+    let name = path.rsplit(['/', ':']).next().unwrap_or(path);
+    let name = name.strip_suffix(".git").unwrap_or(name);
+    let name = match bundle::is_bundle(Path::new(url)) {
+        true => name.strip_suffix(".bundle").unwrap_or(name),
+        false => name,
+    };
+    match mirror {
+        true => PathBuf::from(format!("{}.git", name)),
+        false => PathBuf::from(name),
+    }
+}
+
+/// Clones the repository at `url` into `directory`: fetches every branch and
+/// tag, records them as `refs/remotes/origin/*` and `refs/tags/*`, and checks
+/// out the branch the remote `HEAD` points to.
+///
+/// With `mirror`, `directory` becomes a bare repository holding every
+/// advertised ref under its own name, and nothing is checked out.
+///
+/// With a `depth`, only that many commits of history are fetched, the
+/// commits at the cut being listed in `.git/shallow`. Like git, that clones
+/// a single branch, the remote `HEAD`'s, and the tags pointing into it.
+pub fn clone(url: &str, directory: &Path, mirror: bool, depth: Option<u32>) -> anyhow::Result<()> {
+    let existed = directory.exists();
+    if existed && fs::read_dir(directory)?.next().is_some() {
+        return Err(anyhow!(
+            "Destination path '{}' already exists and is not an empty directory.",
+            directory.display()
+        ));
+    }
+
+    // Like git, a failed clone leaves nothing behind, so it can be retried:
+    // a directory it created goes, and one that was empty is emptied again.
+    let result = clone_into(url, directory, mirror, depth);
+    if result.is_err() {
+        match existed {
+            true => {
+                for entry in fs::read_dir(directory)?.flatten() {
+                    let path = entry.path();
+                    let _ = match path.is_dir() && !path.is_symlink() {
+                        true => fs::remove_dir_all(&path),
+                        false => fs::remove_file(&path),
+                    };
+                }
+            }
+            false => {
+                let _ = fs::remove_dir_all(directory);
+            }
+        }
+    }
+    result
+}
+
+/// Does the work of [`clone`] in a `directory` known to be empty or absent.
+fn clone_into(
+    url: &str,
+    directory: &Path,
+    mirror: bool,
+    mut depth: Option<u32>,
+) -> anyhow::Result<()> {
+    let git_dir = match mirror {
+        true => directory.to_path_buf(),
+        false => directory.join(".git"),
+    };
+    for dir in ["objects", "refs/heads", "refs/tags"] {
+        fs::create_dir_all(git_dir.join(dir))?;
+    }
+    let repository = Repository::open(&git_dir);
+
+    // Like git, a clone of a local path remembers its absolute path, and
+    // copies every object there is.
+    let url = match local::local_path(url) {
+        Some(path) => {
+            if depth.take().is_some() {
+                info!("warning: --depth is ignored in local clones; use file:// instead.");
+            }
+            fs::canonicalize(&path)?.display().to_string()
+        }
+        _ => url.to_string(),
+    };
+    let url = url.as_str();
+
+    let remote = transport::connect(url)?;
+    let remote_head = remote.head()?;
+    let branch = remote_head
+        .as_deref()
+        .and_then(|x| x.strip_prefix("refs/heads/"))
+        .unwrap_or("main");
+    let single_branch = depth.is_some() && !mirror;
+    let mut refs: Vec<(String, String)> = remote
+        .refs()?
+        .into_iter()
+        .filter_map(|(name, object_id)| {
+            if mirror {
+                Some((name, object_id))
+            } else if let Some(name) = name.strip_prefix("refs/heads/") {
+                (!single_branch || name == branch)
+                    .then(|| (format!("refs/remotes/{}/{}", REMOTE, name), object_id))
+            } else if name.starts_with("refs/tags/") {
+                Some((name, object_id))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    let wants: Vec<String> = refs
+        .iter()
+        .filter(|(name, _)| !single_branch || !name.starts_with("refs/tags/"))
+        .map(|(_, object_id)| object_id.clone())
+        .collect();
+    let stats = remote.fetch(&git_dir, &wants, &[], depth)?;
+    info!(
+        "Fetched {} loose objects and {} packs.",
+        stats.objects, stats.packs
+    );
+    if single_branch {
+        let mut kept = Vec::new();
+        for (name, object_id) in refs {
+            if !name.starts_with("refs/tags/") || repository.exists(&object_id)? {
+                kept.push((name, object_id));
+            }
+        }
+        refs = kept;
+    }
+
+    for (name, object_id) in &refs {
+        write_ref(&git_dir, name, object_id)?;
+    }
+
+    fs::write(
+        git_dir.join("HEAD"),
+        format!("ref: refs/heads/{}\n", branch),
+    )?;
+
+    if mirror {
+        fs::write(git_dir.join("config"), mirror_config(url))?;
+        return Ok(());
+    }
+
+    let tracking = format!("refs/remotes/{}/{}", REMOTE, branch);
+    let head = refs
+        .iter()
+        .find(|(name, _)| *name == tracking)
+        .map(|(_, object_id)| object_id);
+    let fetched = match single_branch {
+        true => branch,
+        false => "*",
+    };
+    fs::write(
+        git_dir.join("config"),
+        config(url, fetched, head.map(|_| branch)),
+    )?;
+
+    let Some(head) = head else {
+        info!("warning: You appear to have cloned an empty repository.");
+        return Ok(());
+    };
+    let branch_ref = format!("refs/heads/{}", branch);
+    write_ref(&git_dir, &branch_ref, head)?;
+    // HEAD already points at the branch, so its reflog gets the entry too.
+    record_ref_update(
+        &git_dir,
+        &branch_ref,
+        None,
+        head,
+        &format!("clone: from {}", url),
+    )?;
+    fs::write(
+        git_path(&git_dir, &format!("refs/remotes/{}/HEAD", REMOTE)),
+        format!("ref: {}\n", tracking),
+    )?;
+
+    let (_, content) = repository.read(head)?;
+    let commit = CommitObject::from_content(&content)?;
+    checkout::checkout_tree(&git_dir, directory, &commit.tree)
+}
+
+/// The configuration of a clone whose remote's branches matching `fetched`
+/// are tracked, with `branch` checked out.
+fn config(url: &str, fetched: &str, branch: Option<&str>) -> String {
+    let mut config = String::from(
+        "[core]\n\
+         \trepositoryformatversion = 0\n\
+         \tfilemode = true\n\
+         \tbare = false\n\
+         \tlogallrefupdates = true\n",
+    );
+    config.push_str(&format!(
+        "[remote \"{remote}\"]\n\turl = {url}\n\tfetch = +refs/heads/{fetched}:refs/remotes/{remote}/{fetched}\n",
+        remote = REMOTE,
+        url = url,
+        fetched = fetched
+    ));
+    if let Some(branch) = branch {
+        config.push_str(&format!(
+            "[branch \"{}\"]\n\tremote = {}\n\tmerge = refs/heads/{}\n",
+            branch, REMOTE, branch
+        ));
+    }
+    config
+}
+
+/// A mirror maps every remote ref onto itself, so a later fetch updates them all in place.
+fn mirror_config(url: &str) -> String {
+    format!(
+        "[core]\n\
+         \trepositoryformatversion = 0\n\
+         \tfilemode = true\n\
+         \tbare = true\n\
+         [remote \"{}\"]\n\
+         \turl = {}\n\
+         \tfetch = +refs/*:refs/*\n\
+         \tmirror = true\n",
+        REMOTE, url
+    )
+}
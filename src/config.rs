@@ -0,0 +1,423 @@
+//! The repository's `config` file and the user's, in git's INI-like syntax.
+
+use crate::common_dir;
+use crate::lockfile::LockFile;
+use anyhow::{anyhow, Context};
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+/// The variables of a config file, in file order, keyed by their full name:
+/// `section.key` or `section.subsection.key`.
+///
+/// Section and key names are case-insensitive and stored lowercased; a
+/// subsection given in quotes (`[remote "origin"]`) keeps its case. A key
+/// written without `=` has no value, which booleans read as true.
+#[derive(Debug, Default)]
+pub struct Config {
+    entries: Vec<(String, Option<String>)>,
+}
+
+impl Config {
+    /// Reads the `config` file shared by all worktrees of the repository,
+    /// returning an empty config if there is none.
+    pub fn load(git_dir: &Path) -> anyhow::Result<Config> {
+        let path = Config::path(git_dir);
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let content = fs::read_to_string(&path).context("Read config file.")?;
+        Config::parse(&content).with_context(|| format!("Parse {}.", path.display()))
+    }
+
+    /// The path of the repository's `config` file.
+    pub fn path(git_dir: &Path) -> PathBuf {
+        common_dir(git_dir).join("config")
+    }
+
+    /// Reads the user's config followed by the repository's, so that the
+    /// repository's variables win, which is what git commands see.
+    pub fn load_all(git_dir: &Path) -> anyhow::Result<Config> {
+        let mut config = Config::load_global()?;
+        config.entries.extend(Config::load(git_dir)?.entries);
+        Ok(config)
+    }
+
+    /// Reads the user's own config: `$GIT_CONFIG_GLOBAL` when set, otherwise
+    /// `$XDG_CONFIG_HOME/git/config` (or `~/.config/git/config`) followed by
+    /// `~/.gitconfig`, so the latter wins where both set a variable.
+    pub fn load_global() -> anyhow::Result<Config> {
+        let paths = global_paths();
+        let mut config = Config::default();
+        for path in paths.iter().filter(|x| x.exists()) {
+            let content =
+                fs::read_to_string(path).with_context(|| format!("Read {}.", path.display()))?;
+            let file =
+                Config::parse(&content).with_context(|| format!("Parse {}.", path.display()))?;
+            config.entries.extend(file.entries);
+        }
+        Ok(config)
+    }
+
+    /// The file `config --global` writes to: `$GIT_CONFIG_GLOBAL` when set,
+    /// otherwise `~/.gitconfig`, unless only the XDG file exists.
+    pub fn global_path() -> Option<PathBuf> {
+        let paths = global_paths();
+        match paths.as_slice() {
+            [xdg, home] if xdg.exists() && !home.exists() => Some(xdg.clone()),
+            _ => paths.last().cloned(),
+        }
+    }
+
+    /// Every variable in file order, as `(name, value)`.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_deref()))
+    }
+
+    pub fn parse(content: &str) -> anyhow::Result<Config> {
+        let mut entries = Vec::new();
+        let mut section: Option<String> = None;
+        let mut lines = content.lines().enumerate();
+
+        while let Some((number, line)) = lines.next() {
+            let line = line.trim_start();
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+
+            let rest = match line.strip_prefix('[') {
+                Some(header) => {
+                    let (name, rest) = parse_section(header)
+                        .ok_or_else(|| anyhow!("Bad section header on line {}.", number + 1))?;
+                    section = Some(name);
+                    // A variable may follow the header on the same line.
+                    rest.trim_start()
+                }
+                None => line,
+            };
+            if rest.is_empty() || rest.starts_with(['#', ';']) {
+                continue;
+            }
+
+            let section = section
+                .as_ref()
+                .ok_or_else(|| anyhow!("Variable outside a section on line {}.", number + 1))?;
+            let name_len = rest
+                .find(|x: char| !(x.is_ascii_alphanumeric() || x == '-'))
+                .unwrap_or(rest.len());
+            let key = &rest[..name_len];
+            if key.is_empty() || !key.starts_with(|x: char| x.is_ascii_alphabetic()) {
+                return Err(anyhow!("Bad variable name on line {}.", number + 1));
+            }
+
+            let rest = rest[name_len..].trim_start();
+            let value = match rest.strip_prefix('=') {
+                Some(raw) => {
+                    // A trailing backslash continues the value on the next line.
+                    let mut raw = raw.to_string();
+                    while ends_in_continuation(&raw) {
+                        raw.pop();
+                        match lines.next() {
+                            Some((_, next)) => raw.push_str(next),
+                            None => break,
+                        }
+                    }
+                    Some(
+                        parse_value(&raw)
+                            .ok_or_else(|| anyhow!("Bad value on line {}.", number + 1))?,
+                    )
+                }
+                None if rest.is_empty() || rest.starts_with(['#', ';']) => None,
+                None => return Err(anyhow!("Bad variable on line {}.", number + 1)),
+            };
+
+            entries.push((format!("{}.{}", section, key.to_ascii_lowercase()), value));
+        }
+
+        Ok(Config { entries })
+    }
+
+    /// The value of `name`, like `user.email` or `remote.origin.url`. When a
+    /// variable is set more than once the last value wins, as in git; a key
+    /// without a value reads as "".
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entry(name)
+            .map(|value| value.as_ref().map_or("", |x| x.as_str()))
+    }
+
+    /// Every value of a variable that may be set more than once, like
+    /// `remote.origin.fetch`, in file order.
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        let name = normalize_name(name);
+        self.entries
+            .iter()
+            .filter(|(key, _)| *key == name)
+            .map(|(_, value)| value.as_ref().map_or("", |x| x.as_str()))
+            .collect()
+    }
+
+    /// The value of `name` as a boolean, spelled the ways git accepts.
+    pub fn get_bool(&self, name: &str) -> anyhow::Result<Option<bool>> {
+        let Some(value) = self.entry(name) else {
+            return Ok(None);
+        };
+        match value.as_deref().map(|x| x.to_ascii_lowercase()).as_deref() {
+            None | Some("true" | "yes" | "on" | "1") => Ok(Some(true)),
+            Some("false" | "no" | "off" | "0" | "") => Ok(Some(false)),
+            Some(_) => Err(anyhow!("Bad boolean config value for '{}'.", name)),
+        }
+    }
+
+    fn entry(&self, name: &str) -> Option<&Option<String>> {
+        let name = normalize_name(name);
+        self.entries
+            .iter()
+            .rev()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Sets the variable `name` in the config file at `path` to `value`, or
+/// removes it when `value` is `None`, and returns whether the file changed.
+///
+/// The rest of the file is left as it was: the last line setting the
+/// variable is replaced, or else the variable is added at the end of the
+/// last section of its name, or in a new section at the end of the file.
+pub fn write_value(path: &Path, name: &str, value: Option<&str>) -> anyhow::Result<bool> {
+    let (section, key) = name
+        .rsplit_once('.')
+        .filter(|(section, key)| {
+            !section.is_empty()
+                && key.starts_with(|x: char| x.is_ascii_alphabetic())
+                && key.chars().all(|x| x.is_ascii_alphanumeric() || x == '-')
+        })
+        .ok_or_else(|| anyhow!("Invalid config variable name '{}'.", name))?;
+    let wanted = normalize_name(name);
+    let wanted_section = &wanted[..wanted.len() - key.len() - 1];
+
+    // Held while the file is read, so no other change is lost.
+    let lock = LockFile::acquire(path)?;
+    let content = match path.exists() {
+        true => fs::read_to_string(path).with_context(|| format!("Read {}.", path.display()))?,
+        false => String::new(),
+    };
+    let mut lines: Vec<&str> = content.split_inclusive('\n').collect();
+
+    // The lines of the last variable of that name, and where the last
+    // section of its name ends.
+    let mut found = None;
+    let mut section_end = None;
+    let mut current: Option<String> = None;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim_start();
+        let start = i;
+        i += 1;
+        if let Some(header) = line.strip_prefix('[') {
+            current = parse_section(header).map(|x| x.0);
+            if current.as_deref() == Some(wanted_section) {
+                section_end = Some(i);
+            }
+            continue;
+        }
+        while i < lines.len() && ends_in_continuation(lines[i - 1].trim_end_matches(['\n', '\r'])) {
+            i += 1;
+        }
+        if current.as_deref() != Some(wanted_section) {
+            continue;
+        }
+        section_end = Some(i);
+        let line_key = &line[..line
+            .find(|x: char| !(x.is_ascii_alphanumeric() || x == '-'))
+            .unwrap_or(line.len())];
+        if !line_key.is_empty() && line_key.eq_ignore_ascii_case(key) {
+            found = Some((start, i));
+        }
+    }
+
+    let line = value.map(|x| format!("\t{} = {}\n", key, quote_value(x)));
+    let header;
+    match (found, &line) {
+        (Some((start, end)), line) => {
+            lines.splice(start..end, line.as_deref());
+        }
+        (None, None) => return Ok(false),
+        (None, Some(line)) => match section_end {
+            Some(end) => lines.insert(end, line),
+            None => {
+                if lines.last().is_some_and(|x| !x.ends_with('\n')) {
+                    lines.push("\n");
+                }
+                header = section_header(section);
+                lines.push(&header);
+                lines.push(line);
+            }
+        },
+    }
+
+    lock.commit(lines.concat().as_bytes())?;
+    Ok(true)
+}
+
+/// `[section]`, or `[section "subsection"]` for a name with a subsection.
+fn section_header(name: &str) -> String {
+    match name.split_once('.') {
+        Some((section, subsection)) => format!(
+            "[{} \"{}\"]\n",
+            section.to_ascii_lowercase(),
+            subsection.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+        None => format!("[{}]\n", name.to_ascii_lowercase()),
+    }
+}
+
+/// Writes a value so that [`parse_value`] reads it back unchanged.
+fn quote_value(value: &str) -> String {
+    let mut escaped = String::new();
+    for x in value.chars() {
+        match x {
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\u{8}' => escaped.push_str("\\b"),
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(x);
+            }
+            x => escaped.push(x),
+        }
+    }
+    let needs_quotes = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';']);
+    match needs_quotes {
+        true => format!("\"{}\"", escaped),
+        false => escaped,
+    }
+}
+
+/// The user's config files in the order they're read, later ones winning.
+fn global_paths() -> Vec<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    match env::var_os("GIT_CONFIG_GLOBAL") {
+        Some(path) => vec![PathBuf::from(path)],
+        None => {
+            let xdg = env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| home.as_ref().map(|x| x.join(".config")));
+            xdg.map(|x| x.join("git/config"))
+                .into_iter()
+                .chain(home.map(|x| x.join(".gitconfig")))
+                .collect()
+        }
+    }
+}
+
+/// Lowercases the section and key of a variable name, leaving the
+/// subsection between them alone.
+fn normalize_name(name: &str) -> String {
+    let (section, rest) = name.split_once('.').unwrap_or((name, ""));
+    let (subsection, key) = match rest.rsplit_once('.') {
+        Some((subsection, key)) => (Some(subsection), key),
+        None => (None, rest),
+    };
+    match subsection {
+        Some(subsection) => format!(
+            "{}.{}.{}",
+            section.to_ascii_lowercase(),
+            subsection,
+            key.to_ascii_lowercase()
+        ),
+        None => format!(
+            "{}.{}",
+            section.to_ascii_lowercase(),
+            key.to_ascii_lowercase()
+        ),
+    }
+}
+
+/// Parses what follows the `[` of a section header into the normalized
+/// section name, returning it with the rest of the line after the `]`.
+fn parse_section(header: &str) -> Option<(String, &str)> {
+    let name_len = header
+        .find(|x: char| !(x.is_ascii_alphanumeric() || x == '-' || x == '.'))
+        .unwrap_or(header.len());
+    let name = header[..name_len].to_ascii_lowercase();
+    if name.is_empty() {
+        return None;
+    }
+    let rest = &header[name_len..];
+
+    if let Some(rest) = rest.strip_prefix(']') {
+        return Some((name, rest));
+    }
+
+    // `[section "subsection"]`, where only `\"` and `\\` are escapes.
+    let mut chars = rest.trim_start().strip_prefix('"')?.char_indices();
+    let mut subsection = String::new();
+    while let Some((pos, x)) = chars.next() {
+        match x {
+            '\\' => subsection.push(chars.next()?.1),
+            '"' => {
+                let quoted = &rest.trim_start()[1..];
+                let rest = quoted[pos + 1..].strip_prefix(']')?;
+                return Some((format!("{}.{}", name, subsection), rest));
+            }
+            _ => subsection.push(x),
+        }
+    }
+    None
+}
+
+fn ends_in_continuation(raw: &str) -> bool {
+    let backslashes = raw.len() - raw.trim_end_matches('\\').len();
+    backslashes % 2 == 1
+}
+
+/// Unquotes a raw value: strips a comment outside quotes, trims unquoted
+/// whitespace at both ends and resolves the `\n`, `\t`, `\b`, `\"` and `\\`
+/// escapes. Returns `None` on an unterminated quote or unknown escape.
+fn parse_value(raw: &str) -> Option<String> {
+    let mut value = String::new();
+    // Length of `value` up to its last quoted or non-space character, so
+    // trailing unquoted whitespace can be dropped.
+    let mut kept = 0;
+    let mut quoted = false;
+    let mut chars = raw.chars();
+
+    while let Some(x) = chars.next() {
+        match x {
+            '"' => {
+                quoted = !quoted;
+                kept = value.len();
+            }
+            '\\' => {
+                value.push(match chars.next()? {
+                    'n' => '\n',
+                    't' => '\t',
+                    'b' => '\u{8}',
+                    x @ ('"' | '\\') => x,
+                    _ => return None,
+                });
+                kept = value.len();
+            }
+            '#' | ';' if !quoted => break,
+            x if x.is_whitespace() && !quoted => {
+                if !value.is_empty() {
+                    value.push(x);
+                }
+            }
+            x => {
+                value.push(x);
+                kept = value.len();
+            }
+        }
+    }
+
+    if quoted {
+        return None;
+    }
+    value.truncate(kept);
+    Some(value)
+}
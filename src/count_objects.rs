@@ -0,0 +1,191 @@
+use crate::{git_path, index, list_refs, pack, resolve_ref, shallow, Repository};
+use crate::{CommitObject, ObjectType};
+use anyhow::anyhow;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// Loose objects beyond which git's `gc --auto` would pack them (`gc.auto`).
+const LOOSE_OBJECT_LIMIT: usize = 6700;
+/// Packs beyond which git's `gc --auto` would consolidate them (`gc.autoPackLimit`).
+const PACK_LIMIT: usize = 50;
+
+/// Files that may sit next to a pack without being garbage.
+const PACK_EXTENSIONS: [&str; 7] = ["pack", "idx", "keep", "rev", "bitmap", "promisor", "mtimes"];
+
+/// Prints object store statistics like `git count-objects`.
+///
+/// With `verbose`, prints `key: value` lines (git's, plus `unreachable`)
+/// followed by one `warning: <reason>: <details>` line per maintenance issue.
+#[tracing::instrument(skip(git_dir))]
+pub fn count_objects(git_dir: &Path, verbose: bool) -> anyhow::Result<()> {
+    let objects_dir = git_path(git_dir, "objects");
+
+    let mut loose = HashSet::new();
+    let mut loose_size = 0;
+    let mut garbage = 0;
+    let mut garbage_size = 0;
+    for dir in fs::read_dir(&objects_dir)? {
+        let dir = dir?;
+        let dir_name = dir.file_name().to_string_lossy().to_string();
+        if dir_name.len() != 2 || !dir_name.chars().all(|x| x.is_ascii_hexdigit()) {
+            continue;
+        }
+
+        for file in fs::read_dir(dir.path())? {
+            let file = file?;
+            let file_name = file.file_name().to_string_lossy().to_string();
+            let size = disk_usage(&file.metadata()?);
+            if file_name.len() == 38 && file_name.chars().all(|x| x.is_ascii_hexdigit()) {
+                loose.insert(format!("{}{}", dir_name, file_name));
+                loose_size += size;
+            } else {
+                garbage += 1;
+                garbage_size += size;
+            }
+        }
+    }
+
+    let packs = pack::list_packs(&objects_dir)?;
+    let mut packed = HashSet::new();
+    let mut pack_size = 0;
+    for (pack_path, idx_path) in &packs {
+        packed.extend(pack::index_object_ids(idx_path)?);
+        // Unlike loose objects, git counts packs by their length rather than disk usage.
+        pack_size += fs::metadata(pack_path)?.len() + fs::metadata(idx_path)?.len();
+    }
+    if let Result::Ok(entries) = fs::read_dir(objects_dir.join("pack")) {
+        for entry in entries {
+            let path = entry?.path();
+            let extension = path.extension().and_then(|x| x.to_str()).unwrap_or("");
+            if !PACK_EXTENSIONS.contains(&extension) {
+                garbage += 1;
+                garbage_size += disk_usage(&fs::metadata(&path)?);
+            }
+        }
+    }
+
+    if !verbose {
+        println!("{} objects, {} kilobytes", loose.len(), loose_size / 1024);
+        return Ok(());
+    }
+
+    let duplicates = loose.intersection(&packed).count();
+    let unreachable = count_unreachable(git_dir, &loose, &packed)?;
+
+    println!("count: {}", loose.len());
+    println!("size: {}", loose_size / 1024);
+    println!("in-pack: {}", packed.len());
+    println!("packs: {}", packs.len());
+    println!("size-pack: {}", pack_size / 1024);
+    println!("prune-packable: {}", duplicates);
+    println!("garbage: {}", garbage);
+    println!("size-garbage: {}", garbage_size / 1024);
+    println!("unreachable: {}", unreachable);
+
+    if loose.len() > LOOSE_OBJECT_LIMIT {
+        println!(
+            "warning: too-many-loose: {} loose objects (more than {}), consider gc",
+            loose.len(),
+            LOOSE_OBJECT_LIMIT
+        );
+    }
+    if packs.len() > PACK_LIMIT {
+        println!(
+            "warning: too-many-packs: {} packs (more than {}), consider gc",
+            packs.len(),
+            PACK_LIMIT
+        );
+    }
+    if duplicates > 0 {
+        println!(
+            "warning: loose-and-packed: {} objects are both loose and packed, consider prune-packed",
+            duplicates
+        );
+    }
+    if garbage > 0 {
+        println!(
+            "warning: garbage: {} stray files in the object store",
+            garbage
+        );
+    }
+    if unreachable > 0 {
+        println!(
+            "warning: unreachable: {} loose objects are not reachable from any ref, consider prune",
+            unreachable
+        );
+    }
+
+    Ok(())
+}
+
+/// Counts loose objects not reachable from refs, `HEAD` or the index.
+fn count_unreachable(
+    git_dir: &Path,
+    loose: &HashSet<String>,
+    packed: &HashSet<String>,
+) -> anyhow::Result<usize> {
+    let mut pending: Vec<String> = list_refs(git_dir)?.into_iter().map(|x| x.1).collect();
+    pending.extend(resolve_ref(git_dir, "HEAD")?);
+    let index = index::Index::load(&git_path(git_dir, "index"))?;
+    pending.extend(index.entries.iter().map(|x| hex::encode(&x.hash)));
+
+    let shallow = shallow::read(git_dir)?;
+    let mut reachable = HashSet::new();
+    while let Some(id) = pending.pop() {
+        if !reachable.insert(id.clone()) {
+            continue;
+        }
+        // Missing objects are for fsck to report.
+        if !loose.contains(&id) && !packed.contains(&id) {
+            continue;
+        }
+
+        let (object_type, content) = Repository::open(git_dir).read(&id)?;
+        match object_type {
+            ObjectType::Blob => {}
+            ObjectType::Commit => {
+                let commit = CommitObject::from_content(&content)?;
+                pending.push(commit.tree);
+                if !shallow.contains(&id) {
+                    pending.extend(commit.parents);
+                }
+            }
+            ObjectType::Tag => {
+                let object = crate::header_field(&content, "object")
+                    .ok_or_else(|| anyhow!("Tag {} has no object line.", id))?;
+                pending.push(object);
+            }
+            ObjectType::Tree => pending.extend(tree_children(&content)),
+        }
+    }
+
+    Ok(loose.difference(&reachable).count())
+}
+
+/// Ids of the entries of raw tree content, skipping submodule commits.
+fn tree_children(content: &[u8]) -> Vec<String> {
+    let mut children = Vec::new();
+    let mut rest = content;
+    while let Some(nul) = rest.iter().position(|&x| x == 0) {
+        let Some(hash) = rest.get(nul + 1..nul + 21) else {
+            break;
+        };
+        if !rest.starts_with(b"160000 ") {
+            children.push(hex::encode(hash));
+        }
+        rest = &rest[nul + 21..];
+    }
+    children
+}
+
+/// Bytes the file takes on disk, which is what git reports.
+#[cfg(unix)]
+fn disk_usage(metadata: &fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::blocks(metadata) * 512
+}
+
+#[cfg(not(unix))]
+fn disk_usage(metadata: &fs::Metadata) -> u64 {
+    metadata.len()
+}
@@ -0,0 +1,348 @@
+use crate::{find_git_dir, Config};
+use anyhow::{anyhow, Context};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+
+/// Set by `--token`; `GIT_TOKEN` is used when it isn't.
+static TOKEN: OnceLock<String> = OnceLock::new();
+
+/// Sends `token` as a bearer token to every HTTP server from now on.
+pub fn set_token(token: String) {
+    let _ = TOKEN.set(token);
+}
+
+fn token() -> Option<String> {
+    TOKEN
+        .get()
+        .cloned()
+        .or_else(|| std::env::var("GIT_TOKEN").ok())
+        .filter(|x| !x.is_empty())
+}
+
+/// A username and password, and whether they came from a helper or the
+/// user rather than the URL, so helpers hear whether they worked.
+#[derive(Clone)]
+struct Credential {
+    username: String,
+    password: String,
+    asked: bool,
+}
+
+/// How requests to one HTTP server authenticate: with the bearer token, or
+/// with the username and password in the URL, or else none until the server
+/// answers 401, when credential helpers, then the user, are asked, as git
+/// does. Every transport speaking HTTP shares it.
+pub struct HttpAuth {
+    protocol: String,
+    host: String,
+    username: Option<String>,
+    credential: Mutex<Option<Credential>>,
+}
+
+impl HttpAuth {
+    /// Takes the credentials out of `url`, returning the URL without them,
+    /// which is what is shown and requested.
+    pub fn from_url(url: &str) -> (String, HttpAuth) {
+        let (protocol, rest) = url.split_once("://").unwrap_or(("https", url));
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let (userinfo, host) = match authority.rsplit_once('@') {
+            Some((userinfo, host)) => (Some(userinfo), host),
+            None => (None, authority),
+        };
+        let (username, password) = match userinfo.map(|x| x.split_once(':')) {
+            Some(Some((username, password))) => (
+                Some(percent_decode(username)),
+                Some(percent_decode(password)),
+            ),
+            Some(None) => (userinfo.map(percent_decode), None),
+            None => (None, None),
+        };
+        let credential = match (&username, password) {
+            (Some(username), Some(password)) => Some(Credential {
+                username: username.clone(),
+                password,
+                asked: false,
+            }),
+            _ => None,
+        };
+        let auth = HttpAuth {
+            protocol: protocol.to_string(),
+            host: host.to_string(),
+            username,
+            credential: Mutex::new(credential),
+        };
+        (format!("{}://{}{}", protocol, host, path), auth)
+    }
+
+    fn authorization(&self) -> Option<String> {
+        if let Some(token) = token() {
+            return Some(format!("Bearer {}", token));
+        }
+        let credential = self.credential.lock().unwrap();
+        credential.as_ref().map(|x| {
+            let pair = format!("{}:{}", x.username, x.password);
+            format!("Basic {}", base64(pair.as_bytes()))
+        })
+    }
+
+    /// Sends the request `build` makes, with `body` if it's a `POST`, and
+    /// authenticates it: a 401 gets credentials asked for and the request
+    /// sent again. Credentials that work are given to the helpers to store,
+    /// ones refused to erase.
+    pub fn send(
+        &self,
+        build: impl Fn() -> ureq::Request,
+        body: Option<&[u8]>,
+    ) -> Result<ureq::Response, Box<ureq::Error>> {
+        let mut retried = false;
+        loop {
+            let mut request = build();
+            if let Some(authorization) = self.authorization() {
+                request = request.set("Authorization", &authorization);
+            }
+            let result = match body {
+                Some(body) => request.send_bytes(body),
+                None => request.call(),
+            };
+            let asked = self.credential.lock().unwrap().clone().filter(|x| x.asked);
+            match result {
+                Err(ureq::Error::Status(401, response)) => {
+                    if let Some(credential) = asked {
+                        self.helpers("erase", &credential);
+                    }
+                    if retried || token().is_some() {
+                        return Err(Box::new(ureq::Error::Status(401, response)));
+                    }
+                    match self.fill() {
+                        Result::Ok(credential) => {
+                            *self.credential.lock().unwrap() = Some(credential);
+                        }
+                        Err(err) => {
+                            tracing::debug!(%err, "no credentials");
+                            return Err(Box::new(ureq::Error::Status(401, response)));
+                        }
+                    }
+                    retried = true;
+                }
+                Result::Ok(response) => {
+                    if let Some(credential) = asked.filter(|_| retried) {
+                        self.helpers("store", &credential);
+                    }
+                    return Result::Ok(response);
+                }
+                Err(err) => return Err(Box::new(err)),
+            }
+        }
+    }
+
+    /// The credential description helpers read: `key=value` lines ending
+    /// with a blank one.
+    fn description(&self, credential: Option<&Credential>) -> String {
+        let mut description = format!("protocol={}\nhost={}\n", self.protocol, self.host);
+        let username = credential.map(|x| &x.username).or(self.username.as_ref());
+        if let Some(username) = username {
+            description.push_str(&format!("username={}\n", username));
+        }
+        if let Some(credential) = credential {
+            description.push_str(&format!("password={}\n", credential.password));
+        }
+        description.push('\n');
+        description
+    }
+
+    /// Asks each configured `credential.helper` in turn, then the user.
+    fn fill(&self) -> anyhow::Result<Credential> {
+        let mut username = self.username.clone();
+        for helper in helpers() {
+            let Result::Ok(answer) = run_helper(&helper, "get", &self.description(None)) else {
+                continue;
+            };
+            username = answer.get("username").cloned().or(username);
+            if let (Some(username), Some(password)) = (&username, answer.get("password")) {
+                return Ok(Credential {
+                    username: username.clone(),
+                    password: password.clone(),
+                    asked: true,
+                });
+            }
+        }
+
+        let url = format!("{}://{}", self.protocol, self.host);
+        let username = match username {
+            Some(username) => username,
+            None => prompt(&format!("Username for '{}': ", url), true)?,
+        };
+        let password = prompt(
+            &format!(
+                "Password for '{}://{}@{}': ",
+                self.protocol, username, self.host
+            ),
+            false,
+        )?;
+        Ok(Credential {
+            username,
+            password,
+            asked: true,
+        })
+    }
+
+    /// Tells the helpers to `store` or `erase` credentials; their failures
+    /// don't matter to the request.
+    fn helpers(&self, action: &str, credential: &Credential) {
+        let description = self.description(Some(credential));
+        for helper in helpers() {
+            if let Err(err) = run_helper(&helper, action, &description) {
+                tracing::debug!(%err, helper, action, "credential helper failed");
+            }
+        }
+    }
+}
+
+/// The `credential.helper` values of the repository's config, or the
+/// global one outside a repository.
+fn helpers() -> Vec<String> {
+    let config = match find_git_dir() {
+        Result::Ok(git_dir) => Config::load_all(&git_dir),
+        Err(_) => Config::load_global(),
+    };
+    config
+        .map(|x| {
+            x.get_all("credential.helper")
+                .into_iter()
+                .filter(|x| !x.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Runs a credential helper the way git does: `!<command>` by the shell, an
+/// absolute path as is, and any other name as `git credential-<name>`,
+/// with its arguments after the action.
+fn run_helper(
+    helper: &str,
+    action: &str,
+    description: &str,
+) -> anyhow::Result<HashMap<String, String>> {
+    let script = match helper.strip_prefix('!') {
+        Some(script) => script.to_string(),
+        None if helper.starts_with('/') => helper.to_string(),
+        None => format!("git credential-{}", helper),
+    };
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", script))
+        .arg(helper)
+        .arg(action)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Run the credential helper '{}'.", helper))?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(description.as_bytes())?;
+    let mut answer = HashMap::new();
+    for line in BufReader::new(child.stdout.take().expect("stdout is piped")).lines() {
+        let line = line?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            answer.insert(key.to_string(), value.to_string());
+        }
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("The credential helper '{}' failed.", helper));
+    }
+    Ok(answer)
+}
+
+/// Asks the user: through the `GIT_ASKPASS` program when set, as git does,
+/// or else on the terminal, not echoing what is typed unless `echo`.
+fn prompt(question: &str, echo: bool) -> anyhow::Result<String> {
+    if let Some(askpass) = std::env::var_os("GIT_ASKPASS").filter(|x| !x.is_empty()) {
+        let output = Command::new(&askpass)
+            .arg(question)
+            .stderr(Stdio::inherit())
+            .output()
+            .context("Run GIT_ASKPASS.")?;
+        if !output.status.success() {
+            return Err(anyhow!("GIT_ASKPASS failed."));
+        }
+        let answer = String::from_utf8_lossy(&output.stdout);
+        return Ok(answer.trim_end_matches(['\n', '\r']).to_string());
+    }
+    if std::env::var("GIT_TERMINAL_PROMPT").is_ok_and(|x| x == "0") {
+        return Err(anyhow!("Terminal prompts are disabled."));
+    }
+    let tty = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .context("Open the terminal to ask for credentials.")?;
+    let stty = |setting: &str| {
+        let _ = Command::new("stty")
+            .arg(setting)
+            .stdin(tty.try_clone().map_or(Stdio::null(), Stdio::from))
+            .status();
+    };
+    (&tty).write_all(question.as_bytes())?;
+    if !echo {
+        stty("-echo");
+    }
+    let mut answer = String::new();
+    let read = BufReader::new(&tty).read_line(&mut answer);
+    if !echo {
+        stty("echo");
+        (&tty).write_all(b"\n")?;
+    }
+    read.context("Read from the terminal.")?;
+    Ok(answer.trim_end_matches(['\n', '\r']).to_string())
+}
+
+/// Decodes the `%XX` escapes of a URL component.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = text
+            .get(i + 1..i + 3)
+            .and_then(|x| u8::from_str_radix(x, 16).ok());
+        match (bytes[i], escape) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Standard base64 with padding, for the `Basic` authorization header.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, &byte)| acc | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
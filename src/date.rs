@@ -0,0 +1,185 @@
+use anyhow::anyhow;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DAY: i64 = 24 * 60 * 60;
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Parses a date as accepted by `--since`/`--until` into Unix seconds.
+///
+/// Accepts `@<seconds>`, ISO dates (`2024-03-01`, `2024-03-01 12:30[:00]`,
+/// `2024-03-01T12:30:00`, optionally followed by `Z` or `+hhmm`), `now`,
+/// `yesterday` and relative forms like `2 weeks ago` or `3.days.ago`. Times
+/// without an offset are taken as UTC.
+pub fn parse_date(input: &str) -> anyhow::Result<i64> {
+    let input = input.trim();
+    let now = now();
+
+    if let Some(seconds) = input.strip_prefix('@') {
+        return Ok(seconds.parse()?);
+    }
+    match input {
+        "now" => return Ok(now),
+        "yesterday" => return Ok(now - DAY),
+        _ => {}
+    }
+    if let Some(seconds) = parse_relative(input) {
+        return Ok(now - seconds);
+    }
+    parse_iso(input).ok_or_else(|| anyhow!("Invalid date: {}", input))
+}
+
+/// Formats a timestamp the way `git log` does by default, in the given
+/// `+hhmm` offset: `Tue Nov 14 22:13:20 2023 +0100`.
+pub fn format_date(seconds: i64, offset: &str) -> String {
+    let local = seconds + parse_offset(offset).unwrap_or(0);
+    let days = local.div_euclid(DAY);
+    let time = local.rem_euclid(DAY);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{} {} {} {:02}:{:02}:{:02} {} {}",
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        MONTHS[month as usize - 1],
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        year,
+        offset
+    )
+}
+
+/// Formats a timestamp as `git blame` does, in the given `+hhmm` offset:
+/// `2023-11-14 22:13:20 +0100`.
+pub fn format_iso_date(seconds: i64, offset: &str) -> String {
+    let local = seconds + parse_offset(offset).unwrap_or(0);
+    let time = local.rem_euclid(DAY);
+    let (year, month, day) = civil_from_days(local.div_euclid(DAY));
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}:{:02} {}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        offset
+    )
+}
+
+/// Parses `<seconds> <offset>` off the end of an author or committer line.
+pub fn signature_time(signature: &str) -> Option<(i64, &str)> {
+    let mut fields = signature.rsplitn(3, ' ');
+    let offset = fields.next()?;
+    let seconds = fields.next()?.parse().ok()?;
+    Some((seconds, offset))
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// `<n> <unit>[s] ago`, with spaces or dots between the words.
+fn parse_relative(input: &str) -> Option<i64> {
+    let words: Vec<&str> = input.split([' ', '.']).filter(|x| !x.is_empty()).collect();
+    let [count, unit, "ago"] = words.as_slice() else {
+        return None;
+    };
+
+    let count: i64 = count.parse().ok()?;
+    let unit = match unit.trim_end_matches('s') {
+        "second" | "sec" => 1,
+        "minute" | "min" => 60,
+        "hour" => 60 * 60,
+        "day" => DAY,
+        "week" => 7 * DAY,
+        "month" => 30 * DAY,
+        "year" => 365 * DAY,
+        _ => return None,
+    };
+    Some(count * unit)
+}
+
+fn parse_iso(input: &str) -> Option<i64> {
+    let (date, rest) = input.split_at(input.find([' ', 'T']).unwrap_or(input.len()));
+    let mut parts = date.split('-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if parts.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let rest = rest.trim_start_matches([' ', 'T']);
+    let (time, offset) = match rest.find(['Z', '+', '-', ' ']) {
+        Some(pos) => (&rest[..pos], rest[pos..].trim()),
+        None => (rest, ""),
+    };
+
+    let mut seconds = 0;
+    if !time.is_empty() {
+        let mut parts = time.split(':');
+        let hours: i64 = parts.next()?.parse().ok()?;
+        let minutes: i64 = parts.next()?.parse().ok()?;
+        let secs: i64 = match parts.next() {
+            Some(secs) => secs.parse().ok()?,
+            None => 0,
+        };
+        seconds = hours * 3600 + minutes * 60 + secs;
+    }
+    let offset = match offset {
+        "" | "Z" => 0,
+        offset => parse_offset(offset)?,
+    };
+
+    Some(days_from_civil(year, month, day) * DAY + seconds - offset)
+}
+
+/// `+hhmm` or `-hhmm` as seconds east of UTC.
+fn parse_offset(offset: &str) -> Option<i64> {
+    let (sign, digits) = match offset.split_at_checked(1)? {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+    if digits.len() != 4 {
+        return None;
+    }
+    let value: i64 = digits.parse().ok()?;
+    Some(sign * (value / 100 * 3600 + value % 100 * 60))
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date (Howard Hinnant's algorithm).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,98 @@
+use crate::{list_refs, log, short_id, ObjectType, Repository};
+use anyhow::anyhow;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// How many tagged ancestors are weighed against each other, like git's default.
+const MAX_CANDIDATES: usize = 10;
+
+/// Names a commit after the closest tag it descends from, as
+/// `<tag>-<commits since the tag>-g<abbreviated id>`, or just `<tag>` when the
+/// commit is tagged itself.
+///
+/// Only annotated tags are considered unless `all_tags` is set. An `abbrev`
+/// of 0 leaves off the suffix entirely.
+pub fn describe(
+    git_dir: &Path,
+    commit: &str,
+    all_tags: bool,
+    abbrev: usize,
+) -> anyhow::Result<String> {
+    let tags = tagged_commits(git_dir, all_tags)?;
+    let history = log::walk(git_dir, commit, &log::Filter::default())?;
+
+    if let Some(tag) = tags.get(commit) {
+        return Ok(tag.clone());
+    }
+
+    // Candidates are met newest first; the one with the fewest commits on
+    // top of it wins, and on a tie the newer one.
+    let reachable: HashSet<&str> = history.iter().map(|(id, _)| id.as_str()).collect();
+    let mut best: Option<(usize, &str)> = None;
+    for (id, _) in history
+        .iter()
+        .filter(|(id, _)| tags.contains_key(id))
+        .take(MAX_CANDIDATES)
+    {
+        let tagged: HashSet<String> = log::walk(git_dir, id, &log::Filter::default())?
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        let depth = reachable.iter().filter(|x| !tagged.contains(**x)).count();
+        if best.map_or(true, |(best_depth, _)| depth < best_depth) {
+            best = Some((depth, id));
+        }
+    }
+
+    let Some((depth, tagged_commit)) = best else {
+        return Err(anyhow!(
+            "No names found, cannot describe {}.",
+            short_id(commit)
+        ));
+    };
+    let tag = &tags[tagged_commit];
+    match abbrev {
+        0 => Ok(tag.clone()),
+        abbrev => Ok(format!(
+            "{}-{}-g{}",
+            tag,
+            depth,
+            &commit[..abbrev.clamp(4, 40)]
+        )),
+    }
+}
+
+/// Maps each tagged commit to the name of its tag. When a commit has several
+/// tags, annotated ones are preferred, then the first by name.
+fn tagged_commits(git_dir: &Path, all_tags: bool) -> anyhow::Result<HashMap<String, String>> {
+    let mut tags: HashMap<String, (bool, String)> = HashMap::new();
+
+    let repository = Repository::open(git_dir);
+    for (name, object_id) in list_refs(git_dir)? {
+        let Some(tag) = name.strip_prefix("refs/tags/") else {
+            continue;
+        };
+        let (object_type, _) = repository.read_header(&object_id)?;
+        let annotated = object_type == ObjectType::Tag;
+        if !annotated && !all_tags {
+            continue;
+        }
+        // Tags of trees or blobs can't name a commit.
+        let Result::Ok(commit) = repository.peel_to(&object_id, ObjectType::Commit) else {
+            continue;
+        };
+
+        let replace = match tags.get(&commit) {
+            Some((existing_annotated, _)) => annotated && !existing_annotated,
+            None => true,
+        };
+        if replace {
+            tags.insert(commit, (annotated, tag.to_string()));
+        }
+    }
+
+    Ok(tags
+        .into_iter()
+        .map(|(commit, (_, tag))| (commit, tag))
+        .collect())
+}
@@ -0,0 +1,1042 @@
+use crate::index::{Index, IndexEntry};
+use crate::platform::{self, Filesystem};
+use crate::{
+    git_path, leads_through_symlink, parallel, resolve_ref, status, Object, ObjectFormat,
+    ObjectType, Repository, TreeElement,
+};
+use anyhow::anyhow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// Number of unchanged lines shown around each hunk.
+const CONTEXT_LINES: usize = 3;
+
+/// Width `--stat` output is scaled to fit, like git's default terminal width.
+const STAT_WIDTH: usize = 80;
+
+/// A single step of a line-based edit script.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edit {
+    /// Line `old` of the old file equals line `new` of the new file.
+    Equal(usize, usize),
+    /// Line of the old file that was removed.
+    Delete(usize),
+    /// Line of the new file that was added.
+    Insert(usize),
+}
+
+/// Options affecting how blob contents are compared.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DiffOptions {
+    /// Treat a line ending in CRLF as equal to the same line ending in LF.
+    pub ignore_cr_at_eol: bool,
+}
+
+/// A path whose entry differs between two trees.
+#[derive(Debug)]
+pub struct FileChange {
+    pub path: String,
+    pub old: Option<TreeEntry>,
+    pub new: Option<TreeEntry>,
+    pub rename: Option<Rename>,
+}
+
+/// Set on a change whose old content was found under a different path.
+#[derive(Debug)]
+pub struct Rename {
+    pub from: String,
+    pub similarity: u8,
+}
+
+/// Blob contents that aren't in the object store, such as work tree files
+/// that were hashed but not written, keyed by id.
+pub type PendingBlobs = HashMap<String, Vec<u8>>;
+
+#[derive(Debug, Clone)]
+pub struct TreeEntry {
+    pub mode: String,
+    pub hash: String,
+}
+
+impl FileChange {
+    fn status(&self) -> String {
+        if let Some(rename) = &self.rename {
+            return format!("R{:03}", rename.similarity);
+        }
+
+        match (&self.old, &self.new) {
+            (None, Some(_)) => "A".to_string(),
+            (Some(_), None) => "D".to_string(),
+            _ => "M".to_string(),
+        }
+    }
+
+    /// Path the old side of the change lived at.
+    fn old_path(&self) -> &str {
+        match &self.rename {
+            Some(rename) => &rename.from,
+            None => &self.path,
+        }
+    }
+}
+
+/// Splits content into lines, keeping the trailing newline on each line so a
+/// missing newline at end of file shows up as a difference.
+pub fn split_lines(content: &str) -> Vec<&str> {
+    content.split_inclusive('\n').collect()
+}
+
+/// Computes the shortest edit script between two line sequences using Myers' algorithm.
+pub fn diff_lines(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let n = old.len() as isize;
+    let m = new.len() as isize;
+    let max = n + m;
+    let index = |k: isize| (k + max) as usize;
+
+    let mut v = vec![0isize; 2 * max as usize + 2];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+
+        let mut k = -d;
+        while k <= d {
+            let mut x = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+                v[index(k + 1)]
+            } else {
+                v[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            v[index(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+            k += 2;
+        }
+    }
+
+    // Walk the recorded frontiers backwards to recover the path.
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+
+        let prev_k = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[index(prev_k)];
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Equal(x as usize - 1, y as usize - 1));
+            x -= 1;
+            y -= 1;
+        }
+
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Insert(y as usize - 1));
+            } else {
+                edits.push(Edit::Delete(x as usize - 1));
+            }
+        }
+
+        x = prev_x;
+        y = prev_y;
+    }
+
+    edits.reverse();
+    edits
+}
+
+/// Diffs two line sequences, comparing them as `options` asks.
+fn diff_lines_with(old: &[&str], new: &[&str], options: DiffOptions) -> Vec<Edit> {
+    if !options.ignore_cr_at_eol {
+        return diff_lines(old, new);
+    }
+
+    let normalize = |lines: &[&str]| -> Vec<String> {
+        lines
+            .iter()
+            .map(|line| match line.strip_suffix("\r\n") {
+                Some(line) => format!("{}\n", line),
+                None => line.to_string(),
+            })
+            .collect()
+    };
+    let old = normalize(old);
+    let new = normalize(new);
+    let old: Vec<&str> = old.iter().map(|x| x.as_str()).collect();
+    let new: Vec<&str> = new.iter().map(|x| x.as_str()).collect();
+    diff_lines(&old, &new)
+}
+
+/// Git treats content with a null byte in its first 8000 bytes as binary.
+pub fn is_binary(content: &str) -> bool {
+    content.bytes().take(8000).any(|byte| byte == 0)
+}
+
+/// Compares two trees recursively and returns every changed blob path in tree order.
+#[tracing::instrument(level = "debug", skip(git_dir))]
+pub fn diff_trees(
+    git_dir: &Path,
+    old_tree: Option<&str>,
+    new_tree: Option<&str>,
+) -> anyhow::Result<Vec<FileChange>> {
+    let mut changes = Vec::new();
+    diff_trees_into(git_dir, old_tree, new_tree, "", &mut changes)?;
+    Ok(changes)
+}
+
+fn diff_trees_into(
+    git_dir: &Path,
+    old_tree: Option<&str>,
+    new_tree: Option<&str>,
+    prefix: &str,
+    changes: &mut Vec<FileChange>,
+) -> anyhow::Result<()> {
+    let old_elements = match old_tree {
+        Some(hash) => read_tree_elements(git_dir, hash)?,
+        None => Vec::new(),
+    };
+    let new_elements = match new_tree {
+        Some(hash) => read_tree_elements(git_dir, hash)?,
+        None => Vec::new(),
+    };
+
+    let mut names: Vec<(String, bool)> = old_elements
+        .iter()
+        .chain(new_elements.iter())
+        .map(|x| (x.name.clone(), is_tree_mode(&x.mode)))
+        .collect();
+    // Sort like git does: trees compare as if their name ended in a slash.
+    names.sort_by_key(|(name, is_tree)| tree_sort_key(name, *is_tree));
+    let mut seen = HashSet::new();
+    names.retain(|(name, _)| seen.insert(name.clone()));
+
+    for (name, _) in names {
+        let old = old_elements.iter().find(|x| x.name == name);
+        let new = new_elements.iter().find(|x| x.name == name);
+        let path = format!("{}{}", prefix, name);
+
+        if let (Some(old), Some(new)) = (old, new) {
+            if old.hash == new.hash && old.mode == new.mode {
+                continue;
+            }
+        }
+
+        let old_tree = old.filter(|x| is_tree_mode(&x.mode));
+        let new_tree = new.filter(|x| is_tree_mode(&x.mode));
+        let old_blob = old.filter(|x| !is_tree_mode(&x.mode));
+        let new_blob = new.filter(|x| !is_tree_mode(&x.mode));
+
+        if old_tree.is_some() || new_tree.is_some() {
+            let old_hash = old_tree.map(|x| hex::encode(&x.hash));
+            let new_hash = new_tree.map(|x| hex::encode(&x.hash));
+            diff_trees_into(
+                git_dir,
+                old_hash.as_deref(),
+                new_hash.as_deref(),
+                &format!("{}/", path),
+                changes,
+            )?;
+        }
+
+        if old_blob.is_some() || new_blob.is_some() {
+            changes.push(FileChange {
+                path,
+                old: old_blob.map(TreeEntry::from),
+                new: new_blob.map(TreeEntry::from),
+                rename: None,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Compares the work tree against the index and returns the unstaged change
+/// of every tracked path, in index order. Work tree content is hashed but not
+/// stored; it goes into `pending` so the changes can still be printed.
+///
+/// Files whose mode, size and modification time match their index entry are
+/// taken to be unchanged without being read. Unmerged paths and submodules
+/// are skipped.
+pub fn diff_index_to_work_tree(
+    git_dir: &Path,
+    work_tree: &Path,
+    pending: &mut PendingBlobs,
+) -> anyhow::Result<Vec<FileChange>> {
+    let index = Index::load(&git_path(git_dir, "index"))?;
+    // Reading and hashing the files is the slow part, so it's done on
+    // several threads; the changes are still collected in index order.
+    let checks = parallel::map(&index.entries, |entry| {
+        work_tree_change(git_dir, work_tree, entry)
+    });
+    let mut changes = Vec::new();
+    for check in checks {
+        if let Some((change, content)) = check? {
+            if let (Some(new), Some(content)) = (&change.new, content) {
+                pending.insert(new.hash.clone(), content);
+            }
+            changes.push(change);
+        }
+    }
+    Ok(changes)
+}
+
+/// The unstaged change of an index entry, if any, with the work tree content
+/// it was hashed from.
+fn work_tree_change(
+    git_dir: &Path,
+    work_tree: &Path,
+    entry: &IndexEntry,
+) -> anyhow::Result<Option<(FileChange, Option<Vec<u8>>)>> {
+    if entry.stage() != 0 || entry.mode == 0o160000 {
+        return Ok(None);
+    }
+    // Entries added with `add -N` only hold a placeholder, so their file is new.
+    let old = match entry.is_intent_to_add() {
+        true => None,
+        false => Some(TreeEntry {
+            mode: format!("{:o}", entry.mode),
+            hash: hex::encode(&entry.hash),
+        }),
+    };
+
+    let metadata = match leads_through_symlink(work_tree, &entry.path) {
+        true => None,
+        false => fs::symlink_metadata(work_tree.join(&entry.path)).ok(),
+    };
+    let Some(metadata) = metadata.filter(|x| !x.is_dir()) else {
+        let change = FileChange {
+            path: entry.path.clone(),
+            old,
+            new: None,
+            rename: None,
+        };
+        return Ok(Some((change, None)));
+    };
+    let mode = platform::work_tree_mode(Filesystem::of(git_dir), &metadata, Some(entry.mode));
+    if old.is_some() && mode == entry.mode && status::stat_matches(entry, &metadata) {
+        return Ok(None);
+    }
+
+    let content = status::work_tree_content(git_dir, work_tree, &entry.path, &metadata)?;
+    let new = TreeEntry {
+        mode: format!("{:o}", mode),
+        hash: ObjectFormat::of(git_dir)?.hash_object("blob", &content),
+    };
+    if let Some(old) = &old {
+        if old.mode == new.mode && old.hash == new.hash {
+            return Ok(None);
+        }
+    }
+    let change = FileChange {
+        path: entry.path.clone(),
+        old,
+        new: Some(new),
+        rename: None,
+    };
+    Ok(Some((change, Some(content))))
+}
+
+/// Compares the index against the tree `HEAD` points to and returns what a
+/// commit would change, in path order. On an unborn branch everything staged
+/// is new. Unmerged paths and entries added with `add -N` aren't staged yet,
+/// so they're left out.
+pub fn diff_head_to_index(git_dir: &Path) -> anyhow::Result<Vec<FileChange>> {
+    let tree = match resolve_ref(git_dir, "HEAD")? {
+        Some(head) => Some(Repository::open(git_dir).peel_to(&head, ObjectType::Tree)?),
+        None => None,
+    };
+    diff_tree_to_index(git_dir, tree.as_deref())
+}
+
+/// Compares the index against `tree`, like [`diff_head_to_index`] does
+/// against `HEAD`'s; with no tree, everything staged is new.
+pub fn diff_tree_to_index(git_dir: &Path, tree: Option<&str>) -> anyhow::Result<Vec<FileChange>> {
+    Ok(into_changes(tree_and_index(git_dir, tree)?))
+}
+
+/// Compares the work tree against `tree`, in path order: a tracked file
+/// is compared as it is in the work tree, whether or not its changes are
+/// staged. Work tree content goes into `pending`, as for
+/// [`diff_index_to_work_tree`].
+pub fn diff_tree_to_work_tree(
+    git_dir: &Path,
+    work_tree: &Path,
+    tree: Option<&str>,
+    pending: &mut PendingBlobs,
+) -> anyhow::Result<Vec<FileChange>> {
+    let mut sides = tree_and_index(git_dir, tree)?;
+    for change in diff_index_to_work_tree(git_dir, work_tree, pending)? {
+        sides.entry(change.path).or_default().1 = change.new;
+    }
+    Ok(into_changes(sides))
+}
+
+/// Paths with their entries on the old and new side of a comparison.
+type Sides = BTreeMap<String, (Option<TreeEntry>, Option<TreeEntry>)>;
+
+/// Each path of `tree` or the index, with its entry in both.
+fn tree_and_index(git_dir: &Path, tree: Option<&str>) -> anyhow::Result<Sides> {
+    let index = Index::load(&git_path(git_dir, "index"))?;
+
+    let mut head_entries = BTreeMap::new();
+    if let Some(tree) = tree {
+        status::flatten_tree(git_dir, tree, "", &mut head_entries)?;
+    }
+    let to_entry = |mode: u32, hash: &[u8]| TreeEntry {
+        mode: format!("{:o}", mode),
+        hash: hex::encode(hash),
+    };
+
+    let mut sides: Sides = head_entries
+        .iter()
+        .map(|(path, (mode, hash))| (path.clone(), (Some(to_entry(*mode, hash)), None)))
+        .collect();
+    for entry in &index.entries {
+        if entry.stage() != 0 {
+            sides.remove(entry.path.as_str());
+            continue;
+        }
+        if entry.is_intent_to_add() {
+            continue;
+        }
+        sides.entry(entry.path.clone()).or_default().1 = Some(to_entry(entry.mode, &entry.hash));
+    }
+    Ok(sides)
+}
+
+/// The paths whose two entries differ, as changes.
+fn into_changes(sides: Sides) -> Vec<FileChange> {
+    sides
+        .into_iter()
+        .filter(|(_, (old, new))| match (old, new) {
+            (Some(old), Some(new)) => old.mode != new.mode || old.hash != new.hash,
+            _ => true,
+        })
+        .map(|(path, (old, new))| FileChange {
+            path,
+            old,
+            new,
+            rename: None,
+        })
+        .collect()
+}
+
+/// Pairs deleted and added paths into renames.
+///
+/// Identical content (same blob id) is always paired. With `min_similarity` set,
+/// remaining pairs whose content is at least that percent similar are paired too.
+pub fn detect_renames(
+    git_dir: &Path,
+    changes: Vec<FileChange>,
+    min_similarity: Option<u8>,
+) -> anyhow::Result<Vec<FileChange>> {
+    let is_deleted = |x: &FileChange| x.old.is_some() && x.new.is_none();
+    let is_added = |x: &FileChange| x.old.is_none() && x.new.is_some();
+
+    let deleted: Vec<usize> = (0..changes.len())
+        .filter(|&i| is_deleted(&changes[i]))
+        .collect();
+    let added: Vec<usize> = (0..changes.len())
+        .filter(|&i| is_added(&changes[i]))
+        .collect();
+
+    // (deleted index, added index, similarity)
+    let mut pairs: Vec<(usize, usize, u8)> = Vec::new();
+    let mut paired_deleted = HashSet::new();
+    let mut paired_added = HashSet::new();
+
+    for &a in &added {
+        let new_hash = &changes[a].new.as_ref().unwrap().hash;
+        let source = deleted.iter().find(|&&d| {
+            !paired_deleted.contains(&d) && &changes[d].old.as_ref().unwrap().hash == new_hash
+        });
+        if let Some(&d) = source {
+            pairs.push((d, a, 100));
+            paired_deleted.insert(d);
+            paired_added.insert(a);
+        }
+    }
+
+    if let Some(min_similarity) = min_similarity {
+        let pending = PendingBlobs::new();
+        let mut candidates: Vec<(usize, usize, u8)> = Vec::new();
+        for &d in deleted.iter().filter(|x| !paired_deleted.contains(x)) {
+            let old_content = read_blob(git_dir, &pending, changes[d].old.as_ref())?;
+            for &a in added.iter().filter(|x| !paired_added.contains(x)) {
+                let new_content = read_blob(git_dir, &pending, changes[a].new.as_ref())?;
+
+                // Can't reach the threshold if the sizes are too different; skip the diff.
+                let (smaller, larger) = if old_content.len() < new_content.len() {
+                    (old_content.len(), new_content.len())
+                } else {
+                    (new_content.len(), old_content.len())
+                };
+                if larger > 0 && smaller * 100 / larger < min_similarity as usize {
+                    continue;
+                }
+
+                let score = similarity(&old_content, &new_content);
+                if score >= min_similarity {
+                    candidates.push((d, a, score));
+                }
+            }
+        }
+
+        // Best matches claim their paths first.
+        candidates.sort_by_key(|&(d, a, score)| (std::cmp::Reverse(score), d, a));
+        for (d, a, score) in candidates {
+            if paired_deleted.contains(&d) || paired_added.contains(&a) {
+                continue;
+            }
+            pairs.push((d, a, score));
+            paired_deleted.insert(d);
+            paired_added.insert(a);
+        }
+    }
+
+    let mut changes: Vec<Option<FileChange>> = changes.into_iter().map(Some).collect();
+    for (d, a, score) in pairs {
+        let source = changes[d].take().unwrap();
+        let target = changes[a].as_mut().unwrap();
+        target.old = source.old;
+        target.rename = Some(Rename {
+            from: source.path,
+            similarity: score,
+        });
+    }
+
+    Ok(changes.into_iter().flatten().collect())
+}
+
+/// Percentage of content that survived from `old` to `new`, measured in bytes of unchanged lines.
+fn similarity(old: &str, new: &str) -> u8 {
+    let max_size = old.len().max(new.len());
+    if max_size == 0 {
+        return 100;
+    }
+    // Binary content can't be compared line by line.
+    if is_binary(old) || is_binary(new) {
+        return 0;
+    }
+
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let kept: usize = diff_lines(&old_lines, &new_lines)
+        .iter()
+        .map(|edit| match *edit {
+            Edit::Equal(old, _) => old_lines[old].len(),
+            _ => 0,
+        })
+        .sum();
+
+    (kept * 100 / max_size) as u8
+}
+
+fn read_tree_elements(git_dir: &Path, hash: &str) -> anyhow::Result<Vec<TreeElement>> {
+    match Repository::open(git_dir).object(hash)? {
+        Object::Tree(tree) => Ok(tree.elements),
+        _ => Err(anyhow!("Object {} is not a tree.", hash)),
+    }
+}
+
+fn read_blob(
+    git_dir: &Path,
+    pending: &PendingBlobs,
+    entry: Option<&TreeEntry>,
+) -> anyhow::Result<String> {
+    let Some(entry) = entry else {
+        return Ok(String::new());
+    };
+    if let Some(content) = pending.get(&entry.hash) {
+        return Ok(decode_text(content));
+    }
+
+    match Repository::open(git_dir).read(&entry.hash)? {
+        (crate::ObjectType::Blob, content) => Ok(decode_text(&content)),
+        _ => Err(anyhow!("Object {} is not a blob.", entry.hash)),
+    }
+}
+
+/// Decodes blob content for display, honoring a UTF-8 or UTF-16 byte order
+/// mark so the mark itself doesn't show up in the first line and UTF-16 text
+/// diffs as text instead of as mojibake (or as binary, given its null bytes).
+fn decode_text(content: &[u8]) -> String {
+    let utf16 = |bytes: &[u8], decode: fn([u8; 2]) -> u16| {
+        let units = bytes.chunks_exact(2).map(|x| decode([x[0], x[1]]));
+        char::decode_utf16(units)
+            .map(|x| x.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect()
+    };
+
+    match content {
+        [0xef, 0xbb, 0xbf, rest @ ..] => String::from_utf8_lossy(rest).to_string(),
+        [0xff, 0xfe, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xfe, 0xff, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        _ => String::from_utf8_lossy(content).to_string(),
+    }
+}
+
+fn is_tree_mode(mode: &str) -> bool {
+    mode == "40000"
+}
+
+fn tree_sort_key(name: &str, is_tree: bool) -> String {
+    if is_tree {
+        format!("{}/", name)
+    } else {
+        name.to_string()
+    }
+}
+
+impl From<&TreeElement> for TreeEntry {
+    fn from(element: &TreeElement) -> Self {
+        TreeEntry {
+            mode: element.mode.clone(),
+            hash: hex::encode(&element.hash),
+        }
+    }
+}
+
+/// Prints changes in git's raw format: `:<old mode> <new mode> <old> <new> <status>\t<path>`.
+pub fn print_raw(changes: &[FileChange]) {
+    for change in changes {
+        // The missing side is all zeros, as long as the ids of the other one.
+        let id_len = match (&change.old, &change.new) {
+            (Some(entry), _) | (None, Some(entry)) => entry.hash.len(),
+            (None, None) => 40,
+        };
+        let null_hash = "0".repeat(id_len);
+        let (old_mode, old_hash) = match &change.old {
+            Some(entry) => (format!("{:0>6}", entry.mode), entry.hash.clone()),
+            None => ("000000".to_string(), null_hash.clone()),
+        };
+        let (new_mode, new_hash) = match &change.new {
+            Some(entry) => (format!("{:0>6}", entry.mode), entry.hash.clone()),
+            None => ("000000".to_string(), null_hash.clone()),
+        };
+
+        let paths = match &change.rename {
+            Some(rename) => format!("{}\t{}", rename.from, change.path),
+            None => change.path.clone(),
+        };
+
+        println!(
+            ":{} {} {} {} {}\t{}",
+            old_mode,
+            new_mode,
+            old_hash,
+            new_hash,
+            change.status(),
+            paths
+        );
+    }
+}
+
+/// Prints changes as a unified diff, like `git diff-tree -p`.
+pub fn print_patch(
+    git_dir: &Path,
+    changes: &[FileChange],
+    pending: &PendingBlobs,
+    options: DiffOptions,
+) -> anyhow::Result<()> {
+    for change in changes {
+        let old_content = read_blob(git_dir, pending, change.old.as_ref())?;
+        let new_content = read_blob(git_dir, pending, change.new.as_ref())?;
+
+        println!("diff --git a/{} b/{}", change.old_path(), change.path);
+
+        let short_hash = |entry: &Option<TreeEntry>| match entry {
+            Some(entry) => entry.hash.chars().take(7).collect::<String>(),
+            None => "0000000".to_string(),
+        };
+        let old_short = short_hash(&change.old);
+        let new_short = short_hash(&change.new);
+
+        match (&change.old, &change.new) {
+            (None, Some(new)) => {
+                println!("new file mode {:0>6}", new.mode);
+                println!("index {}..{}", old_short, new_short);
+            }
+            (Some(old), None) => {
+                println!("deleted file mode {:0>6}", old.mode);
+                println!("index {}..{}", old_short, new_short);
+            }
+            (Some(old), Some(new)) => {
+                if old.mode != new.mode {
+                    println!("old mode {:0>6}", old.mode);
+                    println!("new mode {:0>6}", new.mode);
+                }
+                if let Some(rename) = &change.rename {
+                    println!("similarity index {}%", rename.similarity);
+                    println!("rename from {}", rename.from);
+                    println!("rename to {}", change.path);
+                }
+                if old.hash != new.hash {
+                    if old.mode == new.mode {
+                        println!("index {}..{} {:0>6}", old_short, new_short, old.mode);
+                    } else {
+                        println!("index {}..{}", old_short, new_short);
+                    }
+                }
+            }
+            (None, None) => {}
+        }
+
+        let old_name = match change.old {
+            Some(_) => format!("a/{}", change.old_path()),
+            None => "/dev/null".to_string(),
+        };
+        let new_name = match change.new {
+            Some(_) => format!("b/{}", change.path),
+            None => "/dev/null".to_string(),
+        };
+
+        if is_binary(&old_content) || is_binary(&new_content) {
+            println!("Binary files {} and {} differ", old_name, new_name);
+            continue;
+        }
+
+        let old_lines = split_lines(&old_content);
+        let new_lines = split_lines(&new_content);
+        let edits = diff_lines_with(&old_lines, &new_lines, options);
+        if edits.iter().all(|x| matches!(x, Edit::Equal(_, _))) {
+            continue;
+        }
+
+        println!("--- {}", old_name);
+        println!("+++ {}", new_name);
+        print_hunks(&edits, &old_lines, &new_lines);
+    }
+
+    Ok(())
+}
+
+/// Groups an edit script into hunks with surrounding context and prints them.
+fn print_hunks(edits: &[Edit], old_lines: &[&str], new_lines: &[&str]) {
+    let changed: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal(_, _)))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut i = 0;
+    while i < changed.len() {
+        // Extend the hunk while the next change is close enough to share context.
+        let start = changed[i].saturating_sub(CONTEXT_LINES);
+        let mut last = changed[i];
+        while i + 1 < changed.len() && changed[i + 1] - last <= 2 * CONTEXT_LINES + 1 {
+            i += 1;
+            last = changed[i];
+        }
+        let end = (last + CONTEXT_LINES + 1).min(edits.len());
+        i += 1;
+
+        let hunk = &edits[start..end];
+
+        // Line numbers of the first line covered by the hunk on each side.
+        let old_start = edits[..start]
+            .iter()
+            .filter(|x| !matches!(x, Edit::Insert(_)))
+            .count();
+        let new_start = edits[..start]
+            .iter()
+            .filter(|x| !matches!(x, Edit::Delete(_)))
+            .count();
+        let old_len = hunk
+            .iter()
+            .filter(|x| !matches!(x, Edit::Insert(_)))
+            .count();
+        let new_len = hunk
+            .iter()
+            .filter(|x| !matches!(x, Edit::Delete(_)))
+            .count();
+
+        let header = format!(
+            "@@ -{} +{} @@",
+            hunk_range(old_start, old_len),
+            hunk_range(new_start, new_len)
+        );
+        match function_context(&old_lines[..old_start]) {
+            Some(context) => println!("{} {}", header, context),
+            None => println!("{}", header),
+        }
+
+        for edit in hunk {
+            let (marker, line) = match *edit {
+                // Like git, context comes from the new side (they differ with --ignore-cr-at-eol).
+                Edit::Equal(_, new) => (' ', new_lines[new]),
+                Edit::Delete(old) => ('-', old_lines[old]),
+                Edit::Insert(new) => ('+', new_lines[new]),
+            };
+            print_line(marker, line);
+        }
+    }
+}
+
+/// Finds the line git would show after a hunk header: the closest preceding
+/// line starting with a letter, `_` or `$`, cut to 80 bytes.
+fn function_context<'a>(preceding: &[&'a str]) -> Option<&'a str> {
+    let line = preceding
+        .iter()
+        .rev()
+        .find(|line| line.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '$'))?;
+    let line = line.trim_end();
+
+    let mut end = line.len().min(80);
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    Some(&line[..end])
+}
+
+fn print_line(marker: char, line: &str) {
+    match line.strip_suffix('\n') {
+        Some(line) => println!("{}{}", marker, line),
+        None => {
+            println!("{}{}", marker, line);
+            println!("\\ No newline at end of file");
+        }
+    }
+}
+
+/// Formats one side of a hunk header; empty ranges point at the line before them.
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+/// Per-file line counts for `--stat`.
+struct FileStat {
+    path: String,
+    insertions: usize,
+    deletions: usize,
+    binary: Option<(usize, usize)>,
+}
+
+/// Prints a `--stat` summary: one line per file and a final totals line.
+pub fn print_stat(
+    git_dir: &Path,
+    changes: &[FileChange],
+    pending: &PendingBlobs,
+    options: DiffOptions,
+) -> anyhow::Result<()> {
+    let stats = file_stats(git_dir, changes, pending, options)?;
+    if stats.is_empty() {
+        return Ok(());
+    }
+
+    let name_width = stats.iter().map(|x| x.path.len()).max().unwrap_or(0);
+    let max_change = stats
+        .iter()
+        .map(|x| x.insertions + x.deletions)
+        .max()
+        .unwrap_or(0);
+    let mut count_width = max_change.to_string().len();
+    if stats.iter().any(|x| x.binary.is_some()) {
+        count_width = count_width.max("Bin".len());
+    }
+    let graph_width = STAT_WIDTH
+        .saturating_sub(name_width + count_width + 6)
+        .max(10);
+
+    for stat in &stats {
+        if let Some((old_size, new_size)) = stat.binary {
+            println!(
+                " {:<name_width$} | {:>count_width$} {} -> {} bytes",
+                stat.path, "Bin", old_size, new_size
+            );
+            continue;
+        }
+
+        let total = stat.insertions + stat.deletions;
+        let (plus, minus) = if max_change > graph_width {
+            (
+                scale(stat.insertions, graph_width, max_change),
+                scale(stat.deletions, graph_width, max_change),
+            )
+        } else {
+            (stat.insertions, stat.deletions)
+        };
+
+        let graph = format!("{}{}", "+".repeat(plus), "-".repeat(minus));
+        let line = format!(
+            " {:<name_width$} | {:>count_width$} {}",
+            stat.path, total, graph
+        );
+        println!("{}", line.trim_end());
+    }
+
+    print_totals(&stats);
+    Ok(())
+}
+
+/// Prints only the totals line of `--stat`, like `--shortstat`.
+pub fn print_shortstat(
+    git_dir: &Path,
+    changes: &[FileChange],
+    pending: &PendingBlobs,
+    options: DiffOptions,
+) -> anyhow::Result<()> {
+    let stats = file_stats(git_dir, changes, pending, options)?;
+    if !stats.is_empty() {
+        print_totals(&stats);
+    }
+    Ok(())
+}
+
+fn file_stats(
+    git_dir: &Path,
+    changes: &[FileChange],
+    pending: &PendingBlobs,
+    options: DiffOptions,
+) -> anyhow::Result<Vec<FileStat>> {
+    let mut stats = Vec::new();
+
+    for change in changes {
+        let old_content = read_blob(git_dir, pending, change.old.as_ref())?;
+        let new_content = read_blob(git_dir, pending, change.new.as_ref())?;
+
+        if is_binary(&old_content) || is_binary(&new_content) {
+            stats.push(FileStat {
+                path: stat_path(change),
+                insertions: 0,
+                deletions: 0,
+                binary: Some((old_content.len(), new_content.len())),
+            });
+            continue;
+        }
+
+        let old_lines = split_lines(&old_content);
+        let new_lines = split_lines(&new_content);
+        let edits = diff_lines_with(&old_lines, &new_lines, options);
+
+        stats.push(FileStat {
+            path: stat_path(change),
+            insertions: edits
+                .iter()
+                .filter(|x| matches!(x, Edit::Insert(_)))
+                .count(),
+            deletions: edits
+                .iter()
+                .filter(|x| matches!(x, Edit::Delete(_)))
+                .count(),
+            binary: None,
+        });
+    }
+    Ok(stats)
+}
+
+fn print_totals(stats: &[FileStat]) {
+    let files = stats.len();
+    let insertions: usize = stats.iter().map(|x| x.insertions).sum();
+    let deletions: usize = stats.iter().map(|x| x.deletions).sum();
+
+    let mut summary = format!(
+        " {} file{} changed",
+        files,
+        if files == 1 { "" } else { "s" }
+    );
+    if insertions > 0 || deletions == 0 {
+        summary.push_str(&format!(
+            ", {} insertion{}(+)",
+            insertions,
+            if insertions == 1 { "" } else { "s" }
+        ));
+    }
+    if deletions > 0 || insertions == 0 {
+        summary.push_str(&format!(
+            ", {} deletion{}(-)",
+            deletions,
+            if deletions == 1 { "" } else { "s" }
+        ));
+    }
+    println!("{}", summary);
+}
+
+/// Prints a `--summary` of the files created, deleted, renamed or given a
+/// new mode, as git does below the diffstat of a merge.
+pub fn print_summary(changes: &[FileChange]) {
+    for change in changes {
+        match (&change.old, &change.new, &change.rename) {
+            (_, Some(_), Some(rename)) => {
+                println!(" rename {} ({}%)", stat_path(change), rename.similarity)
+            }
+            (None, Some(new), None) => println!(" create mode {} {}", new.mode, change.path),
+            (Some(old), None, _) => println!(" delete mode {} {}", old.mode, change.path),
+            (Some(old), Some(new), None) if old.mode != new.mode => {
+                println!(" mode change {} => {} {}", old.mode, new.mode, change.path)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Formats the path shown in `--stat`, compacting renames to `dir/{old => new}`.
+fn stat_path(change: &FileChange) -> String {
+    let Some(rename) = &change.rename else {
+        return change.path.clone();
+    };
+    let (old, new) = (rename.from.as_str(), change.path.as_str());
+
+    // Common leading directories and common trailing path components.
+    let prefix_len = old
+        .char_indices()
+        .zip(new.chars())
+        .take_while(|((_, a), b)| a == b)
+        .filter(|((_, a), _)| *a == '/')
+        .map(|((i, _), _)| i + 1)
+        .last()
+        .unwrap_or(0);
+    let max_suffix = old.len().min(new.len()) - prefix_len;
+    let suffix_len = old
+        .bytes()
+        .rev()
+        .zip(new.bytes().rev())
+        .take(max_suffix)
+        .enumerate()
+        .take_while(|(_, (a, b))| a == b)
+        .filter(|(_, (a, _))| *a == b'/')
+        .map(|(i, _)| i + 1)
+        .last()
+        .unwrap_or(0);
+
+    if prefix_len == 0 && suffix_len == 0 {
+        return format!("{} => {}", old, new);
+    }
+
+    format!(
+        "{}{{{} => {}}}{}",
+        &old[..prefix_len],
+        &old[prefix_len..old.len() - suffix_len],
+        &new[prefix_len..new.len() - suffix_len],
+        &old[old.len() - suffix_len..]
+    )
+}
+
+/// Scales a change count to the graph width, keeping at least one marker for non-zero counts.
+fn scale(count: usize, width: usize, max_change: usize) -> usize {
+    if count == 0 {
+        0
+    } else {
+        1 + count * (width - 1) / max_change
+    }
+}
@@ -0,0 +1,76 @@
+//! The errors reading and writing objects can fail with, so callers can
+//! tell a missing object from a corrupt one without matching on messages.
+
+use crate::object::ObjectType;
+use std::io;
+
+pub type Result<T> = std::result::Result<T, GitError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum GitError {
+    #[error("Object {0} not found.")]
+    ObjectNotFound(String),
+
+    /// An id, or abbreviated id, that isn't hex of a possible length.
+    #[error("{0} is not a valid abbreviated object id.")]
+    InvalidObjectId(String),
+
+    #[error("Short object id {prefix} is ambiguous: {}", .candidates.join(", "))]
+    AmbiguousObjectId {
+        prefix: String,
+        candidates: Vec<String>,
+    },
+
+    /// A type name other than `blob`, `tree`, `commit` or `tag`.
+    #[error("Invalid object type '{0}'.")]
+    InvalidObjectType(String),
+
+    /// An object that exists, but isn't of the type it had to be.
+    #[error("Object {id} is a {actual}, not a {expected}.")]
+    WrongObjectType {
+        id: String,
+        actual: ObjectType,
+        expected: ObjectType,
+    },
+
+    /// A stored object that doesn't inflate, or whose header doesn't match
+    /// its content.
+    #[error("Object {id} is corrupt: {reason}")]
+    CorruptObject { id: String, reason: String },
+
+    /// Object bytes or content that don't parse as the type they claim.
+    #[error("{0}")]
+    MalformedObject(String),
+
+    #[error("Hash collision: object {0} is already stored with different content.")]
+    HashCollision(String),
+
+    #[error("Cycle detected: tag {0} leads back to itself.")]
+    TagCycle(String),
+
+    #[error("Too many levels of tags while peeling {0}.")]
+    TooManyTags(String),
+
+    #[error("Unknown object format '{0}'.")]
+    UnknownObjectFormat(String),
+
+    #[error("Not a git repository (or any of the parent directories): .git")]
+    NotARepository,
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    /// Errors of the parts not given their own variants yet, like packs and
+    /// config files.
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl GitError {
+    pub(crate) fn corrupt(id: &str, reason: impl Into<String>) -> GitError {
+        GitError::CorruptObject {
+            id: id.to_string(),
+            reason: reason.into(),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{
+    diff, expand_ref, header_field, log, resolve_ref, CommitObject, ObjectType, Repository,
+};
+use anyhow::anyhow;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+
+/// Writes the history reachable from the ref `rev` as a `git fast-export` stream.
+///
+/// Commits come parents first and are written against the ref's full name; each
+/// lists its changes against its first parent, preceded by the blobs not yet
+/// emitted. Blobs and commits get marks (`:1`, `:2`, ...) which later commands
+/// refer to. An annotated tag is followed by a `tag` command.
+#[tracing::instrument(skip(git_dir, out))]
+pub fn fast_export(git_dir: &Path, rev: &str, out: &mut impl Write) -> anyhow::Result<()> {
+    let ref_name = expand_ref(git_dir, rev)?.ok_or_else(|| anyhow!("Unknown ref: {}", rev))?;
+    let tip = resolve_ref(git_dir, &ref_name)?.ok_or_else(|| anyhow!("Unknown ref: {}", rev))?;
+
+    let repository = Repository::open(git_dir);
+    let (object_type, content) = repository.read(&tip)?;
+    let (tag, head) = match object_type {
+        ObjectType::Tag => {
+            let target = header_field(&content, "object")
+                .ok_or_else(|| anyhow!("Tag {} has no object line.", tip))?;
+            (Some(content), target)
+        }
+        ObjectType::Commit => (None, tip),
+        other => return Err(anyhow!("Cannot export {}: it points at a {}.", rev, other)),
+    };
+
+    let mut marks: HashMap<String, usize> = HashMap::new();
+    for commit_id in log::parents_first(git_dir, &head)? {
+        let (_, content) = repository.read(&commit_id)?;
+        let commit = CommitObject::from_content(&content)?;
+        let first_parent = commit.parents.first();
+
+        let old_tree = match first_parent {
+            Some(parent) => {
+                let (_, content) = repository.read(parent)?;
+                Some(CommitObject::from_content(&content)?.tree)
+            }
+            None => None,
+        };
+        let changes = diff::diff_trees(git_dir, old_tree.as_deref(), Some(&commit.tree))?;
+
+        for entry in changes.iter().filter_map(|x| x.new.as_ref()) {
+            if entry.mode == "160000" || marks.contains_key(&entry.hash) {
+                continue;
+            }
+            let (_, data) = repository.read(&entry.hash)?;
+            let mark = marks.len() + 1;
+            marks.insert(entry.hash.clone(), mark);
+            writeln!(out, "blob\nmark :{}", mark)?;
+            write_data(out, data.as_slice())?;
+            writeln!(out)?;
+        }
+
+        if first_parent.is_none() {
+            writeln!(out, "reset {}", ref_name)?;
+        }
+        let mark = marks.len() + 1;
+        marks.insert(commit_id.clone(), mark);
+        writeln!(out, "commit {}\nmark :{}", ref_name, mark)?;
+        writeln!(
+            out,
+            "author {}\ncommitter {}",
+            commit.author, commit.committer
+        )?;
+        if let Some(encoding) = &commit.encoding {
+            writeln!(out, "encoding {}", encoding)?;
+        }
+        write_data(out, commit.message.as_bytes())?;
+        for (i, parent) in commit.parents.iter().enumerate() {
+            let keyword = if i == 0 { "from" } else { "merge" };
+            writeln!(out, "{} :{}", keyword, marks[parent])?;
+        }
+        for change in &changes {
+            match &change.new {
+                Some(entry) if entry.mode == "160000" => writeln!(
+                    out,
+                    "M {} {} {}",
+                    entry.mode,
+                    entry.hash,
+                    quote_path(&change.path)
+                )?,
+                Some(entry) => writeln!(
+                    out,
+                    "M {} :{} {}",
+                    entry.mode,
+                    marks[&entry.hash],
+                    quote_path(&change.path)
+                )?,
+                None => writeln!(out, "D {}", quote_path(&change.path))?,
+            }
+        }
+        writeln!(out)?;
+    }
+
+    if let Some(content) = tag {
+        let name = ref_name.strip_prefix("refs/tags/").unwrap_or(&ref_name);
+        let text = String::from_utf8_lossy(&content);
+        let message = text.split_once("\n\n").map(|x| x.1).unwrap_or("");
+        writeln!(out, "tag {}\nfrom :{}", name, marks[&head])?;
+        if let Some(tagger) = header_field(&content, "tagger") {
+            writeln!(out, "tagger {}", tagger)?;
+        }
+        write_data(out, message.as_bytes())?;
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+fn write_data(out: &mut impl Write, data: &[u8]) -> anyhow::Result<()> {
+    writeln!(out, "data {}", data.len())?;
+    out.write_all(data)?;
+    Ok(())
+}
+
+/// Quotes a path like git does: C-style when it has control characters, quotes,
+/// backslashes or non-ASCII bytes, and in plain quotes when it has spaces.
+fn quote_path(path: &str) -> String {
+    let needs_escape = path
+        .bytes()
+        .any(|x| !(0x20..0x7f).contains(&x) || x == b'"' || x == b'\\');
+    if !needs_escape {
+        return match path.contains(' ') {
+            true => format!("\"{}\"", path),
+            false => path.to_string(),
+        };
+    }
+
+    let mut quoted = String::from("\"");
+    for byte in path.bytes() {
+        match byte {
+            b'\n' => quoted.push_str("\\n"),
+            b'\t' => quoted.push_str("\\t"),
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            byte if !(0x20..0x7f).contains(&byte) => quoted.push_str(&format!("\\{:03o}", byte)),
+            byte => quoted.push(byte as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
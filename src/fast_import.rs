@@ -0,0 +1,436 @@
+use crate::{header_field, resolve_rev, write_ref, Object, ObjectType, Repository};
+use anyhow::{anyhow, Context};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Files of a commit being built: path to `(mode, blob id)`.
+type Files = BTreeMap<String, (String, String)>;
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub blobs: usize,
+    pub commits: usize,
+    pub tags: usize,
+    pub resets: usize,
+}
+
+/// Reads a `git fast-export` stream and writes the objects and refs it describes.
+///
+/// Supports the `blob`, `commit` (with `M`, `D` and `deleteall` file changes),
+/// `reset` and `tag` commands, plus `feature`, `progress`, `checkpoint` and
+/// `done`. Refs are written once the whole stream has been read.
+#[tracing::instrument(skip_all, fields(size = stream.len()))]
+pub fn fast_import(git_dir: &Path, stream: &[u8]) -> anyhow::Result<Stats> {
+    let mut importer = Importer {
+        git_dir,
+        input: Input { stream, pos: 0 },
+        marks: HashMap::new(),
+        branches: HashMap::new(),
+        tags: HashMap::new(),
+        stats: Stats::default(),
+    };
+
+    while let Some(line) = importer.input.next_line() {
+        let line = line.to_string();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (command, argument) = line.split_once(' ').unwrap_or((&line, ""));
+        match command {
+            "blob" => importer.blob()?,
+            "commit" => importer.commit(argument)?,
+            "reset" => importer.reset(argument)?,
+            "tag" => importer.tag(argument)?,
+            "feature" | "option" | "checkpoint" => {}
+            "progress" => info!("{}", argument),
+            "done" => break,
+            _ => return Err(anyhow!("Unsupported fast-import command: {}", line)),
+        }
+    }
+
+    for (name, branch) in &importer.branches {
+        if let Some(tip) = &branch.tip {
+            write_ref(git_dir, name, tip)?;
+        }
+    }
+    // Like git, tags win over a branch of the same name (which fast-export
+    // emits to carry the tagged commit).
+    for (name, hash) in &importer.tags {
+        write_ref(git_dir, name, hash)?;
+    }
+
+    Ok(importer.stats)
+}
+
+struct Input<'a> {
+    stream: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Input<'a> {
+    /// Returns the next line without its newline, or `None` at the end of the stream.
+    fn next_line(&mut self) -> Option<&'a str> {
+        if self.pos >= self.stream.len() {
+            return None;
+        }
+
+        let rest = &self.stream[self.pos..];
+        let len = rest.iter().position(|&x| x == b'\n').unwrap_or(rest.len());
+        self.pos += (len + 1).min(rest.len());
+        std::str::from_utf8(&rest[..len]).ok()
+    }
+
+    /// Returns the next line if it starts with `prefix`, without consuming anything otherwise.
+    fn next_if(&mut self, prefix: &str) -> Option<&'a str> {
+        let start = self.pos;
+        match self.next_line() {
+            Some(line) if line.starts_with(prefix) => Some(line),
+            _ => {
+                self.pos = start;
+                None
+            }
+        }
+    }
+
+    fn skip_blank_line(&mut self) {
+        if self.stream.get(self.pos) == Some(&b'\n') {
+            self.pos += 1;
+        }
+    }
+
+    /// Reads a `data <count>` or `data <<<delimiter>` block.
+    fn data(&mut self) -> anyhow::Result<Vec<u8>> {
+        let line = self
+            .next_line()
+            .ok_or_else(|| anyhow!("Expected a data command."))?;
+        let spec = line
+            .strip_prefix("data ")
+            .ok_or_else(|| anyhow!("Expected a data command, got: {}", line))?;
+
+        if let Some(delimiter) = spec.strip_prefix("<<") {
+            let mut data = Vec::new();
+            loop {
+                let line = self
+                    .next_line()
+                    .ok_or_else(|| anyhow!("Unterminated data block."))?;
+                if line == delimiter {
+                    break;
+                }
+                data.extend_from_slice(line.as_bytes());
+                data.push(b'\n');
+            }
+            return Ok(data);
+        }
+
+        let count: usize = spec.parse().context("Parse data length.")?;
+        let end = self.pos + count;
+        let data = self
+            .stream
+            .get(self.pos..end)
+            .ok_or_else(|| anyhow!("Data block runs past the end of the stream."))?
+            .to_vec();
+        self.pos = end;
+        // An optional newline may follow the data.
+        if self.stream.get(self.pos) == Some(&b'\n') {
+            self.pos += 1;
+        }
+        Ok(data)
+    }
+}
+
+#[derive(Default)]
+struct Branch {
+    tip: Option<String>,
+    /// Files of `tip`, kept so consecutive commits on a branch needn't re-read its tree.
+    files: Files,
+}
+
+struct Importer<'a> {
+    git_dir: &'a Path,
+    input: Input<'a>,
+    marks: HashMap<String, String>,
+    branches: HashMap<String, Branch>,
+    tags: HashMap<String, String>,
+    stats: Stats,
+}
+
+impl Importer<'_> {
+    fn blob(&mut self) -> anyhow::Result<()> {
+        let mark = self.mark();
+        self.input.next_if("original-oid ");
+        let data = self.input.data()?;
+
+        let hash = Repository::open(self.git_dir).write(ObjectType::Blob, &data)?;
+        if let Some(mark) = mark {
+            self.marks.insert(mark, hash);
+        }
+        self.stats.blobs += 1;
+        Ok(())
+    }
+
+    fn commit(&mut self, name: &str) -> anyhow::Result<()> {
+        let mark = self.mark();
+        self.input.next_if("original-oid ");
+        let author = self.input.next_if("author ").map(|x| x[7..].to_string());
+        let committer = self
+            .input
+            .next_if("committer ")
+            .map(|x| x[10..].to_string())
+            .ok_or_else(|| anyhow!("Commit to {} has no committer.", name))?;
+        let encoding = self.input.next_if("encoding ").map(|x| x[9..].to_string());
+        let message = self.input.data()?;
+
+        let mut branch = self.branches.remove(name).unwrap_or_default();
+        let mut parents: Vec<String> = branch.tip.iter().cloned().collect();
+        if let Some(from) = self.input.next_if("from ") {
+            let from = self.resolve(&from[5..])?;
+            if branch.tip.as_ref() != Some(&from) {
+                branch.files = self.read_files(&from)?;
+            }
+            parents = vec![from];
+        }
+        while let Some(merge) = self.input.next_if("merge ") {
+            parents.push(self.resolve(&merge[6..])?);
+        }
+
+        self.file_changes(&mut branch.files)?;
+
+        let tree = write_tree(self.git_dir, &branch.files)?;
+        let mut content = format!("tree {}\n", tree);
+        for parent in &parents {
+            content.push_str(&format!("parent {}\n", parent));
+        }
+        content.push_str(&format!(
+            "author {}\n",
+            author.as_deref().unwrap_or(&committer)
+        ));
+        content.push_str(&format!("committer {}\n", committer));
+        if let Some(encoding) = encoding {
+            content.push_str(&format!("encoding {}\n", encoding));
+        }
+        content.push('\n');
+        let mut content = content.into_bytes();
+        content.extend_from_slice(&message);
+
+        let hash = Repository::open(self.git_dir).write(ObjectType::Commit, &content)?;
+        if let Some(mark) = mark {
+            self.marks.insert(mark, hash.clone());
+        }
+        branch.tip = Some(hash);
+        self.branches.insert(name.to_string(), branch);
+        self.stats.commits += 1;
+        Ok(())
+    }
+
+    fn file_changes(&mut self, files: &mut Files) -> anyhow::Result<()> {
+        loop {
+            if self.input.next_if("deleteall").is_some() {
+                files.clear();
+            } else if let Some(line) = self.input.next_if("D ") {
+                let path = unquote(&line[2..])?;
+                let prefix = format!("{}/", path);
+                files.retain(|x, _| x != &path && !x.starts_with(&prefix));
+            } else if let Some(line) = self.input.next_if("M ") {
+                let mut parts = line[2..].splitn(3, ' ');
+                let (Some(mode), Some(data_ref), Some(path)) =
+                    (parts.next(), parts.next(), parts.next())
+                else {
+                    return Err(anyhow!("Malformed file modification: {}", line));
+                };
+                let mode = match mode {
+                    "644" => "100644",
+                    "755" => "100755",
+                    mode => mode,
+                };
+                let path = unquote(path)?;
+
+                let hash = if data_ref == "inline" {
+                    let data = self.input.data()?;
+                    Repository::open(self.git_dir).write(ObjectType::Blob, &data)?
+                } else {
+                    self.resolve(data_ref)?
+                };
+                files.insert(path, (mode.to_string(), hash));
+            } else {
+                break;
+            }
+        }
+
+        // A blank line may terminate the commit.
+        self.input.skip_blank_line();
+        Ok(())
+    }
+
+    fn reset(&mut self, name: &str) -> anyhow::Result<()> {
+        let mut branch = Branch::default();
+        if let Some(from) = self.input.next_if("from ") {
+            let from = self.resolve(&from[5..])?;
+            branch.files = self.read_files(&from)?;
+            branch.tip = Some(from);
+        }
+        self.input.skip_blank_line();
+
+        self.branches.insert(name.to_string(), branch);
+        self.stats.resets += 1;
+        Ok(())
+    }
+
+    fn tag(&mut self, name: &str) -> anyhow::Result<()> {
+        self.mark();
+        let from = self
+            .input
+            .next_if("from ")
+            .ok_or_else(|| anyhow!("Tag {} has no from line.", name))?;
+        let object = self.resolve(&from[5..])?;
+        self.input.next_if("original-oid ");
+        let tagger = self.input.next_if("tagger ").map(|x| x.to_string());
+        let message = self.input.data()?;
+
+        let mut repository = Repository::open(self.git_dir);
+        let (object_type, _) = repository.read(&object)?;
+        let mut content = format!("object {}\ntype {}\ntag {}\n", object, object_type, name);
+        if let Some(tagger) = tagger {
+            content.push_str(&tagger);
+            content.push('\n');
+        }
+        content.push('\n');
+        let mut content = content.into_bytes();
+        content.extend_from_slice(&message);
+
+        let hash = repository.write(ObjectType::Tag, &content)?;
+        self.tags.insert(format!("refs/tags/{}", name), hash);
+        self.stats.tags += 1;
+        Ok(())
+    }
+
+    fn mark(&mut self) -> Option<String> {
+        self.input.next_if("mark ").map(|x| x[5..].to_string())
+    }
+
+    /// Resolves a `:mark`, a branch written earlier in the stream, or a revision.
+    fn resolve(&self, commit_ish: &str) -> anyhow::Result<String> {
+        if commit_ish.starts_with(':') {
+            return self
+                .marks
+                .get(commit_ish)
+                .cloned()
+                .ok_or_else(|| anyhow!("Unknown mark {}", commit_ish));
+        }
+        if let Some(tip) = self.branches.get(commit_ish).and_then(|x| x.tip.clone()) {
+            return Ok(tip);
+        }
+        resolve_rev(self.git_dir, commit_ish)
+    }
+
+    /// Lists the files of an existing commit's tree.
+    fn read_files(&self, commit: &str) -> anyhow::Result<Files> {
+        let (object_type, content) = Repository::open(self.git_dir).read(commit)?;
+        if object_type != ObjectType::Commit {
+            return Err(anyhow!(
+                "Object {} is a {}, not a commit.",
+                commit,
+                object_type
+            ));
+        }
+        let tree = header_field(&content, "tree")
+            .ok_or_else(|| anyhow!("Commit {} has no tree.", commit))?;
+
+        let mut files = Files::new();
+        read_tree_files(self.git_dir, &tree, "", &mut files)?;
+        Ok(files)
+    }
+}
+
+fn read_tree_files(
+    git_dir: &Path,
+    tree: &str,
+    prefix: &str,
+    files: &mut Files,
+) -> anyhow::Result<()> {
+    let Object::Tree(tree) = Repository::open(git_dir).object(tree)? else {
+        return Err(anyhow!("Object {} is not a tree.", tree));
+    };
+
+    for element in tree.elements {
+        let path = format!("{}{}", prefix, element.name);
+        let hash = hex::encode(&element.hash);
+        if element.mode == "40000" {
+            read_tree_files(git_dir, &hash, &format!("{}/", path), files)?;
+        } else {
+            files.insert(path, (element.mode, hash));
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the nested tree objects for a flat list of files and returns the root tree id.
+fn write_tree(git_dir: &Path, files: &Files) -> anyhow::Result<String> {
+    let entries: Vec<(&str, &str, &str)> = files
+        .iter()
+        .map(|(path, (mode, hash))| (path.as_str(), mode.as_str(), hash.as_str()))
+        .collect();
+    write_tree_level(git_dir, &entries)
+}
+
+fn write_tree_level(git_dir: &Path, entries: &[(&str, &str, &str)]) -> anyhow::Result<String> {
+    let mut subdirs: BTreeMap<&str, Vec<(&str, &str, &str)>> = BTreeMap::new();
+    // Keyed by git's tree order, where a directory sorts as if named `<name>/`.
+    let mut records: BTreeMap<String, (String, String, Vec<u8>)> = BTreeMap::new();
+
+    for &(path, mode, hash) in entries {
+        match path.split_once('/') {
+            Some((dir, rest)) => subdirs.entry(dir).or_default().push((rest, mode, hash)),
+            None => {
+                let record = (mode.to_string(), path.to_string(), hex::decode(hash)?);
+                records.insert(path.to_string(), record);
+            }
+        }
+    }
+
+    for (dir, entries) in subdirs {
+        let hash = write_tree_level(git_dir, &entries)?;
+        let record = ("40000".to_string(), dir.to_string(), hex::decode(hash)?);
+        records.insert(format!("{}/", dir), record);
+    }
+
+    let records: Vec<_> = records.into_values().collect();
+    Ok(crate::write_tree(git_dir, &records)?)
+}
+
+/// Undoes the C-style quoting fast-export uses for paths with special
+/// characters, where `\ooo` is a byte in octal.
+fn unquote(path: &str) -> anyhow::Result<String> {
+    let Some(inner) = path.strip_prefix('"').and_then(|x| x.strip_suffix('"')) else {
+        return Ok(path.to_string());
+    };
+
+    let mut bytes = Vec::new();
+    let mut chars = inner.bytes();
+    while let Some(byte) = chars.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        match chars.next() {
+            Some(b'n') => bytes.push(b'\n'),
+            Some(b't') => bytes.push(b'\t'),
+            Some(digit @ b'0'..=b'7') => {
+                let mut value = (digit - b'0') as u32;
+                for _ in 0..2 {
+                    match chars.next() {
+                        Some(digit @ b'0'..=b'7') => value = value * 8 + (digit - b'0') as u32,
+                        _ => return Err(anyhow!("Malformed octal escape in path: {}", path)),
+                    }
+                }
+                let byte = u8::try_from(value)
+                    .map_err(|_| anyhow!("Octal escape out of range in path: {}", path))?;
+                bytes.push(byte);
+            }
+            Some(other) => bytes.push(other),
+            None => {}
+        }
+    }
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
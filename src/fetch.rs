@@ -0,0 +1,241 @@
+use crate::graph::CommitGraph;
+use crate::refs::RefLock;
+use crate::short_ref_name;
+use crate::{list_refs, record_ref_update, resolve_ref, short_id, Repository};
+use crate::{pack, transport};
+use crate::{Config, ObjectType};
+use anyhow::{anyhow, Context};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Width of the column summarizing each ref update, like git's.
+const SUMMARY_WIDTH: usize = 17;
+
+/// Fetches from the remote `name`, whose URL is `remote.<name>.url`: the
+/// remote refs its `remote.<name>.fetch` refspecs match are downloaded,
+/// leaving out objects already stored here, and stored under the local
+/// names the refspecs map them to, `refs/remotes/<name>/*` for a clone's.
+///
+/// Like git, a ref only moves to a commit that doesn't descend from the one
+/// it was at when its refspec starts with `+`.
+///
+/// With a `depth`, the fetched history is cut that many commits below the
+/// remote refs, as in a shallow clone.
+pub fn fetch(git_dir: &Path, name: &str, depth: Option<u32>) -> anyhow::Result<()> {
+    pack::require_sha1(git_dir)?;
+    let config = Config::load_all(git_dir)?;
+    let url = config
+        .get(&format!("remote.{}.url", name))
+        .ok_or_else(|| anyhow!("'{}' does not appear to be a git repository.", name))?;
+    let refspecs = fetch_refspecs(&config, name)?;
+
+    let remote = transport::connect(url)?;
+    let mut updates = Vec::new();
+    for (remote_name, object_id) in remote.refs()? {
+        let mapped = refspecs
+            .iter()
+            .find_map(|x| x.map(&remote_name).map(|local| (local, x.force)));
+        if let Some((local_name, force)) = mapped {
+            updates.push(Update {
+                remote_name,
+                local_name,
+                object_id,
+                force,
+            });
+        }
+    }
+
+    let wants: Vec<String> = updates.iter().map(|x| x.object_id.clone()).collect();
+    let stats = remote.fetch(git_dir, &wants, &local_commits(git_dir)?, depth)?;
+    tracing::debug!(objects = stats.objects, packs = stats.packs, "fetched");
+
+    let width = updates
+        .iter()
+        .map(|x| short_ref_name(&x.remote_name).len())
+        .fold(10, usize::max);
+    let mut lines = Vec::new();
+    let mut rejected = false;
+    for update in &updates {
+        let old = resolve_ref(git_dir, &update.local_name)?;
+        let Some((flag, summary, note)) = update.describe(git_dir, old.as_deref())? else {
+            continue;
+        };
+        lines.push(format!(
+            " {} {:<summary_width$} {:<width$} -> {}{}",
+            flag,
+            summary,
+            short_ref_name(&update.remote_name),
+            short_ref_name(&update.local_name),
+            note,
+            summary_width = SUMMARY_WIDTH,
+            width = width
+        ));
+        if flag == '!' {
+            rejected = true;
+            continue;
+        }
+
+        let lock = RefLock::acquire(git_dir, &update.local_name)?;
+        if resolve_ref(git_dir, &update.local_name)? != old {
+            return Err(anyhow!(
+                "Cannot update {}: it moved while fetching.",
+                update.local_name
+            ));
+        }
+        lock.commit(&update.object_id)
+            .with_context(|| format!("Update {}.", update.local_name))?;
+        let reason = match flag {
+            '*' => "storing head",
+            '+' => "forced-update",
+            _ => "fast-forward",
+        };
+        record_ref_update(
+            git_dir,
+            &update.local_name,
+            old.as_deref(),
+            &update.object_id,
+            &format!("fetch: {}", reason),
+        )?;
+    }
+
+    if !lines.is_empty() {
+        // git shows the URL without the `.git` a repository's name ends in.
+        let shown = url.trim_end_matches('/');
+        info!("From {}", shown.strip_suffix(".git").unwrap_or(shown));
+        for line in &lines {
+            info!("{}", line);
+        }
+    }
+    if rejected {
+        return Err(anyhow!(
+            "Some refs weren't updated: they would lose commits and their refspec has no '+'."
+        ));
+    }
+    Ok(())
+}
+
+/// A remote ref and where it is stored locally.
+struct Update {
+    remote_name: String,
+    local_name: String,
+    object_id: String,
+    force: bool,
+}
+
+impl Update {
+    /// The flag, summary and note git shows for moving the local ref from
+    /// `old`, or `None` when it is already up to date.
+    fn describe(
+        &self,
+        git_dir: &Path,
+        old: Option<&str>,
+    ) -> anyhow::Result<Option<(char, String, &'static str)>> {
+        let new = &self.object_id;
+        let Some(old) = old else {
+            let kind = match self.remote_name.strip_prefix("refs/") {
+                Some(x) if x.starts_with("heads/") => "[new branch]",
+                Some(x) if x.starts_with("tags/") => "[new tag]",
+                _ => "[new ref]",
+            };
+            return Ok(Some(('*', kind.to_string(), "")));
+        };
+        if old == new {
+            return Ok(None);
+        }
+        if is_fast_forward(git_dir, old, new)? {
+            return Ok(Some((
+                ' ',
+                format!("{}..{}", short_id(old), short_id(new)),
+                "",
+            )));
+        }
+        match self.force {
+            true => Ok(Some((
+                '+',
+                format!("{}...{}", short_id(old), short_id(new)),
+                "  (forced update)",
+            ))),
+            false => Ok(Some((
+                '!',
+                "[rejected]".to_string(),
+                "  (non-fast-forward)",
+            ))),
+        }
+    }
+}
+
+/// The refspecs `remote.<name>.fetch` maps the remote's refs with.
+pub fn fetch_refspecs(config: &Config, name: &str) -> anyhow::Result<Vec<Refspec>> {
+    config
+        .get_all(&format!("remote.{}.fetch", name))
+        .into_iter()
+        .map(Refspec::parse)
+        .collect()
+}
+
+/// Whether `old` is a commit that `new` descends from.
+pub fn is_fast_forward(git_dir: &Path, old: &str, new: &str) -> anyhow::Result<bool> {
+    for id in [old, new] {
+        if Repository::open(git_dir).read_header(id)?.0 != ObjectType::Commit {
+            return Ok(false);
+        }
+    }
+    CommitGraph::new(git_dir).is_ancestor(old, new)
+}
+
+/// The commits local refs point at, the tips of the history the remote
+/// doesn't need to send.
+fn local_commits(git_dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut tips = Vec::new();
+    let mut seen = HashSet::new();
+    let refs = list_refs(git_dir)?.into_iter().map(|(_, id)| id);
+    for id in resolve_ref(git_dir, "HEAD")?.into_iter().chain(refs) {
+        // Refs to trees or blobs, or to objects that are missing, have no history to offer.
+        let Result::Ok(commit) = Repository::open(git_dir).peel_to(&id, ObjectType::Commit) else {
+            continue;
+        };
+        if seen.insert(commit.clone()) {
+            tips.push(commit);
+        }
+    }
+    Ok(tips)
+}
+
+/// A fetch refspec: `[+]<remote pattern>:<local pattern>`, where both
+/// patterns hold one `*` or neither does.
+pub struct Refspec {
+    force: bool,
+    source: String,
+    destination: String,
+}
+
+impl Refspec {
+    pub fn parse(spec: &str) -> anyhow::Result<Refspec> {
+        let (force, spec) = match spec.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, spec),
+        };
+        let (source, destination) = spec
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Unsupported refspec '{}': it has no destination.", spec))?;
+        if source.matches('*').count() != destination.matches('*').count()
+            || source.matches('*').count() > 1
+        {
+            return Err(anyhow!("Invalid refspec '{}'.", spec));
+        }
+        Ok(Refspec {
+            force,
+            source: source.to_string(),
+            destination: destination.to_string(),
+        })
+    }
+
+    /// The local name `name` is fetched into, if this refspec matches it.
+    pub fn map(&self, name: &str) -> Option<String> {
+        let Some((prefix, suffix)) = self.source.split_once('*') else {
+            return (name == self.source).then(|| self.destination.clone());
+        };
+        let matched = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+        Some(self.destination.replacen('*', matched, 1))
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{log, write_tree, Object, ObjectType, Repository};
+use anyhow::anyhow;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Rewrites the history reachable from `head` with `paths` removed from every
+/// tree, oldest commit first, and returns the rewritten `head`.
+///
+/// Each commit keeps its metadata and message; its tree and parents are
+/// replaced by their rewritten counterparts. Directories left empty are dropped.
+#[tracing::instrument(skip(git_dir))]
+pub fn remove_paths(git_dir: &Path, head: &str, paths: &[String]) -> anyhow::Result<String> {
+    let paths: Vec<Vec<&str>> = paths
+        .iter()
+        .map(|x| x.trim_matches('/').split('/').collect())
+        .collect();
+
+    let mut rewritten_commits: HashMap<String, String> = HashMap::new();
+    let mut rewritten_trees = HashMap::new();
+
+    let mut repository = Repository::open(git_dir);
+    for commit_id in log::parents_first(git_dir, head)? {
+        let (_, content) = repository.read(&commit_id)?;
+        let (headers, message) = split_message(&content);
+
+        let mut new_content = Vec::new();
+        for line in headers.split(|&x| x == b'\n') {
+            if let Some(tree) = line.strip_prefix(b"tree ") {
+                let tree = String::from_utf8_lossy(tree).to_string();
+                let new_tree = match rewrite_tree(git_dir, &tree, "", &paths, &mut rewritten_trees)?
+                {
+                    Some(tree) => tree,
+                    None => write_tree(git_dir, &[])?,
+                };
+                new_content.extend_from_slice(format!("tree {}", new_tree).as_bytes());
+            } else if let Some(parent) = line.strip_prefix(b"parent ") {
+                let parent = String::from_utf8_lossy(parent).to_string();
+                let new_parent = rewritten_commits.get(&parent).unwrap_or(&parent);
+                new_content.extend_from_slice(format!("parent {}", new_parent).as_bytes());
+            } else {
+                new_content.extend_from_slice(line);
+            }
+            new_content.push(b'\n');
+        }
+        new_content.push(b'\n');
+        new_content.extend_from_slice(message);
+
+        let new_id = repository.write(ObjectType::Commit, &new_content)?;
+        rewritten_commits.insert(commit_id, new_id);
+    }
+
+    rewritten_commits
+        .remove(head)
+        .ok_or_else(|| anyhow!("Commit {} was not rewritten.", head))
+}
+
+/// Splits commit content into its header block and message.
+fn split_message(content: &[u8]) -> (&[u8], &[u8]) {
+    match content.windows(2).position(|x| x == b"\n\n") {
+        Some(pos) => (&content[..pos], &content[pos + 2..]),
+        None => (content, &[]),
+    }
+}
+
+/// Returns the id of `tree` without the given paths (each split into
+/// components, relative to this tree), or `None` if nothing is left of it.
+///
+/// A directory's rewrite only depends on its id and where it sits, and most
+/// directories are shared by many commits, so results are cached by both.
+fn rewrite_tree(
+    git_dir: &Path,
+    tree: &str,
+    prefix: &str,
+    paths: &[Vec<&str>],
+    cache: &mut HashMap<(String, String), Option<String>>,
+) -> anyhow::Result<Option<String>> {
+    let key = (prefix.to_string(), tree.to_string());
+    if let Some(result) = cache.get(&key) {
+        return Ok(result.clone());
+    }
+
+    let Object::Tree(tree_object) = Repository::open(git_dir).object(tree)? else {
+        return Err(anyhow!("Object {} is not a tree.", tree));
+    };
+
+    let mut changed = false;
+    let mut entries = Vec::new();
+    for element in tree_object.elements {
+        let nested: Vec<Vec<&str>> = paths
+            .iter()
+            .filter(|x| x.first() == Some(&element.name.as_str()))
+            .map(|x| x[1..].to_vec())
+            .collect();
+
+        if nested.iter().any(|x| x.is_empty()) {
+            changed = true;
+            continue;
+        }
+        if !nested.is_empty() && element.mode == "40000" {
+            let hash = hex::encode(&element.hash);
+            let nested_prefix = format!("{}{}/", prefix, element.name);
+            match rewrite_tree(git_dir, &hash, &nested_prefix, &nested, cache)? {
+                Some(new_hash) if new_hash == hash => {
+                    entries.push((element.mode, element.name, element.hash))
+                }
+                Some(new_hash) => {
+                    changed = true;
+                    entries.push((element.mode, element.name, hex::decode(new_hash)?));
+                }
+                None => changed = true,
+            }
+            continue;
+        }
+        entries.push((element.mode, element.name, element.hash));
+    }
+
+    let result = if !changed {
+        Some(tree.to_string())
+    } else if entries.is_empty() {
+        None
+    } else {
+        Some(write_tree(git_dir, &entries)?)
+    };
+    cache.insert(key, result.clone());
+    Ok(result)
+}
@@ -0,0 +1,340 @@
+use crate::refs::is_valid_ref_name;
+use crate::{bytes_to_object_type, gc, shallow};
+use crate::{ObjectFormat, ObjectType};
+use anyhow::anyhow;
+use codecrafters_git::odb::Repository;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+/// Tree entry modes git writes; others only draw a warning, as from git.
+const TREE_MODES: [&str; 5] = ["100644", "100755", "120000", "40000", "160000"];
+
+/// Checks the repository like `git fsck`: re-hashes every stored object,
+/// loose and packed, validates the syntax of trees, commits and tags, and
+/// follows every link between objects.
+///
+/// Prints `error in <type> <id>: <check>: <reason>` for malformed objects,
+/// `missing <type> <id>` for objects something links to but that aren't
+/// stored, and `dangling <type> <id>` for the unreachable objects nothing
+/// links to. Fails if anything but dangling objects was found.
+pub fn fsck(git_dir: &Path) -> anyhow::Result<()> {
+    let format = ObjectFormat::of(git_dir)?;
+    let repository = Repository::open(git_dir);
+    let mut object_ids = repository.object_ids()?;
+    object_ids.sort();
+    object_ids.dedup();
+
+    let mut errors = 0;
+    let mut types = HashMap::new();
+    let mut links: HashMap<String, Vec<(String, ObjectType)>> = HashMap::new();
+    for object_id in &object_ids {
+        if let Err(err) = repository.verify(object_id) {
+            println!("corrupt: {:#}", err);
+            errors += 1;
+            continue;
+        }
+        let (object_type, content) = repository.read(object_id)?;
+        let checked = check_object(object_type, &content, format);
+        for problem in &checked.problems {
+            let level = match problem.error {
+                true => "error",
+                false => "warning",
+            };
+            println!(
+                "{} in {} {}: {}: {}",
+                level, object_type, object_id, problem.check, problem.reason
+            );
+            errors += problem.error as usize;
+        }
+        types.insert(object_id.clone(), object_type);
+        links.insert(object_id.clone(), checked.links);
+    }
+
+    // Every object links to ones of a known type.
+    let mut linked = HashSet::new();
+    for (object_id, targets) in &links {
+        for (target, expected) in targets {
+            linked.insert(target.as_str());
+            match types.get(target) {
+                Some(actual) if actual != expected => {
+                    println!(
+                        "error in {} {}: badType: {} is a {}, not a {}",
+                        types[object_id], object_id, target, actual, expected
+                    );
+                    errors += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Like git, only objects reachable from the roots must have all they
+    // link to; a ref's type isn't known when what it names is missing.
+    let shallow = shallow::read(git_dir)?;
+    let mut reachable = HashSet::new();
+    let mut missing = BTreeMap::new();
+    let mut pending: Vec<(String, Option<ObjectType>)> =
+        gc::roots(git_dir)?.into_iter().map(|x| (x, None)).collect();
+    while let Some((object_id, expected)) = pending.pop() {
+        if !types.contains_key(&object_id) {
+            let entry = missing.entry(object_id).or_insert(None);
+            *entry = entry.or(expected);
+            continue;
+        }
+        if reachable.insert(object_id.clone()) {
+            // The parents of a shallow clone's edge were never fetched.
+            let is_shallow = shallow.contains(&object_id);
+            pending.extend(
+                links[&object_id]
+                    .iter()
+                    .filter(|x| !(is_shallow && x.1 == ObjectType::Commit))
+                    .map(|x| (x.0.clone(), Some(x.1))),
+            );
+        }
+    }
+
+    for (object_id, object_type) in &missing {
+        match object_type {
+            Some(object_type) => println!("missing {} {}", object_type, object_id),
+            None => println!("missing object {}", object_id),
+        }
+    }
+    errors += missing.len();
+    for object_id in &object_ids {
+        if let Some(object_type) = types.get(object_id) {
+            if !reachable.contains(object_id) && !linked.contains(object_id.as_str()) {
+                println!("dangling {} {}", object_type, object_id);
+            }
+        }
+    }
+
+    info!("Checked {} objects.", object_ids.len());
+    if errors > 0 {
+        return Err(anyhow!("fsck found {} problems.", errors));
+    }
+    Ok(())
+}
+
+/// What checking one object found: the objects it links to, with the type
+/// each must have, and what is wrong with it.
+#[derive(Default)]
+struct Checked {
+    links: Vec<(String, ObjectType)>,
+    problems: Vec<Problem>,
+}
+
+/// A check an object failed, named like git's fsck message ids.
+struct Problem {
+    check: &'static str,
+    reason: String,
+    /// Whether the problem is an error, or only worth a warning.
+    error: bool,
+}
+
+impl Checked {
+    fn error(&mut self, check: &'static str, reason: impl Into<String>) {
+        self.problems.push(Problem {
+            check,
+            reason: reason.into(),
+            error: true,
+        });
+    }
+
+    fn warning(&mut self, check: &'static str, reason: impl Into<String>) {
+        self.problems.push(Problem {
+            check,
+            reason: reason.into(),
+            error: false,
+        });
+    }
+}
+
+fn check_object(object_type: ObjectType, content: &[u8], format: ObjectFormat) -> Checked {
+    match object_type {
+        ObjectType::Blob => Checked::default(),
+        ObjectType::Tree => check_tree(content, format),
+        ObjectType::Commit => check_commit(content, format),
+        ObjectType::Tag => check_tag(content, format),
+    }
+}
+
+/// Each entry is `<mode> <name>\0<raw id>`, sorted the way git sorts them:
+/// by name, with a tree's name compared as if it ended in `/`.
+fn check_tree(content: &[u8], format: ObjectFormat) -> Checked {
+    let mut checked = Checked::default();
+    let mut previous: Option<(&str, Vec<u8>)> = None;
+    let mut bad_mode = false;
+    let mut rest = content;
+    while !rest.is_empty() {
+        let entry = rest.iter().position(|&x| x == 0).and_then(|nul| {
+            let header = std::str::from_utf8(&rest[..nul]).ok()?;
+            let (mode, name) = header.split_once(' ')?;
+            let id = rest.get(nul + 1..nul + 1 + format.raw_len())?;
+            Some((mode, name, id, nul + 1 + format.raw_len()))
+        });
+        let Some((mode, name, id, length)) = entry else {
+            checked.error("badTree", "cannot be parsed as a tree");
+            return checked;
+        };
+        rest = &rest[length..];
+
+        if name.is_empty() {
+            checked.error("emptyName", "contains empty pathname");
+        } else if name.contains('/') {
+            checked.error("fullPathname", "contains full pathnames");
+        } else if name == "." {
+            checked.error("hasDot", "contains '.'");
+        } else if name == ".." {
+            checked.error("hasDotdot", "contains '..'");
+        } else if name.eq_ignore_ascii_case(".git") {
+            checked.error("hasDotgit", "contains '.git'");
+        }
+        if !TREE_MODES.contains(&mode) && !bad_mode {
+            checked.warning("badFilemode", "contains bad file modes");
+            bad_mode = true;
+        }
+
+        let mut key = name.as_bytes().to_vec();
+        if mode == "40000" {
+            key.push(b'/');
+        }
+        match &previous {
+            Some((previous, _)) if *previous == name => {
+                checked.error("duplicateEntries", "contains duplicate file entries")
+            }
+            Some((_, previous)) if *previous > key => {
+                checked.error("treeNotSorted", "not properly sorted")
+            }
+            _ => {}
+        }
+        previous = Some((name, key));
+
+        let target = match mode {
+            "40000" => ObjectType::Tree,
+            "160000" => continue,
+            _ => ObjectType::Blob,
+        };
+        checked.links.push((hex::encode(id), target));
+    }
+    checked
+}
+
+/// A commit starts with its `tree`, then any `parent`s, then `author` and
+/// `committer` lines.
+fn check_commit(content: &[u8], format: ObjectFormat) -> Checked {
+    let mut checked = Checked::default();
+    let text = String::from_utf8_lossy(content);
+    let headers = text.split_once("\n\n").map_or(&*text, |x| x.0);
+    let mut lines = headers.lines().peekable();
+
+    match lines.next().and_then(|x| x.strip_prefix("tree ")) {
+        None => checked.error("missingTree", "invalid format - expected 'tree' line"),
+        Some(id) if !is_object_id(id, format) => {
+            checked.error("badTreeSha1", "invalid 'tree' line format - bad sha1")
+        }
+        Some(id) => checked.links.push((id.to_string(), ObjectType::Tree)),
+    }
+    while let Some(id) = lines.peek().and_then(|x| x.strip_prefix("parent ")) {
+        match is_object_id(id, format) {
+            true => checked.links.push((id.to_string(), ObjectType::Commit)),
+            false => checked.error("badParentSha1", "invalid 'parent' line format - bad sha1"),
+        }
+        lines.next();
+    }
+    for (header, check) in [
+        ("author", "missingAuthor"),
+        ("committer", "missingCommitter"),
+    ] {
+        match lines
+            .next()
+            .and_then(|x| x.strip_prefix(header)?.strip_prefix(' '))
+        {
+            Some(ident) => check_ident(&mut checked, ident),
+            None => checked.error(
+                check,
+                format!("invalid format - expected '{}' line", header),
+            ),
+        }
+    }
+    checked
+}
+
+/// A tag names its `object` and that object's `type`, then its own `tag`
+/// name and, usually, a `tagger`.
+fn check_tag(content: &[u8], format: ObjectFormat) -> Checked {
+    let mut checked = Checked::default();
+    let text = String::from_utf8_lossy(content);
+    let headers = text.split_once("\n\n").map_or(&*text, |x| x.0);
+    let mut lines = headers.lines();
+
+    let object = match lines.next().and_then(|x| x.strip_prefix("object ")) {
+        None => {
+            checked.error("missingObject", "invalid format - expected 'object' line");
+            return checked;
+        }
+        Some(id) if !is_object_id(id, format) => {
+            checked.error("badObjectSha1", "invalid 'object' line format - bad sha1");
+            return checked;
+        }
+        Some(id) => id,
+    };
+    match lines.next().and_then(|x| x.strip_prefix("type ")) {
+        None => checked.error("missingTypeEntry", "invalid format - expected 'type' line"),
+        Some(name) => match bytes_to_object_type(name.as_bytes()) {
+            Result::Ok(object_type) => checked.links.push((object.to_string(), object_type)),
+            Err(_) => checked.error("badType", "invalid 'type' value"),
+        },
+    }
+    match lines.next().and_then(|x| x.strip_prefix("tag ")) {
+        None => checked.error("missingTagEntry", "invalid format - expected 'tag' line"),
+        Some(name) if !is_valid_ref_name(&format!("refs/tags/{}", name)) => {
+            checked.warning("badTagName", format!("invalid 'tag' name: {}", name))
+        }
+        Some(_) => {}
+    }
+    match lines.next().and_then(|x| x.strip_prefix("tagger ")) {
+        Some(ident) => check_ident(&mut checked, ident),
+        None => checked.warning(
+            "missingTaggerEntry",
+            "invalid format - expected 'tagger' line",
+        ),
+    }
+    checked
+}
+
+/// An identity is `Name <email> <seconds> <+hhmm>`.
+fn check_ident(checked: &mut Checked, ident: &str) {
+    let Some((_, rest)) = ident.split_once('<') else {
+        checked.error(
+            "missingEmail",
+            "invalid author/committer line - missing email",
+        );
+        return;
+    };
+    let Some((_, date)) = rest.split_once("> ") else {
+        checked.error("badEmail", "invalid author/committer line - bad email");
+        return;
+    };
+    let Some((seconds, zone)) = date.split_once(' ') else {
+        checked.error(
+            "missingSpaceBeforeDate",
+            "invalid author/committer line - missing space before date",
+        );
+        return;
+    };
+    if seconds.is_empty() || !seconds.bytes().all(|x| x.is_ascii_digit()) {
+        checked.error("badDate", "invalid author/committer line - bad date");
+    } else if zone.len() != 5
+        || !zone.starts_with(['+', '-'])
+        || !zone[1..].bytes().all(|x| x.is_ascii_digit())
+    {
+        checked.error(
+            "badTimezone",
+            "invalid author/committer line - bad time zone",
+        );
+    }
+}
+
+fn is_object_id(id: &str, format: ObjectFormat) -> bool {
+    id.len() == format.hex_len() && id.bytes().all(|x| matches!(x, b'0'..=b'9' | b'a'..=b'f'))
+}
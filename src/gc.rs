@@ -0,0 +1,188 @@
+use crate::index::Index;
+use crate::pack_objects::missing_objects;
+use crate::resolve_ref;
+use crate::{common_dir, git_path, list_refs, pack, FsBackend, Repository};
+use anyhow::Context;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Packs every object reachable from refs, the `HEAD`s and the indexes of
+/// all worktrees into one new pack, then deletes the loose objects and packs
+/// it replaces, like `git gc`.
+///
+/// Objects nothing reaches are kept loose, those from old packs taking the
+/// pack's modification time, unless `prune` is given: then unreachable
+/// objects last modified before that Unix time are dropped. Packs with a
+/// `.keep` file are left alone, and their objects aren't packed again.
+#[tracing::instrument(skip(git_dir))]
+pub fn gc(git_dir: &Path, prune: Option<i64>) -> anyhow::Result<()> {
+    pack::require_sha1(git_dir)?;
+    let objects_dir = git_path(git_dir, "objects");
+    let mut repository = Repository::open(git_dir);
+    let mut old_packs = Vec::new();
+    let mut kept = HashSet::new();
+    for (pack_path, idx_path) in pack::list_packs(&objects_dir)? {
+        let ids = pack::index_object_ids(&idx_path)?;
+        match pack_path.with_extension("keep").exists() {
+            true => kept.extend(ids),
+            false => old_packs.push((pack_path, ids)),
+        }
+    }
+    let loose = loose_objects(&repository)?;
+
+    let reachable: HashSet<String> = missing_objects(git_dir, &roots(git_dir)?, &[])?
+        .into_iter()
+        .collect();
+    let objects: Vec<String> = reachable
+        .iter()
+        .filter(|x| !kept.contains(*x))
+        .cloned()
+        .collect();
+    let new_pack = match objects.is_empty() {
+        true => None,
+        false => {
+            let mut data = Vec::new();
+            pack::write_pack(git_dir, &objects, &mut data)?;
+            Some(pack::store_pack(git_dir, &data).context("Store the new pack.")?)
+        }
+    };
+
+    // Unreachable objects only in packs about to go are written out loose.
+    let cutoff = prune.map(|x| UNIX_EPOCH + Duration::from_secs(x.max(0) as u64));
+    let mut loosened = 0;
+    for (pack_path, ids) in &old_packs {
+        let modified = fs::metadata(pack_path)?.modified()?;
+        if cutoff.is_some_and(|x| modified < x) {
+            continue;
+        }
+        for id in ids {
+            if reachable.contains(id) || kept.contains(id) || loose.contains_key(id) {
+                continue;
+            }
+            let (object_type, content) = repository.read(id)?;
+            repository.write(object_type, &content)?;
+            fs::File::options()
+                .write(true)
+                .open(repository.loose_path(id))?
+                .set_modified(modified)?;
+            loosened += 1;
+        }
+    }
+
+    let mut removed_packs = 0;
+    for (pack_path, _) in &old_packs {
+        let name = pack_path
+            .file_stem()
+            .map(|x| x.to_string_lossy().to_string());
+        if name.is_some() && name == new_pack {
+            continue;
+        }
+        // The index goes first, so no reader finds an index without its pack.
+        for extension in ["idx", "pack", "rev", "bitmap", "mtimes", "promisor"] {
+            match fs::remove_file(pack_path.with_extension(extension)) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        removed_packs += 1;
+    }
+
+    let mut removed_loose = 0;
+    let mut pruned = 0;
+    for (id, (path, modified)) in &loose {
+        if reachable.contains(id) || kept.contains(id) {
+            removed_loose += 1;
+        } else if cutoff.is_some_and(|x| *modified < x) {
+            pruned += 1;
+        } else {
+            continue;
+        }
+        fs::remove_file(path)?;
+        if let Some(shard) = path.parent() {
+            // Only succeeds once the shard is empty.
+            let _ = fs::remove_dir(shard);
+        }
+    }
+
+    info!(
+        "Packed {} objects; removed {} loose objects and {} packs.",
+        objects.len(),
+        removed_loose,
+        removed_packs
+    );
+    if loosened + pruned > 0 {
+        info!(
+            "Unreachable objects: {} pruned, {} unpacked as loose objects.",
+            pruned, loosened
+        );
+    }
+    Ok(())
+}
+
+/// The objects everything reachable starts from: what refs point at, the
+/// `HEAD` and index entries of the main worktree and each linked one, and
+/// the commits git's reflogs remember.
+pub fn roots(git_dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut roots: Vec<String> = list_refs(git_dir)?.into_iter().map(|x| x.1).collect();
+    let common = common_dir(git_dir);
+    let mut worktree_dirs = vec![common.clone()];
+    if let Result::Ok(entries) = fs::read_dir(common.join("worktrees")) {
+        worktree_dirs.extend(entries.flatten().map(|x| x.path()));
+    }
+    reflog_entries(git_dir, &common.join("logs"), &mut roots)?;
+    for dir in &worktree_dirs {
+        roots.extend(resolve_ref(dir, "HEAD")?);
+        if dir != &common {
+            reflog_entries(git_dir, &dir.join("logs"), &mut roots)?;
+        }
+        let index = Index::load(&git_path(dir, "index"))?;
+        roots.extend(
+            index
+                .entries
+                .iter()
+                .filter(|x| x.mode != 0o160000)
+                .map(|x| hex::encode(&x.hash)),
+        );
+    }
+    Ok(roots)
+}
+
+/// Collects the old and new ids of every entry of the reflogs under `dir`,
+/// each line of which starts `<old> <new> `. Entries for objects that are
+/// already gone are skipped, as git expires them.
+fn reflog_entries(git_dir: &Path, dir: &Path, roots: &mut Vec<String>) -> anyhow::Result<()> {
+    let Result::Ok(entries) = fs::read_dir(dir) else {
+        return Ok(());
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            reflog_entries(git_dir, &path, roots)?;
+            continue;
+        }
+        for line in fs::read_to_string(&path)?.lines() {
+            for id in line.split(' ').take(2) {
+                let is_id = id.len() >= 40 && id.bytes().all(|x| x.is_ascii_hexdigit());
+                if is_id && id.bytes().any(|x| x != b'0') && Repository::open(git_dir).exists(id)? {
+                    roots.push(id.to_string());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Every loose object, by id, with its path and modification time.
+fn loose_objects(
+    repository: &Repository<FsBackend>,
+) -> anyhow::Result<HashMap<String, (PathBuf, SystemTime)>> {
+    let mut objects = HashMap::new();
+    for id in repository.loose_object_ids()? {
+        let path = repository.loose_path(&id);
+        let modified = fs::metadata(&path)?.modified()?;
+        objects.insert(id, (path, modified));
+    }
+    Ok(objects)
+}
@@ -0,0 +1,171 @@
+use crate::log::read_commit;
+use crate::CommitObject;
+use anyhow::anyhow;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::path::Path;
+
+/// Which side of a merge base search a commit was reached from.
+const FROM_A: u8 = 1;
+const FROM_B: u8 = 2;
+/// Reached from both sides through a common ancestor already found.
+const STALE: u8 = 4;
+/// Already taken as a candidate.
+const RESULT: u8 = 8;
+
+/// The commit graph of a repository, read lazily and kept in memory, so
+/// walks that meet the same commits again don't read them twice.
+///
+/// Each commit gets a generation number: 1 for root commits, otherwise one
+/// more than its highest parent's. A commit's ancestors all have lower
+/// generations, which lets walks stop early without trusting commit dates.
+pub struct CommitGraph<'a> {
+    git_dir: &'a Path,
+    commits: HashMap<String, CommitObject>,
+    generations: HashMap<String, u32>,
+}
+
+impl<'a> CommitGraph<'a> {
+    pub fn new(git_dir: &'a Path) -> CommitGraph<'a> {
+        CommitGraph {
+            git_dir,
+            commits: HashMap::new(),
+            generations: HashMap::new(),
+        }
+    }
+
+    pub fn commit(&mut self, id: &str) -> anyhow::Result<&CommitObject> {
+        if !self.commits.contains_key(id) {
+            let commit = read_commit(self.git_dir, id)?;
+            self.commits.insert(id.to_string(), commit);
+        }
+        Ok(&self.commits[id])
+    }
+
+    pub fn parents(&mut self, id: &str) -> anyhow::Result<Vec<String>> {
+        Ok(self.commit(id)?.parents.clone())
+    }
+
+    /// The generation number of `id`, computed for it and all its ancestors.
+    ///
+    /// Fails if the history has a cycle, which only a corrupt repository can have.
+    pub fn generation(&mut self, id: &str) -> anyhow::Result<u32> {
+        if let Some(&generation) = self.generations.get(id) {
+            return Ok(generation);
+        }
+        // (commit, whether its parents have been pushed already)
+        let mut stack = vec![(id.to_string(), false)];
+        let mut on_path = HashSet::new();
+        while let Some((commit_id, expanded)) = stack.pop() {
+            if self.generations.contains_key(&commit_id) {
+                continue;
+            }
+            let parents = self.parents(&commit_id)?;
+            if expanded {
+                let generation = parents
+                    .iter()
+                    .map(|x| self.generations[x])
+                    .max()
+                    .unwrap_or(0);
+                self.generations.insert(commit_id.clone(), generation + 1);
+                on_path.remove(&commit_id);
+                continue;
+            }
+            if !on_path.insert(commit_id.clone()) {
+                return Err(anyhow!(
+                    "Cycle detected: commit {} is its own ancestor.",
+                    commit_id
+                ));
+            }
+            stack.push((commit_id, true));
+            for parent in parents {
+                if on_path.contains(&parent) {
+                    return Err(anyhow!(
+                        "Cycle detected: commit {} is its own ancestor.",
+                        parent
+                    ));
+                }
+                if !self.generations.contains_key(&parent) {
+                    stack.push((parent, false));
+                }
+            }
+        }
+        Ok(self.generations[id])
+    }
+
+    /// Whether `descendant` is `ancestor` or descends from it.
+    pub fn is_ancestor(&mut self, ancestor: &str, descendant: &str) -> anyhow::Result<bool> {
+        let floor = self.generation(ancestor)?;
+        let mut seen = HashSet::new();
+        let mut pending = vec![descendant.to_string()];
+        while let Some(id) = pending.pop() {
+            if id == ancestor {
+                return Ok(true);
+            }
+            // Nothing below the ancestor's generation can lead back up to it.
+            if self.generation(&id)? <= floor || !seen.insert(id.clone()) {
+                continue;
+            }
+            pending.extend(self.parents(&id)?);
+        }
+        Ok(false)
+    }
+
+    /// The best common ancestors of `a` and `b`: the commits both descend
+    /// from that aren't ancestors of another such commit, highest generation
+    /// first. Empty when the histories are unrelated.
+    ///
+    /// Walks down from both commits at once, highest generation first, like
+    /// git's `paint_down_to_common`: a commit reached from both sides is a
+    /// candidate, and everything below it is only walked to mark it stale.
+    pub fn merge_bases(&mut self, a: &str, b: &str) -> anyhow::Result<Vec<String>> {
+        if a == b {
+            return Ok(vec![a.to_string()]);
+        }
+        let mut flags: HashMap<String, u8> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        for (id, side) in [(a, FROM_A), (b, FROM_B)] {
+            flags.insert(id.to_string(), side);
+            queue.push((self.generation(id)?, Reverse(id.to_string())));
+        }
+
+        let mut candidates = Vec::new();
+        while queue.iter().any(|(_, Reverse(id))| flags[id] & STALE == 0) {
+            let (_, Reverse(id)) = queue.pop().unwrap();
+            let mut flag = flags[&id] & (FROM_A | FROM_B | STALE);
+            if flag == FROM_A | FROM_B {
+                if flags[&id] & RESULT == 0 {
+                    *flags.get_mut(&id).unwrap() |= RESULT;
+                    candidates.push(id.clone());
+                }
+                flag |= STALE;
+            }
+            for parent in self.parents(&id)? {
+                let parent_flag = flags.entry(parent.clone()).or_default();
+                if *parent_flag & flag == flag {
+                    continue;
+                }
+                *parent_flag |= flag;
+                queue.push((self.generation(&parent)?, Reverse(parent)));
+            }
+        }
+
+        // Candidates reached from one found later are behind it, and one
+        // may still be an ancestor of another found through a different path.
+        candidates.retain(|x| flags[x] & STALE == 0);
+        let mut bases = Vec::new();
+        for (i, id) in candidates.iter().enumerate() {
+            let mut redundant = false;
+            for other in candidates.iter().enumerate().filter(|(j, _)| *j != i) {
+                if self.is_ancestor(id, other.1)? {
+                    redundant = true;
+                    break;
+                }
+            }
+            if !redundant {
+                bases.push(id.clone());
+            }
+        }
+        Ok(bases)
+    }
+}
@@ -0,0 +1,162 @@
+use crate::index::Index;
+use crate::status::flatten_tree;
+use crate::{git_path, leads_through_symlink, resolve_rev, ObjectType, Repository};
+use anyhow::Context;
+use regex::bytes::{Regex, RegexBuilder};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+/// How much of a file is looked at for a NUL byte, to tell binary files, as
+/// git does.
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// How `grep` matches and prints lines.
+pub struct GrepOptions {
+    pub line_number: bool,
+    pub ignore_case: bool,
+}
+
+/// Where `grep` looks.
+pub enum GrepSource<'a> {
+    /// The work tree files the index tracks.
+    WorkTree(&'a Path),
+    /// The blobs staged in the index.
+    Index,
+    /// The trees of these revisions.
+    Revs(&'a [String]),
+}
+
+/// Prints the lines of tracked content that match `pattern`, like
+/// `git grep`, as `<path>:<line>`, with the line number in between when
+/// asked for. Matches in a revision's tree are prefixed with the revision,
+/// and binary files only report that they match. Only files under `paths`
+/// are searched when any are given. Returns whether anything matched.
+pub fn grep(
+    git_dir: &Path,
+    source: GrepSource,
+    pattern: &str,
+    paths: &[String],
+    options: &GrepOptions,
+) -> anyhow::Result<bool> {
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(options.ignore_case)
+        .build()
+        .with_context(|| format!("Invalid pattern '{}'.", pattern))?;
+    let wanted = |path: &str| {
+        paths.is_empty()
+            || paths.iter().map(|x| x.trim_end_matches('/')).any(|x| {
+                x.is_empty()
+                    || path == x
+                    || path.strip_prefix(x).is_some_and(|x| x.starts_with('/'))
+            })
+    };
+    let stdout = std::io::stdout();
+    let mut out = std::io::BufWriter::new(stdout.lock());
+    let mut matched = false;
+
+    let repository = Repository::open(git_dir);
+    match source {
+        GrepSource::WorkTree(work_tree) => {
+            for path in tracked_paths(git_dir, false)?.keys() {
+                if !wanted(path) || leads_through_symlink(work_tree, path) {
+                    continue;
+                }
+                let full_path = work_tree.join(path);
+                let Result::Ok(metadata) = fs::symlink_metadata(&full_path) else {
+                    continue;
+                };
+                matched |= if metadata.file_type().is_symlink() {
+                    let target = fs::read_link(&full_path)?;
+                    let target = target.to_string_lossy().into_owned();
+                    search(path, target.as_bytes(), &regex, options, &mut out)?
+                } else if metadata.is_file() {
+                    let file = fs::File::open(&full_path)
+                        .with_context(|| format!("Read {}.", full_path.display()))?;
+                    search(path, file, &regex, options, &mut out)?
+                } else {
+                    false
+                };
+            }
+        }
+        GrepSource::Index => {
+            for (path, id) in tracked_paths(git_dir, true)? {
+                if wanted(&path) {
+                    let (_, _, reader) = repository.open_object(&id)?;
+                    matched |= search(&path, reader, &regex, options, &mut out)?;
+                }
+            }
+        }
+        GrepSource::Revs(revs) => {
+            for rev in revs {
+                let tree = repository.peel_to(&resolve_rev(git_dir, rev)?, ObjectType::Tree)?;
+                let mut entries = BTreeMap::new();
+                flatten_tree(git_dir, &tree, "", &mut entries)?;
+                let prefix = format!("{}:", rev);
+                for (path, (mode, id)) in entries {
+                    if mode == 0o160000 || !wanted(&path) {
+                        continue;
+                    }
+                    let (_, _, reader) = repository.open_object(&hex::encode(id))?;
+                    let name = format!("{}{}", prefix, path);
+                    matched |= search(&name, reader, &regex, options, &mut out)?;
+                }
+            }
+        }
+    }
+    out.flush()?;
+    Ok(matched)
+}
+
+/// The paths the index tracks, submodules aside, with their staged blob
+/// ids. `staged_only` leaves out paths that are unmerged, or only added
+/// with `add -N`, which have no blob to search.
+fn tracked_paths(git_dir: &Path, staged_only: bool) -> anyhow::Result<BTreeMap<String, String>> {
+    let index = Index::load(&git_path(git_dir, "index"))?;
+    Ok(index
+        .entries
+        .iter()
+        .filter(|x| x.mode != 0o160000)
+        .filter(|x| !staged_only || (x.stage() == 0 && !x.is_intent_to_add()))
+        .map(|x| (x.path.clone(), hex::encode(&x.hash)))
+        .collect())
+}
+
+/// Prints the matching lines of the content `reader` streams, read a line
+/// at a time, under `name`. Returns whether any line matched.
+fn search(
+    name: &str,
+    reader: impl Read,
+    regex: &Regex,
+    options: &GrepOptions,
+    out: &mut impl Write,
+) -> anyhow::Result<bool> {
+    let mut reader = BufReader::with_capacity(BINARY_CHECK_LEN, reader);
+    let binary = reader.fill_buf()?.contains(&0);
+    let mut matched = false;
+    let mut line = Vec::new();
+    let mut number = 0;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(matched);
+        }
+        number += 1;
+        let text = line.strip_suffix(b"\n").unwrap_or(&line);
+        if !regex.is_match(text) {
+            continue;
+        }
+        if binary {
+            writeln!(out, "Binary file {} matches", name)?;
+            return Ok(true);
+        }
+        matched = true;
+        write!(out, "{}:", name)?;
+        if options.line_number {
+            write!(out, "{}:", number)?;
+        }
+        out.write_all(text)?;
+        out.write_all(b"\n")?;
+    }
+}
@@ -0,0 +1,65 @@
+use crate::{common_dir, find_work_tree, Config};
+use anyhow::Context;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Runs the hook `name` if the repository has one, as git does: the
+/// executable of that name in the directory `core.hooksPath` names, or else
+/// in the git directory's `hooks/`. It runs from the top of the work tree
+/// (the git directory when bare) with `args` and `input` on its stdin; its
+/// output goes to stderr, so it can't be mistaken for the command's.
+///
+/// Returns whether the hook succeeded; a repository without the hook
+/// succeeds too. A hook file that isn't executable is skipped with a hint.
+pub fn run(git_dir: &Path, name: &str, args: &[&str], input: &[u8]) -> anyhow::Result<bool> {
+    let work_tree = find_work_tree().unwrap_or_else(|_| git_dir.to_path_buf());
+    let config = Config::load_all(git_dir)?;
+    let dir = match config.get("core.hooksPath") {
+        Some(path) => work_tree.join(path),
+        None => common_dir(git_dir).join("hooks"),
+    };
+    let path = dir.join(name);
+    if !path.is_file() {
+        return Ok(true);
+    }
+    if !is_executable(&path) {
+        eprintln!(
+            "hint: The '{}' hook was ignored because it's not set as executable.",
+            name
+        );
+        return Ok(true);
+    }
+
+    let mut child = Command::new(&path)
+        .args(args)
+        .current_dir(&work_tree)
+        .stdin(Stdio::piped())
+        .stdout(std::io::stderr())
+        .spawn()
+        .with_context(|| format!("Run the {} hook.", name))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // A hook that doesn't read its input may exit before taking all of it.
+    match stdin.write_all(input) {
+        Err(err) if err.kind() != std::io::ErrorKind::BrokenPipe => {
+            return Err(err).with_context(|| format!("Write to the {} hook.", name))
+        }
+        _ => drop(stdin),
+    }
+    let status = child
+        .wait()
+        .with_context(|| format!("Run the {} hook.", name))?;
+    Ok(status.success())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|x| x.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> bool {
+    true
+}
@@ -0,0 +1,360 @@
+use crate::credential::HttpAuth;
+use crate::log;
+use crate::pack::PackIndex;
+use crate::pktline::{Packet, PktLines};
+use crate::smart::AGENT;
+use crate::transport::{FetchStats, Transport};
+use crate::{git_path, loose_name, Repository};
+use crate::{progress, Progress};
+use anyhow::{anyhow, Context};
+use flate2::read::ZlibDecoder;
+use sha1::{Digest, Sha1};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// A repository served as plain files over HTTP (git's "dumb" protocol): refs
+/// are listed in `info/refs` and objects are fetched one by one by path.
+pub struct DumbHttp {
+    base_url: String,
+    agent: ureq::Agent,
+    auth: HttpAuth,
+}
+
+impl DumbHttp {
+    pub fn new(url: &str) -> DumbHttp {
+        let (url, auth) = HttpAuth::from_url(url);
+        DumbHttp {
+            base_url: url.trim_end_matches('/').to_string(),
+            agent: ureq::AgentBuilder::new()
+                .user_agent(concat!("codecrafters-git/", env!("CARGO_PKG_VERSION")))
+                .build(),
+            auth,
+        }
+    }
+
+    /// Downloads a file relative to the repository URL, or `None` on a 404.
+    #[tracing::instrument(level = "debug", skip(self))]
+    fn get(&self, path: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let url = format!("{}/{}", self.base_url, path);
+        let response = match self.auth.send(|| self.agent.get(&url), None) {
+            Result::Ok(response) => response,
+            Err(err) if matches!(*err, ureq::Error::Status(404, _)) => return Ok(None),
+            Err(err) => return Err(http_error("GET", &url, &self.base_url, err)),
+        };
+
+        let mut body = Vec::new();
+        response
+            .into_reader()
+            .read_to_end(&mut body)
+            .with_context(|| format!("GET {}", url))?;
+        tracing::trace!(size = body.len(), "downloaded");
+        Ok(Some(body))
+    }
+
+    /// Lists the advertised refs as `(name, object id)` from `info/refs`,
+    /// without the peeled `^{}` entries of annotated tags.
+    #[tracing::instrument(skip(self))]
+    pub fn refs(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let content = self.get("info/refs")?.ok_or_else(|| {
+            anyhow!(
+                "{}/info/refs not found; is this a git repository served over dumb HTTP?",
+                self.base_url
+            )
+        })?;
+
+        let mut refs = Vec::new();
+        for line in String::from_utf8_lossy(&content).lines() {
+            let Some((object_id, name)) = line.split_once('\t') else {
+                continue;
+            };
+            if !name.ends_with("^{}") {
+                refs.push((name.to_string(), object_id.to_string()));
+            }
+        }
+        Ok(refs)
+    }
+
+    /// The branch the remote `HEAD` points to, if it's a symbolic ref.
+    pub fn head(&self) -> anyhow::Result<Option<String>> {
+        let Some(content) = self.get("HEAD")? else {
+            return Ok(None);
+        };
+        Ok(String::from_utf8_lossy(&content)
+            .trim_end()
+            .strip_prefix("ref: ")
+            .map(|x| x.to_string()))
+    }
+
+    /// Downloads every object reachable from `wants`, walking commits, trees
+    /// and tags as they arrive.
+    ///
+    /// Objects are fetched loose when the server has them loose; otherwise the
+    /// pack holding them is found through `objects/info/packs` and downloaded
+    /// whole. Objects already stored locally are walked but not fetched again,
+    /// except for the history of `haves`, which isn't walked at all.
+    #[tracing::instrument(skip(self, git_dir))]
+    pub fn fetch(
+        &self,
+        git_dir: &Path,
+        wants: &[String],
+        haves: &[String],
+    ) -> anyhow::Result<FetchStats> {
+        let mut pending: Vec<String> = wants.to_vec();
+        let mut seen = complete_commits(git_dir, haves)?;
+        let mut remote_packs = None;
+        let mut stats = FetchStats::default();
+        let repository = Repository::open(git_dir);
+
+        while let Some(object_id) = pending.pop() {
+            if !seen.insert(object_id.clone()) {
+                continue;
+            }
+
+            if !repository.exists(&object_id)? {
+                if self.fetch_loose(git_dir, &object_id)? {
+                    stats.objects += 1;
+                } else {
+                    let packs = match &mut remote_packs {
+                        Some(packs) => packs,
+                        None => remote_packs.insert(self.pack_indexes()?),
+                    };
+                    if !self.fetch_pack_containing(git_dir, &object_id, packs)? {
+                        return Err(anyhow!("Object {} is missing on the remote.", object_id));
+                    }
+                    stats.packs += 1;
+                }
+            }
+
+            let (object_type, content) = repository.read(&object_id)?;
+            pending.extend(repository.linked_objects(object_type, &content)?);
+        }
+
+        Ok(stats)
+    }
+
+    /// Downloads a loose object, returning `false` if the server doesn't have it loose.
+    fn fetch_loose(&self, git_dir: &Path, object_id: &str) -> anyhow::Result<bool> {
+        let path = format!("objects/{}", loose_name(object_id));
+        let Some(compressed) = self.get(&path)? else {
+            return Ok(false);
+        };
+
+        let mut buffer = Vec::new();
+        ZlibDecoder::new(&compressed[..])
+            .read_to_end(&mut buffer)
+            .with_context(|| format!("Inflate object {}.", object_id))?;
+        if hex::encode(Sha1::digest(&buffer)) != object_id {
+            return Err(anyhow!("Object {} from the remote is corrupt.", object_id));
+        }
+
+        // The download is already in loose object form, so store it verbatim.
+        Repository::open(git_dir)
+            .write_compressed(object_id, &compressed)
+            .context("Write object file.")?;
+        Ok(true)
+    }
+
+    /// Downloads the index of every pack listed in `objects/info/packs`.
+    #[tracing::instrument(skip(self))]
+    fn pack_indexes(&self) -> anyhow::Result<Vec<RemotePack>> {
+        // No listing means the server has no packs (or never ran update-server-info).
+        let Some(listing) = self.get("objects/info/packs")? else {
+            return Ok(Vec::new());
+        };
+
+        let mut packs = Vec::new();
+        for line in String::from_utf8_lossy(&listing).lines() {
+            let Some(name) = line
+                .strip_prefix("P ")
+                .and_then(|x| x.strip_suffix(".pack"))
+            else {
+                continue;
+            };
+            let idx_path = format!("objects/pack/{}.idx", name);
+            let idx = self
+                .get(&idx_path)?
+                .ok_or_else(|| anyhow!("{} is listed but missing.", idx_path))?;
+            let index =
+                PackIndex::from_bytes(&idx).with_context(|| format!("Read {}.", idx_path))?;
+            packs.push(RemotePack {
+                name: name.to_string(),
+                idx,
+                index,
+            });
+        }
+        Ok(packs)
+    }
+
+    /// Downloads whichever of `packs` holds the object into `objects/pack`,
+    /// returning `false` if none does.
+    fn fetch_pack_containing(
+        &self,
+        git_dir: &Path,
+        object_id: &str,
+        packs: &mut Vec<RemotePack>,
+    ) -> anyhow::Result<bool> {
+        let Some(pos) = packs.iter().position(|x| x.index.find(object_id).is_some()) else {
+            return Ok(false);
+        };
+        let remote_pack = packs.remove(pos);
+
+        let pack_path = format!("objects/pack/{}.pack", remote_pack.name);
+        let pack = self
+            .get(&pack_path)?
+            .ok_or_else(|| anyhow!("{} is listed but missing.", pack_path))?;
+        // A pack ends with the SHA-1 of everything before it.
+        let (content, checksum) = pack.split_at(pack.len().saturating_sub(20));
+        if Sha1::digest(content)[..] != checksum[..] {
+            return Err(anyhow!("{} from the remote is corrupt.", pack_path));
+        }
+
+        // Write the index last: packs are only picked up once their index exists.
+        let pack_dir = git_path(git_dir, "objects/pack");
+        fs::create_dir_all(&pack_dir)?;
+        fs::write(pack_dir.join(format!("{}.pack", remote_pack.name)), &pack)?;
+        fs::write(
+            pack_dir.join(format!("{}.idx", remote_pack.name)),
+            &remote_pack.idx,
+        )?;
+        info!("Downloaded {}.pack", remote_pack.name);
+        Ok(true)
+    }
+}
+
+/// A smart protocol service over HTTP: the advertisement answers `GET
+/// info/refs?service=<service>`, and each request is a `POST` to
+/// `<service>` whose whole body is the response.
+pub struct HttpTransport {
+    base_url: String,
+    agent: ureq::Agent,
+    service: String,
+    advertisement: Vec<u8>,
+    auth: HttpAuth,
+}
+
+impl HttpTransport {
+    /// Asks for the advertisement of `service`, or returns `None` if the
+    /// server only serves plain files (a dumb server answers the request
+    /// with its `info/refs`, not an advertisement).
+    #[tracing::instrument]
+    pub fn connect(url: &str, service: &str) -> anyhow::Result<Option<HttpTransport>> {
+        let (url, auth) = HttpAuth::from_url(url);
+        let base_url = url.trim_end_matches('/').to_string();
+        let agent = ureq::AgentBuilder::new().user_agent(AGENT).build();
+
+        let url = format!("{}/info/refs?service={}", base_url, service);
+        let request = || {
+            let request = agent.get(&url);
+            // Pushing still speaks the original protocol.
+            match service {
+                "git-upload-pack" => request.set("Git-Protocol", "version=2"),
+                _ => request,
+            }
+        };
+        let response = match auth.send(request, None) {
+            Result::Ok(response) => response,
+            Err(err) if matches!(*err, ureq::Error::Status(404, _)) => return Ok(None),
+            Err(err) => return Err(http_error("GET", &url, &base_url, err)),
+        };
+        if response.content_type() != format!("application/x-{}-advertisement", service) {
+            return Ok(None);
+        }
+        let mut body = Vec::new();
+        response
+            .into_reader()
+            .read_to_end(&mut body)
+            .with_context(|| format!("GET {}", url))?;
+
+        // Over HTTP the advertisement may start with a `# service=` line,
+        // which ends with a flush of its own.
+        let mut lines = PktLines::new(&body);
+        let advertisement = match lines.next()? {
+            Some(Packet::Data(line)) if line.starts_with(b"# service=") => {
+                lines.next()?;
+                lines.rest().to_vec()
+            }
+            _ => body,
+        };
+        Ok(Some(HttpTransport {
+            base_url,
+            agent,
+            service: service.to_string(),
+            advertisement,
+            auth,
+        }))
+    }
+}
+
+impl Transport for HttpTransport {
+    fn url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn advertisement(&self) -> &[u8] {
+        &self.advertisement
+    }
+
+    fn request(&self, request: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let url = format!("{}/{}", self.base_url, self.service);
+        let post = || {
+            let post = self
+                .agent
+                .post(&url)
+                .set(
+                    "Content-Type",
+                    &format!("application/x-{}-request", self.service),
+                )
+                .set("Accept", &format!("application/x-{}-result", self.service));
+            match self.service.as_str() {
+                "git-upload-pack" => post.set("Git-Protocol", "version=2"),
+                _ => post,
+            }
+        };
+        let response = self
+            .auth
+            .send(post, Some(request))
+            .map_err(|err| http_error("POST", &url, &self.base_url, err))?;
+
+        let mut body = Vec::new();
+        let progress = match self.service.as_str() {
+            "git-upload-pack" => Progress::delayed("Receiving objects", None),
+            _ => Progress::disabled(),
+        };
+        let mut reader = progress::Reader::new(response.into_reader(), progress);
+        reader
+            .read_to_end(&mut body)
+            .with_context(|| format!("POST {}", url))?;
+        reader.finish();
+        Ok(body)
+    }
+}
+
+/// Describes a failed request to `url`; a 401 that survived asking for
+/// credentials reads as git's message, naming the repository at `base_url`.
+fn http_error(method: &str, url: &str, base_url: &str, err: Box<ureq::Error>) -> anyhow::Error {
+    match *err {
+        ureq::Error::Status(401, _) => anyhow!("Authentication failed for '{}/'", base_url),
+        err => anyhow!("{} {}: {}", method, url, err),
+    }
+}
+
+/// The commits reachable from `haves`, all of whose objects are stored.
+fn complete_commits(git_dir: &Path, haves: &[String]) -> anyhow::Result<HashSet<String>> {
+    let mut complete = HashSet::new();
+    let mut pending: Vec<String> = haves.to_vec();
+    while let Some(id) = pending.pop() {
+        if complete.insert(id.clone()) {
+            pending.extend(log::read_commit(git_dir, &id)?.parents);
+        }
+    }
+    Ok(complete)
+}
+
+/// A pack advertised by the server, with its already downloaded index.
+struct RemotePack {
+    name: String,
+    idx: Vec<u8>,
+    index: PackIndex,
+}
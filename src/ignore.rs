@@ -0,0 +1,170 @@
+use crate::platform;
+use crate::wildmatch::wildmatch;
+use std::fs;
+use std::path::Path;
+
+/// The ignore rules in effect for a directory of the work tree: those of
+/// `$GIT_DIR/info/exclude`, then of each `.gitignore` from the work tree root
+/// down to the directory. A later rule wins over an earlier one.
+///
+/// Unlike git, `core.excludesFile` isn't read.
+#[derive(Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+/// The rule that decided whether a path is ignored, as `check-ignore -v`
+/// reports it.
+pub struct IgnoreMatch {
+    /// The ignore file the rule is in, relative to the work tree.
+    pub source: String,
+    pub line: usize,
+    /// The pattern as written, with its `!` or trailing `/`.
+    pub pattern: String,
+    pub negated: bool,
+}
+
+#[derive(Clone)]
+struct Rule {
+    /// Directory of the ignore file, relative to the work tree ("" or "dir/").
+    base: String,
+    /// The ignore file, and the line the rule is on.
+    source: String,
+    line: usize,
+    text: String,
+    pattern: String,
+    /// `!pattern`: re-includes what an earlier rule ignored.
+    negated: bool,
+    /// `pattern/`: matches directories only.
+    dir_only: bool,
+}
+
+impl IgnoreRules {
+    /// The rules for the work tree root.
+    pub fn load(work_tree: &Path, git_dir: &Path) -> IgnoreRules {
+        let mut rules = IgnoreRules::default();
+        let exclude = git_dir.join("info").join("exclude");
+        let source = match exclude.strip_prefix(work_tree) {
+            Result::Ok(relative) => platform::tree_path(relative),
+            Err(_) => exclude.display().to_string(),
+        };
+        rules.read(&exclude, "", &source);
+        rules.read(&work_tree.join(".gitignore"), "", ".gitignore");
+        rules
+    }
+
+    /// The rules for `dir` (`/` terminated, relative to the work tree), a
+    /// subdirectory of the one these rules are for: these plus its own
+    /// `.gitignore`.
+    pub fn for_subdir(&self, work_tree: &Path, dir: &str) -> IgnoreRules {
+        let mut rules = self.clone();
+        let source = format!("{}.gitignore", dir);
+        rules.read(&work_tree.join(&source), dir, &source);
+        rules
+    }
+
+    /// Whether `path` (relative to the work tree) is ignored. Callers walking
+    /// the work tree don't descend into ignored directories, so, as in git, a
+    /// file can't be re-included once a directory above it is ignored.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        self.matching(path, is_dir).is_some_and(|x| !x.negated)
+    }
+
+    /// The last rule matching `path`, which decides whether it's ignored.
+    pub fn matching(&self, path: &str, is_dir: bool) -> Option<IgnoreMatch> {
+        let rule = self
+            .rules
+            .iter()
+            .rev()
+            .find(|x| (is_dir || !x.dir_only) && rule_matches(x, path))?;
+        Some(IgnoreMatch {
+            source: rule.source.clone(),
+            line: rule.line,
+            pattern: rule.text.clone(),
+            negated: rule.negated,
+        })
+    }
+
+    fn read(&mut self, path: &Path, base: &str, source: &str) {
+        let Some(content) = fs::read_to_string(path).ok() else {
+            return;
+        };
+
+        for (number, raw) in content.lines().enumerate() {
+            // Trailing spaces are dropped unless escaped with a backslash,
+            // which wildmatch then reads as a literal space.
+            let trimmed = raw.trim_end_matches(' ');
+            let line = match trimmed.len() < raw.len() && trimmed.ends_with('\\') {
+                true => &raw[..trimmed.len() + 1],
+                false => trimmed,
+            };
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let text = line;
+
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                // `\!` and `\#` stand for a leading `!` or `#`.
+                None if line.starts_with("\\!") || line.starts_with("\\#") => (false, &line[1..]),
+                None => (false, line),
+            };
+            let (dir_only, pattern) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            if pattern.is_empty() {
+                continue;
+            }
+
+            self.rules.push(Rule {
+                base: base.to_string(),
+                source: source.to_string(),
+                line: number + 1,
+                text: text.to_string(),
+                pattern: pattern.to_string(),
+                negated,
+                dir_only,
+            });
+        }
+    }
+}
+
+/// The rule deciding whether `path` (relative to the work tree) is ignored,
+/// if any: the one that ignores a directory above it, since nothing under an
+/// ignored directory can be re-included, or else the last one matching it.
+pub fn check_path(
+    work_tree: &Path,
+    git_dir: &Path,
+    path: &str,
+    is_dir: bool,
+) -> Option<IgnoreMatch> {
+    let mut rules = IgnoreRules::load(work_tree, git_dir);
+    let mut dir = String::new();
+    let mut components: Vec<&str> = path.split('/').collect();
+    components.pop();
+    for component in components {
+        dir.push_str(component);
+        if let Some(rule) = rules.matching(&dir, true).filter(|x| !x.negated) {
+            return Some(rule);
+        }
+        dir.push('/');
+        rules = rules.for_subdir(work_tree, &dir);
+    }
+    rules.matching(path, is_dir)
+}
+
+/// Patterns without a slash match the name at any depth; others match the
+/// path relative to the directory of the ignore file.
+fn rule_matches(rule: &Rule, path: &str) -> bool {
+    let Some(relative) = path.strip_prefix(&rule.base) else {
+        return false;
+    };
+
+    if rule.pattern.contains('/') {
+        wildmatch(rule.pattern.trim_start_matches('/'), relative)
+    } else {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        wildmatch(&rule.pattern, name)
+    }
+}
@@ -0,0 +1,703 @@
+use crate::{write_tree, LockFile, ObjectFormat, Repository};
+use anyhow::{anyhow, Context};
+use bytes::{Buf, BufMut};
+use std::fs;
+use std::path::Path;
+
+/// Size of the fixed part of an index entry, from ctime up to and including
+/// the flags, besides the object id.
+const ENTRY_FIXED_SIZE: usize = 42;
+
+/// Flag bit telling that a v3+ entry carries a second, extended flags field.
+const FLAG_EXTENDED: u16 = 0x4000;
+
+/// Extended flag of entries added with `add -N`, which aren't written into trees.
+const EXTENDED_FLAG_INTENT_TO_ADD: u16 = 0x2000;
+
+/// The staging area, as stored in `.git/index`.
+#[derive(Debug)]
+pub struct Index {
+    pub version: u32,
+    pub entries: Vec<IndexEntry>,
+    /// The TREE extension: tree ids of directories as of the last write-tree.
+    pub cache_tree: Option<CacheTree>,
+    /// The repository's object format, which sets the width of every id
+    /// and of the trailing checksum.
+    pub format: ObjectFormat,
+}
+
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct IndexEntry {
+    pub ctime_seconds: u32,
+    pub ctime_nanoseconds: u32,
+    pub mtime_seconds: u32,
+    pub mtime_nanoseconds: u32,
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub hash: Vec<u8>,
+    pub flags: u16,
+    pub extended_flags: u16,
+    pub path: String,
+}
+
+/// A node of the TREE extension.
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct CacheTree {
+    /// Directory name relative to its parent ("" for the root).
+    pub name: String,
+    /// Number of index entries covered, or -1 when the node was invalidated.
+    pub entry_count: i32,
+    /// Tree id, present only for valid nodes.
+    pub hash: Option<Vec<u8>>,
+    pub subtrees: Vec<CacheTree>,
+}
+
+impl IndexEntry {
+    /// Builds an entry without stat data, which is compared by content
+    /// rather than trusted to match the work tree.
+    pub fn new(path: &str, mode: u32, hash: Vec<u8>, stage: u16) -> IndexEntry {
+        IndexEntry {
+            ctime_seconds: 0,
+            ctime_nanoseconds: 0,
+            mtime_seconds: 0,
+            mtime_nanoseconds: 0,
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            hash,
+            flags: stage << 12,
+            extended_flags: 0,
+            path: path.to_string(),
+        }
+    }
+
+    /// Builds a stage 0 entry for a file just written to the work tree, taking
+    /// its stat data from `metadata` so later comparisons can skip rehashing it.
+    pub fn from_metadata(
+        path: &str,
+        mode: u32,
+        hash: Vec<u8>,
+        metadata: &fs::Metadata,
+    ) -> IndexEntry {
+        let mut entry = IndexEntry::new(path, mode, hash, 0);
+        // The index stores sizes truncated to 32 bits.
+        entry.size = metadata.len() as u32;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            entry.ctime_seconds = metadata.ctime() as u32;
+            entry.ctime_nanoseconds = metadata.ctime_nsec() as u32;
+            entry.mtime_seconds = metadata.mtime() as u32;
+            entry.mtime_nanoseconds = metadata.mtime_nsec() as u32;
+            entry.dev = metadata.dev() as u32;
+            entry.ino = metadata.ino() as u32;
+            entry.uid = metadata.uid();
+            entry.gid = metadata.gid();
+        }
+        // Elsewhere only the times are known; the creation time stands in
+        // for the change time, as in Git for Windows.
+        #[cfg(not(unix))]
+        {
+            let seconds = |time: std::io::Result<std::time::SystemTime>| {
+                time.ok()
+                    .and_then(|x| x.duration_since(std::time::UNIX_EPOCH).ok())
+                    .unwrap_or_default()
+            };
+            let modified = seconds(metadata.modified());
+            let created = seconds(metadata.created());
+            entry.ctime_seconds = created.as_secs() as u32;
+            entry.ctime_nanoseconds = created.subsec_nanos();
+            entry.mtime_seconds = modified.as_secs() as u32;
+            entry.mtime_nanoseconds = modified.subsec_nanos();
+        }
+
+        entry
+    }
+
+    /// Merge stage: 0 for normal entries, 1-3 for the sides of a conflict.
+    pub fn stage(&self) -> u16 {
+        (self.flags >> 12) & 0x3
+    }
+
+    pub fn is_intent_to_add(&self) -> bool {
+        self.extended_flags & EXTENDED_FLAG_INTENT_TO_ADD != 0
+    }
+}
+
+impl CacheTree {
+    fn is_valid(&self) -> bool {
+        self.entry_count >= 0 && self.hash.is_some()
+    }
+
+    /// Marks this node and every node on the way to `path` as out of date.
+    fn invalidate(&mut self, path: &str) {
+        self.entry_count = -1;
+        self.hash = None;
+
+        if let Some((dir, rest)) = path.split_once('/') {
+            if let Some(subtree) = self.subtrees.iter_mut().find(|x| x.name == dir) {
+                subtree.invalidate(rest);
+            }
+        }
+    }
+}
+
+impl Index {
+    /// Reads the index file, returning an empty index if it doesn't exist yet.
+    /// The file sits in the git directory it belongs to, whose object format
+    /// it is written in.
+    pub fn load(path: &Path) -> anyhow::Result<Index> {
+        let git_dir = path.parent().unwrap_or(Path::new("."));
+        let format = ObjectFormat::of(git_dir)?;
+        if !path.exists() {
+            return Ok(Index {
+                version: 2,
+                entries: Vec::new(),
+                cache_tree: None,
+                format,
+            });
+        }
+
+        let data = fs::read(path).context("Read index file.")?;
+        Index::from_bytes(&data, format)
+    }
+
+    pub fn from_bytes(input: &[u8], format: ObjectFormat) -> anyhow::Result<Index> {
+        let id_len = format.raw_len();
+        if input.len() < 12 + id_len {
+            return Err(anyhow!("Index file is too short."));
+        }
+
+        // The file ends with a hash, in the object format, of everything before it.
+        let (content, checksum) = input.split_at(input.len() - id_len);
+        if format.digest(content)[..] != checksum[..] {
+            return Err(anyhow!("Index file checksum mismatch."));
+        }
+
+        let mut buf = content;
+        if &buf[..4] != b"DIRC" {
+            return Err(anyhow!("Index file has an invalid signature."));
+        }
+        buf.advance(4);
+
+        let version = buf.get_u32();
+        if !(2..=4).contains(&version) {
+            return Err(anyhow!("Unsupported index version {}.", version));
+        }
+        let entry_count = buf.get_u32();
+
+        let mut entries = Vec::with_capacity(entry_count as usize);
+        let mut previous_path = String::new();
+        for _ in 0..entry_count {
+            let entry = read_entry(&mut buf, version, &previous_path, id_len)?;
+            previous_path.clone_from(&entry.path);
+            entries.push(entry);
+        }
+
+        // Extensions: a 4-byte signature, a 4-byte size, then the data.
+        let mut cache_tree = None;
+        while buf.remaining() >= 8 {
+            let signature: [u8; 4] = buf[..4].try_into().unwrap();
+            buf.advance(4);
+            let size = buf.get_u32() as usize;
+            if buf.remaining() < size {
+                return Err(anyhow!("Index extension is truncated."));
+            }
+            let (data, rest) = buf.split_at(size);
+
+            match &signature {
+                b"TREE" => cache_tree = Some(read_cache_tree(data, id_len)?),
+                // Extensions starting with an uppercase letter are optional and can be skipped.
+                signature if signature[0].is_ascii_uppercase() => {}
+                signature => {
+                    return Err(anyhow!(
+                        "Index uses unsupported extension {}.",
+                        String::from_utf8_lossy(signature)
+                    ))
+                }
+            }
+
+            buf = rest;
+        }
+
+        if buf.has_remaining() {
+            return Err(anyhow!("Index file has trailing garbage."));
+        }
+
+        Ok(Index {
+            version,
+            entries,
+            cache_tree,
+            format,
+        })
+    }
+
+    /// Writes the index, with its TREE extension and trailing checksum.
+    ///
+    /// The file is written through `index.lock` and renamed into place, so
+    /// readers never see a partial index and a concurrent writer fails.
+    ///
+    /// Entries whose file was modified in the second the index is written
+    /// are racily clean: the file could still change without its stat data
+    /// showing it. Like git, their size is written as 0, so they don't match
+    /// the work tree and are compared by content until refreshed later.
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let lock = LockFile::acquire(path)?;
+        let needs_extended = self.entries.iter().any(|x| x.extended_flags != 0);
+        let version = if needs_extended {
+            self.version.max(3)
+        } else {
+            self.version
+        };
+
+        let mut content: Vec<u8> = Vec::new();
+        content.put_slice(b"DIRC");
+        content.put_u32(version);
+        content.put_u32(self.entries.len() as u32);
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs() as u32);
+        let mut previous_path = "";
+        for entry in &self.entries {
+            let racy = entry.mtime_seconds >= now;
+            write_entry(&mut content, entry, version, previous_path, racy);
+            previous_path = &entry.path;
+        }
+
+        if let Some(cache_tree) = &self.cache_tree {
+            let mut data = Vec::new();
+            write_cache_tree_node(&mut data, cache_tree);
+            content.put_slice(b"TREE");
+            content.put_u32(data.len() as u32);
+            content.put_slice(&data);
+        }
+
+        let checksum = self.format.digest(&content);
+        content.put_slice(&checksum);
+
+        lock.commit(&content).context("Write index file.")
+    }
+
+    /// Drops the cached tree ids of the directories containing `path`.
+    ///
+    /// Must be called whenever an entry is added, changed or removed.
+    pub fn invalidate_path(&mut self, path: &str) {
+        if let Some(cache_tree) = &mut self.cache_tree {
+            cache_tree.invalidate(path);
+        }
+    }
+
+    /// Stages `entry` in place of every entry for its path, conflict stages
+    /// included, keeping the entries sorted.
+    ///
+    /// A path can't be both a file and a directory in a tree, so an entry whose
+    /// path is a directory of another entry, or the other way around, is refused.
+    pub fn add(&mut self, entry: IndexEntry) -> anyhow::Result<()> {
+        // Sorted, the entries under `path/` come right where it would go, and
+        // only the directories above `path` could be files already.
+        let as_dir = format!("{}/", entry.path);
+        let below = self
+            .entries
+            .get(self.position(&as_dir))
+            .filter(|x| x.path.starts_with(&as_dir));
+        let above = entry
+            .path
+            .match_indices('/')
+            .map(|(i, _)| &entry.path[..i])
+            .find_map(|dir| {
+                self.entries
+                    .get(self.position(dir))
+                    .filter(|x| x.path == dir)
+            });
+        if let Some(conflict) = below.or(above) {
+            return Err(anyhow!(
+                "'{}' appears as both a file and as a directory (conflicts with '{}').",
+                entry.path,
+                conflict.path
+            ));
+        }
+
+        self.invalidate_path(&entry.path);
+        let range = self.range(&entry.path);
+        self.entries.splice(range, [entry]);
+        Ok(())
+    }
+
+    /// The mode `path` is staged with, if it is, leaving out conflict stages.
+    pub fn mode_of(&self, path: &str) -> Option<u32> {
+        self.entries[self.range(path)]
+            .iter()
+            .find(|x| x.stage() == 0)
+            .map(|x| x.mode)
+    }
+
+    /// Unstages every entry for `path`, returning whether there was one.
+    pub fn remove(&mut self, path: &str) -> bool {
+        let range = self.range(path);
+        let removed = !range.is_empty();
+        self.entries.drain(range);
+        self.invalidate_path(path);
+        removed
+    }
+
+    /// Where entries for `path` are, or would go, in the sorted entries.
+    fn position(&self, path: &str) -> usize {
+        self.entries
+            .partition_point(|x| x.path.as_bytes() < path.as_bytes())
+    }
+
+    /// The entries for `path`, one per stage.
+    fn range(&self, path: &str) -> std::ops::Range<usize> {
+        let start = self.position(path);
+        let len = self.entries[start..]
+            .iter()
+            .take_while(|x| x.path == path)
+            .count();
+        start..start + len
+    }
+
+    /// Writes tree objects for the staged entries and returns the root tree id.
+    ///
+    /// Directories whose TREE extension node is still valid reuse the cached tree
+    /// id instead of being serialized again, and the extension is refreshed so the
+    /// next call stays fast.
+    pub fn write_tree(&mut self, git_dir: &Path) -> anyhow::Result<String> {
+        if let Some(entry) = self.entries.iter().find(|x| x.stage() != 0) {
+            return Err(anyhow!(
+                "{}: unmerged (stage {})",
+                entry.path,
+                entry.stage()
+            ));
+        }
+
+        let (hash, cache_tree) =
+            write_tree_level(git_dir, &self.entries, "", "", self.cache_tree.as_ref())?;
+        self.cache_tree = Some(cache_tree);
+
+        Ok(hex::encode(hash))
+    }
+}
+
+/// Writes the tree for `entries`, which all live under `prefix`, returning its id
+/// and the TREE extension node describing it.
+fn write_tree_level(
+    git_dir: &Path,
+    entries: &[IndexEntry],
+    prefix: &str,
+    name: &str,
+    cache: Option<&CacheTree>,
+) -> anyhow::Result<(Vec<u8>, CacheTree)> {
+    if let Some(cache) = cache {
+        let hash = cache.hash.as_ref().map(hex::encode).unwrap_or_default();
+        if cache.is_valid()
+            && cache.entry_count as usize == entries.len()
+            && Repository::open(git_dir).exists(&hash)?
+        {
+            return Ok((cache.hash.clone().unwrap(), cache.clone()));
+        }
+    }
+
+    let mut tree_entries: Vec<(String, String, Vec<u8>)> = Vec::new();
+    let mut subtrees = Vec::new();
+    let mut valid = true;
+
+    let mut i = 0;
+    while i < entries.len() {
+        let entry = &entries[i];
+        let relative = &entry.path[prefix.len()..];
+
+        match relative.split_once('/') {
+            Some((dir, _)) => {
+                // Index entries are sorted, so a directory's entries are contiguous.
+                let sub_prefix = format!("{}{}/", prefix, dir);
+                let count = entries[i..]
+                    .iter()
+                    .take_while(|x| x.path.starts_with(&sub_prefix))
+                    .count();
+                let sub_cache = cache.and_then(|x| x.subtrees.iter().find(|y| y.name == dir));
+
+                let (hash, node) =
+                    write_tree_level(git_dir, &entries[i..i + count], &sub_prefix, dir, sub_cache)?;
+                valid &= node.is_valid();
+                tree_entries.push(("40000".to_string(), dir.to_string(), hash));
+                subtrees.push(node);
+                i += count;
+            }
+            None => {
+                if entry.is_intent_to_add() {
+                    valid = false;
+                } else {
+                    tree_entries.push((
+                        format!("{:o}", entry.mode),
+                        relative.to_string(),
+                        entry.hash.clone(),
+                    ));
+                }
+                i += 1;
+            }
+        }
+    }
+
+    let hash = write_tree(git_dir, &tree_entries)?;
+    let hash = hex::decode(hash)?;
+
+    let node = CacheTree {
+        name: name.to_string(),
+        entry_count: if valid { entries.len() as i32 } else { -1 },
+        hash: valid.then(|| hash.clone()),
+        subtrees,
+    };
+
+    Ok((hash, node))
+}
+
+fn write_entry(
+    content: &mut Vec<u8>,
+    entry: &IndexEntry,
+    version: u32,
+    previous_path: &str,
+    smudge: bool,
+) {
+    let start_len = content.len();
+
+    content.put_u32(entry.ctime_seconds);
+    content.put_u32(entry.ctime_nanoseconds);
+    content.put_u32(entry.mtime_seconds);
+    content.put_u32(entry.mtime_nanoseconds);
+    content.put_u32(entry.dev);
+    content.put_u32(entry.ino);
+    content.put_u32(entry.mode);
+    content.put_u32(entry.uid);
+    content.put_u32(entry.gid);
+    content.put_u32(if smudge { 0 } else { entry.size });
+    content.put_slice(&entry.hash);
+
+    // Keep the assume-valid and stage bits; the name length saturates at 0xFFF.
+    let extended = version >= 3 && entry.extended_flags != 0;
+    let mut flags = (entry.flags & 0xB000) | entry.path.len().min(0xFFF) as u16;
+    if extended {
+        flags |= FLAG_EXTENDED;
+    }
+    content.put_u16(flags);
+    if extended {
+        content.put_u16(entry.extended_flags);
+    }
+
+    if version >= 4 {
+        // Number of bytes to drop from the end of the previous path, then the new suffix.
+        let common = entry
+            .path
+            .bytes()
+            .zip(previous_path.bytes())
+            .take_while(|(a, b)| a == b)
+            .count();
+        write_offset_varint(content, (previous_path.len() - common) as u64);
+        content.put_slice(&entry.path.as_bytes()[common..]);
+        content.put_u8(0);
+        return;
+    }
+
+    content.put_slice(entry.path.as_bytes());
+
+    // Pad with 1-8 null bytes to a multiple of 8.
+    let entry_len = content.len() - start_len;
+    let padded_len = (entry_len + 8) & !7;
+    content.put_bytes(0, padded_len - entry_len);
+}
+
+/// Writes git's offset varint, where each continuation step also adds one so
+/// every value has a single encoding.
+fn write_offset_varint(content: &mut Vec<u8>, mut value: u64) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value > 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    bytes.reverse();
+    content.put_slice(&bytes);
+}
+
+fn read_offset_varint(buf: &mut &[u8]) -> anyhow::Result<u64> {
+    let mut next = || -> anyhow::Result<u8> {
+        if !buf.has_remaining() {
+            return Err(anyhow!("Index entry is truncated."));
+        }
+        Ok(buf.get_u8())
+    };
+
+    let mut byte = next()?;
+    let mut value = (byte & 0x7f) as u64;
+    while byte & 0x80 != 0 {
+        byte = next()?;
+        value = ((value + 1) << 7) | (byte & 0x7f) as u64;
+    }
+    Ok(value)
+}
+
+fn write_cache_tree_node(data: &mut Vec<u8>, node: &CacheTree) {
+    data.put_slice(node.name.as_bytes());
+    data.put_u8(0);
+    data.put_slice(format!("{} {}\n", node.entry_count, node.subtrees.len()).as_bytes());
+    if let Some(hash) = &node.hash {
+        data.put_slice(hash);
+    }
+    for subtree in &node.subtrees {
+        write_cache_tree_node(data, subtree);
+    }
+}
+
+fn read_entry(
+    buf: &mut &[u8],
+    version: u32,
+    previous_path: &str,
+    id_len: usize,
+) -> anyhow::Result<IndexEntry> {
+    let start_len = buf.remaining();
+    if start_len < ENTRY_FIXED_SIZE + id_len {
+        return Err(anyhow!("Index entry is truncated."));
+    }
+
+    let ctime_seconds = buf.get_u32();
+    let ctime_nanoseconds = buf.get_u32();
+    let mtime_seconds = buf.get_u32();
+    let mtime_nanoseconds = buf.get_u32();
+    let dev = buf.get_u32();
+    let ino = buf.get_u32();
+    let mode = buf.get_u32();
+    let uid = buf.get_u32();
+    let gid = buf.get_u32();
+    let size = buf.get_u32();
+    let hash = buf[..id_len].to_vec();
+    buf.advance(id_len);
+    let flags = buf.get_u16();
+
+    let extended_flags = if version >= 3 && flags & FLAG_EXTENDED != 0 {
+        if buf.remaining() < 2 {
+            return Err(anyhow!("Index entry is truncated."));
+        }
+        buf.get_u16()
+    } else {
+        0
+    };
+
+    // Version 4 prefix-compresses paths: strip some bytes from the end of the
+    // previous entry's path, then append a suffix. There is no padding.
+    let strip_len = if version >= 4 {
+        let strip_len = read_offset_varint(buf)? as usize;
+        if strip_len > previous_path.len() {
+            return Err(anyhow!("Index entry path compression is corrupt."));
+        }
+        Some(strip_len)
+    } else {
+        None
+    };
+
+    let path_len = buf
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or_else(|| anyhow!("Index entry path is not terminated."))?;
+    let mut path_bytes = Vec::new();
+    if let Some(strip_len) = strip_len {
+        path_bytes.extend_from_slice(&previous_path.as_bytes()[..previous_path.len() - strip_len]);
+    }
+    path_bytes.extend_from_slice(&buf[..path_len]);
+    let path = String::from_utf8(path_bytes)?;
+
+    if strip_len.is_some() {
+        buf.advance(path_len + 1);
+    } else {
+        // Entries are padded with 1-8 null bytes to a multiple of 8 bytes.
+        let entry_len = start_len - buf.remaining() + path_len;
+        let padded_len = (entry_len + 8) & !7;
+        let skip = padded_len - (start_len - buf.remaining());
+        if buf.remaining() < skip {
+            return Err(anyhow!("Index entry is truncated."));
+        }
+        buf.advance(skip);
+    }
+
+    Ok(IndexEntry {
+        ctime_seconds,
+        ctime_nanoseconds,
+        mtime_seconds,
+        mtime_nanoseconds,
+        dev,
+        ino,
+        mode,
+        uid,
+        gid,
+        size,
+        hash,
+        flags,
+        extended_flags,
+        path,
+    })
+}
+
+/// Parses the TREE extension, which stores the nodes in pre-order:
+/// `<name>\0<entry count> <subtree count>\n[<id>]`, the id `id_len` bytes.
+fn read_cache_tree(data: &[u8], id_len: usize) -> anyhow::Result<CacheTree> {
+    let mut buf = data;
+    let tree = read_cache_tree_node(&mut buf, id_len)?;
+    if buf.has_remaining() {
+        return Err(anyhow!("Index TREE extension has trailing data."));
+    }
+    Ok(tree)
+}
+
+fn read_cache_tree_node(buf: &mut &[u8], id_len: usize) -> anyhow::Result<CacheTree> {
+    let name_len = buf
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or_else(|| anyhow!("Index TREE extension is truncated."))?;
+    let name = String::from_utf8(buf[..name_len].to_vec())?;
+    buf.advance(name_len + 1);
+
+    let line_len = buf
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .ok_or_else(|| anyhow!("Index TREE extension is truncated."))?;
+    let line = std::str::from_utf8(&buf[..line_len])?;
+    let (entry_count, subtree_count) = line
+        .split_once(' ')
+        .ok_or_else(|| anyhow!("Index TREE extension has a malformed node."))?;
+    let entry_count: i32 = entry_count.parse()?;
+    let subtree_count: usize = subtree_count.parse()?;
+    buf.advance(line_len + 1);
+
+    let hash = if entry_count >= 0 {
+        if buf.remaining() < id_len {
+            return Err(anyhow!("Index TREE extension is truncated."));
+        }
+        let hash = buf[..id_len].to_vec();
+        buf.advance(id_len);
+        Some(hash)
+    } else {
+        None
+    };
+
+    let mut subtrees = Vec::with_capacity(subtree_count);
+    for _ in 0..subtree_count {
+        subtrees.push(read_cache_tree_node(buf, id_len)?);
+    }
+
+    Ok(CacheTree {
+        name,
+        entry_count,
+        hash,
+        subtrees,
+    })
+}
@@ -0,0 +1,52 @@
+//! The object store, refs and repository layout, shared by the
+//! `codecrafters-git` binary and its benchmarks.
+//!
+//! [`odb::Repository`] reads, writes and iterates objects, loose or packed,
+//! [`object`] parses and serializes them, and
+//! [`refs`] resolves and updates refs, whether they are loose files,
+//! `packed-refs` entries or reftables.
+//!
+//! [`odb`] and [`object`] fail with [`error::GitError`] through
+//! [`error::Result`], so callers can tell a missing object from a corrupt
+//! one. The other modules, refs, packs and config among them, return
+//! [`anyhow::Result`], with context naming what failed.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub mod config;
+pub mod error;
+pub mod lockfile;
+pub mod object;
+pub mod odb;
+pub mod pack;
+pub mod parallel;
+pub mod progress;
+pub mod refs;
+pub mod reftable;
+
+/// Returns the directory shared by all worktrees of a repository.
+///
+/// A linked worktree's git directory (`.git/worktrees/<name>`) contains a
+/// `commondir` file pointing back at the main git directory.
+pub fn common_dir(git_dir: &Path) -> PathBuf {
+    match fs::read_to_string(git_dir.join("commondir")).ok() {
+        Some(content) => git_dir.join(content.trim_end()),
+        None => git_dir.to_path_buf(),
+    }
+}
+
+/// Resolves a path inside the git directory, taking the split between
+/// per-worktree files (HEAD, index) and shared files (objects, refs) into account.
+pub fn git_path(git_dir: &Path, name: &str) -> PathBuf {
+    let per_worktree = matches!(name, "HEAD" | "index" | "logs/HEAD")
+        || name.starts_with("refs/bisect/")
+        || name.starts_with("refs/worktree/")
+        || name.starts_with("refs/rewritten/");
+
+    if per_worktree {
+        git_dir.join(name)
+    } else {
+        common_dir(git_dir).join(name)
+    }
+}
@@ -0,0 +1,105 @@
+use crate::pack_objects::missing_objects;
+use crate::transport::FetchStats;
+use crate::{git_dir_at, list_refs, pack, read_symref, shallow, Repository};
+use anyhow::{anyhow, Context};
+use std::path::{Path, PathBuf};
+
+/// A repository on this machine, named by its path, whose objects are
+/// copied straight out of its object store.
+pub struct LocalRepository {
+    git_dir: PathBuf,
+}
+
+/// The path `url` names if it's an existing path rather than a URL. Like
+/// git, a directory that exists is taken for one even when it looks like
+/// `host:path`.
+pub fn local_path(url: &str) -> Option<PathBuf> {
+    let path = Path::new(url);
+    (!url.contains("://") && path.exists()).then(|| path.to_path_buf())
+}
+
+/// The git directory of the repository at `path`: its `.git` when it has a
+/// work tree, or the path itself when it's bare.
+pub fn git_dir_of(path: &Path) -> anyhow::Result<PathBuf> {
+    match git_dir_at(path) {
+        Result::Ok(git_dir) => Ok(git_dir),
+        Err(_) if path.join("objects").is_dir() && path.join("HEAD").is_file() => {
+            Ok(path.to_path_buf())
+        }
+        Err(_) => Err(anyhow!(
+            "'{}' does not appear to be a git repository.",
+            path.display()
+        )),
+    }
+}
+
+impl LocalRepository {
+    pub fn open(path: &Path) -> anyhow::Result<LocalRepository> {
+        Ok(LocalRepository {
+            git_dir: git_dir_of(path)?,
+        })
+    }
+
+    /// The repository's branches and tags as `(name, object id)`.
+    pub fn refs(&self) -> anyhow::Result<Vec<(String, String)>> {
+        Ok(list_refs(&self.git_dir)?
+            .into_iter()
+            .filter(|(name, _)| name.starts_with("refs/heads/") || name.starts_with("refs/tags/"))
+            .collect())
+    }
+
+    /// The branch `HEAD` points to.
+    pub fn head(&self) -> anyhow::Result<Option<String>> {
+        read_symref(&self.git_dir, "HEAD")
+    }
+
+    /// Copies the objects reachable from `wants` but not from `haves` into
+    /// `git_dir` as one pack. Commits that are shallow in the repository stay
+    /// shallow in the copy.
+    pub fn fetch(
+        &self,
+        git_dir: &Path,
+        wants: &[String],
+        haves: &[String],
+    ) -> anyhow::Result<FetchStats> {
+        pack::require_sha1(&self.git_dir)?;
+        let mut wanted = Vec::new();
+        for want in wants {
+            if !wanted.contains(want) && !Repository::open(git_dir).exists(want)? {
+                wanted.push(want.clone());
+            }
+        }
+        if wanted.is_empty() {
+            return Ok(FetchStats::default());
+        }
+        let mut known = Vec::new();
+        for have in haves {
+            if Repository::open(&self.git_dir).exists(have)? {
+                known.push(have.clone());
+            }
+        }
+
+        let objects = missing_objects(&self.git_dir, &wanted, &known)?;
+        let mut data = Vec::new();
+        pack::write_pack(&self.git_dir, &objects, &mut data)?;
+        let name = pack::store_pack(git_dir, &data).context("Index the copied pack.")?;
+        info!("Copied {} objects into {}.pack", objects.len(), name);
+
+        let source_shallow = shallow::read(&self.git_dir)?;
+        let mut shallow = shallow::read(git_dir)?;
+        let count = shallow.len();
+        shallow.extend(
+            objects
+                .iter()
+                .filter(|x| source_shallow.contains(*x))
+                .cloned(),
+        );
+        if shallow.len() != count {
+            shallow::write(git_dir, &shallow)?;
+        }
+        Ok(FetchStats {
+            objects: 0,
+            packs: 1,
+        })
+    }
+}
@@ -0,0 +1,74 @@
+//! Lock files, through which refs, the index and config files are replaced
+//! as git replaces them.
+
+use anyhow::{anyhow, Context};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A file locked for replacing: the new content is written to
+/// `<file>.lock`, created exclusively so a concurrent writer fails instead
+/// of racing, synced to disk, and renamed over the file, so readers never
+/// see a torn one. Dropped uncommitted, the lock is removed and the file
+/// left as it was.
+pub struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<fs::File>,
+}
+
+impl LockFile {
+    /// Takes the lock on `path`, failing if another process holds it.
+    pub fn acquire(path: &Path) -> anyhow::Result<LockFile> {
+        let mut lock_path = path.as_os_str().to_os_string();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+        let file = match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Result::Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                return Err(anyhow!(
+                    "Unable to create '{}': File exists. Another process seems to be \
+                     updating {}; if none is, remove the lock file.",
+                    lock_path.display(),
+                    path.display()
+                ))
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("Create {}.", lock_path.display()))
+            }
+        };
+        Ok(LockFile {
+            path: path.to_path_buf(),
+            lock_path,
+            file: Some(file),
+        })
+    }
+
+    /// The file the lock is for.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replaces the file with `content` and releases the lock.
+    pub fn commit(mut self, content: &[u8]) -> anyhow::Result<()> {
+        let mut file = self.file.take().expect("lock is held until committed");
+        file.write_all(content)
+            .and_then(|_| file.sync_all())
+            .with_context(|| format!("Write {}.", self.lock_path.display()))?;
+        drop(file);
+        fs::rename(&self.lock_path, &self.path)
+            .with_context(|| format!("Replace {}.", self.path.display()))
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if self.file.is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
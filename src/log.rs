@@ -0,0 +1,233 @@
+use crate::date::{format_date, signature_time};
+use crate::graph::CommitGraph;
+use crate::shallow;
+use crate::{short_id, CommitObject, ObjectType, Repository};
+use anyhow::anyhow;
+use regex::Regex;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::path::Path;
+
+/// Limits on which walked commits are shown. Commits that don't match are
+/// skipped, but the walk still continues through them to their parents.
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Follow only the first parent of merges, so merged-in branches are left out.
+    pub first_parent: bool,
+    /// Committer dates, in Unix seconds.
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    /// Author `Name <email>` must match one of these, if any are given.
+    pub authors: Vec<Regex>,
+    /// The message must match one of these, if any are given...
+    pub greps: Vec<Regex>,
+    /// ...or all of them when set.
+    pub all_match: bool,
+}
+
+impl Filter {
+    fn matches(&self, time: i64, commit: &CommitObject) -> bool {
+        if self.since.is_some_and(|since| time < since)
+            || self.until.is_some_and(|until| time > until)
+        {
+            return false;
+        }
+
+        let author = commit
+            .author
+            .rsplitn(3, ' ')
+            .nth(2)
+            .unwrap_or(&commit.author);
+        if !self.authors.is_empty() && !self.authors.iter().any(|x| x.is_match(author)) {
+            return false;
+        }
+
+        let mut greps = self.greps.iter();
+        match self.all_match {
+            true => greps.all(|x| x.is_match(&commit.message)),
+            false => self.greps.is_empty() || greps.any(|x| x.is_match(&commit.message)),
+        }
+    }
+}
+
+/// Walks the commits reachable from `start`, newest committer date first.
+///
+/// Fails if the history has a cycle, which only a corrupt repository can have.
+pub fn walk(
+    git_dir: &Path,
+    start: &str,
+    filter: &Filter,
+) -> anyhow::Result<Vec<(String, CommitObject)>> {
+    walk_range(git_dir, &[start.to_string()], &[], filter)
+}
+
+/// Walks the commits reachable from any of `starts` but from none of
+/// `excluded`, newest committer date first, as `git rev-list <starts>
+/// --not <excluded>` does.
+#[tracing::instrument(skip(git_dir, filter))]
+pub fn walk_range(
+    git_dir: &Path,
+    starts: &[String],
+    excluded: &[String],
+    filter: &Filter,
+) -> anyhow::Result<Vec<(String, CommitObject)>> {
+    // Only a corrupt repository has a cycle, which would leave no order to
+    // walk in.
+    let mut graph = CommitGraph::new(git_dir);
+    for start in starts {
+        graph.generation(start)?;
+    }
+
+    // Everything the excluded commits reach is left out, whichever parents
+    // the walk follows.
+    let mut seen = HashSet::new();
+    let mut pending = excluded.to_vec();
+    while let Some(id) = pending.pop() {
+        if seen.insert(id.clone()) {
+            pending.extend(graph.parents(&id)?);
+        }
+    }
+
+    let mut commits = Vec::new();
+    // The newest pending commit comes out first; as in git, commits with the
+    // same date come out in the order they were queued, so a merge's parents
+    // keep their order.
+    let mut queue = BinaryHeap::new();
+    let mut queued = 0;
+
+    for start in starts {
+        if seen.insert(start.clone()) {
+            queued += 1;
+            let time = committer_time(graph.commit(start)?);
+            queue.push((time, Reverse(queued), start.clone()));
+        }
+    }
+
+    while let Some((time, _, id)) = queue.pop() {
+        let commit = graph.commit(&id)?.clone();
+        let parents = match filter.first_parent {
+            true => &commit.parents[..commit.parents.len().min(1)],
+            false => &commit.parents[..],
+        };
+        for parent in parents {
+            if seen.insert(parent.clone()) {
+                queued += 1;
+                let time = committer_time(graph.commit(parent)?);
+                queue.push((time, Reverse(queued), parent.clone()));
+            }
+        }
+
+        if filter.matches(time, &commit) {
+            commits.push((id, commit));
+        }
+    }
+    Ok(commits)
+}
+
+/// Lists every commit reachable from `head` such that parents precede their children.
+///
+/// Fails if a commit turns out to be its own ancestor, as no such order exists.
+#[tracing::instrument(skip(git_dir))]
+pub fn parents_first(git_dir: &Path, head: &str) -> anyhow::Result<Vec<String>> {
+    let mut order = Vec::new();
+    let mut visited = HashSet::new();
+    // Visited commits whose ancestors are all listed; the others are on the current path.
+    let mut finished = HashSet::new();
+    // (commit, whether its parents have been pushed already)
+    let mut stack = vec![(head.to_string(), false)];
+
+    while let Some((commit_id, expanded)) = stack.pop() {
+        if expanded {
+            finished.insert(commit_id.clone());
+            order.push(commit_id);
+            continue;
+        }
+        if !visited.insert(commit_id.clone()) {
+            continue;
+        }
+
+        let (_, content) = Repository::open(git_dir).read(&commit_id)?;
+        let commit = CommitObject::from_content(&content)?;
+        stack.push((commit_id, true));
+        for parent in commit.parents.into_iter().rev() {
+            if !visited.contains(&parent) {
+                stack.push((parent, false));
+            } else if !finished.contains(&parent) {
+                return Err(anyhow!(
+                    "Cycle detected: commit {} is its own ancestor.",
+                    parent
+                ));
+            }
+        }
+    }
+
+    Ok(order)
+}
+
+/// Prints commits in `git log`'s default (medium) format.
+pub fn print_log(commits: &[(String, CommitObject)]) {
+    for (i, (id, commit)) in commits.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("commit {}", id);
+        if commit.parents.len() > 1 {
+            let parents: Vec<&str> = commit.parents.iter().map(|x| short_id(x)).collect();
+            println!("Merge: {}", parents.join(" "));
+        }
+
+        print_signature("Author", &commit.author);
+        println!();
+
+        for line in commit.message.trim_end().lines() {
+            println!("    {}", line);
+        }
+    }
+}
+
+/// Prints a `<label>: Name <email>` line and the `Date:` line below it for
+/// a signature, the way commits and tags are shown.
+pub fn print_signature(label: &str, signature: &str) {
+    let (name, time) = match signature_time(signature) {
+        Some((seconds, offset)) => {
+            let name = signature.rsplitn(3, ' ').nth(2).unwrap_or_default();
+            (name, format_date(seconds, offset))
+        }
+        None => (signature, String::new()),
+    };
+    println!("{}: {}", label, name);
+    println!("Date:   {}", time);
+}
+
+/// Prints commits in `git log --oneline`'s format: the abbreviated id and
+/// the subject, which is the message's first paragraph joined into a line.
+pub fn print_oneline(commits: &[(String, CommitObject)]) {
+    for (id, commit) in commits {
+        let subject: Vec<&str> = commit
+            .message
+            .trim_start_matches('\n')
+            .lines()
+            .take_while(|x| !x.trim().is_empty())
+            .map(|x| x.trim())
+            .collect();
+        println!("{} {}", short_id(id), subject.join(" "));
+    }
+}
+
+pub fn read_commit(git_dir: &Path, id: &str) -> anyhow::Result<CommitObject> {
+    match Repository::open(git_dir).read(id)? {
+        (ObjectType::Commit, content) => {
+            let mut commit = CommitObject::from_content(&content)?;
+            // Like git, the edge of a shallow clone is treated as a root.
+            if shallow::read(git_dir)?.contains(id) {
+                commit.parents.clear();
+            }
+            Ok(commit)
+        }
+        (object_type, _) => Err(anyhow!("Object {} is a {}, not a commit.", id, object_type)),
+    }
+}
+
+pub fn committer_time(commit: &CommitObject) -> i64 {
+    signature_time(&commit.committer).map_or(0, |(seconds, _)| seconds)
+}
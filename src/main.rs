@@ -1,12 +1,87 @@
 use anyhow::{anyhow, Context, Ok};
 use clap::{Parser, Subcommand};
-use flate2::read::ZlibDecoder;
-use flate2::write::ZlibEncoder;
-use flate2::Compression;
-use sha1::{Digest, Sha1};
-use std::io::{BufReader, Read, Write};
-use std::path::Path;
+use codecrafters_git::config::{self, Config};
+use codecrafters_git::error::GitError;
+use codecrafters_git::lockfile::LockFile;
+use codecrafters_git::object::{
+    bytes_to_object_type, canonical_mode, encode_object, header_field, short_id, sort_tree_entries,
+    tree_cache_stats, write_tree, CommitObject, Object, ObjectFormat, ObjectType, TagObject,
+    TreeElement, TreeObject, MIN_ABBREV_LEN,
+};
+use codecrafters_git::odb::{loose_name, FsBackend, Repository};
+use codecrafters_git::progress::{self, Progress, Verbosity};
+use codecrafters_git::refs::{
+    self, expand_ref, is_valid_ref_name, list_refs, read_symref, resolve_ref, write_ref, RefLock,
+};
+use codecrafters_git::{common_dir, git_path, pack, parallel};
+use ignore::IgnoreRules;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{env, fs};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+
+/// Set by `--quiet`; silences everything printed through `info!`.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Prints an informational message to stderr unless `--quiet` was given.
+/// stdout is reserved for results (object ids, content, listings) so commands
+/// compose in pipelines; errors must not go through this either.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if !$crate::QUIET.load(std::sync::atomic::Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+mod add;
+mod archive;
+mod attributes;
+mod blame;
+mod bundle;
+mod checkout;
+mod clone;
+mod count_objects;
+mod credential;
+mod date;
+mod describe;
+mod diff;
+mod fast_export;
+mod fast_import;
+mod fetch;
+mod filter;
+mod fsck;
+mod gc;
+mod graph;
+mod grep;
+mod hooks;
+mod http;
+mod ignore;
+mod index;
+mod local;
+mod log;
+mod merge;
+mod mv;
+mod pack_objects;
+mod pktline;
+mod platform;
+mod push;
+mod rev_list;
+mod rm;
+mod shallow;
+mod shards;
+mod show;
+mod smart;
+mod ssh;
+mod stash;
+mod status;
+mod transport;
+mod upload_pack;
+mod verify_pack;
+mod wildmatch;
+mod worktree;
 
 #[derive(Parser)]
 #[clap(version, about, long_about = None)]
@@ -15,312 +90,3246 @@ struct Cli {
     #[arg(short, long)]
     pretty_print: Option<bool>,
 
+    /// Suppress informational messages.
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Show progress even when stderr isn't a terminal.
+    #[arg(long, global = true, conflicts_with = "quiet")]
+    verbose: bool,
+
+    /// Log spans and events to stderr; `RUST_LOG` takes precedence when set.
+    #[arg(long, global = true)]
+    trace: bool,
+
     #[command(subcommand)]
     commands: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    Init,
+    Init {
+        /// Directory to create the repository in; created if missing.
+        #[arg(default_value = ".")]
+        directory: PathBuf,
+        /// Hash function naming the objects: `sha1` or `sha256`.
+        #[arg(long, default_value = "sha1")]
+        object_format: ObjectFormat,
+        /// Branch `HEAD` starts on, instead of `init.defaultBranch` or `main`.
+        #[arg(short = 'b', long)]
+        initial_branch: Option<String>,
+        /// Make the directory itself the git directory, with no work tree.
+        #[arg(long)]
+        bare: bool,
+    },
     CatFile {
-        object_id: String,
+        #[arg(required_unless_present_any = ["batch", "batch_check"])]
+        object_id: Option<String>,
 
         #[arg(long, short)]
         pretty: bool,
+
+        /// Print the object's type instead of its content.
+        #[arg(short = 't', long = "type", conflicts_with_all = ["pretty", "size"])]
+        show_type: bool,
+
+        /// Print the object's size instead of its content.
+        #[arg(short, long, conflicts_with = "pretty")]
+        size: bool,
+
+        /// Print nothing; exit with status 1 if the object doesn't exist.
+        #[arg(short, conflicts_with_all = ["pretty", "show_type", "size"])]
+        exists: bool,
+
+        /// Print the content a git-lfs pointer blob refers to instead of the pointer itself.
+        #[arg(long)]
+        lfs: bool,
+
+        /// Directory holding git-lfs objects (defaults to `.git/lfs`).
+        #[arg(long, requires = "lfs")]
+        lfs_store: Option<PathBuf>,
+
+        /// Print only the first N bytes of a blob, without inflating the rest.
+        #[arg(long, value_name = "N", conflicts_with = "lfs")]
+        head: Option<u64>,
+
+        /// Count --head in lines instead of bytes.
+        #[arg(long, requires = "head")]
+        lines: bool,
+
+        /// Print where the object is stored: its loose file, or its pack and offset.
+        #[arg(long, conflicts_with_all = ["lfs", "head"])]
+        path: bool,
+
+        /// Read objects from stdin, one per line, and print `<id> <type> <size>` for each.
+        #[arg(long, conflicts_with_all = ["object_id", "lfs", "head", "path"])]
+        batch_check: bool,
+
+        /// Like --batch-check, but follow each header line with the content and a newline.
+        #[arg(long, conflicts_with_all = ["object_id", "lfs", "head", "path", "batch_check"])]
+        batch: bool,
     },
     HashObject {
-        file_path: String,
+        /// Files to hash; one id is printed per file, in order.
+        #[arg(required_unless_present = "stdin", conflicts_with = "stdin")]
+        file_paths: Vec<String>,
+
+        /// Hash the content read from stdin instead of a file, without attribute conversions.
+        #[arg(long)]
+        stdin: bool,
+
+        /// Type of the object to make; anything but a blob must be a valid
+        /// object of that type.
+        #[arg(short = 't', default_value = "blob", value_parser = parse_object_type)]
+        object_type: ObjectType,
+
+        #[arg(long, short)]
+        write: bool,
+
+        /// Hash the file exactly as on disk, without gitattributes conversions.
+        #[arg(long)]
+        no_filters: bool,
+    },
+    LsTree {
+        object_id: String,
+
+        #[arg(long, short)]
+        name_only: bool,
+
+        /// Recurse into subtrees, listing the entries under them with their full path.
+        #[arg(short)]
+        recursive: bool,
+
+        /// Start each line with the id of the tree the entry was read from.
+        #[arg(long)]
+        show_tree_sha: bool,
+
+        /// `raw` writes the tree body exactly as stored: `<mode> <name>\0<20-byte id>` per entry.
+        #[arg(long, value_enum, conflicts_with_all = ["name_only", "recursive", "show_tree_sha"])]
+        format: Option<LsTreeFormat>,
+    },
+    DiffTree {
+        old_tree: String,
+        new_tree: String,
+
+        /// Show a unified patch instead of the raw listing.
+        #[arg(short, long)]
+        patch: bool,
+
+        /// Show per-file insertion and deletion counts.
+        #[arg(long)]
+        stat: bool,
+
+        /// Also detect renames of files whose content changed, at the given similarity percent.
+        #[arg(
+            short = 'M',
+            long = "find-renames",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "50"
+        )]
+        find_renames: Option<u8>,
+
+        /// Report renamed files as a deletion and an addition.
+        #[arg(long, conflicts_with = "find_renames")]
+        no_renames: bool,
+
+        /// Ignore carriage returns at the end of lines when comparing content.
+        #[arg(long)]
+        ignore_cr_at_eol: bool,
+    },
+    /// Show the changes in the work tree that aren't staged in the index, or
+    /// against a commit, or between two commits.
+    Diff {
+        /// Show the changes staged for the next commit instead, against `HEAD`
+        /// or the given commit.
+        #[arg(long, visible_alias = "staged")]
+        cached: bool,
+
+        /// `<commit>` to compare the work tree (or index) with, or two commits
+        /// to compare, also written `<commit>..<commit>`; `<commit>...<commit>`
+        /// compares the second with its merge base with the first.
+        #[arg(num_args = 0..=2)]
+        revs: Vec<String>,
+
+        /// Show per-file insertion and deletion counts instead of the patch.
+        #[arg(long)]
+        stat: bool,
+
+        /// Ignore carriage returns at the end of lines when comparing content.
+        #[arg(long)]
+        ignore_cr_at_eol: bool,
+    },
+    MergeFile {
+        current: String,
+        base: String,
+        other: String,
+
+        /// Write the result to stdout instead of overwriting the current file.
+        #[arg(short = 'p', long)]
+        stdout: bool,
+
+        /// Labels for the current, base and other versions in conflict markers.
+        #[arg(short = 'L', num_args = 1, action = clap::ArgAction::Append)]
+        labels: Vec<String>,
+
+        /// Also show the base version inside conflicts.
+        #[arg(long)]
+        diff3: bool,
+
+        /// Resolve conflicts by taking the current version.
+        #[arg(long, conflicts_with_all = ["theirs", "union"])]
+        ours: bool,
+
+        /// Resolve conflicts by taking the other version.
+        #[arg(long, conflicts_with = "union")]
+        theirs: bool,
+
+        /// Resolve conflicts by taking both versions.
+        #[arg(long)]
+        union: bool,
+    },
+    /// List `<id> <size> <path>` for every blob reachable from a tree, recursively.
+    Manifest {
+        tree_ish: String,
+
+        /// Only list the first path of each distinct blob.
+        #[arg(long)]
+        dedup: bool,
+    },
+    /// Write a tree of the index, or of the work tree, and print its id.
+    WriteTree {
+        /// Snapshot the files in the work tree instead of the index.
+        #[arg(long)]
+        work_tree: bool,
+
+        #[command(flatten)]
+        stats: TreeCacheStats,
+    },
+    CommitTree {
+        tree: String,
+
+        /// A parent commit; repeat for merge commits. Order is kept as given.
+        #[arg(short, action = clap::ArgAction::Append)]
+        parents: Vec<String>,
+
+        /// The commit message; read from stdin when omitted.
+        #[arg(short)]
+        message: Option<String>,
+    },
+    /// Record the index as a new commit on the current branch.
+    Commit {
+        #[arg(short, long)]
+        message: String,
+
+        /// Commit even if the tree is the same as the parent's.
+        #[arg(long)]
+        allow_empty: bool,
+
+        /// Skip the pre-commit and commit-msg hooks.
+        #[arg(short = 'n', long)]
+        no_verify: bool,
+    },
+    /// Show the commits reachable from a revision, newest first.
+    Log {
+        #[arg(default_value = "HEAD")]
+        rev: String,
+
+        /// Only show commits committed at or after this date.
+        #[arg(long, alias = "after")]
+        since: Option<String>,
+
+        /// Only show commits committed at or before this date.
+        #[arg(long, alias = "before")]
+        until: Option<String>,
+
+        /// Follow only the first parent of merge commits.
+        #[arg(long)]
+        first_parent: bool,
+
+        /// Show at most this many commits.
+        #[arg(short = 'n', long)]
+        max_count: Option<usize>,
+
+        /// Show each commit on one line: its abbreviated id and subject.
+        #[arg(long)]
+        oneline: bool,
+
+        #[command(flatten)]
+        matching: CommitMatching,
+    },
+    /// List the updates of a ref recorded in its reflog, newest first.
+    Reflog {
+        #[arg(default_value = "HEAD")]
+        name: String,
+    },
+    /// Show an object: a commit with its patch, a tree's entries, a blob's
+    /// content, or a tag and what it tags.
+    Show {
+        #[arg(default_value = "HEAD")]
+        rev: String,
+
+        /// Show per-file insertion and deletion counts instead of the patch.
+        #[arg(long)]
+        stat: bool,
+    },
+    /// List the ids of the commits reachable from revisions, newest first.
+    RevList {
+        /// Revisions to list from; `^<rev>` excludes a revision's history
+        /// and `<a>..<b>` lists what `<b>` has and `<a>` hasn't.
+        #[arg(required = true)]
+        revs: Vec<String>,
+
+        /// Exclude the history of these revisions too.
+        #[arg(long, num_args = 1.., value_name = "REV")]
+        not: Vec<String>,
+
+        /// Also list the trees and blobs the commits hold, with their paths.
+        #[arg(long)]
+        objects: bool,
+
+        /// Only list commits committed at or after this date.
+        #[arg(long, alias = "after")]
+        since: Option<String>,
+
+        /// Only list commits committed at or before this date.
+        #[arg(long, alias = "before")]
+        until: Option<String>,
+
+        /// Follow only the first parent of merge commits.
+        #[arg(long)]
+        first_parent: bool,
+
+        #[command(flatten)]
+        matching: CommitMatching,
+    },
+    /// Rewrite the history of a ref with the given paths removed from every commit.
+    FilterBranch {
+        /// A file or directory to remove; repeat to remove several.
+        #[arg(long = "remove-path", required = true)]
+        remove_paths: Vec<String>,
+
+        #[arg(default_value = "HEAD")]
+        rev: String,
+
+        #[command(flatten)]
+        stats: TreeCacheStats,
+    },
+    /// Count loose and packed objects and report maintenance issues.
+    CountObjects {
+        /// Print detailed `key: value` statistics and warnings.
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Check packs against their indexes.
+    VerifyPack {
+        /// The packs' `.idx` (or `.pack`) files.
+        #[arg(required = true)]
+        idx_paths: Vec<PathBuf>,
+
+        /// List every object with its type, sizes and offset, then a
+        /// histogram of delta chain lengths.
+        #[arg(short, long)]
+        verbose: bool,
+
+        /// Only print the delta chain histogram.
+        #[arg(short, long)]
+        stat_only: bool,
+    },
+    /// Print the lines of tracked files that match a pattern.
+    Grep {
+        /// A regular expression, in the syntax of the `regex` crate.
+        pattern: String,
+
+        /// Search the trees of these commits or trees instead of the work tree.
+        #[arg(conflicts_with = "cached")]
+        revs: Vec<String>,
+
+        /// Only search files under these paths, relative to the top of the
+        /// work tree.
+        #[arg(last = true)]
+        paths: Vec<String>,
+
+        /// Search the blobs staged in the index instead of the work tree.
+        #[arg(long)]
+        cached: bool,
+
+        /// Prefix each matching line with its line number.
+        #[arg(short = 'n', long)]
+        line_number: bool,
+
+        #[arg(short, long)]
+        ignore_case: bool,
+    },
+    /// Name a commit after the closest tag it descends from.
+    Describe {
+        #[arg(conflicts_with = "dirty")]
+        commit_ish: Option<String>,
+
+        /// Also consider lightweight tags.
+        #[arg(long)]
+        tags: bool,
+
+        /// Digits of the commit id to show; 0 shows only the tag.
+        #[arg(long, default_value_t = 7)]
+        abbrev: usize,
+
+        /// Append this suffix when tracked files have uncommitted changes.
+        #[arg(
+            long,
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "-dirty"
+        )]
+        dirty: Option<String>,
+    },
+    /// List branches, or create or delete one.
+    Branch {
+        /// Branch to create; lists the branches when omitted.
+        name: Option<String>,
+        /// Commit the new branch starts at, instead of `HEAD`.
+        start_point: Option<String>,
+        /// Delete the branch, which must be merged into `HEAD`.
+        #[arg(short, long, requires = "name")]
+        delete: bool,
+        /// Delete the branch even if it isn't merged.
+        #[arg(short = 'D', requires = "name", conflicts_with = "delete")]
+        force_delete: bool,
+    },
+    /// List tags, or create or delete one.
+    Tag {
+        /// Tag to create; lists the tags when omitted.
+        name: Option<String>,
+        /// Object the tag points at, instead of `HEAD`.
+        object: Option<String>,
+        /// Make an annotated tag: a tag object with a tagger and a message.
+        #[arg(short, requires = "message")]
+        annotate: bool,
+        /// Message of the annotated tag; implies `-a`.
+        #[arg(short, requires = "name")]
+        message: Option<String>,
+        /// Delete the tag.
+        #[arg(short, long, requires = "name", conflicts_with_all = ["annotate", "message"])]
+        delete: bool,
+    },
+    /// Point a ref (or the ref a symbolic ref leads to) at an object, only
+    /// if it currently holds `old_value` when that is given.
+    UpdateRef {
+        /// Delete the ref instead; the only value given is then the old one.
+        #[arg(short)]
+        delete: bool,
+        name: String,
+        /// `<new value> [<old value>]`; an old value of all zeros requires
+        /// that the ref doesn't exist yet.
+        #[arg(num_args = 0..=2)]
+        values: Vec<String>,
+        /// Reason for the update, recorded in the ref's reflog.
+        #[arg(short = 'm')]
+        message: Option<String>,
+    },
+    /// Print the ref a symbolic ref like `HEAD` points to, or point it elsewhere.
+    SymbolicRef {
+        name: String,
+        target: Option<String>,
+        /// Print the target without `refs/heads/` and the like.
+        #[arg(long)]
+        short: bool,
+    },
+    /// Switch the work tree, index and `HEAD` to a branch or commit.
+    #[command(alias = "switch")]
+    Checkout { rev: String },
+    /// Show the commit and author that last changed each line of a file.
+    Blame { path: String },
+    /// Write the tree of a commit as a tar or zip archive.
+    Archive {
+        #[arg(default_value = "HEAD")]
+        rev: String,
+
+        /// Defaults to zip when the output file ends in `.zip`, otherwise tar.
+        #[arg(long, value_enum)]
+        format: Option<archive::Format>,
+
+        /// Put every path under this directory, given with a trailing `/`.
+        #[arg(long, default_value = "")]
+        prefix: String,
+
+        /// Write the archive to this file instead of stdout.
+        #[arg(short)]
+        output: Option<PathBuf>,
+    },
+    /// Save the changes to tracked files away and reset to `HEAD`, or manage
+    /// the saved stashes.
+    Stash {
+        #[command(subcommand)]
+        command: Option<StashCommands>,
+    },
+    /// Apply the change a commit made onto the current branch.
+    CherryPick { rev: String },
+    /// Merge a branch or commit into the current branch.
+    Merge { branch: String },
+    /// Print the best common ancestor of two commits.
+    MergeBase {
+        rev1: String,
+        rev2: String,
+        /// Print every best common ancestor, not just one.
+        #[arg(long)]
+        all: bool,
+        /// Print nothing, but exit with 0 if `rev1` is an ancestor of `rev2`
+        /// and with 1 if not.
+        #[arg(long, conflicts_with = "all")]
+        is_ancestor: bool,
+    },
+    /// Move the current branch to a commit, resetting the index (the
+    /// default) or also the work tree to it.
+    Reset {
+        #[arg(default_value = "HEAD")]
+        rev: String,
+        /// Only move the branch.
+        #[arg(long, conflicts_with_all = ["mixed", "hard"])]
+        soft: bool,
+        /// Also reset the index.
+        #[arg(long, conflicts_with = "hard")]
+        mixed: bool,
+        /// Also reset the index and the work tree.
+        #[arg(long)]
+        hard: bool,
+        /// Don't report where `HEAD` is now or what is left unstaged.
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// List refs with the object ids they point to.
+    ShowRef {
+        /// Also show `HEAD`.
+        #[arg(long)]
+        head: bool,
+    },
+    /// Print the value of a config variable, like `user.email`, or set it;
+    /// exits with 1 when it isn't set.
+    Config {
+        name: Option<String>,
+        /// The value to set the variable to.
+        value: Option<String>,
+        /// Read and write the user's config instead of the repository's.
+        #[arg(long)]
+        global: bool,
+        /// List every variable as `name=value`.
+        #[arg(short, long, conflicts_with_all = ["name", "unset", "get_all"])]
+        list: bool,
+        /// Print every value of a variable set more than once.
+        #[arg(long, requires = "name", conflicts_with = "value")]
+        get_all: bool,
+        /// Remove the variable; exits with 5 when it isn't set.
+        #[arg(long, requires = "name", conflicts_with_all = ["value", "get_all"])]
+        unset: bool,
+    },
+    /// Print the full object id each revision resolves to.
+    RevParse {
+        #[arg(required = true)]
+        revs: Vec<String>,
+    },
+    /// Check that `HEAD` resolves to an existing commit.
+    CheckHead,
+    /// Re-hash every object, check its syntax and the links between objects,
+    /// and report corrupt, missing and dangling ones.
+    Fsck,
+    /// Report loose objects stored outside the shard directory of their id.
+    CheckSharding {
+        /// Move misplaced objects to where they belong.
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Write the history of a ref as a `git fast-export` stream on stdout.
+    FastExport { rev: String },
+    /// Create objects and refs from a `git fast-export` stream read on stdin.
+    FastImport {
+        #[command(flatten)]
+        stats: TreeCacheStats,
+    },
+    /// Write the objects of a pack read on stdin as loose objects.
+    UnpackObjects,
+    /// Write the index of a pack, resolving its deltas to learn each object's id.
+    IndexPack {
+        /// The pack to index; its index goes next to it as `<name>.idx`.
+        #[arg(required_unless_present = "stdin")]
+        pack: Option<PathBuf>,
+        /// Where to write the index instead.
+        #[arg(short)]
+        output: Option<PathBuf>,
+        /// Read the pack on stdin and store it, indexed, in the repository.
+        #[arg(long, conflicts_with_all = ["pack", "output"])]
+        stdin: bool,
+    },
+    /// Pack every reachable object into one pack, replacing the loose
+    /// objects and packs that held them.
+    Gc {
+        /// Drop unreachable objects older than this date.
+        #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "2.weeks.ago")]
+        prune: Option<String>,
+    },
+    /// Pack the objects whose ids are read on stdin, one per line.
+    PackObjects {
+        /// Write `<base-name>-<checksum>.pack` and its index, and print the checksum.
+        #[arg(required_unless_present = "stdout")]
+        base_name: Option<PathBuf>,
+        /// Write the pack to stdout instead.
+        #[arg(long, conflicts_with = "base_name")]
+        stdout: bool,
+        /// Read revisions instead, packing every object they reach except
+        /// those reachable from `^<rev>` lines.
+        #[arg(long)]
+        revs: bool,
+    },
+    /// Show the branch, staged and unstaged changes, and untracked files.
+    Status {
+        /// Also report whether each submodule is checked out at its recorded commit.
+        #[arg(long)]
+        recurse_submodules: bool,
+        /// Print `XY path` lines for scripts instead of the long format.
+        #[arg(long)]
+        porcelain: bool,
+    },
+    /// Update the stat data of unchanged index entries and list the modified ones.
+    RefreshIndex,
+    /// Stage files, or everything under directories that isn't ignored, in the index.
+    Add {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+
+        /// Stage the files exactly as on disk, without gitattributes conversions.
+        #[arg(long)]
+        no_filters: bool,
+    },
+    /// Remove tracked files from the index and the work tree.
+    Rm {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+
+        /// Only remove the files from the index.
+        #[arg(long)]
+        cached: bool,
+
+        /// Remove the files under directories given.
+        #[arg(short)]
+        recursive: bool,
+
+        /// Remove files even when their changes aren't committed.
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Move or rename a tracked file or directory, in the index too.
+    Mv { from: PathBuf, to: PathBuf },
+    /// Print the paths that are ignored; exits with 1 when none are.
+    CheckIgnore {
+        /// Print the rule that decided each path, as `<file>:<line>:<pattern>\t<path>`.
+        #[arg(short, long)]
+        verbose: bool,
+        /// Also print paths no rule matches, with empty rule fields.
+        #[arg(short, long, requires = "verbose")]
+        non_matching: bool,
+        /// Check tracked paths too, which otherwise are never ignored.
+        #[arg(long)]
+        no_index: bool,
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Stage the current content of work tree files in the index.
+    UpdateIndex {
+        /// Stage files that aren't in the index yet.
+        #[arg(long)]
+        add: bool,
+        /// Unstage files that no longer exist in the work tree.
+        #[arg(long)]
+        remove: bool,
+        /// First update the stat data of unchanged entries and list the
+        /// modified ones, as `refresh-index` does.
+        #[arg(long)]
+        refresh: bool,
+        #[arg(required_unless_present = "refresh")]
+        paths: Vec<PathBuf>,
+    },
+    LsFiles {
+        /// Show the mode, object id and stage of each entry.
+        #[arg(short, long)]
+        stage: bool,
+
+        /// List the files in the index; the default unless `--others` is given.
+        #[arg(short, long)]
+        cached: bool,
+
+        /// List work tree files that aren't in the index, before those that are.
+        #[arg(short, long)]
+        others: bool,
+
+        /// Leave out files the ignore rules match from `--others`.
+        #[arg(long)]
+        exclude_standard: bool,
+    },
+    /// Manage the work trees checked out from this repository.
+    Worktree {
+        #[command(subcommand)]
+        command: WorktreeCommands,
+    },
+    /// Write history to a single file, or read one, for moving it without a
+    /// network; `clone` and `fetch` take a bundle file in place of a URL.
+    Bundle {
+        #[command(subcommand)]
+        command: BundleCommands,
+    },
+    /// Clone a local repository, or one served over SSH or HTTP, by the smart
+    /// protocol (version 2) or, over HTTP, the dumb one.
+    Clone {
+        url: String,
+        directory: Option<PathBuf>,
+        /// Make a bare copy of every remote ref, set up to be refreshed as a mirror.
+        #[arg(long)]
+        mirror: bool,
+        /// Fetch only this many commits of history, and only the remote `HEAD`'s branch.
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        depth: Option<u32>,
+        #[command(flatten)]
+        auth: HttpToken,
+    },
+    /// Download what a remote has that this repository doesn't, and update
+    /// the remote-tracking refs its fetch refspecs map it to.
+    Fetch {
+        #[arg(default_value = "origin")]
+        remote: String,
+        /// Cut the history of what is fetched this many commits below the
+        /// remote refs, deepening or shortening a shallow clone.
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        depth: Option<u32>,
+        #[command(flatten)]
+        auth: HttpToken,
+    },
+    /// Serve fetches from a repository over stdin and stdout, as the other
+    /// end of `clone` and `fetch` (protocol version 2 only).
+    UploadPack { directory: PathBuf },
+    /// Update refs of a remote served over SSH or smart HTTP, sending the
+    /// objects they need.
+    Push {
+        /// A configured remote, or a URL.
+        #[arg(default_value = "origin")]
+        remote: String,
+        /// `[+]<src>[:<dst>]`, or `:<dst>` to delete; the current branch
+        /// when none are given.
+        refspecs: Vec<String>,
+        /// Update remote refs even when that loses commits they had.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Skip the pre-push hook.
+        #[arg(long)]
+        no_verify: bool,
+        #[command(flatten)]
+        auth: HttpToken,
+    },
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum LsTreeFormat {
+    Raw,
+}
+
+// Regex filters shared by `log` and `rev-list`. Flattened argument structs
+// get plain comments: clap would make a doc comment the help text of any
+// command without its own.
+#[derive(clap::Args)]
+struct CommitMatching {
+    /// Only commits whose author `Name <email>` matches this regex; repeat to allow several.
+    #[arg(long = "author")]
+    authors: Vec<String>,
+
+    /// Only commits whose message matches this regex; repeat to allow several.
+    #[arg(long = "grep")]
+    greps: Vec<String>,
+
+    /// Require the message to match every --grep pattern instead of any.
+    #[arg(long)]
+    all_match: bool,
+}
+
+// The `--token` option of commands that talk to remotes.
+#[derive(clap::Args)]
+struct HttpToken {
+    /// Authenticate to HTTP servers with this bearer token instead of a
+    /// username and password; `GIT_TOKEN` is used when not given.
+    #[arg(long)]
+    token: Option<String>,
+}
+
+impl HttpToken {
+    fn apply(self) {
+        if let Some(token) = self.token {
+            credential::set_token(token);
+        }
+    }
+}
+
+// The `--stats` flag of commands that write trees in bulk.
+#[derive(clap::Args)]
+struct TreeCacheStats {
+    /// Report how many tree writes were served from the in-process tree cache.
+    #[arg(long = "stats")]
+    enabled: bool,
+}
+
+impl TreeCacheStats {
+    fn print(&self) {
+        if self.enabled {
+            let (hits, misses) = tree_cache_stats();
+            info!("Tree cache: {} hits, {} misses.", hits, misses);
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum StashCommands {
+    /// Save the changes; what `stash` alone does.
+    Push {
+        /// Describe the stash with this instead of `HEAD`'s subject.
+        #[arg(short, long)]
+        message: Option<String>,
+    },
+    /// List the stashes, newest first.
+    List,
+    /// Reapply a stash, `stash@{0}` by default, keeping it.
+    Apply { stash: Option<String> },
+    /// Reapply a stash and drop it if that went cleanly.
+    Pop { stash: Option<String> },
+    /// Drop a stash.
+    Drop { stash: Option<String> },
+}
+
+#[derive(Subcommand)]
+enum WorktreeCommands {
+    /// List the main worktree and the linked ones.
+    List,
+    /// Check out a commit in a new linked worktree at `path`.
+    Add {
+        path: PathBuf,
+        /// A branch to check out, or a commit to detach at; `HEAD` by
+        /// default, on a new branch named after the directory.
+        commit_ish: Option<String>,
+        /// Create this branch at the commit and check it out.
+        #[arg(short = 'b', conflicts_with = "detach")]
+        new_branch: Option<String>,
+        /// Detach `HEAD` at the commit, even when a branch was named.
+        #[arg(long)]
+        detach: bool,
+    },
+    /// Delete a linked worktree.
+    Remove {
+        path: PathBuf,
+        /// Delete it even if it has changes or untracked files.
+        #[arg(short, long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum BundleCommands {
+    /// Bundle the refs the revs name, with the history they reach; `^<rev>`
+    /// and `<a>..<b>` leave out what the receiver is known to have.
+    Create {
+        file: PathBuf,
+        #[arg(allow_hyphen_values = true, required_unless_present = "all")]
+        revs: Vec<String>,
+        /// Bundle every ref.
+        #[arg(long)]
+        all: bool,
+    },
+    /// Check that a bundle is intact and that this repository has what it
+    /// requires.
+    Verify { file: PathBuf },
+    /// List the refs a bundle carries.
+    ListHeads { file: PathBuf },
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse_from(env::args().map(normalize_glued_value));
+    QUIET.store(cli.quiet, Ordering::Relaxed);
+    progress::set_verbosity(match (cli.quiet, cli.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (_, true) => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    });
+    init_tracing(cli.trace);
+
+    match cli.commands {
+        Commands::Init {
+            directory,
+            object_format,
+            initial_branch,
+            bare,
+        } => init(&directory, object_format, initial_branch, bare),
+        Commands::CatFile {
+            object_id,
+            pretty: _,
+            show_type,
+            size,
+            exists,
+            lfs,
+            lfs_store,
+            head,
+            lines,
+            path,
+            batch_check,
+            batch,
+        } => {
+            let git_dir = find_git_dir()?;
+            let repository = Repository::open(&git_dir);
+            if batch || batch_check {
+                return cat_file_batch(&git_dir, batch);
+            }
+            let object_id = object_id.expect("clap requires an object id without --batch");
+            if path {
+                return cat_file_path(&git_dir, &object_id);
+            }
+            if let Some(limit) = head {
+                return cat_file_head(&git_dir, &object_id, limit, lines);
+            }
+            let object_id = resolve_rev(&git_dir, &object_id)?;
+            let stat = repository.stat_object(&object_id)?;
+            if exists {
+                std::process::exit(if stat.is_some() { 0 } else { 1 });
+            }
+            let (object_type, object_size) =
+                stat.ok_or_else(|| GitError::ObjectNotFound(object_id.to_string()))?;
+            if show_type || size {
+                match show_type {
+                    true => println!("{}", object_type),
+                    false => println!("{}", object_size),
+                }
+                return Ok(());
+            }
+            // Commits and tags are printed as stored, so headers their parsed
+            // forms don't model (signatures, mergetags) come out untouched.
+            if matches!(object_type, ObjectType::Commit | ObjectType::Tag) {
+                let (_, content) = repository.read(&object_id)?;
+                std::io::stdout().write_all(&content)?;
+                return Ok(());
+            }
+            // Blobs are streamed as they inflate, so even huge ones are never
+            // held in memory; only `--lfs` needs to look at one small enough
+            // to be a pointer first.
+            if object_type == ObjectType::Blob && (!lfs || object_size > LfsPointer::MAX_LEN as u64)
+            {
+                let (_, _, mut reader) = repository.open_object(&object_id)?;
+                std::io::copy(&mut reader, &mut std::io::stdout().lock())?;
+                return Ok(());
+            }
+            match repository.object(&object_id)? {
+                Object::Blob(blob) => {
+                    let pointer = std::str::from_utf8(&blob.content)
+                        .ok()
+                        .and_then(LfsPointer::parse);
+                    match pointer {
+                        Some(pointer) if lfs => {
+                            let lfs_store = lfs_store.unwrap_or_else(|| git_path(&git_dir, "lfs"));
+                            let content = pointer.read_content(&lfs_store)?;
+                            std::io::stdout().write_all(&content)?;
+                        }
+                        _ => std::io::stdout().write_all(&blob.content)?,
+                    }
+                }
+                Object::Tree(tree) => {
+                    for element in tree.elements {
+                        println!(
+                            "{:0>6} {} {}\t{}",
+                            element.mode,
+                            element.object_type,
+                            hex::encode(&element.hash),
+                            element.name
+                        );
+                    }
+                }
+                Object::Commit(_) | Object::Tag(_) => {
+                    unreachable!("commits and tags are printed as stored above")
+                }
+            }
+            Ok(())
+        }
+        Commands::HashObject {
+            file_paths,
+            stdin,
+            object_type,
+            write,
+            no_filters,
+        } => {
+            if stdin {
+                let mut content = Vec::new();
+                std::io::stdin().read_to_end(&mut content)?;
+                println!("{}", hash_content(object_type, &content, write)?);
+                return Ok(());
+            }
+
+            for file_path in file_paths {
+                // Only small files can be pointers, so big ones aren't read here.
+                let content = match fs::metadata(&file_path) {
+                    Result::Ok(x) if x.len() <= LfsPointer::MAX_LEN as u64 => {
+                        fs::read(&file_path).unwrap_or_default()
+                    }
+                    _ => Vec::new(),
+                };
+                let pointer = std::str::from_utf8(&content)
+                    .ok()
+                    .and_then(LfsPointer::parse);
+                if let Some(pointer) = pointer {
+                    info!(
+                        "{}: git-lfs pointer to sha256:{} ({} bytes)",
+                        file_path, pointer.oid, pointer.size
+                    );
+                }
+                let sha_hash = hash_file(&file_path, object_type, write, no_filters)
+                    .with_context(|| format!("Hash {}.", file_path))?;
+                println!("{}", sha_hash);
+            }
+            Ok(())
+        }
+        Commands::LsTree {
+            object_id,
+            name_only,
+            recursive,
+            show_tree_sha,
+            format,
+        } => {
+            let git_dir = find_git_dir()?;
+            let repository = Repository::open(&git_dir);
+            if let Some(LsTreeFormat::Raw) = format {
+                let tree_id =
+                    repository.peel_to(&resolve_rev(&git_dir, &object_id)?, ObjectType::Tree)?;
+                let (_, content) = repository.read(&tree_id)?;
+                std::io::stdout().write_all(&content)?;
+                return Ok(());
+            }
+            let options = LsTreeOptions {
+                name_only,
+                recursive,
+                show_tree_sha,
+            };
+            ls_tree(&git_dir, object_id, &options)?;
+            Ok(())
+        }
+        Commands::DiffTree {
+            old_tree,
+            new_tree,
+            patch,
+            stat,
+            find_renames,
+            no_renames,
+            ignore_cr_at_eol,
+        } => {
+            let options = diff::DiffOptions { ignore_cr_at_eol };
+            let git_dir = find_git_dir()?;
+            let repository = Repository::open(&git_dir);
+            let old_tree = resolve_rev(&git_dir, &old_tree)?;
+            let old_tree = repository.peel_to(&old_tree, ObjectType::Tree)?;
+            let new_tree = resolve_rev(&git_dir, &new_tree)?;
+            let new_tree = repository.peel_to(&new_tree, ObjectType::Tree)?;
+            let mut changes = diff::diff_trees(&git_dir, Some(&old_tree), Some(&new_tree))?;
+            if !no_renames {
+                changes = diff::detect_renames(&git_dir, changes, find_renames)?;
+            }
+            if stat {
+                diff::print_stat(&git_dir, &changes, &diff::PendingBlobs::new(), options)?;
+            }
+            if patch {
+                if stat {
+                    println!();
+                }
+                diff::print_patch(&git_dir, &changes, &diff::PendingBlobs::new(), options)?;
+            }
+            if !stat && !patch {
+                diff::print_raw(&changes);
+            }
+            Ok(())
+        }
+        Commands::Diff {
+            cached,
+            revs,
+            stat,
+            ignore_cr_at_eol,
+        } => {
+            let options = diff::DiffOptions { ignore_cr_at_eol };
+            let git_dir = find_git_dir()?;
+            let repository = Repository::open(&git_dir);
+            // An omitted end of a range is `HEAD`.
+            let or_head = |rev: &str| match rev {
+                "" => "HEAD".to_string(),
+                rev => rev.to_string(),
+            };
+            let revs: Vec<String> = match revs.as_slice() {
+                // `A...B` is what `B` changed since it forked from `A`: the
+                // diff from their merge base to `B`.
+                [range] if range.contains("...") => {
+                    let (a, b) = range.split_once("...").expect("checked to hold `...`");
+                    let commit = |rev: &str| {
+                        repository
+                            .peel_to(&resolve_rev(&git_dir, &or_head(rev))?, ObjectType::Commit)
+                    };
+                    let (a, b) = (commit(a)?, commit(b)?);
+                    let base = graph::CommitGraph::new(&git_dir)
+                        .merge_bases(&a, &b)?
+                        .into_iter()
+                        .next()
+                        .ok_or_else(|| anyhow!("{}: no merge base.", range))?;
+                    vec![base, b]
+                }
+                [range] if range.contains("..") => range.splitn(2, "..").map(or_head).collect(),
+                revs => revs.to_vec(),
+            };
+            let tree =
+                |rev: &str| repository.peel_to(&resolve_rev(&git_dir, rev)?, ObjectType::Tree);
+            let mut pending = diff::PendingBlobs::new();
+            // Like git, additions and deletions between trees or staged are
+            // paired into renames.
+            let changes = match (revs.as_slice(), cached) {
+                ([], true) => {
+                    diff::detect_renames(&git_dir, diff::diff_head_to_index(&git_dir)?, Some(50))?
+                }
+                ([], false) => {
+                    diff::diff_index_to_work_tree(&git_dir, &find_work_tree()?, &mut pending)?
+                }
+                ([rev], true) => {
+                    let changes = diff::diff_tree_to_index(&git_dir, Some(&tree(rev)?))?;
+                    diff::detect_renames(&git_dir, changes, Some(50))?
+                }
+                ([rev], false) => diff::diff_tree_to_work_tree(
+                    &git_dir,
+                    &find_work_tree()?,
+                    Some(&tree(rev)?),
+                    &mut pending,
+                )?,
+                ([old, new], false) => {
+                    let changes = diff::diff_trees(&git_dir, Some(&tree(old)?), Some(&tree(new)?))?;
+                    diff::detect_renames(&git_dir, changes, Some(50))?
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "--cached compares the index with one commit, not two."
+                    ))
+                }
+            };
+            match stat {
+                true => diff::print_stat(&git_dir, &changes, &pending, options),
+                false => diff::print_patch(&git_dir, &changes, &pending, options),
+            }
+        }
+        Commands::MergeFile {
+            current,
+            base,
+            other,
+            stdout,
+            labels,
+            diff3,
+            ours,
+            theirs,
+            union,
+        } => {
+            if labels.len() > 3 {
+                return Err(anyhow!("Too many labels; at most 3 are allowed."));
+            }
+
+            let read = |path: &str| -> anyhow::Result<String> {
+                let content = fs::read(path).with_context(|| format!("Read {}.", path))?;
+                if diff::is_binary(&String::from_utf8_lossy(&content)) {
+                    return Err(anyhow!("Cannot merge binary files: {}", path));
+                }
+                Ok(String::from_utf8(content)?)
+            };
+            let current_content = read(&current)?;
+            let base_content = read(&base)?;
+            let other_content = read(&other)?;
+
+            let labels = merge::Labels {
+                ours: labels.first().unwrap_or(&current),
+                base: labels.get(1).unwrap_or(&base),
+                theirs: labels.get(2).unwrap_or(&other),
+            };
+            let style = match diff3 {
+                true => merge::ConflictStyle::Diff3,
+                false => merge::ConflictStyle::Merge,
+            };
+            let favor = match (ours, theirs, union) {
+                (true, _, _) => merge::Favor::Ours,
+                (_, true, _) => merge::Favor::Theirs,
+                (_, _, true) => merge::Favor::Union,
+                _ => merge::Favor::None,
+            };
+
+            let result = merge::merge_lines(
+                &base_content,
+                &current_content,
+                &other_content,
+                &labels,
+                style,
+                favor,
+            );
+
+            if stdout {
+                print!("{}", result.content);
+            } else {
+                fs::write(&current, result.content).context("Write merged file.")?;
+            }
+
+            // Like git, the exit status is the number of conflicts.
+            std::process::exit(result.conflicts.min(127) as i32);
+        }
+        Commands::Manifest { tree_ish, dedup } => {
+            let git_dir = find_git_dir()?;
+            let object_id = resolve_rev(&git_dir, &tree_ish)?;
+            let tree_id = Repository::open(&git_dir).peel_to(&object_id, ObjectType::Tree)?;
+
+            let mut blobs = Vec::new();
+            walk_blobs(&git_dir, &tree_id, "", &mut blobs)?;
+
+            let mut seen = std::collections::HashSet::new();
+            for (path, hash) in blobs {
+                if dedup && !seen.insert(hash.clone()) {
+                    continue;
+                }
+                let (_, size) = Repository::open(&git_dir).read_header(&hash)?;
+                println!("{} {} {}", hash, size, path);
+            }
+            Ok(())
+        }
+        Commands::WriteTree { work_tree, stats } => {
+            let git_dir = find_git_dir()?;
+            if work_tree {
+                let work_tree = find_work_tree()?;
+                let ignore = IgnoreRules::load(&work_tree, &git_dir);
+                let entries = write_work_tree_entries(&git_dir, &work_tree, "", &ignore)?;
+                let tree_hash = write_tree(&git_dir, &entries)?;
+                println!("{}", tree_hash);
+                stats.print();
+                return Ok(());
+            }
+            let index_path = git_path(&git_dir, "index");
+            let mut index = index::Index::load(&index_path)?;
+            let tree_hash = index.write_tree(&git_dir)?;
+            index.write(&index_path)?;
+            println!("{}", tree_hash);
+            stats.print();
+            Ok(())
+        }
+        Commands::CommitTree {
+            tree,
+            parents,
+            message,
+        } => {
+            let git_dir = find_git_dir()?;
+            let repository = Repository::open(&git_dir);
+            let tree = repository.peel_to(&resolve_rev(&git_dir, &tree)?, ObjectType::Tree)?;
+            let parents = parents
+                .iter()
+                .map(|x| Ok(repository.peel_to(&resolve_rev(&git_dir, x)?, ObjectType::Commit)?))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let message = match message {
+                Some(message) => message,
+                None => {
+                    let mut message = String::new();
+                    std::io::stdin().read_to_string(&mut message)?;
+                    message
+                }
+            };
+
+            let commit_hash = commit_tree(&git_dir, &tree, &parents, None, &message)?;
+            println!("{}", commit_hash);
+            Ok(())
+        }
+        Commands::Commit {
+            message,
+            allow_empty,
+            no_verify,
+        } => {
+            let git_dir = find_git_dir()?;
+            commit(&git_dir, &message, allow_empty, !no_verify)
+        }
+        Commands::Log {
+            rev,
+            since,
+            until,
+            first_parent,
+            max_count,
+            oneline,
+            matching,
+        } => {
+            let git_dir = find_git_dir()?;
+            let start = Repository::open(&git_dir)
+                .peel_to(&resolve_rev(&git_dir, &rev)?, ObjectType::Commit)?;
+            let filter = log_filter(since.as_deref(), until.as_deref(), first_parent, matching)?;
+            let mut commits = log::walk(&git_dir, &start, &filter)?;
+            if let Some(max_count) = max_count {
+                commits.truncate(max_count);
+            }
+            match oneline {
+                true => log::print_oneline(&commits),
+                false => log::print_log(&commits),
+            }
+            Ok(())
+        }
+        Commands::Reflog { name } => {
+            let git_dir = find_git_dir()?;
+            let full_name = match name.as_str() {
+                "HEAD" => name.clone(),
+                _ => expand_ref(&git_dir, &name)?
+                    .ok_or_else(|| anyhow!("Unknown revision: {}", name))?,
+            };
+            let entries = refs::read_reflog(&git_dir, &full_name)?;
+            for (n, entry) in entries.iter().rev().enumerate() {
+                println!(
+                    "{} {}@{{{}}}: {}",
+                    short_id(&entry.new),
+                    name,
+                    n,
+                    entry.message
+                );
+            }
+            Ok(())
+        }
+        Commands::Show { rev, stat } => {
+            let git_dir = find_git_dir()?;
+            show::show(&git_dir, &rev, &resolve_rev(&git_dir, &rev)?, stat)
+        }
+        Commands::RevList {
+            revs,
+            not,
+            objects,
+            since,
+            until,
+            first_parent,
+            matching,
+        } => {
+            let git_dir = find_git_dir()?;
+            let filter = log_filter(since.as_deref(), until.as_deref(), first_parent, matching)?;
+            rev_list::rev_list(&git_dir, &revs, &not, &filter, objects)
+        }
+        Commands::FilterBranch {
+            remove_paths,
+            rev,
+            stats,
+        } => {
+            let git_dir = find_git_dir()?;
+            let ref_name =
+                expand_ref(&git_dir, &rev)?.ok_or_else(|| anyhow!("Unknown ref: {}", rev))?;
+            let old_head =
+                resolve_ref(&git_dir, &ref_name)?.ok_or_else(|| anyhow!("Unknown ref: {}", rev))?;
+            let old_head = Repository::open(&git_dir).peel_to(&old_head, ObjectType::Commit)?;
+
+            let new_head = filter::remove_paths(&git_dir, &old_head, &remove_paths)?;
+            // Like filter-branch, keep the old history reachable under refs/original/.
+            write_ref(&git_dir, &format!("refs/original/{}", ref_name), &old_head)?;
+            write_ref(&git_dir, &ref_name, &new_head)?;
+            info!("Ref '{}' was rewritten", ref_name);
+            stats.print();
+            Ok(())
+        }
+        Commands::CountObjects { verbose } => {
+            let git_dir = find_git_dir()?;
+            count_objects::count_objects(&git_dir, verbose)?;
+            Ok(())
+        }
+        Commands::VerifyPack {
+            idx_paths,
+            verbose,
+            stat_only,
+        } => {
+            for idx_path in idx_paths {
+                verify_pack::verify_pack(&idx_path, verbose, stat_only)
+                    .with_context(|| format!("Verify {}.", idx_path.display()))?;
+            }
+            Ok(())
+        }
+        Commands::Grep {
+            pattern,
+            revs,
+            paths,
+            cached,
+            line_number,
+            ignore_case,
+        } => {
+            let git_dir = find_git_dir()?;
+            let work_tree;
+            let source = match (cached, revs.is_empty()) {
+                (true, _) => grep::GrepSource::Index,
+                (false, false) => grep::GrepSource::Revs(&revs),
+                (false, true) => {
+                    work_tree = find_work_tree()?;
+                    grep::GrepSource::WorkTree(&work_tree)
+                }
+            };
+            let options = grep::GrepOptions {
+                line_number,
+                ignore_case,
+            };
+            if !grep::grep(&git_dir, source, &pattern, &paths, &options)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Commands::Describe {
+            commit_ish,
+            tags,
+            abbrev,
+            dirty,
+        } => {
+            let git_dir = find_git_dir()?;
+            let rev = commit_ish.as_deref().unwrap_or("HEAD");
+            let commit = Repository::open(&git_dir)
+                .peel_to(&resolve_rev(&git_dir, rev)?, ObjectType::Commit)?;
+            let mut name = describe::describe(&git_dir, &commit, tags, abbrev)?;
+            if let Some(suffix) = dirty {
+                if status::is_dirty(&git_dir, &find_work_tree()?)? {
+                    name.push_str(&suffix);
+                }
+            }
+            println!("{}", name);
+            Ok(())
+        }
+        Commands::Branch {
+            name,
+            start_point,
+            delete,
+            force_delete,
+        } => {
+            let git_dir = find_git_dir()?;
+            match name {
+                Some(name) if delete || force_delete => {
+                    delete_branch(&git_dir, &name, force_delete)
+                }
+                Some(name) => create_branch(&git_dir, &name, start_point.as_deref()),
+                None => list_branches(&git_dir),
+            }
+        }
+        Commands::Tag {
+            name,
+            object,
+            annotate: _,
+            message,
+            delete,
+        } => {
+            let git_dir = find_git_dir()?;
+            match name {
+                Some(name) if delete => delete_tag(&git_dir, &name),
+                Some(name) => create_tag(&git_dir, &name, object.as_deref(), message.as_deref()),
+                None => list_tags(&git_dir),
+            }
+        }
+        Commands::UpdateRef {
+            delete,
+            name,
+            values,
+            message,
+        } => update_ref(
+            &find_git_dir()?,
+            &name,
+            delete,
+            &values,
+            message.as_deref().unwrap_or_default(),
+        ),
+        Commands::SymbolicRef {
+            name,
+            target,
+            short,
+        } => {
+            let git_dir = find_git_dir()?;
+            if let Some(target) = target {
+                if !target.starts_with("refs/") || !is_valid_ref_name(&target) {
+                    return Err(anyhow!("Refusing to point {} at '{}'.", name, target));
+                }
+                return RefLock::acquire(&git_dir, &name)?.commit_symref(&target);
+            }
+            let target = read_symref(&git_dir, &name)?
+                .ok_or_else(|| anyhow!("ref {} is not a symbolic ref", name))?;
+            match short {
+                true => println!("{}", short_ref_name(&target)),
+                false => println!("{}", target),
+            }
+            Ok(())
+        }
+        Commands::Checkout { rev } => {
+            checkout::checkout(&find_git_dir()?, &find_work_tree()?, &rev)
+        }
+        Commands::Merge { branch } => {
+            if !merge::merge(&find_git_dir()?, &find_work_tree()?, &branch)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Commands::Archive {
+            rev,
+            format,
+            prefix,
+            output,
+        } => {
+            let git_dir = find_git_dir()?;
+            let format = format.unwrap_or(match &output {
+                Some(path) if path.extension().is_some_and(|x| x == "zip") => archive::Format::Zip,
+                _ => archive::Format::Tar,
+            });
+            match output {
+                Some(path) => {
+                    let mut file = std::io::BufWriter::new(
+                        fs::File::create(&path)
+                            .with_context(|| format!("Failed to create {}", path.display()))?,
+                    );
+                    archive::archive(&git_dir, &rev, format, &prefix, &mut file)?;
+                    file.flush()?;
+                }
+                None => {
+                    let mut out = std::io::stdout().lock();
+                    archive::archive(&git_dir, &rev, format, &prefix, &mut out)?;
+                    out.flush()?;
+                }
+            }
+            Ok(())
+        }
+        Commands::Blame { path } => {
+            let work_tree = find_work_tree()?;
+            let path = index_path_of(&work_tree, Path::new(&path))?;
+            blame::blame(&find_git_dir()?, &work_tree, &path)
+        }
+        Commands::Stash { command } => {
+            let git_dir = find_git_dir()?;
+            let clean = match command.unwrap_or(StashCommands::Push { message: None }) {
+                StashCommands::Push { message } => {
+                    stash::push(&git_dir, &find_work_tree()?, message.as_deref())?;
+                    true
+                }
+                StashCommands::List => {
+                    stash::list(&git_dir)?;
+                    true
+                }
+                StashCommands::Apply { stash } => {
+                    stash::apply(&git_dir, &find_work_tree()?, stash.as_deref())?
+                }
+                StashCommands::Pop { stash } => {
+                    stash::pop(&git_dir, &find_work_tree()?, stash.as_deref())?
+                }
+                StashCommands::Drop { stash } => {
+                    stash::drop(&git_dir, stash.as_deref())?;
+                    true
+                }
+            };
+            if !clean {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Commands::CherryPick { rev } => {
+            if !merge::cherry_pick(&find_git_dir()?, &find_work_tree()?, &rev)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Commands::MergeBase {
+            rev1,
+            rev2,
+            all,
+            is_ancestor,
+        } => {
+            let git_dir = find_git_dir()?;
+            let repository = Repository::open(&git_dir);
+            let a = repository.peel_to(&resolve_rev(&git_dir, &rev1)?, ObjectType::Commit)?;
+            let b = repository.peel_to(&resolve_rev(&git_dir, &rev2)?, ObjectType::Commit)?;
+            let mut graph = graph::CommitGraph::new(&git_dir);
+            if is_ancestor {
+                std::process::exit(match graph.is_ancestor(&a, &b)? {
+                    true => 0,
+                    false => 1,
+                });
+            }
+            let bases = graph.merge_bases(&a, &b)?;
+            // Like git, unrelated histories only show in the exit status.
+            if bases.is_empty() {
+                std::process::exit(1);
+            }
+            let shown = match all {
+                true => bases.len(),
+                false => 1,
+            };
+            for base in &bases[..shown] {
+                println!("{}", base);
+            }
+            Ok(())
+        }
+        Commands::Reset {
+            rev,
+            soft,
+            mixed: _,
+            hard,
+            quiet,
+        } => {
+            let mode = match (soft, hard) {
+                (true, _) => checkout::ResetMode::Soft,
+                (_, true) => checkout::ResetMode::Hard,
+                _ => checkout::ResetMode::Mixed,
+            };
+            checkout::reset(&find_git_dir()?, &find_work_tree()?, &rev, mode, quiet)
+        }
+        Commands::ShowRef { head } => {
+            let git_dir = find_git_dir()?;
+            if head {
+                if let Some(object_id) = resolve_ref(&git_dir, "HEAD")? {
+                    println!("{} HEAD", object_id);
+                }
+            }
+            for (name, object_id) in list_refs(&git_dir)? {
+                println!("{} {}", object_id, name);
+            }
+            Ok(())
+        }
+        Commands::Config {
+            name,
+            value,
+            global,
+            list,
+            get_all,
+            unset,
+        } => {
+            let path = match global {
+                true => Some(Config::global_path().ok_or_else(|| anyhow!("$HOME is not set."))?),
+                false => find_git_dir().ok().map(|x| Config::path(&x)),
+            };
+            if unset || value.is_some() {
+                let path = path.ok_or_else(|| anyhow!("Not in a git repository."))?;
+                let name = name.expect("clap requires a name with a value or --unset");
+                if !config::write_value(&path, &name, value.as_deref())? && unset {
+                    std::process::exit(5);
+                }
+                return Ok(());
+            }
+
+            // Without --global, git reads the user's config and then the repository's.
+            let config = match (global, find_git_dir()) {
+                (true, _) => Config::load_global()?,
+                (false, Result::Ok(git_dir)) => Config::load_all(&git_dir)?,
+                (false, Err(_)) => Config::load_global()?,
+            };
+            let Some(name) = name.filter(|_| !list) else {
+                for (name, value) in config.entries() {
+                    match value {
+                        Some(value) => println!("{}={}", name, value),
+                        None => println!("{}", name),
+                    }
+                }
+                return Ok(());
+            };
+            let values = match get_all {
+                true => config.get_all(&name),
+                false => config.get(&name).into_iter().collect(),
+            };
+            if values.is_empty() {
+                std::process::exit(1);
+            }
+            for value in values {
+                println!("{}", value);
+            }
+            Ok(())
+        }
+        Commands::RevParse { revs } => {
+            let git_dir = find_git_dir()?;
+            for rev in revs {
+                println!("{}", resolve_rev(&git_dir, &rev)?);
+            }
+            Ok(())
+        }
+        Commands::CheckHead => {
+            let git_dir = find_git_dir()?;
+            check_head(&git_dir)
+        }
+        Commands::Fsck => fsck::fsck(&find_git_dir()?),
+        Commands::CheckSharding { fix } => {
+            let git_dir = find_git_dir()?;
+            shards::check_sharding(&git_dir, fix)
+        }
+        Commands::FastExport { rev } => {
+            let git_dir = find_git_dir()?;
+            let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+            fast_export::fast_export(&git_dir, &rev, &mut stdout)?;
+            stdout.flush()?;
+            Ok(())
+        }
+        Commands::FastImport { stats } => {
+            let git_dir = find_git_dir()?;
+            let mut stream = Vec::new();
+            std::io::stdin().read_to_end(&mut stream)?;
+
+            let import_stats = fast_import::fast_import(&git_dir, &stream)?;
+            info!(
+                "Imported {} blobs, {} commits, {} tags and {} resets.",
+                import_stats.blobs, import_stats.commits, import_stats.tags, import_stats.resets
+            );
+            stats.print();
+            Ok(())
+        }
+        Commands::UnpackObjects => {
+            let git_dir = find_git_dir()?;
+            pack::require_sha1(&git_dir)?;
+            let mut data = Vec::new();
+            std::io::stdin().read_to_end(&mut data)?;
+
+            let count = pack::unpack_objects(&git_dir, &data)?;
+            info!("Unpacked {} objects.", count);
+            Ok(())
+        }
+        Commands::IndexPack { pack: None, .. } => {
+            let git_dir = find_git_dir()?;
+            pack::require_sha1(&git_dir)?;
+            let mut data = Vec::new();
+            std::io::stdin().read_to_end(&mut data)?;
+            let name = pack::store_pack(&git_dir, &data)?;
+            println!("pack\t{}", name.trim_start_matches("pack-"));
+            Ok(())
+        }
+        Commands::IndexPack {
+            pack: Some(pack_path),
+            output,
+            ..
+        } => {
+            if !pack_path.extension().is_some_and(|x| x == "pack") {
+                return Err(anyhow!(
+                    "{}: a pack's name must end in .pack.",
+                    pack_path.display()
+                ));
+            }
+            let data =
+                fs::read(&pack_path).with_context(|| format!("Read {}", pack_path.display()))?;
+            let idx = pack::index_pack(&data)?;
+            let idx_path = output.unwrap_or_else(|| pack_path.with_extension("idx"));
+            fs::write(&idx_path, &idx).with_context(|| format!("Write {}", idx_path.display()))?;
+            println!("{}", hex::encode(&data[data.len() - 20..]));
+            Ok(())
+        }
+        Commands::Gc { prune } => {
+            let prune = prune.as_deref().map(date::parse_date).transpose()?;
+            gc::gc(&find_git_dir()?, prune)
+        }
+        Commands::PackObjects {
+            base_name,
+            stdout: _,
+            revs,
+        } => {
+            let git_dir = find_git_dir()?;
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            pack_objects::pack_objects(
+                &git_dir,
+                base_name.as_deref(),
+                revs,
+                &input,
+                &mut std::io::stdout().lock(),
+            )
+        }
+        Commands::Status {
+            recurse_submodules,
+            porcelain,
+        } => {
+            let git_dir = find_git_dir()?;
+            status::status(&git_dir, &find_work_tree()?, recurse_submodules, porcelain)
+        }
+        Commands::RefreshIndex => {
+            if !refresh_index(&find_git_dir()?, &find_work_tree()?)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Commands::Add { paths, no_filters } => {
+            add::add(&find_git_dir()?, &find_work_tree()?, &paths, no_filters)
+        }
+        Commands::Rm {
+            paths,
+            cached,
+            recursive,
+            force,
+        } => {
+            let options = rm::RmOptions {
+                cached,
+                recursive,
+                force,
+            };
+            rm::rm(&find_git_dir()?, &find_work_tree()?, &paths, options)
+        }
+        Commands::Mv { from, to } => mv::mv(&find_git_dir()?, &find_work_tree()?, &from, &to),
+        Commands::CheckIgnore {
+            verbose,
+            non_matching,
+            no_index,
+            paths,
+        } => {
+            let git_dir = find_git_dir()?;
+            let work_tree = find_work_tree()?;
+            let index = index::Index::load(&git_path(&git_dir, "index"))?;
+            let mut any = false;
+            for file_path in &paths {
+                let path = index_path_of(&work_tree, file_path)?;
+                // A trailing slash says the path is a directory, as does the work tree.
+                let is_dir = file_path.to_string_lossy().ends_with('/') || file_path.is_dir();
+                let tracked = !no_index && index.entries.iter().any(|x| x.path == path);
+                let rule = match tracked {
+                    true => None,
+                    false => ignore::check_path(&work_tree, &git_dir, &path, is_dir),
+                };
+                match rule {
+                    Some(rule) if verbose => {
+                        println!(
+                            "{}:{}:{}\t{}",
+                            rule.source,
+                            rule.line,
+                            rule.pattern,
+                            file_path.display()
+                        );
+                        any = true;
+                    }
+                    Some(rule) if !rule.negated => {
+                        println!("{}", file_path.display());
+                        any = true;
+                    }
+                    _ if non_matching => println!("::\t{}", file_path.display()),
+                    _ => {}
+                }
+            }
+            if !any {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Commands::UpdateIndex {
+            add,
+            remove,
+            refresh,
+            paths,
+        } => {
+            let (git_dir, work_tree) = (find_git_dir()?, find_work_tree()?);
+            let clean = !refresh || refresh_index(&git_dir, &work_tree)?;
+            if !paths.is_empty() {
+                update_index(&git_dir, &work_tree, &paths, add, remove)?;
+            }
+            if !clean {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Commands::LsFiles {
+            stage,
+            cached,
+            others,
+            exclude_standard,
+        } => {
+            let git_dir = find_git_dir()?;
+            let index = index::Index::load(&git_path(&git_dir, "index"))?;
+            if others {
+                let work_tree = find_work_tree()?;
+                let ignore = exclude_standard.then(|| IgnoreRules::load(&work_tree, &git_dir));
+                for path in
+                    status::other_files(&git_dir, &work_tree, &index, ignore.as_ref(), false)
+                {
+                    println!("{}", path);
+                }
+                // Like git, `--stage` shows the index too.
+                if !cached && !stage {
+                    return Ok(());
+                }
+            }
+            for entry in index.entries {
+                if stage {
+                    println!(
+                        "{:06o} {} {}\t{}",
+                        entry.mode,
+                        hex::encode(&entry.hash),
+                        entry.stage(),
+                        entry.path
+                    );
+                } else {
+                    println!("{}", entry.path);
+                }
+            }
+            Ok(())
+        }
+        Commands::Worktree { command } => {
+            let git_dir = find_git_dir()?;
+            match command {
+                WorktreeCommands::List => worktree::list(&git_dir),
+                WorktreeCommands::Add {
+                    path,
+                    commit_ish,
+                    new_branch,
+                    detach,
+                } => {
+                    let head = match (new_branch.as_deref(), detach) {
+                        (Some(name), _) => worktree::NewHead::NewBranch(name),
+                        (None, true) => worktree::NewHead::Detach,
+                        (None, false) => worktree::NewHead::Checkout,
+                    };
+                    worktree::add(&git_dir, &path, commit_ish.as_deref(), head)
+                }
+                WorktreeCommands::Remove { path, force } => {
+                    worktree::remove(&git_dir, &path, force)
+                }
+            }
+        }
+        Commands::Bundle { command } => match command {
+            BundleCommands::Create { file, revs, all } => {
+                bundle::create(&find_git_dir()?, &file, &revs, all)
+            }
+            BundleCommands::Verify { file } => bundle::verify(&find_git_dir()?, &file),
+            BundleCommands::ListHeads { file } => bundle::list_heads(&file),
+        },
+        Commands::Clone {
+            url,
+            directory,
+            mirror,
+            depth,
+            auth,
+        } => {
+            auth.apply();
+            let directory = directory.unwrap_or_else(|| clone::default_directory(&url, mirror));
+            match mirror {
+                true => info!("Cloning into bare repository '{}'...", directory.display()),
+                false => info!("Cloning into '{}'...", directory.display()),
+            }
+            clone::clone(&url, &directory, mirror, depth)
+        }
+        Commands::Fetch {
+            remote,
+            depth,
+            auth,
+        } => {
+            auth.apply();
+            fetch::fetch(&find_git_dir()?, &remote, depth)
+        }
+        Commands::UploadPack { directory } => upload_pack::upload_pack(
+            &local::git_dir_of(&directory)?,
+            std::io::stdin().lock(),
+            std::io::stdout().lock(),
+        ),
+        Commands::Push {
+            remote,
+            refspecs,
+            force,
+            no_verify,
+            auth,
+        } => {
+            auth.apply();
+            push::push(&find_git_dir()?, &remote, &refspecs, force, !no_verify)
+        }
+    }
+}
+
+/// Installs a stderr subscriber when `RUST_LOG` or `--trace` asks for one.
+///
+/// Without a subscriber every span and event is disabled at its callsite, so
+/// the instrumentation costs nothing unless tracing was requested.
+fn init_tracing(trace: bool) {
+    let filter = match env::var("RUST_LOG") {
+        Result::Ok(directives) if !directives.is_empty() => EnvFilter::new(directives),
+        _ if trace => EnvFilter::new("codecrafters_git=trace"),
+        _ => return,
+    };
 
-        #[arg(long, short)]
-        write: bool,
-    },
-    LsTree {
-        object_id: String,
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
+        .init();
+}
 
-        #[arg(long, short)]
-        name_only: bool,
-    },
+/// Rewrites git's `-M<n>` form to `-M=<n>`.
+///
+/// clap can only accept an optional value for a short flag when it's attached with
+/// `=`; otherwise `-M <tree>` would swallow the tree as the value.
+fn normalize_glued_value(arg: String) -> String {
+    match arg.strip_prefix("-M") {
+        Some(value) if !value.is_empty() && !value.starts_with('=') => {
+            format!("-M={}", value.trim_end_matches('%'))
+        }
+        _ => arg,
+    }
 }
 
-fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+fn log_filter(
+    since: Option<&str>,
+    until: Option<&str>,
+    first_parent: bool,
+    matching: CommitMatching,
+) -> anyhow::Result<log::Filter> {
+    let compile = |patterns: Vec<String>| {
+        patterns
+            .iter()
+            .map(|x| {
+                // Like git, `^` and `$` anchor at each line of the message.
+                regex::RegexBuilder::new(x)
+                    .multi_line(true)
+                    .build()
+                    .with_context(|| format!("Invalid pattern: {}", x))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+    };
 
-    match cli.commands {
-        Commands::Init => {
-            let cwd = env::current_dir()?;
-            println!("Initializing git in {:#?}", cwd);
-            fs::create_dir(".git").unwrap();
-            fs::create_dir(".git/objects").unwrap();
-            fs::create_dir(".git/refs").unwrap();
-            fs::write(".git/HEAD", "ref: refs/heads/main\n").unwrap();
-            println!("Initialized git directory");
-            Ok(())
-        }
-        Commands::CatFile {
-            object_id,
-            pretty: _,
-        } => {
-            let result = cat_file(object_id)?;
-            match result {
-                Object::Blob(blob) => {
-                    print!("{}", blob.content);
-                }
-                Object::Tree(_tree) => {
-                    print!("TODO");
+    Ok(log::Filter {
+        first_parent,
+        since: since.map(date::parse_date).transpose()?,
+        until: until.map(date::parse_date).transpose()?,
+        authors: compile(matching.authors)?,
+        greps: compile(matching.greps)?,
+        all_match: matching.all_match,
+    })
+}
+
+/// Answers `cat-file --batch-check` for each revision read from stdin, with
+/// `<id> <type> <size>`, or `<rev> missing` when it doesn't name an object.
+/// Only object headers are read, unless `contents` asks for `--batch`, where
+/// each header is followed by the content streamed out and a newline.
+fn cat_file_batch(git_dir: &Path, contents: bool) -> anyhow::Result<()> {
+    let repository = Repository::open(git_dir);
+    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        let rev = line.trim();
+        let header = resolve_rev(git_dir, rev)
+            .and_then(|object_id| Ok((repository.stat_object(&object_id)?, object_id)));
+        match header {
+            Result::Ok((Some((object_type, size)), object_id)) => {
+                writeln!(stdout, "{} {} {}", object_id, object_type, size)?;
+                if contents {
+                    let (_, _, mut reader) = repository.open_object(&object_id)?;
+                    std::io::copy(&mut reader, &mut stdout)?;
+                    writeln!(stdout)?;
                 }
             }
-            Ok(())
+            _ => writeln!(stdout, "{} missing", rev)?,
         }
-        Commands::HashObject { file_path, write } => {
-            let sha_hash = hash_object(file_path, write)?;
-            print!("{}", sha_hash);
+        // Flush per object, so a caller can wait for each answer before asking again.
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+/// Prints the loose object file holding an object, or `pack <file> offset <n>`
+/// when it's only in a pack.
+fn cat_file_path(git_dir: &Path, object_id: &str) -> anyhow::Result<()> {
+    let object_id = resolve_rev(git_dir, object_id)?;
+    let loose_path = Repository::open(git_dir).loose_path(&object_id);
+    if loose_path.exists() {
+        println!("{}", loose_path.display());
+        return Ok(());
+    }
+
+    match pack::find_packed_object(git_dir, &object_id)? {
+        Some((pack_path, offset)) => {
+            println!("pack {} offset {}", pack_path.display(), offset);
             Ok(())
         }
-        Commands::LsTree {
+        None => Err(GitError::ObjectNotFound(object_id.to_string()).into()),
+    }
+}
+
+/// Copies the start of a blob to stdout, stopping the inflate stream as soon as
+/// `limit` bytes (or lines) have been written.
+fn cat_file_head(git_dir: &Path, object_id: &str, limit: u64, lines: bool) -> anyhow::Result<()> {
+    let object_id = resolve_rev(git_dir, object_id)?;
+    let (object_type, _, reader) = Repository::open(git_dir).open_object(&object_id)?;
+    if object_type != ObjectType::Blob {
+        return Err(anyhow!(
+            "Object {} is a {}, not a blob.",
             object_id,
-            name_only,
-        } => {
-            ls_tree(object_id, name_only)?;
-            Ok(())
+            object_type
+        ));
+    }
+
+    let mut stdout = std::io::stdout().lock();
+    if !lines {
+        std::io::copy(&mut reader.take(limit), &mut stdout)?;
+        return Ok(());
+    }
+
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    for _ in 0..limit {
+        line.clear();
+        if std::io::BufRead::read_until(&mut reader, b'\n', &mut line)? == 0 {
+            break;
         }
+        stdout.write_all(&line)?;
     }
+    Ok(())
 }
 
-fn cat_file(object_id: String) -> anyhow::Result<Object> {
-    let git_object = load_git_object(object_id)?;
-    Ok(git_object)
-}
+fn hash_file(
+    file_path: &str,
+    object_type: ObjectType,
+    write: bool,
+    no_filters: bool,
+) -> anyhow::Result<String> {
+    // Outside a repository there are no attributes to apply, and only blobs
+    // are converted.
+    let mut attributes = None;
+    if !no_filters && object_type == ObjectType::Blob {
+        if let (Result::Ok(git_dir), Result::Ok(work_tree)) = (find_git_dir(), find_work_tree()) {
+            if let Some(path) = work_tree_relative_path(&work_tree, Path::new(file_path)) {
+                attributes = Some(attributes::attributes_for(&work_tree, &git_dir, &path));
+            }
+        }
+    }
 
-fn hash_object(file_path: String, write: bool) -> anyhow::Result<String> {
-    let file_content = fs::read_to_string(file_path)?;
-    let content_length = file_content.len();
-    let object_content = format!("blob {}{}{}", content_length, '\0', file_content);
+    // Blobs nothing converts are streamed, so files of any size can be hashed.
+    if object_type == ObjectType::Blob && !attributes.as_ref().is_some_and(attributes::may_convert)
+    {
+        let mut file = fs::File::open(file_path)?;
+        let size = file.metadata()?.len();
+        return hash_blob_stream(size, &mut file, write);
+    }
 
-    let sha_hash = calculate_sha_hash(&object_content);
-    let sha_hash = hex::encode(sha_hash);
+    let mut file_content = fs::read(file_path)?;
+    if let Some(attributes) = &attributes {
+        file_content = attributes::convert_to_git(attributes, file_content);
+    }
+    hash_content(object_type, &file_content, write)
+}
 
+/// Like `hash_content` for a blob of `size` bytes read from `reader`.
+fn hash_blob_stream(size: u64, reader: &mut dyn Read, write: bool) -> anyhow::Result<String> {
     if write {
-        let zlib_content = zlib_compress(&object_content)?;
-        let folder: String = sha_hash.chars().take(2).collect();
-        let object_file_name: String = sha_hash.chars().skip(2).collect();
-        let full_path = format!("./.git/objects/{}/{}", folder, object_file_name);
-        let full_path = Path::new(&full_path);
+        return Ok(Repository::open(&find_git_dir()?).write_stream(
+            ObjectType::Blob,
+            size,
+            reader,
+        )?);
+    }
+    let format = match find_git_dir() {
+        Result::Ok(git_dir) => ObjectFormat::of(&git_dir)?,
+        Err(_) => ObjectFormat::Sha1,
+    };
+    Ok(format.hash_stream("blob", size, reader)?)
+}
+
+/// Refreshes the index's stat data and lists the entries that need
+/// attention, as `git update-index --refresh` does. Returns whether there
+/// were none.
+fn refresh_index(git_dir: &Path, work_tree: &Path) -> anyhow::Result<bool> {
+    let refresh = status::refresh_index(git_dir, work_tree)?;
+    info!("Refreshed {} index entries.", refresh.refreshed);
+    for path in &refresh.unmerged {
+        println!("{}: needs merge", path);
+    }
+    for path in &refresh.modified {
+        println!("{}: needs update", path);
+    }
+    Ok(refresh.modified.is_empty() && refresh.unmerged.is_empty())
+}
 
-        if let Some(parent) = full_path.parent() {
-            fs::create_dir_all(parent)?;
+/// Stages each of `paths` like `git update-index`: hashes the file into a
+/// blob and records it with its mode and stat data. New files need `add`,
+/// and deleted ones are unstaged only with `remove`.
+fn update_index(
+    git_dir: &Path,
+    work_tree: &Path,
+    paths: &[PathBuf],
+    add: bool,
+    remove: bool,
+) -> anyhow::Result<()> {
+    let index_path = git_path(git_dir, "index");
+    let mut index = index::Index::load(&index_path)?;
+
+    for file_path in paths {
+        let path = index_path_of(work_tree, file_path)?;
+        let metadata = match fs::symlink_metadata(file_path) {
+            Result::Ok(metadata) => metadata,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                if !remove {
+                    return Err(anyhow!("{}: does not exist and --remove not passed.", path));
+                }
+                index.remove(&path);
+                continue;
+            }
+            Err(err) => return Err(err).with_context(|| format!("Stat {}.", path)),
+        };
+        if metadata.is_dir() {
+            return Err(anyhow!(
+                "{}: is a directory - add individual files instead.",
+                path
+            ));
         }
+        if !add && !index.entries.iter().any(|x| x.path == path) {
+            return Err(anyhow!(
+                "{}: cannot add to the index - missing --add option?",
+                path
+            ));
+        }
+
+        let content = status::work_tree_content(git_dir, work_tree, &path, &metadata)?;
+        let object_id = Repository::open(git_dir).write(ObjectType::Blob, &content)?;
+        index.add(index::IndexEntry::from_metadata(
+            &path,
+            platform::work_tree_mode(
+                platform::Filesystem::of(git_dir),
+                &metadata,
+                index.mode_of(&path),
+            ),
+            hex::decode(object_id)?,
+            &metadata,
+        ))?;
+    }
+
+    index.write(&index_path)
+}
+
+/// The index path of `path`, a file given relative to the current directory.
+/// Only the directory holding it is resolved, so the file may be missing or
+/// a symlink.
+fn index_path_of(work_tree: &Path, path: &Path) -> anyhow::Result<String> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{}: not a file path.", path.display()))?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let dir = work_tree_relative_path(work_tree, parent)
+        .ok_or_else(|| anyhow!("{}: is outside repository.", path.display()))?;
+    let name = name.to_string_lossy();
+    Ok(match dir.is_empty() {
+        true => name.to_string(),
+        false => format!("{}/{}", dir, name),
+    })
+}
 
-        fs::write(full_path, zlib_content).context("Write object file.")?
+/// Computes the id of `content` as a blob, storing it too with `write`.
+fn hash_content(object_type: ObjectType, content: &[u8], write: bool) -> anyhow::Result<String> {
+    // Outside a repository, ids are SHA-1 like git's.
+    let git_dir = find_git_dir();
+    let format = match &git_dir {
+        Result::Ok(git_dir) => ObjectFormat::of(git_dir)?,
+        Err(_) => ObjectFormat::Sha1,
+    };
+    check_object(object_type, content, format)?;
+
+    if write {
+        return Ok(Repository::open(&git_dir?).write(object_type, content)?);
     }
+    Ok(format.hash_object(&object_type.to_string(), content))
+}
 
-    Ok(sha_hash)
+/// Refuses content that doesn't parse as an object of its type, so that
+/// `hash-object -t` can't write a tree or commit other commands choke on.
+fn check_object(
+    object_type: ObjectType,
+    content: &[u8],
+    format: ObjectFormat,
+) -> anyhow::Result<()> {
+    let parsed = match object_type {
+        ObjectType::Blob => return Ok(()),
+        ObjectType::Tree => {
+            TreeObject::from_bytes(&encode_object("tree", content), format).map(|_| ())
+        }
+        ObjectType::Commit => CommitObject::from_content(content).map(|_| ()),
+        ObjectType::Tag => TagObject::from_content(content).map(|_| ()),
+    };
+    parsed.with_context(|| format!("corrupt {}", object_type))
+}
+
+fn parse_object_type(name: &str) -> anyhow::Result<ObjectType> {
+    Ok(bytes_to_object_type(name.as_bytes())?)
+}
+
+/// Writes every file under `dir` (a `/` terminated path relative to the work
+/// tree, or "" for its root) as a blob and each directory as a tree, and
+/// returns the entries of the tree for `dir` in git's order. `.git` is
+/// skipped, and so are paths `ignore` matches and directories without files,
+/// which git can't store either. Symlinks are stored as links, not followed,
+/// and a directory that is a repository of its own becomes a gitlink to its
+/// checked out commit.
+///
+/// The whole directory is scanned first so that the blobs can be hashed and
+/// written on several threads; the trees are then built in the order of the
+/// scan, so the result doesn't depend on which thread finished first.
+fn write_work_tree_entries(
+    git_dir: &Path,
+    work_tree: &Path,
+    dir: &str,
+    ignore: &IgnoreRules,
+) -> anyhow::Result<Vec<(String, String, Vec<u8>)>> {
+    let nodes = scan_work_tree(work_tree, dir, ignore)?;
+    let mut files = Vec::new();
+    work_tree_files(&nodes, &mut files);
+    let blobs = parallel::map(&files, |(path, metadata)| {
+        let content = status::work_tree_content(git_dir, work_tree, path, metadata)?;
+        Ok(Repository::open(git_dir).write(ObjectType::Blob, &content)?)
+    });
+    build_work_tree_entries(git_dir, nodes, &mut blobs.into_iter())
 }
 
-fn zlib_compress(object_content: &str) -> anyhow::Result<Vec<u8>> {
-    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-    encoder.write_all(object_content.as_bytes())?;
-    let compressed = encoder.finish()?;
-    Ok(compressed)
+/// A work tree file or directory seen by `scan_work_tree`.
+enum WorkTreeNode {
+    /// A file or symlink, its blob yet to be written.
+    File {
+        name: String,
+        path: String,
+        metadata: fs::Metadata,
+    },
+    Gitlink {
+        name: String,
+        commit: String,
+    },
+    Dir {
+        name: String,
+        children: Vec<WorkTreeNode>,
+    },
 }
 
-fn calculate_sha_hash(object_content: &str) -> Vec<u8> {
-    let mut hasher = Sha1::new();
-    hasher.update(object_content.as_bytes());
-    let result = hasher.finalize();
+/// Lists what `write_work_tree_entries` stores under `dir`.
+fn scan_work_tree(
+    work_tree: &Path,
+    dir: &str,
+    ignore: &IgnoreRules,
+) -> anyhow::Result<Vec<WorkTreeNode>> {
+    let mut nodes = Vec::new();
+    for entry in fs::read_dir(work_tree.join(dir))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name == ".git" {
+            continue;
+        }
+        let path = format!("{}{}", dir, name);
+        let metadata = fs::symlink_metadata(entry.path())?;
+        if ignore.is_ignored(&path, metadata.is_dir()) {
+            continue;
+        }
 
-    result[..].to_vec()
+        if metadata.is_dir() && entry.path().join(".git").exists() {
+            let sub_git_dir = git_dir_at(&entry.path())?;
+            let commit = resolve_ref(&sub_git_dir, "HEAD")?
+                .ok_or_else(|| anyhow!("{}: repository has no commit checked out.", path))?;
+            nodes.push(WorkTreeNode::Gitlink { name, commit });
+            continue;
+        }
+        if metadata.is_dir() {
+            let subdir = format!("{}/", path);
+            let children =
+                scan_work_tree(work_tree, &subdir, &ignore.for_subdir(work_tree, &subdir))?;
+            nodes.push(WorkTreeNode::Dir { name, children });
+            continue;
+        }
+        nodes.push(WorkTreeNode::File {
+            name,
+            path,
+            metadata,
+        });
+    }
+    Ok(nodes)
 }
 
-fn ls_tree(object_id: String, name_only: bool) -> anyhow::Result<()> {
-    let tree_object = load_git_object(object_id)?;
+/// The files among `nodes` and under them, depth first.
+fn work_tree_files<'a>(nodes: &'a [WorkTreeNode], files: &mut Vec<(&'a str, &'a fs::Metadata)>) {
+    for node in nodes {
+        match node {
+            WorkTreeNode::File { path, metadata, .. } => files.push((path, metadata)),
+            WorkTreeNode::Dir { children, .. } => work_tree_files(children, files),
+            WorkTreeNode::Gitlink { .. } => {}
+        }
+    }
+}
 
-    match tree_object {
-        Object::Tree(tree) => {
-            if name_only {
-                let names: Vec<String> = tree.elements.iter().map(|x| x.name.clone()).collect();
-                println!("{}", names.join("\n"));
-            } else {
-                for element in tree.elements {
-                    println!(
-                        "{:0>8} {} {}",
-                        element.mode,
-                        hex::encode(element.hash),
-                        element.name
-                    );
+/// Writes the trees for `nodes`, taking the ids of their files' blobs from
+/// `blobs` in the order `work_tree_files` lists them.
+fn build_work_tree_entries(
+    git_dir: &Path,
+    nodes: Vec<WorkTreeNode>,
+    blobs: &mut impl Iterator<Item = anyhow::Result<String>>,
+) -> anyhow::Result<Vec<(String, String, Vec<u8>)>> {
+    let mut entries = Vec::new();
+    for node in nodes {
+        match node {
+            WorkTreeNode::File { name, metadata, .. } => {
+                let blob = blobs.next().expect("a blob is written for every file")?;
+                let filesystem = platform::Filesystem::of(git_dir);
+                let mode = platform::work_tree_mode(filesystem, &metadata, None);
+                let mode = format!("{:o}", mode);
+                entries.push((mode, name, hex::decode(blob)?));
+            }
+            WorkTreeNode::Gitlink { name, commit } => {
+                entries.push(("160000".to_string(), name, hex::decode(commit)?));
+            }
+            WorkTreeNode::Dir { name, children } => {
+                let subtree = build_work_tree_entries(git_dir, children, blobs)?;
+                if !subtree.is_empty() {
+                    let tree = write_tree(git_dir, &subtree)?;
+                    entries.push(("40000".to_string(), name, hex::decode(tree)?));
                 }
             }
+        }
+    }
+    sort_tree_entries(&mut entries);
+    Ok(entries)
+}
 
-            Ok(())
+/// How `ls-tree` prints entries.
+struct LsTreeOptions {
+    name_only: bool,
+    recursive: bool,
+    show_tree_sha: bool,
+}
+
+fn ls_tree(git_dir: &Path, object_id: String, options: &LsTreeOptions) -> anyhow::Result<()> {
+    let object_id = resolve_rev(git_dir, &object_id)?;
+    let tree_id = Repository::open(git_dir).peel_to(&object_id, ObjectType::Tree)?;
+    ls_tree_level(git_dir, &tree_id, "", options)
+}
+
+fn ls_tree_level(
+    git_dir: &Path,
+    tree_id: &str,
+    prefix: &str,
+    options: &LsTreeOptions,
+) -> anyhow::Result<()> {
+    let Object::Tree(tree) = Repository::open(git_dir).object(tree_id)? else {
+        return Err(anyhow!("Invalid object type."));
+    };
+
+    for element in tree.elements {
+        let path = format!("{}{}", prefix, element.name);
+        let hash = hex::encode(&element.hash);
+        if options.recursive && element.mode == "40000" {
+            ls_tree_level(git_dir, &hash, &format!("{}/", path), options)?;
+            continue;
+        }
+
+        if options.show_tree_sha {
+            print!("{} ", tree_id);
+        }
+        match options.name_only {
+            true => println!("{}", path),
+            false => println!(
+                "{:0>6} {} {}\t{}",
+                element.mode, element.object_type, hash, path
+            ),
         }
-        _ => Err(anyhow!("Invalid object type.")),
     }
+
+    Ok(())
 }
 
-fn load_git_object(object_id: String) -> anyhow::Result<Object> {
-    let folder: String = object_id.chars().take(2).collect();
-    let file_name: String = object_id.chars().skip(2).collect();
-    let object_path = format!("./.git/objects/{}/{}", folder, file_name);
+/// Creates an empty repository in `directory`, or fills in what's missing
+/// of an existing one without touching its `HEAD`, config or objects. A
+/// `bare` repository has no work tree: `directory` is its git directory.
+fn init(
+    directory: &Path,
+    object_format: ObjectFormat,
+    initial_branch: Option<String>,
+    bare: bool,
+) -> anyhow::Result<()> {
+    let git_dir = match bare {
+        true => directory.to_path_buf(),
+        false => directory.join(".git"),
+    };
+    let existed = git_dir.join("HEAD").exists();
 
-    let file = fs::File::open(object_path)?;
-    let reader = BufReader::new(file);
+    let branch = match initial_branch {
+        Some(branch) if existed => {
+            info!("warning: re-init: ignored --initial-branch={}", branch);
+            "main".to_string()
+        }
+        Some(branch) => branch,
+        None => Config::load_global()?
+            .get("init.defaultBranch")
+            .map_or("main".to_string(), |x| x.to_string()),
+    };
+    if branch.starts_with('-')
+        || matches!(branch.as_str(), "@" | "HEAD")
+        || !is_valid_ref_name(&format!("refs/heads/{}", branch))
+    {
+        return Err(anyhow!("'{}' is not a valid branch name.", branch));
+    }
+    let head = format!("ref: refs/heads/{}\n", branch);
 
-    let mut decoder = ZlibDecoder::new(reader);
-    let mut buffer: Vec<u8> = Vec::new();
-    decoder.read_to_end(&mut buffer)?;
+    for dir in ["objects", "refs/heads", "refs/tags"] {
+        fs::create_dir_all(git_dir.join(dir))
+            .with_context(|| format!("Create {}.", git_dir.join(dir).display()))?;
+    }
+    // Repository extensions need format version 1, which older readers refuse.
+    let filesystem = platform::Filesystem::probe(&git_dir).config_lines();
+    let config = match object_format {
+        ObjectFormat::Sha1 => {
+            format!(
+                "[core]\n\trepositoryformatversion = 0\n{}\tbare = {}\n",
+                filesystem, bare
+            )
+        }
+        _ => format!(
+            "[core]\n\trepositoryformatversion = 1\n{}\tbare = {}\n\
+             [extensions]\n\tobjectformat = {}\n",
+            filesystem, bare, object_format
+        ),
+    };
+    let files = [
+        ("HEAD", head.as_str()),
+        ("config", config.as_str()),
+        (
+            "description",
+            "Unnamed repository; edit this file 'description' to name the repository.\n",
+        ),
+    ];
+    for (name, content) in files {
+        let path = git_dir.join(name);
+        if !path.exists() {
+            fs::write(&path, content).with_context(|| format!("Write {}.", path.display()))?;
+        }
+    }
 
-    let parts: Vec<&[u8]> = buffer.split(|&byte| byte == 0x00).collect();
-    let header = parts.first().expect("Zlib header not found.");
-    let mut header_iter = header.split(|&byte| byte == 0x20);
-    let object_type_bytes = header_iter.next().unwrap();
-    let object_type = bytes_to_object_type(object_type_bytes)?;
+    let git_dir = git_dir.canonicalize()?;
+    match existed {
+        true => info!(
+            "Reinitialized existing Git repository in {}/",
+            git_dir.display()
+        ),
+        false => info!("Initialized empty Git repository in {}/", git_dir.display()),
+    }
+    Ok(())
+}
 
-    match object_type {
-        ObjectType::Blob => Ok(Object::Blob(BlobObject::from_bytes(&buffer)?)),
-        ObjectType::Tree => Ok(Object::Tree(TreeObject::from_bytes(&buffer)?)),
+/// Locates the git directory for the current working directory: `$GIT_DIR`
+/// when set, otherwise the `.git` of the nearest directory at or above it
+/// that has one.
+///
+/// `.git` is usually a directory, but in submodules and linked worktrees it is
+/// a file containing `gitdir: <path>` that points at the real git directory.
+fn find_git_dir() -> anyhow::Result<PathBuf> {
+    if let Some(git_dir) = env::var_os("GIT_DIR") {
+        return Ok(PathBuf::from(git_dir));
     }
+    git_dir_at(&find_work_tree()?)
 }
 
-#[allow(dead_code)]
-struct BlobObject {
-    length: u32,
-    content: String,
+/// Locates the git directory of the repository whose work tree is `dir`,
+/// following a `.git` file the same way; used for submodules too. Unlike
+/// `find_git_dir`, parent directories aren't searched.
+fn git_dir_at(dir: &Path) -> anyhow::Result<PathBuf> {
+    let dot_git = dir.join(".git");
+
+    if dot_git.is_dir() {
+        return Ok(dot_git);
+    }
+
+    if dot_git.is_file() {
+        return read_gitdir_file(&dot_git);
+    }
+
+    Err(GitError::NotARepository.into())
 }
 
-#[allow(dead_code)]
-struct TreeObject {
-    length: u32,
-    elements: Vec<TreeElement>,
+/// Locates the root of the working tree: the nearest directory at or above the
+/// current one containing `.git`. With `$GIT_DIR` set, it's `$GIT_WORK_TREE`,
+/// or else the current directory, as in git.
+fn find_work_tree() -> anyhow::Result<PathBuf> {
+    let cwd = env::current_dir()?;
+
+    if env::var_os("GIT_DIR").is_some() {
+        return Ok(env::var_os("GIT_WORK_TREE").map_or(cwd, PathBuf::from));
+    }
+    if let Some(dir) = cwd.ancestors().find(|x| x.join(".git").exists()) {
+        return Ok(dir.to_path_buf());
+    }
+
+    Err(GitError::NotARepository.into())
 }
 
-#[derive(Debug)]
-#[allow(dead_code)]
-struct TreeElement {
-    mode: String,
-    object_type: ObjectType,
-    hash: Vec<u8>,
-    name: String,
+/// Whether a directory on the way from the work tree to `path` (a `/`
+/// separated path relative to it) is a symbolic link. Work tree walks and
+/// writes must not go through those, or they could leave the work tree.
+fn leads_through_symlink(work_tree: &Path, path: &str) -> bool {
+    let mut dir = work_tree.to_path_buf();
+    let mut components: Vec<&str> = path.split('/').collect();
+    components.pop();
+
+    components.into_iter().any(|component| {
+        dir.push(component);
+        fs::symlink_metadata(&dir).is_ok_and(|x| x.file_type().is_symlink())
+    })
 }
 
-impl BlobObject {
-    fn from_bytes(input: &[u8]) -> anyhow::Result<BlobObject> {
-        // Split input on null byte
-        let parts: Vec<&[u8]> = input.split(|&byte| byte == 0x00).collect();
+/// Returns `path` relative to the work tree with `/` separators, or `None` if
+/// it lies outside of it.
+fn work_tree_relative_path(work_tree: &Path, path: &Path) -> Option<String> {
+    let work_tree = work_tree.canonicalize().ok()?;
+    let path = path.canonicalize().ok()?;
+    let relative = path.strip_prefix(work_tree).ok()?;
+    Some(platform::tree_path(relative))
+}
 
-        let header = parts.first().expect("Zlib header not found.");
-        // Split header on space
-        let mut header_iter = header.split(|&byte| byte == 0x20);
-        // Check if correct object type.
-        if !bytes_to_object_type(header_iter.next().unwrap()).is_ok_and(|x| x == ObjectType::Blob) {
-            return Err(anyhow!("Object is not of type Blob."));
-        }
-        // Extract length
-        let length = header_iter.next().unwrap();
-        let length: u32 = String::from_utf8(length.to_vec())?.parse::<u32>()?;
+/// Follows a `.git` file of the form `gitdir: <path>` to the directory it names.
+fn read_gitdir_file(dot_git: &Path) -> anyhow::Result<PathBuf> {
+    let content = fs::read_to_string(dot_git).context("Read .git file.")?;
+    let target = content
+        .trim_end()
+        .strip_prefix("gitdir: ")
+        .ok_or_else(|| anyhow!("Invalid gitfile format: {}", dot_git.display()))?;
 
-        // Extract content
-        let content_bytes = parts.last().unwrap().to_vec();
-        let content = String::from_utf8_lossy(&content_bytes);
+    // Relative paths are relative to the directory containing the .git file.
+    let target = Path::new(target);
+    let git_dir = match dot_git.parent() {
+        Some(parent) if target.is_relative() => parent.join(target),
+        _ => target.to_path_buf(),
+    };
 
-        Ok(BlobObject {
-            length,
-            content: content.to_string(),
-        })
+    if !git_dir.is_dir() {
+        return Err(anyhow!("Not a git repository: {}", git_dir.display()));
     }
+
+    Ok(git_dir)
 }
 
-impl TreeObject {
-    fn from_bytes(input: &[u8]) -> anyhow::Result<TreeObject> {
-        // Read bytes until null byte.
-        let (header_bytes, content_bytes) = match input.iter().position(|&byte| byte == 0) {
-            Some(pos) => (&input[..pos], &input[pos + 1..]),
-            None => (input, input),
+/// Resolves a revision to an object id.
+///
+/// Accepts a full object id, `HEAD`, a ref name (tried as given, then under
+/// `refs/`, `refs/tags/`, `refs/heads/` and `refs/remotes/`) or a unique
+/// abbreviation of at least four hex digits of an object id, or
+/// `<ref>@{<n>}` for what a ref pointed at `n` updates ago by its reflog
+/// (`@{<n>}` alone for the current branch), followed by any number of
+/// suffixes, applied left to right:
+///
+/// - `~N`: the Nth first-parent ancestor (`~` alone is `~1`);
+/// - `^N`: the Nth parent (`^` alone is `^1`, and `^0` the commit itself);
+/// - `^{tree}`, `^{commit}`, `^{blob}`, `^{tag}`: peel to that type, or `^{}`
+///   to dereference tags down to whatever they finally point at.
+#[tracing::instrument(level = "debug", skip(git_dir))]
+fn resolve_rev(git_dir: &Path, rev: &str) -> anyhow::Result<String> {
+    // Ref names can't hold `~` or `^`, so the first one starts the suffixes.
+    let suffix_start = rev.find(['~', '^']).unwrap_or(rev.len());
+    let (name, mut suffixes) = rev.split_at(suffix_start);
+    let repository = Repository::open(git_dir);
+
+    let is_hex = name.chars().all(|x| x.is_ascii_hexdigit());
+    let mut object_id = if name.len() == ObjectFormat::of(git_dir)?.hex_len() && is_hex {
+        name.to_lowercase()
+    } else if let Some((base, n)) = name.strip_suffix('}').and_then(|x| x.rsplit_once("@{")) {
+        resolve_reflog_rev(git_dir, base, n)?
+    } else if let Some(full_name) = expand_ref(git_dir, name)? {
+        resolve_ref(git_dir, &full_name)?.ok_or_else(|| anyhow!("Unknown revision: {}", name))?
+    } else if is_hex && name.len() >= MIN_ABBREV_LEN {
+        // Like git, a ref of the same name wins over an abbreviated id.
+        repository.expand_id(name)?
+    } else {
+        return Err(anyhow!("Unknown revision: {}", name));
+    };
+
+    while !suffixes.is_empty() {
+        if let Some(rest) = suffixes.strip_prefix("^{") {
+            let (peel, rest) = rest
+                .split_once('}')
+                .ok_or_else(|| anyhow!("Unterminated peel suffix in {}", rev))?;
+            suffixes = rest;
+            let target = match peel {
+                "" => {
+                    object_id = repository.peel_tags(&object_id)?;
+                    continue;
+                }
+                "tree" => ObjectType::Tree,
+                "commit" => ObjectType::Commit,
+                "blob" => ObjectType::Blob,
+                "tag" => ObjectType::Tag,
+                other => return Err(anyhow!("Unknown peel type '{}' in {}", other, rev)),
+            };
+            tracing::debug!(%object_id, %target, "peeling");
+            object_id = repository.peel_to(&object_id, target)?;
+            continue;
+        }
+
+        let operator = suffixes.as_bytes()[0];
+        let rest = &suffixes[1..];
+        let digits = rest.len() - rest.trim_start_matches(|x: char| x.is_ascii_digit()).len();
+        let count: usize = match digits {
+            0 => 1,
+            _ => rest[..digits]
+                .parse()
+                .with_context(|| format!("Invalid count in {}", rev))?,
         };
+        suffixes = &rest[digits..];
 
-        // Split header on space
-        let mut header_iter = header_bytes.split(|&byte| byte == 0x20);
-        // Check if correct object type.
-        if !bytes_to_object_type(header_iter.next().unwrap()).is_ok_and(|x| x == ObjectType::Tree) {
-            return Err(anyhow!("Object is not of type Tree."));
-        }
-        // Extract length
-        let length = header_iter.next().unwrap();
-        let length: u32 = String::from_utf8(length.to_vec())?.parse::<u32>()?;
-
-        let mut elements: Vec<TreeElement> = Vec::new();
-
-        let mut current_pos: usize = 0;
-        let mut element_bytes: Vec<u8> = Vec::new();
-        while current_pos < length.try_into().unwrap() {
-            let byte = content_bytes[current_pos];
-
-            if byte == 0 {
-                let hash_bytes: &[u8] = &content_bytes[current_pos..current_pos + 1 + 20];
-                element_bytes.extend_from_slice(hash_bytes);
-                elements.push(TreeElement::from_bytes(&element_bytes)?);
-                current_pos += 21;
-                element_bytes.clear();
-            } else {
-                element_bytes.push(byte);
-                current_pos += 1;
+        object_id = repository.peel_to(&object_id, ObjectType::Commit)?;
+        match operator {
+            b'~' => {
+                for _ in 0..count {
+                    object_id = nth_parent(git_dir, &object_id, 1)?
+                        .ok_or_else(|| anyhow!("Unknown revision: {}", rev))?;
+                }
             }
+            _ if count == 0 => {}
+            _ => {
+                object_id = nth_parent(git_dir, &object_id, count)?
+                    .ok_or_else(|| anyhow!("Unknown revision: {}", rev))?;
+            }
+        }
+    }
+
+    Ok(object_id)
+}
+
+/// The id `base@{n}` names: what the ref `base` pointed at `n` updates ago.
+fn resolve_reflog_rev(git_dir: &Path, base: &str, n: &str) -> anyhow::Result<String> {
+    let n: usize = n
+        .parse()
+        .map_err(|_| anyhow!("Unsupported reflog selector: {}@{{{}}}", base, n))?;
+    let full_name = match base {
+        "HEAD" => "HEAD".to_string(),
+        "" | "@" => read_symref(git_dir, "HEAD")?.unwrap_or_else(|| "HEAD".to_string()),
+        _ => expand_ref(git_dir, base)?.ok_or_else(|| anyhow!("Unknown revision: {}", base))?,
+    };
+
+    let entries = refs::read_reflog(git_dir, &full_name)?;
+    let shown = short_ref_name(&full_name);
+    match entries.len().checked_sub(n + 1) {
+        Some(index) => Ok(entries[index].new.clone()),
+        // Like git, one past the oldest entry is what the ref held before it.
+        None if entries.is_empty() => Err(anyhow!("Log for '{}' is empty.", shown)),
+        None if n == entries.len() && entries[0].old.bytes().any(|x| x != b'0') => {
+            Ok(entries[0].old.clone())
+        }
+        None => Err(anyhow!(
+            "Log for '{}' only has {} entries.",
+            shown,
+            entries.len()
+        )),
+    }
+}
+
+/// The `n`th parent (counting from 1) of a commit, or `None` if it has
+/// fewer parents.
+fn nth_parent(git_dir: &Path, commit_id: &str, n: usize) -> anyhow::Result<Option<String>> {
+    let (_, content) = Repository::open(git_dir).read(commit_id)?;
+    let commit = CommitObject::from_content(&content)?;
+    Ok(commit.parents.into_iter().nth(n - 1))
+}
+
+/// Reports whether `HEAD` is on a branch, detached or unborn, and fails when
+/// it leads to a missing object or to something other than a commit.
+fn check_head(git_dir: &Path) -> anyhow::Result<()> {
+    let head_target = read_symref(git_dir, "HEAD")?;
+    let branch = match head_target {
+        Some(_) => expand_ref(git_dir, "HEAD")?,
+        None => None,
+    };
+
+    let Some(object_id) = resolve_ref(git_dir, "HEAD")? else {
+        println!(
+            "HEAD: unborn branch {}",
+            head_target.as_deref().unwrap_or("HEAD")
+        );
+        return Ok(());
+    };
+    let repository = Repository::open(git_dir);
+    if !repository.exists(&object_id)? {
+        return Err(anyhow!(
+            "broken HEAD (points to missing object {})",
+            object_id
+        ));
+    }
+    let (object_type, _) = repository.read_header(&object_id)?;
+    if object_type != ObjectType::Commit {
+        return Err(anyhow!(
+            "broken HEAD (points to {}, a {} rather than a commit)",
+            object_id,
+            object_type
+        ));
+    }
+
+    match branch {
+        Some(branch) => println!("HEAD: {} at {}", branch, object_id),
+        None => println!("HEAD: detached at {}", object_id),
+    }
+    Ok(())
+}
+
+/// Prints every branch, marking the one `HEAD` is on with `*`, or first
+/// where `HEAD` is detached.
+fn list_branches(git_dir: &Path) -> anyhow::Result<()> {
+    let current = read_symref(git_dir, "HEAD")?;
+    if current.is_none() {
+        if let Some(head) = resolve_ref(git_dir, "HEAD")? {
+            println!("* (HEAD detached at {})", short_id(&head));
         }
+    }
+    for (name, _) in list_refs(git_dir)? {
+        let Some(branch) = name.strip_prefix("refs/heads/") else {
+            continue;
+        };
+        match current.as_deref() == Some(name.as_str()) {
+            true => println!("* {}", branch),
+            false => println!("  {}", branch),
+        }
+    }
+    Ok(())
+}
 
-        Ok(TreeObject { length, elements })
+fn create_branch(git_dir: &Path, name: &str, start_point: Option<&str>) -> anyhow::Result<()> {
+    let full_name = format!("refs/heads/{}", name);
+    if name.starts_with('-') || name == "HEAD" || !is_valid_ref_name(&full_name) {
+        return Err(anyhow!("'{}' is not a valid branch name.", name));
+    }
+    if resolve_ref(git_dir, &full_name)?.is_some() {
+        return Err(anyhow!("A branch named '{}' already exists.", name));
     }
+    let start_point = start_point.unwrap_or("HEAD");
+    let commit = resolve_rev(git_dir, start_point)
+        .and_then(|x| Ok(Repository::open(git_dir).peel_to(&x, ObjectType::Commit)?))
+        .with_context(|| format!("Not a valid object name: '{}'.", start_point))?;
+    write_ref(git_dir, &full_name, &commit)?;
+    record_ref_update(
+        git_dir,
+        &full_name,
+        None,
+        &commit,
+        &format!("branch: Created from {}", start_point),
+    )
 }
 
-impl TreeElement {
-    fn from_bytes(input: &[u8]) -> anyhow::Result<TreeElement> {
-        // Read bytes until space.
-        let mode_bytes = match input.iter().position(|&byte| byte == 32) {
-            Some(pos) => &input[..pos],
-            None => input,
+/// Deletes a branch other than the current one. Unless `force`d, its tip
+/// must be reachable from `HEAD`, so no commits are lost with it.
+fn delete_branch(git_dir: &Path, name: &str, force: bool) -> anyhow::Result<()> {
+    let full_name = format!("refs/heads/{}", name);
+    let tip =
+        resolve_ref(git_dir, &full_name)?.ok_or_else(|| anyhow!("branch '{}' not found.", name))?;
+    if read_symref(git_dir, "HEAD")?.as_deref() == Some(full_name.as_str()) {
+        return Err(anyhow!("Cannot delete branch '{}' checked out.", name));
+    }
+    if !force {
+        let merged = match resolve_ref(git_dir, "HEAD")? {
+            Some(head) => log::walk(git_dir, &head, &log::Filter::default())?
+                .iter()
+                .any(|(id, _)| *id == tip),
+            None => false,
         };
+        if !merged {
+            return Err(anyhow!(
+                "The branch '{}' is not fully merged. If you are sure you want to delete it, run 'branch -D {}'.",
+                name,
+                name
+            ));
+        }
+    }
+    refs::delete_ref(git_dir, &full_name)?;
+    println!("Deleted branch {} (was {}).", name, short_id(&tip));
+    Ok(())
+}
 
-        let mode = String::from_utf8(mode_bytes.to_vec())?;
+fn list_tags(git_dir: &Path) -> anyhow::Result<()> {
+    for (name, _) in list_refs(git_dir)? {
+        if let Some(tag) = name.strip_prefix("refs/tags/") {
+            println!("{}", tag);
+        }
+    }
+    Ok(())
+}
 
-        let content_iter = input[mode_bytes.len() + 1..].iter();
+/// Tags `object` (`HEAD` by default): with a `message`, through an annotated
+/// tag object tagged by the committer, otherwise with just the ref.
+fn create_tag(
+    git_dir: &Path,
+    name: &str,
+    object: Option<&str>,
+    message: Option<&str>,
+) -> anyhow::Result<()> {
+    let full_name = format!("refs/tags/{}", name);
+    if name.starts_with('-') || !is_valid_ref_name(&full_name) {
+        return Err(anyhow!("'{}' is not a valid tag name.", name));
+    }
+    if resolve_ref(git_dir, &full_name)?.is_some() {
+        return Err(anyhow!("tag '{}' already exists", name));
+    }
+    let object = object.unwrap_or("HEAD");
+    let object_id = resolve_rev(git_dir, object)
+        .with_context(|| format!("Failed to resolve '{}' as a valid ref.", object))?;
 
-        let mut name_bytes: Vec<u8> = Vec::new();
-        for b in content_iter {
-            if *b == 0 {
-                break;
+    let mut repository = Repository::open(git_dir);
+    let target = match message {
+        Some(message) => {
+            let (object_type, _) = repository.read_header(&object_id)?;
+            let mut message = message.to_string();
+            if !message.is_empty() && !message.ends_with('\n') {
+                message.push('\n');
             }
-            name_bytes.push(*b);
+            let tag = TagObject {
+                object: object_id,
+                object_type,
+                tag: name.to_string(),
+                tagger: Some(identity(&Config::load_all(git_dir)?, "COMMITTER")?),
+                message,
+            };
+            repository.write(ObjectType::Tag, &tag.to_content())?
         }
-        let name = String::from_utf8(name_bytes)?;
+        None => object_id,
+    };
+    write_ref(git_dir, &full_name, &target)
+}
 
-        let hash_begin_pos = mode_bytes.len() + 1 + name.len();
-        let hash: Vec<u8> = input[hash_begin_pos..hash_begin_pos + 20].to_vec();
+fn delete_tag(git_dir: &Path, name: &str) -> anyhow::Result<()> {
+    let full_name = format!("refs/tags/{}", name);
+    let target =
+        resolve_ref(git_dir, &full_name)?.ok_or_else(|| anyhow!("tag '{}' not found.", name))?;
+    refs::delete_ref(git_dir, &full_name)?;
+    println!("Deleted tag '{}' (was {})", name, short_id(&target));
+    Ok(())
+}
 
-        Ok(TreeElement {
-            mode,
-            object_type: ObjectType::Blob,
-            hash,
-            name,
-        })
+/// Updates or deletes a ref like `git update-ref`. A symbolic ref is
+/// followed, so updating `HEAD` moves the branch it is on. Updates are
+/// recorded in the reflog with `message`.
+fn update_ref(
+    git_dir: &Path,
+    name: &str,
+    delete: bool,
+    values: &[String],
+    message: &str,
+) -> anyhow::Result<()> {
+    if name != "HEAD" && !is_valid_ref_name(name) {
+        return Err(anyhow!("'{}' is not a valid ref name.", name));
+    }
+    let (new_value, old_value) = match (delete, values) {
+        (true, [old]) => (None, Some(old)),
+        (true, []) => (None, None),
+        (false, [new]) => (Some(new), None),
+        (false, [new, old]) => (Some(new), Some(old)),
+        _ => {
+            return Err(anyhow!(
+                "usage: update-ref [-d] <ref> [<new value>] [<old value>]"
+            ))
+        }
+    };
+
+    let mut target = name.to_string();
+    for _ in 0..5 {
+        match read_symref(git_dir, &target)? {
+            Some(next) => target = next,
+            None => break,
+        }
     }
+
+    let lock = RefLock::acquire(git_dir, &target)?;
+    let current = resolve_ref(git_dir, &target)?;
+    if let Some(old_value) = old_value {
+        let expected = match old_value.bytes().all(|x| x == b'0') {
+            true => None,
+            false => Some(resolve_rev(git_dir, old_value)?),
+        };
+        if current != expected {
+            return Err(anyhow!(
+                "Cannot update {}: it is at {} but {} was expected.",
+                target,
+                current.as_deref().unwrap_or("nothing"),
+                expected.as_deref().unwrap_or("nothing")
+            ));
+        }
+    }
+    match new_value {
+        Some(new_value) => {
+            let new_value = resolve_rev(git_dir, new_value)?;
+            lock.commit(&new_value)?;
+            record_ref_update(git_dir, &target, current.as_deref(), &new_value, message)
+        }
+        None => match lock.delete(git_dir)? {
+            true => Ok(()),
+            false => Err(anyhow!("Cannot delete {}: it doesn't exist.", target)),
+        },
+    }
+}
+
+/// A ref name without the `refs/heads/`, `refs/tags/` or `refs/remotes/`
+/// prefix it was found under.
+fn short_ref_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|x| name.strip_prefix(x))
+        .unwrap_or(name)
+}
+
+/// Writes a commit object for `tree` with the given parents, in order.
+///
+/// The author and committer come from `GIT_{AUTHOR,COMMITTER}_{NAME,EMAIL,DATE}`,
+/// falling back to `user.name` and `user.email`; dates are taken as
+/// `<unix seconds> <offset>` and default to now in UTC. A given `author`
+/// line, such as one kept from another commit, is used as is.
+fn commit_tree(
+    git_dir: &Path,
+    tree: &str,
+    parents: &[String],
+    author: Option<&str>,
+    message: &str,
+) -> anyhow::Result<String> {
+    let mut content = format!("tree {}\n", tree);
+    for parent in parents {
+        content.push_str(&format!("parent {}\n", parent));
+    }
+    let config = Config::load_all(git_dir)?;
+    let author = match author {
+        Some(author) => author.to_string(),
+        None => identity(&config, "AUTHOR")?,
+    };
+    content.push_str(&format!("author {}\n", author));
+    content.push_str(&format!("committer {}\n", identity(&config, "COMMITTER")?));
+    content.push('\n');
+    content.push_str(message);
+    if !message.ends_with('\n') {
+        content.push('\n');
+    }
+
+    Ok(Repository::open(git_dir).write(ObjectType::Commit, content.as_bytes())?)
+}
+
+/// Writes the index as a tree and commits it on top of `HEAD`, moving the
+/// branch `HEAD` points to (or `HEAD` itself when detached).
+///
+/// Like git, a commit that wouldn't change the tree is refused unless
+/// `allow_empty` is set; on an unborn branch that means an empty index.
+/// Concluding a conflicted merge, the commits in `MERGE_HEAD` become further
+/// parents; concluding a cherry-pick, the picked commit's author is kept.
+///
+/// With `verify`, the pre-commit hook may stop the commit before it's made,
+/// and the commit-msg hook may reject or rewrite the message, which it gets
+/// in `COMMIT_EDITMSG`. The post-commit hook runs once the branch moved.
+fn commit(git_dir: &Path, message: &str, allow_empty: bool, verify: bool) -> anyhow::Result<()> {
+    // The hook may stage more, so the index is read after it.
+    if verify && !hooks::run(git_dir, "pre-commit", &[], &[])? {
+        return Err(anyhow!(
+            "The pre-commit hook failed; nothing was committed."
+        ));
+    }
+    let index_path = git_path(git_dir, "index");
+    let mut index = index::Index::load(&index_path)?;
+    if index.entries.iter().any(|x| x.stage() != 0) {
+        return Err(anyhow!(
+            "Committing is not possible because you have unmerged files."
+        ));
+    }
+    let tree = index.write_tree(git_dir)?;
+    index.write(&index_path)?;
+    let merge_heads: Vec<String> = match fs::read_to_string(git_path(git_dir, "MERGE_HEAD")) {
+        Result::Ok(content) => content.lines().map(str::to_string).collect(),
+        Err(_) => Vec::new(),
+    };
+
+    let head = resolve_ref(git_dir, "HEAD")?;
+    let repository = Repository::open(git_dir);
+    let parent = match &head {
+        Some(head) => Some(repository.peel_to(head, ObjectType::Commit)?),
+        None => None,
+    };
+    let unchanged = match &parent {
+        Some(parent) => {
+            let (_, content) = repository.read(parent)?;
+            CommitObject::from_content(&content)?.tree == tree
+        }
+        None => index.entries.is_empty(),
+    };
+    if unchanged && !allow_empty && merge_heads.is_empty() {
+        return Err(anyhow!(
+            "nothing to commit (use --allow-empty to commit anyway)"
+        ));
+    }
+
+    let message_path = git_dir.join("COMMIT_EDITMSG");
+    let mut message = message.to_string();
+    if !message.ends_with('\n') {
+        message.push('\n');
+    }
+    fs::write(&message_path, &message)
+        .with_context(|| format!("Write {}.", message_path.display()))?;
+    if verify {
+        let path = message_path.to_string_lossy();
+        if !hooks::run(git_dir, "commit-msg", &[&path], &[])? {
+            return Err(anyhow!(
+                "The commit-msg hook failed; nothing was committed."
+            ));
+        }
+        message = fs::read_to_string(&message_path)
+            .with_context(|| format!("Read {}.", message_path.display()))?;
+        if message.trim().is_empty() {
+            return Err(anyhow!("Aborting commit due to empty commit message."));
+        }
+    }
+
+    // A conflicted cherry-pick keeps the picked commit's author.
+    let author = match fs::read_to_string(git_path(git_dir, "CHERRY_PICK_HEAD")) {
+        Result::Ok(content) => Some(log::read_commit(git_dir, content.trim())?.author),
+        Err(_) => None,
+    };
+
+    let parents: Vec<String> = parent.into_iter().chain(merge_heads).collect();
+    let commit_hash = commit_tree(git_dir, &tree, &parents, author.as_deref(), &message)?;
+
+    // Only advance the branch if nobody moved it since it was read.
+    let branch = read_symref(git_dir, "HEAD")?.unwrap_or_else(|| "HEAD".to_string());
+    let lock = RefLock::acquire(git_dir, &branch)?;
+    if resolve_ref(git_dir, &branch)? != head {
+        return Err(anyhow!(
+            "Cannot update {}: it moved while committing.",
+            branch
+        ));
+    }
+    lock.commit(&commit_hash)?;
+
+    let subject = message.lines().next().unwrap_or_default();
+    let action = match parents.len() {
+        0 => "commit (initial)",
+        1 => "commit",
+        _ => "commit (merge)",
+    };
+    record_ref_update(
+        git_dir,
+        &branch,
+        head.as_deref(),
+        &commit_hash,
+        &format!("{}: {}", action, subject),
+    )?;
+    merge::remove_merge_state(git_dir);
+
+    // The summary goes to stdout like git's, but is only informational.
+    if !QUIET.load(Ordering::Relaxed) {
+        let root = match parents.is_empty() {
+            true => " (root-commit)",
+            false => "",
+        };
+        println!(
+            "[{}{} {}] {}",
+            match branch.as_str() {
+                "HEAD" => "detached HEAD",
+                _ => branch.strip_prefix("refs/heads/").unwrap_or(&branch),
+            },
+            root,
+            short_id(&commit_hash),
+            message.lines().next().unwrap_or_default()
+        );
+    }
+    hooks::run(git_dir, "post-commit", &[], &[])?;
+    Ok(())
+}
+
+/// Records an update of the ref `name` in its reflog, and in `HEAD`'s when
+/// it is the branch `HEAD` is on, as git does: `old` is `None` for a ref
+/// that didn't exist yet.
+fn record_ref_update(
+    git_dir: &Path,
+    name: &str,
+    old: Option<&str>,
+    new: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    let committer = identity(&Config::load_all(git_dir)?, "COMMITTER")?;
+    refs::append_reflog(git_dir, name, old, new, &committer, message)?;
+    if name != "HEAD" && read_symref(git_dir, "HEAD")?.as_deref() == Some(name) {
+        refs::append_reflog(git_dir, "HEAD", old, new, &committer, message)?;
+    }
+    Ok(())
 }
 
-fn bytes_to_object_type(object_type_bytes: &[u8]) -> Result<ObjectType, anyhow::Error> {
-    let object_type = String::from_utf8(object_type_bytes.to_vec())?;
+/// Formats `<name> <<email>> <seconds> <offset>` for the author or committer.
+fn identity(config: &Config, role: &str) -> anyhow::Result<String> {
+    let name = env::var(format!("GIT_{}_NAME", role))
+        .ok()
+        .or_else(|| config.get("user.name").map(|x| x.to_string()))
+        .unwrap_or_else(|| "unknown".to_string());
+    let email = env::var(format!("GIT_{}_EMAIL", role))
+        .ok()
+        .or_else(|| config.get("user.email").map(|x| x.to_string()))
+        .unwrap_or_default();
+    let date = match env::var(format!("GIT_{}_DATE", role)) {
+        Result::Ok(date) => date,
+        Err(_) => {
+            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+            format!("{} +0000", now.as_secs())
+        }
+    };
+
+    Ok(format!("{} <{}> {}", name, email, date))
+}
 
-    let object_type = match object_type.as_str() {
-        "blob" => ObjectType::Blob,
-        "tree" => ObjectType::Tree,
-        _ => return Err(anyhow!("Invalid object type.")),
+/// Collects `(path, blob id)` for every blob under a tree, in tree order.
+///
+/// Submodule entries (gitlinks) point at commits in another repository and are skipped.
+fn walk_blobs(
+    git_dir: &Path,
+    tree_id: &str,
+    prefix: &str,
+    blobs: &mut Vec<(String, String)>,
+) -> anyhow::Result<()> {
+    let Object::Tree(tree) = Repository::open(git_dir).object(tree_id)? else {
+        return Err(anyhow!("Object {} is not a tree.", tree_id));
     };
 
-    Ok(object_type)
+    for element in tree.elements {
+        let path = format!("{}{}", prefix, element.name);
+        let hash = hex::encode(&element.hash);
+        match element.mode.as_str() {
+            "40000" => walk_blobs(git_dir, &hash, &format!("{}/", path), blobs)?,
+            "160000" => {}
+            _ => blobs.push((path, hash)),
+        }
+    }
+
+    Ok(())
 }
 
-#[derive(Debug, PartialEq)]
-enum ObjectType {
-    Blob,
-    Tree,
+/// A git-lfs pointer file, stored in place of the real content of large files.
+///
+/// ```text
+/// version https://git-lfs.github.com/spec/v1
+/// oid sha256:<64 hex chars>
+/// size <bytes>
+/// ```
+struct LfsPointer {
+    oid: String,
+    size: u64,
 }
 
-enum Object {
-    Blob(BlobObject),
-    Tree(TreeObject),
+impl LfsPointer {
+    /// Pointer files are tiny; anything bigger is real content.
+    const MAX_LEN: usize = 1024;
+
+    fn parse(content: &str) -> Option<LfsPointer> {
+        if content.len() > LfsPointer::MAX_LEN {
+            return None;
+        }
+
+        let mut lines = content.lines();
+        if !lines
+            .next()?
+            .starts_with("version https://git-lfs.github.com/spec/")
+        {
+            return None;
+        }
+
+        let mut oid = None;
+        let mut size = None;
+        for line in lines {
+            if let Some(value) = line.strip_prefix("oid sha256:") {
+                oid = Some(value.to_string());
+            } else if let Some(value) = line.strip_prefix("size ") {
+                size = value.parse::<u64>().ok();
+            }
+        }
+
+        let oid = oid.filter(|x| x.len() == 64 && x.chars().all(|c| c.is_ascii_hexdigit()))?;
+        Some(LfsPointer { oid, size: size? })
+    }
+
+    /// Reads the real content from an LFS store laid out as `objects/<2>/<2>/<oid>`.
+    fn read_content(&self, lfs_store: &Path) -> anyhow::Result<Vec<u8>> {
+        let path = lfs_store
+            .join("objects")
+            .join(&self.oid[..2])
+            .join(&self.oid[2..4])
+            .join(&self.oid);
+
+        let content = fs::read(&path)
+            .with_context(|| format!("LFS object sha256:{} not found in store.", self.oid))?;
+
+        if content.len() as u64 != self.size {
+            return Err(anyhow!(
+                "LFS object sha256:{} has size {}, pointer expects {}.",
+                self.oid,
+                content.len(),
+                self.size
+            ));
+        }
+
+        Ok(content)
+    }
 }
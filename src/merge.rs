@@ -0,0 +1,781 @@
+use crate::checkout::{self, Entries};
+use crate::date::{format_date, signature_time};
+use crate::diff::{self, diff_lines, split_lines, Edit};
+use crate::graph::CommitGraph;
+use crate::index::{Index, IndexEntry};
+use crate::refs::RefLock;
+use crate::{
+    commit_tree, git_path, hooks, log, read_symref, record_ref_update, resolve_ref, resolve_rev,
+    short_id, write_ref, ObjectType, Repository,
+};
+use anyhow::anyhow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// Length of the `<<<<<<<`, `=======` and `>>>>>>>` conflict markers.
+const MARKER_SIZE: usize = 7;
+
+/// How conflicting hunks are written out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictStyle {
+    /// `<<<<<<<` ours `=======` theirs `>>>>>>>`.
+    Merge,
+    /// Like `Merge`, with the base version between `|||||||` and `=======`.
+    Diff3,
+}
+
+/// How conflicting hunks are resolved instead of writing markers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Favor {
+    None,
+    Ours,
+    Theirs,
+    Union,
+}
+
+/// Names written after the conflict markers.
+pub struct Labels<'a> {
+    pub ours: &'a str,
+    pub base: &'a str,
+    pub theirs: &'a str,
+}
+
+pub struct MergeResult {
+    pub content: String,
+    pub conflicts: usize,
+}
+
+/// A region of the three inputs, either agreed on by all sides or changed by at least one.
+enum Chunk<'a> {
+    Stable(&'a str),
+    Unstable {
+        base: &'a [&'a str],
+        ours: &'a [&'a str],
+        theirs: &'a [&'a str],
+    },
+}
+
+/// Merges the changes from `base` to `ours` and from `base` to `theirs` line by line.
+///
+/// Uses the diff3 approach: lines of `base` matched in both diffs are stable and
+/// split the inputs into chunks. A chunk changed on one side only takes that
+/// side; a chunk changed identically on both sides is taken once; anything else
+/// is a conflict.
+pub fn merge_lines(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    labels: &Labels,
+    style: ConflictStyle,
+    favor: Favor,
+) -> MergeResult {
+    let base_lines = split_lines(base);
+    let our_lines = split_lines(ours);
+    let their_lines = split_lines(theirs);
+
+    let chunks = diff3_chunks(&base_lines, &our_lines, &their_lines);
+
+    let mut content = String::new();
+    let mut conflicts = 0;
+    for chunk in chunks {
+        match chunk {
+            Chunk::Stable(line) => content.push_str(line),
+            Chunk::Unstable { base, ours, theirs } => {
+                if ours == base {
+                    push_lines(&mut content, theirs);
+                } else if theirs == base || ours == theirs {
+                    push_lines(&mut content, ours);
+                } else {
+                    match favor {
+                        Favor::Ours => push_lines(&mut content, ours),
+                        Favor::Theirs => push_lines(&mut content, theirs),
+                        Favor::Union => {
+                            push_lines(&mut content, ours);
+                            push_lines(&mut content, theirs);
+                        }
+                        Favor::None => {
+                            conflicts += 1;
+                            push_conflict(&mut content, base, ours, theirs, labels, style);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    MergeResult { content, conflicts }
+}
+
+/// A path's `(mode, id)` in one commit, if it has it there.
+type Side = Option<(u32, Vec<u8>)>;
+
+/// What a path comes out of a tree merge as.
+enum Merged {
+    Clean(Side),
+    /// The base, our and their versions go to stages 1 to 3 of the index,
+    /// and the work tree gets the `(mode, id)` of `work`: the content with
+    /// conflict markers, or the version one side kept.
+    Conflict {
+        sides: [Side; 3],
+        work: (u32, Vec<u8>),
+    },
+}
+
+/// Merges `name` into the branch `HEAD` is on, like `git merge`.
+///
+/// When `HEAD` is behind, the branch is fast-forwarded. Otherwise the trees
+/// are merged from their merge base path by path, and files both sides
+/// changed line by line, and a merge commit with both parents is made. A
+/// conflicted merge is left half done: conflicted paths get their three
+/// versions staged and markers in the work tree, and `MERGE_HEAD` is written
+/// for `commit` to pick up. Returns whether the merge was clean. A clean
+/// merge runs the post-merge hook, whose result doesn't change that.
+///
+/// In criss-cross histories, which have more than one merge base, the base
+/// of the highest generation is used, where git would merge the bases first.
+pub fn merge(git_dir: &Path, work_tree: &Path, name: &str) -> anyhow::Result<bool> {
+    let (ours_id, index) = prepare(git_dir, "Merging")?;
+    let repository = Repository::open(git_dir);
+    let theirs_id = repository.peel_to(&resolve_rev(git_dir, name)?, ObjectType::Commit)?;
+
+    let bases = CommitGraph::new(git_dir).merge_bases(&ours_id, &theirs_id)?;
+    let Some(base_id) = bases.first() else {
+        return Err(anyhow!("Refusing to merge unrelated histories."));
+    };
+    if *base_id == theirs_id {
+        println!("Already up to date.");
+        return Ok(true);
+    }
+
+    let ours = commit_entries(git_dir, &ours_id)?;
+    let theirs = commit_entries(git_dir, &theirs_id)?;
+    let fast_forward = *base_id == ours_id;
+    let merged = match fast_forward {
+        true => {
+            println!("Updating {}..{}", short_id(&ours_id), short_id(&theirs_id));
+            println!("Fast-forward");
+            ours.keys()
+                .chain(theirs.keys())
+                .map(|x| (x.clone(), Merged::Clean(theirs.get(x).cloned())))
+                .collect()
+        }
+        false => {
+            let base = commit_entries(git_dir, base_id)?;
+            let labels = Labels {
+                ours: "HEAD",
+                base: short_id(base_id),
+                theirs: name,
+            };
+            merge_trees(git_dir, &base, &ours, &theirs, &labels)?
+        }
+    };
+    update_work_tree(git_dir, work_tree, &index, &ours, &merged, "merge")?;
+    write_ref(git_dir, "ORIG_HEAD", &ours_id)?;
+
+    let message = merge_message(git_dir, name)?;
+    if has_conflicts(&merged) {
+        fs::write(git_path(git_dir, "MERGE_HEAD"), format!("{}\n", theirs_id))?;
+        fs::write(git_path(git_dir, "MERGE_MODE"), "")?;
+        write_merge_msg(git_dir, &format!("{}\n", message), &merged)?;
+        println!("Automatic merge failed; fix conflicts and then commit the result.");
+        return Ok(false);
+    }
+
+    let (new_id, action) = match fast_forward {
+        true => (theirs_id, "Fast-forward"),
+        false => {
+            let index_path = git_path(git_dir, "index");
+            let mut index = Index::load(&index_path)?;
+            let tree = index.write_tree(git_dir)?;
+            index.write(&index_path)?;
+            let parents = [ours_id.clone(), theirs_id];
+            let id = commit_tree(git_dir, &tree, &parents, None, &message)?;
+            // Not git's `ort`: paths merge from one base, renames unfollowed.
+            println!("Merge made.");
+            (id, "Merge made.")
+        }
+    };
+    let branch = read_symref(git_dir, "HEAD")?.unwrap_or_else(|| "HEAD".to_string());
+    RefLock::acquire(git_dir, &branch)?.commit(&new_id)?;
+    record_ref_update(
+        git_dir,
+        &branch,
+        Some(&ours_id),
+        &new_id,
+        &format!("merge {}: {}", name, action),
+    )?;
+
+    let old_tree = repository.peel_to(&ours_id, ObjectType::Tree)?;
+    let new_tree = repository.peel_to(&new_id, ObjectType::Tree)?;
+    let changes = diff::diff_trees(git_dir, Some(&old_tree), Some(&new_tree))?;
+    let changes = diff::detect_renames(git_dir, changes, Some(50))?;
+    let pending = diff::PendingBlobs::new();
+    diff::print_stat(git_dir, &changes, &pending, diff::DiffOptions::default())?;
+    diff::print_summary(&changes);
+    hooks::run(git_dir, "post-merge", &["0"], &[])?;
+    Ok(true)
+}
+
+/// Applies the change `rev` made to its parent onto `HEAD`, like
+/// `git cherry-pick`, and commits it with the original author and message.
+///
+/// The change is carried over by a three-way merge with the parent as base.
+/// On conflicts, the merge is left half done as by [`merge`], with
+/// `CHERRY_PICK_HEAD` naming the commit, for `commit` to conclude. Returns
+/// whether the pick was clean.
+pub fn cherry_pick(git_dir: &Path, work_tree: &Path, rev: &str) -> anyhow::Result<bool> {
+    let (ours_id, index) = prepare(git_dir, "Cherry-picking")?;
+    let theirs_id =
+        Repository::open(git_dir).peel_to(&resolve_rev(git_dir, rev)?, ObjectType::Commit)?;
+    let commit = log::read_commit(git_dir, &theirs_id)?;
+    let base = match commit.parents.as_slice() {
+        [] => Entries::new(),
+        [parent] => commit_entries(git_dir, parent)?,
+        _ => {
+            return Err(anyhow!(
+                "Commit {} is a merge, which can't be cherry-picked.",
+                theirs_id
+            ))
+        }
+    };
+
+    let subject = commit.message.lines().next().unwrap_or_default();
+    let picked = format!("{} ({})", short_id(&theirs_id), subject);
+    let labels = Labels {
+        ours: "HEAD",
+        base: &format!("parent of {}", picked),
+        theirs: &picked,
+    };
+    let ours = commit_entries(git_dir, &ours_id)?;
+    let theirs = commit_entries(git_dir, &theirs_id)?;
+    let merged = merge_trees(git_dir, &base, &ours, &theirs, &labels)?;
+    update_work_tree(git_dir, work_tree, &index, &ours, &merged, "cherry-pick")?;
+
+    if has_conflicts(&merged) {
+        fs::write(
+            git_path(git_dir, "CHERRY_PICK_HEAD"),
+            format!("{}\n", theirs_id),
+        )?;
+        write_merge_msg(git_dir, &commit.message, &merged)?;
+        eprintln!(
+            "error: could not apply {}... {}",
+            short_id(&theirs_id),
+            subject
+        );
+        eprintln!("hint: After resolving the conflicts, stage them with `add` and conclude with `commit`.");
+        return Ok(false);
+    }
+
+    let index_path = git_path(git_dir, "index");
+    let mut index = Index::load(&index_path)?;
+    let tree = index.write_tree(git_dir)?;
+    index.write(&index_path)?;
+    let old_tree = log::read_commit(git_dir, &ours_id)?.tree;
+    if tree == old_tree {
+        return Err(anyhow!(
+            "The cherry-pick of {} is empty: HEAD already has its changes.",
+            short_id(&theirs_id)
+        ));
+    }
+    let new_id = commit_tree(
+        git_dir,
+        &tree,
+        std::slice::from_ref(&ours_id),
+        Some(&commit.author),
+        &commit.message,
+    )?;
+    let branch = read_symref(git_dir, "HEAD")?.unwrap_or_else(|| "HEAD".to_string());
+    RefLock::acquire(git_dir, &branch)?.commit(&new_id)?;
+    record_ref_update(
+        git_dir,
+        &branch,
+        Some(&ours_id),
+        &new_id,
+        &format!("cherry-pick: {}", subject),
+    )?;
+
+    println!(
+        "[{} {}] {}",
+        match branch.as_str() {
+            "HEAD" => "detached HEAD",
+            _ => branch.strip_prefix("refs/heads/").unwrap_or(&branch),
+        },
+        short_id(&new_id),
+        subject
+    );
+    // Like git, the reused author is pointed out where it isn't the committer.
+    let new_commit = log::read_commit(git_dir, &new_id)?;
+    let name = |signature: &str| signature.rsplitn(3, ' ').nth(2).map(str::to_string);
+    if name(&new_commit.author) != name(&new_commit.committer) {
+        println!(" Author: {}", name(&new_commit.author).unwrap_or_default());
+    }
+    let author_time = signature_time(&new_commit.author);
+    if author_time != signature_time(&new_commit.committer) {
+        if let Some((seconds, offset)) = author_time {
+            println!(" Date: {}", format_date(seconds, offset));
+        }
+    }
+    let changes = diff::diff_trees(git_dir, Some(&old_tree), Some(&tree))?;
+    let changes = diff::detect_renames(git_dir, changes, Some(50))?;
+    let pending = diff::PendingBlobs::new();
+    diff::print_shortstat(git_dir, &changes, &pending, diff::DiffOptions::default())?;
+    diff::print_summary(&changes);
+    Ok(true)
+}
+
+/// Reapplies what the stash commit `stash_id` recorded onto the index and
+/// work tree, like `git stash apply`: the stashed work tree is merged into
+/// the current index, with the commit the stash was made on as base.
+///
+/// The changes come back unstaged, except files the stash added, which are
+/// staged again. On conflicts, the conflicted paths are left with their
+/// three versions staged. Returns whether the changes applied cleanly.
+pub fn apply_stash(git_dir: &Path, work_tree: &Path, stash_id: &str) -> anyhow::Result<bool> {
+    let (_, index) = prepare(git_dir, "Applying a stash")?;
+    let stash = log::read_commit(git_dir, stash_id)?;
+    let Some(base_id) = stash.parents.first() else {
+        return Err(anyhow!("{} is not a stash commit.", stash_id));
+    };
+    let base = commit_entries(git_dir, base_id)?;
+    let theirs = commit_entries(git_dir, stash_id)?;
+    let ours: Entries = index
+        .entries
+        .iter()
+        .map(|x| (x.path.clone(), (x.mode, x.hash.clone())))
+        .collect();
+    let labels = Labels {
+        ours: "Updated upstream",
+        base: "Stash base",
+        theirs: "Stashed changes",
+    };
+    let merged = merge_trees(git_dir, &base, &ours, &theirs, &labels)?;
+    update_work_tree(git_dir, work_tree, &index, &ours, &merged, "merge")?;
+    if has_conflicts(&merged) {
+        return Ok(false);
+    }
+
+    let applied = Index::load(&git_path(git_dir, "index"))?;
+    let added = applied
+        .entries
+        .into_iter()
+        .filter(|x| !ours.contains_key(&x.path));
+    checkout::write_index(git_dir, index.entries.into_iter().chain(added).collect())?;
+    Ok(true)
+}
+
+/// Forgets a merge or cherry-pick in progress, once it is committed or reset away.
+pub fn remove_merge_state(git_dir: &Path) {
+    for name in ["MERGE_HEAD", "MERGE_MODE", "MERGE_MSG", "CHERRY_PICK_HEAD"] {
+        let _ = fs::remove_file(git_path(git_dir, name));
+    }
+}
+
+fn diff3_chunks<'a>(
+    base: &'a [&'a str],
+    ours: &'a [&'a str],
+    theirs: &'a [&'a str],
+) -> Vec<Chunk<'a>> {
+    let our_match = match_map(base, ours);
+    let their_match = match_map(base, theirs);
+
+    let mut chunks = Vec::new();
+    let (mut i, mut j, mut k) = (0, 0, 0);
+
+    while i < base.len() || j < ours.len() || k < theirs.len() {
+        if i < base.len() && our_match[i] == Some(j) && their_match[i] == Some(k) {
+            chunks.push(Chunk::Stable(base[i]));
+            i += 1;
+            j += 1;
+            k += 1;
+            continue;
+        }
+
+        // The unstable chunk runs up to the next base line matched on both sides.
+        let next = (i..base.len()).find(|&x| our_match[x].is_some() && their_match[x].is_some());
+        let (next_i, next_j, next_k) = match next {
+            Some(x) => (x, our_match[x].unwrap(), their_match[x].unwrap()),
+            None => (base.len(), ours.len(), theirs.len()),
+        };
+
+        chunks.push(Chunk::Unstable {
+            base: &base[i..next_i],
+            ours: &ours[j..next_j],
+            theirs: &theirs[k..next_k],
+        });
+
+        i = next_i;
+        j = next_j;
+        k = next_k;
+    }
+
+    chunks
+}
+
+/// For each line of `base`, the line of `other` it was matched to by the diff, if any.
+fn match_map(base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+    let mut matches = vec![None; base.len()];
+    for edit in diff_lines(base, other) {
+        if let Edit::Equal(old, new) = edit {
+            matches[old] = Some(new);
+        }
+    }
+    matches
+}
+
+fn push_lines(content: &mut String, lines: &[&str]) {
+    for line in lines {
+        content.push_str(line);
+    }
+}
+
+fn push_conflict(
+    content: &mut String,
+    base: &[&str],
+    ours: &[&str],
+    theirs: &[&str],
+    labels: &Labels,
+    style: ConflictStyle,
+) {
+    // Lines both sides agree on at the edges of the conflict aren't part of it.
+    let (ours, theirs, leading, trailing) = if style == ConflictStyle::Merge {
+        let leading = ours
+            .iter()
+            .zip(theirs.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let max_trailing = ours.len().min(theirs.len()) - leading;
+        let trailing = ours
+            .iter()
+            .rev()
+            .zip(theirs.iter().rev())
+            .take(max_trailing)
+            .take_while(|(a, b)| a == b)
+            .count();
+        (
+            &ours[leading..ours.len() - trailing],
+            &theirs[leading..theirs.len() - trailing],
+            &ours[..leading],
+            &ours[ours.len() - trailing..],
+        )
+    } else {
+        (ours, theirs, &ours[..0], &ours[..0])
+    };
+
+    push_lines(content, leading);
+
+    push_marker(content, '<', labels.ours);
+    push_side(content, ours);
+    if style == ConflictStyle::Diff3 {
+        push_marker(content, '|', labels.base);
+        push_side(content, base);
+    }
+    push_marker(content, '=', "");
+    push_side(content, theirs);
+    push_marker(content, '>', labels.theirs);
+
+    push_lines(content, trailing);
+}
+
+/// Writes one side of a conflict, making sure the following marker starts on its own line.
+fn push_side(content: &mut String, lines: &[&str]) {
+    push_lines(content, lines);
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+}
+
+fn push_marker(content: &mut String, marker: char, label: &str) {
+    content.push_str(&marker.to_string().repeat(MARKER_SIZE));
+    if !label.is_empty() {
+        content.push(' ');
+        content.push_str(label);
+    }
+    content.push('\n');
+}
+
+/// Checks that nothing is half merged, returning the commit `HEAD` is at and
+/// the index.
+fn prepare(git_dir: &Path, action: &str) -> anyhow::Result<(String, Index)> {
+    let head = resolve_ref(git_dir, "HEAD")?
+        .ok_or_else(|| anyhow!("No commits yet on the current branch."))?;
+    let head = Repository::open(git_dir).peel_to(&head, ObjectType::Commit)?;
+
+    for (file, operation) in [("MERGE_HEAD", "merge"), ("CHERRY_PICK_HEAD", "cherry-pick")] {
+        if git_path(git_dir, file).exists() {
+            return Err(anyhow!(
+                "You have not concluded your {} ({} exists).\nCommit your changes first.",
+                operation,
+                file
+            ));
+        }
+    }
+    let index = Index::load(&git_path(git_dir, "index"))?;
+    if index.entries.iter().any(|x| x.stage() != 0) {
+        return Err(anyhow!(
+            "{} is not possible because you have unmerged files.",
+            action
+        ));
+    }
+    Ok((head, index))
+}
+
+fn has_conflicts(merged: &BTreeMap<String, Merged>) -> bool {
+    merged
+        .values()
+        .any(|x| matches!(x, Merged::Conflict { .. }))
+}
+
+/// Leaves the message the concluding commit is meant to get in `MERGE_MSG`,
+/// followed by the conflicted paths, as git does.
+fn write_merge_msg(
+    git_dir: &Path,
+    message: &str,
+    merged: &BTreeMap<String, Merged>,
+) -> anyhow::Result<()> {
+    let mut content = format!("{}\n# Conflicts:\n", message);
+    for (path, result) in merged {
+        if let Merged::Conflict { .. } = result {
+            content.push_str(&format!("#\t{}\n", path));
+        }
+    }
+    Ok(fs::write(git_path(git_dir, "MERGE_MSG"), content)?)
+}
+
+fn commit_entries(git_dir: &Path, commit_id: &str) -> anyhow::Result<Entries> {
+    let mut entries = Entries::new();
+    let tree = log::read_commit(git_dir, commit_id)?.tree;
+    checkout::tree_entries(git_dir, &tree, "", 0, &mut entries)?;
+    Ok(entries)
+}
+
+/// Merges the changes from `base` to `ours` and to `theirs` path by path,
+/// printing git's `Auto-merging` and `CONFLICT` lines as it goes.
+fn merge_trees(
+    git_dir: &Path,
+    base: &Entries,
+    ours: &Entries,
+    theirs: &Entries,
+    labels: &Labels,
+) -> anyhow::Result<BTreeMap<String, Merged>> {
+    let paths: BTreeSet<&String> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+    let mut merged = BTreeMap::new();
+    for path in paths {
+        let b = base.get(path).cloned();
+        let o = ours.get(path).cloned();
+        let t = theirs.get(path).cloned();
+        let result = if o == t || b == t {
+            Merged::Clean(o)
+        } else if b == o {
+            Merged::Clean(t)
+        } else {
+            match (&o, &t) {
+                (Some(our_side), Some(their_side)) => {
+                    merge_file(git_dir, path, &b, our_side, their_side, labels)?
+                }
+                // One side deleted what the other changed.
+                _ => {
+                    let (deleted, modified) = match o.is_some() {
+                        true => (labels.theirs, labels.ours),
+                        false => (labels.ours, labels.theirs),
+                    };
+                    println!(
+                        "CONFLICT (modify/delete): {} deleted in {} and modified in {}.  Version {} of {} left in tree.",
+                        path, deleted, modified, modified, path
+                    );
+                    let work = o.clone().or(t.clone()).unwrap();
+                    Merged::Conflict {
+                        sides: [b, o, t],
+                        work,
+                    }
+                }
+            }
+        };
+        merged.insert(path.clone(), result);
+    }
+    Ok(merged)
+}
+
+/// Merges a file both sides changed, or both added differently.
+fn merge_file(
+    git_dir: &Path,
+    path: &str,
+    base: &Side,
+    ours: &(u32, Vec<u8>),
+    theirs: &(u32, Vec<u8>),
+    labels: &Labels,
+) -> anyhow::Result<Merged> {
+    let kind = match base {
+        Some(_) => "content",
+        None => "add/add",
+    };
+    // A mode only one side changed is taken; if both did, ours wins.
+    let mode = match base {
+        Some(base) if base.0 == ours.0 => theirs.0,
+        _ => ours.0,
+    };
+    let sides = [base.clone(), Some(ours.clone()), Some(theirs.clone())];
+    let conflict = |work: (u32, Vec<u8>)| {
+        println!("CONFLICT ({}): Merge conflict in {}", kind, path);
+        Merged::Conflict {
+            sides: sides.clone(),
+            work,
+        }
+    };
+
+    // Symlinks and submodules have no lines to merge.
+    let regular = |mode: u32| matches!(mode, 0o100644 | 0o100755);
+    if !regular(ours.0) || !regular(theirs.0) {
+        return Ok(conflict(ours.clone()));
+    }
+    println!("Auto-merging {}", path);
+    let mut repository = Repository::open(git_dir);
+    let read = |side: Option<&(u32, Vec<u8>)>| -> anyhow::Result<Option<String>> {
+        let content = match side {
+            Some((_, hash)) => repository.read(&hex::encode(hash))?.1,
+            None => Vec::new(),
+        };
+        Ok(String::from_utf8(content)
+            .ok()
+            .filter(|x| !diff::is_binary(x)))
+    };
+    let (Some(base_text), Some(our_text), Some(their_text)) =
+        (read(base.as_ref())?, read(Some(ours))?, read(Some(theirs))?)
+    else {
+        eprintln!(
+            "warning: Cannot merge binary files: {} ({} vs. {})",
+            path, labels.ours, labels.theirs
+        );
+        return Ok(conflict(ours.clone()));
+    };
+
+    let result = merge_lines(
+        &base_text,
+        &our_text,
+        &their_text,
+        labels,
+        ConflictStyle::Merge,
+        Favor::None,
+    );
+    let id = hex::decode(repository.write(ObjectType::Blob, result.content.as_bytes())?)?;
+    match result.conflicts {
+        0 => Ok(Merged::Clean(Some((mode, id)))),
+        _ => Ok(conflict((mode, id))),
+    }
+}
+
+/// Brings the index and work tree from `ours` to the merge result, first
+/// making sure no local change or untracked file is in the way, as
+/// `checkout` does.
+fn update_work_tree(
+    git_dir: &Path,
+    work_tree: &Path,
+    index: &Index,
+    ours: &Entries,
+    merged: &BTreeMap<String, Merged>,
+    action: &str,
+) -> anyhow::Result<()> {
+    let touched: BTreeSet<&String> = merged
+        .iter()
+        .filter(|(path, result)| match result {
+            Merged::Clean(side) => side.as_ref() != ours.get(*path),
+            Merged::Conflict { .. } => true,
+        })
+        .map(|(path, _)| path)
+        .collect();
+
+    let changed: BTreeSet<String> = diff::diff_head_to_index(git_dir)?
+        .into_iter()
+        .chain(diff::diff_index_to_work_tree(
+            git_dir,
+            work_tree,
+            &mut Default::default(),
+        )?)
+        .map(|x| x.path)
+        .collect();
+    let overwritten: Vec<&String> = changed.iter().filter(|x| touched.contains(x)).collect();
+    if !overwritten.is_empty() {
+        return Err(anyhow!(
+            "Your local changes to the following files would be overwritten by {}:\n{}\nCommit them or discard them before you {}.",
+            action,
+            checkout::list_paths(overwritten),
+            action
+        ));
+    }
+
+    let tracked: BTreeMap<&str, &IndexEntry> =
+        index.entries.iter().map(|x| (x.path.as_str(), x)).collect();
+    let in_the_way: Vec<&String> = touched
+        .iter()
+        .copied()
+        .filter(|x| !tracked.contains_key(x.as_str()))
+        .filter(|x| !matches!(merged[*x], Merged::Clean(None)))
+        .filter(|x| checkout::untracked_in_the_way(work_tree, x, &tracked))
+        .collect();
+    if !in_the_way.is_empty() {
+        return Err(anyhow!(
+            "The following untracked work tree files would be overwritten by {}:\n{}\nMove or remove them before you {}.",
+            action,
+            checkout::list_paths(in_the_way),
+            action
+        ));
+    }
+
+    let mut entries: Vec<IndexEntry> = index
+        .entries
+        .iter()
+        .filter(|x| !touched.contains(&x.path))
+        .cloned()
+        .collect();
+    // Removals go first, so a file may take the place of a directory.
+    for path in &touched {
+        if let Merged::Clean(None) = merged[*path] {
+            checkout::remove_work_tree_file(work_tree, path)?;
+        }
+    }
+    for path in touched {
+        match &merged[path] {
+            Merged::Clean(None) => {}
+            Merged::Clean(Some((mode, hash))) => entries.push(checkout::write_entry(
+                git_dir, work_tree, path, *mode, hash,
+            )?),
+            Merged::Conflict { sides, work } => {
+                if ours.get(path) != Some(work) {
+                    checkout::write_entry(git_dir, work_tree, path, work.0, &work.1)?;
+                }
+                for (stage, side) in sides.iter().enumerate() {
+                    if let Some((mode, hash)) = side {
+                        entries.push(IndexEntry::new(path, *mode, hash.clone(), stage as u16 + 1));
+                    }
+                }
+            }
+        }
+    }
+    checkout::write_index(git_dir, entries)
+}
+
+/// The subject git gives a merge commit: `Merge branch 'topic'`, followed by
+/// ` into <branch>` unless merging into `main` or `master`.
+fn merge_message(git_dir: &Path, name: &str) -> anyhow::Result<String> {
+    let what = if resolve_ref(git_dir, &format!("refs/heads/{}", name))?.is_some() {
+        "branch"
+    } else if resolve_ref(git_dir, &format!("refs/remotes/{}", name))?.is_some() {
+        "remote-tracking branch"
+    } else {
+        "commit"
+    };
+    let mut message = format!("Merge {} '{}'", what, name);
+    let head = read_symref(git_dir, "HEAD")?;
+    let current = match &head {
+        Some(target) => target.strip_prefix("refs/heads/").unwrap_or(target),
+        None => "HEAD",
+    };
+    if !matches!(current, "main" | "master") {
+        message.push_str(&format!(" into {}", current));
+    }
+    Ok(message)
+}
@@ -0,0 +1,63 @@
+use crate::index::Index;
+use crate::{git_path, index_path_of};
+use anyhow::{anyhow, Context};
+use std::fs;
+use std::path::Path;
+
+/// Renames a tracked file or directory like `git mv`, in the work tree and
+/// in the index, where the moved entries keep their staged content. Moving
+/// onto an existing directory moves into it.
+pub fn mv(git_dir: &Path, work_tree: &Path, from: &Path, to: &Path) -> anyhow::Result<()> {
+    let index_path = git_path(git_dir, "index");
+    let mut index = Index::load(&index_path)?;
+
+    let source = index_path_of(work_tree, from)?;
+    let mut to = to.to_path_buf();
+    let mut destination = index_path_of(work_tree, &to)?;
+    let fail = |problem: &str, destination: &str| {
+        anyhow!(
+            "{}, source={}, destination={}",
+            problem,
+            source,
+            destination
+        )
+    };
+    let Result::Ok(metadata) = fs::symlink_metadata(from) else {
+        return Err(fail("bad source", &destination));
+    };
+    if to.is_dir() {
+        to = to.join(from.file_name().unwrap_or_default());
+        destination = index_path_of(work_tree, &to)?;
+    }
+    if destination == source || destination.starts_with(&format!("{}/", source)) {
+        return Err(fail("can not move directory into itself", &destination));
+    }
+
+    let dir = format!("{}/", source);
+    let moved: Vec<String> = index
+        .entries
+        .iter()
+        .filter(|x| x.path == source || (metadata.is_dir() && x.path.starts_with(&dir)))
+        .map(|x| x.path.clone())
+        .collect();
+    if moved.is_empty() {
+        return Err(match metadata.is_dir() {
+            true => fail("source directory is empty", &destination),
+            false => fail("not under version control", &destination),
+        });
+    }
+    if fs::symlink_metadata(&to).is_ok() {
+        return Err(fail("destination exists", &destination));
+    }
+
+    fs::rename(from, &to).with_context(|| format!("renaming '{}' failed", source))?;
+    for path in moved {
+        let Some(mut entry) = index.entries.iter().find(|x| x.path == path).cloned() else {
+            continue;
+        };
+        index.remove(&path);
+        entry.path = format!("{}{}", destination, &path[source.len()..]);
+        index.add(entry)?;
+    }
+    index.write(&index_path)
+}
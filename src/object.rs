@@ -0,0 +1,770 @@
+use crate::config::Config;
+use crate::error::{GitError, Result};
+use crate::odb::Repository;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// Shortest object id prefix `Repository::expand_id` accepts, as git does.
+pub const MIN_ABBREV_LEN: usize = 4;
+
+/// How much of a streamed object is hashed and compressed at a time.
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// The hash function objects are named by, chosen per repository with
+/// `extensions.objectFormat`. `Sha256` repositories were introduced by git
+/// 2.29; packs and the HTTP transport here still assume SHA-1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObjectFormat {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl ObjectFormat {
+    /// The format of the repository at `git_dir`, read from its config once
+    /// per process.
+    pub fn of(git_dir: &Path) -> Result<ObjectFormat> {
+        static FORMATS: OnceLock<Mutex<HashMap<PathBuf, ObjectFormat>>> = OnceLock::new();
+
+        let formats = FORMATS.get_or_init(Default::default);
+        if let Some(format) = formats.lock().unwrap().get(git_dir) {
+            return Ok(*format);
+        }
+        let format = match Config::load(git_dir)?.get("extensions.objectformat") {
+            Some(name) => name.parse()?,
+            None => ObjectFormat::Sha1,
+        };
+        formats
+            .lock()
+            .unwrap()
+            .insert(git_dir.to_path_buf(), format);
+        Ok(format)
+    }
+
+    /// Length of an object id in bytes; twice that in hex.
+    pub fn raw_len(self) -> usize {
+        match self {
+            ObjectFormat::Sha1 => 20,
+            ObjectFormat::Sha256 => 32,
+        }
+    }
+
+    pub fn hex_len(self) -> usize {
+        self.raw_len() * 2
+    }
+
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            ObjectFormat::Sha1 => Sha1::digest(data).to_vec(),
+            ObjectFormat::Sha256 => Sha256::digest(data).to_vec(),
+        }
+    }
+
+    /// Computes the id an object would be stored under in this format. The
+    /// header and content are fed to the hasher in turn, so the content is
+    /// never copied.
+    pub fn hash_object(self, object_type: &str, content: &[u8]) -> String {
+        let header = object_header(object_type, content);
+        match self {
+            ObjectFormat::Sha1 => hex::encode(
+                Sha1::new()
+                    .chain_update(header)
+                    .chain_update(content)
+                    .finalize(),
+            ),
+            ObjectFormat::Sha256 => hex::encode(
+                Sha256::new()
+                    .chain_update(header)
+                    .chain_update(content)
+                    .finalize(),
+            ),
+        }
+    }
+
+    /// Like `hash_object`, for `size` bytes of content read from `reader` a
+    /// chunk at a time, so the content is never held in memory whole.
+    pub fn hash_stream(
+        self,
+        object_type: &str,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> Result<String> {
+        stream_object(self, object_type, size, reader, |_| Ok(()))
+    }
+
+    fn hasher(self) -> Box<dyn sha1::digest::DynDigest> {
+        match self {
+            ObjectFormat::Sha1 => Box::new(Sha1::new()),
+            ObjectFormat::Sha256 => Box::new(Sha256::new()),
+        }
+    }
+}
+
+impl std::str::FromStr for ObjectFormat {
+    type Err = GitError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "sha1" => Ok(ObjectFormat::Sha1),
+            "sha256" => Ok(ObjectFormat::Sha256),
+            _ => Err(GitError::UnknownObjectFormat(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for ObjectFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjectFormat::Sha1 => write!(f, "sha1"),
+            ObjectFormat::Sha256 => write!(f, "sha256"),
+        }
+    }
+}
+
+/// Builds the `<type> <length>\0<content>` form object ids are computed over.
+pub fn encode_object(object_type: &str, content: &[u8]) -> Vec<u8> {
+    let mut object_content = object_header(object_type, content);
+    object_content.extend_from_slice(content);
+    object_content
+}
+
+/// The `<type> <size>\0` header that precedes an object's content.
+pub(crate) fn object_header(object_type: &str, content: &[u8]) -> Vec<u8> {
+    format!("{} {}\0", object_type, content.len()).into_bytes()
+}
+
+/// Computes the id an object would be stored under, without storing it, in
+/// a SHA-1 repository.
+pub fn hash_object(object_type: &str, content: &[u8]) -> String {
+    ObjectFormat::Sha1.hash_object(object_type, content)
+}
+
+/// Hashes the header and `size` bytes of content from `reader`, handing
+/// each piece to `sink` as well, and returns the object id.
+pub(crate) fn stream_object(
+    format: ObjectFormat,
+    object_type: &str,
+    size: u64,
+    reader: &mut dyn Read,
+    mut sink: impl FnMut(&[u8]) -> io::Result<()>,
+) -> Result<String> {
+    let header = format!("{} {}\0", object_type, size);
+    let mut hasher = format.hasher();
+    hasher.update(header.as_bytes());
+    sink(header.as_bytes())?;
+
+    let mut buffer = vec![0u8; CHUNK_LEN];
+    let mut remaining = size;
+    while remaining > 0 {
+        let wanted = remaining.min(CHUNK_LEN as u64) as usize;
+        let read = match reader.read(&mut buffer[..wanted]) {
+            Ok(0) => {
+                return Err(GitError::MalformedObject(format!(
+                    "The content ended {} bytes short of its size of {}.",
+                    remaining, size
+                )))
+            }
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        hasher.update(&buffer[..read]);
+        sink(&buffer[..read])?;
+        remaining -= read as u64;
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+pub fn zlib_compress(object_content: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(object_content)?;
+    let compressed = encoder.finish()?;
+    Ok(compressed)
+}
+
+pub fn calculate_sha_hash(object_content: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha1::new();
+    hasher.update(object_content);
+    let result = hasher.finalize();
+
+    result[..].to_vec()
+}
+
+/// The first seven characters of an object id, which is how messages show
+/// ids. Anything too short to abbreviate, like a ref file holding garbage,
+/// is shown whole rather than sliced.
+pub fn short_id(object_id: &str) -> &str {
+    object_id.get(..7).unwrap_or(object_id)
+}
+
+/// Returns the value of a `<name> <value>` header line of a commit or tag.
+pub fn header_field(content: &[u8], name: &str) -> Option<String> {
+    let prefix = format!("{} ", name);
+
+    content
+        .split(|&byte| byte == b'\n')
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| line.strip_prefix(prefix.as_bytes()))
+        .map(|value| String::from_utf8_lossy(value).to_string())
+}
+
+pub struct BlobObject {
+    pub length: u32,
+    /// The bytes as stored; blobs needn't be text.
+    pub content: Vec<u8>,
+}
+
+pub struct TreeObject {
+    pub length: u32,
+    pub elements: Vec<TreeElement>,
+}
+
+#[derive(Debug)]
+pub struct TreeElement {
+    pub mode: String,
+    pub object_type: ObjectType,
+    pub hash: Vec<u8>,
+    pub name: String,
+}
+
+/// A parsed commit; author and committer lines are kept verbatim (name, email,
+/// timestamp and timezone) so the commit can be written back unchanged.
+#[derive(Debug, Clone)]
+pub struct CommitObject {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: String,
+    pub committer: String,
+    pub encoding: Option<String>,
+    pub message: String,
+}
+
+/// A parsed annotated tag. The tagger line is kept verbatim like a commit's
+/// author, and a signature stays part of the message, where git stores it.
+#[derive(Debug)]
+pub struct TagObject {
+    pub object: String,
+    pub object_type: ObjectType,
+    pub tag: String,
+    /// Missing in tags made by very old versions of git.
+    pub tagger: Option<String>,
+    pub message: String,
+}
+
+impl BlobObject {
+    pub fn from_bytes(input: &[u8]) -> Result<BlobObject> {
+        let (length, content) = split_object(input, ObjectType::Blob)?;
+
+        Ok(BlobObject {
+            length,
+            content: content.to_vec(),
+        })
+    }
+}
+
+impl TreeObject {
+    /// Parses `tree <length>\0<entries>` bytes whose entry ids are as long as
+    /// `format` makes them.
+    pub fn from_bytes(input: &[u8], format: ObjectFormat) -> Result<TreeObject> {
+        let (_, content) = split_object(input, ObjectType::Tree)?;
+        TreeObject::from_content(content, format)
+    }
+
+    /// Parses the entries of a tree, without the header.
+    pub fn from_content(content: &[u8], format: ObjectFormat) -> Result<TreeObject> {
+        let length = content.len() as u32;
+        let mut content_bytes = content;
+
+        // Each entry is `<mode> <name>\0` followed by the raw object id.
+        let mut elements: Vec<TreeElement> = Vec::new();
+        while !content_bytes.is_empty() {
+            let name_end = content_bytes
+                .iter()
+                .position(|&byte| byte == 0)
+                .ok_or_else(|| {
+                    malformed(format!(
+                        "Tree entry {} has no NUL after its name.",
+                        elements.len()
+                    ))
+                })?;
+            let entry_len = (name_end + 1 + format.raw_len()).min(content_bytes.len());
+            elements.push(
+                TreeElement::from_bytes(&content_bytes[..entry_len], format).map_err(|err| {
+                    malformed(format!("Invalid tree entry {}: {}", elements.len(), err))
+                })?,
+            );
+            content_bytes = &content_bytes[entry_len..];
+        }
+
+        Ok(TreeObject { length, elements })
+    }
+}
+
+/// Splits `input` around the first `byte`, leaving it out of both halves.
+fn split_at_byte(input: &[u8], byte: u8) -> Option<(&[u8], &[u8])> {
+    let pos = input.iter().position(|&x| x == byte)?;
+    Some((&input[..pos], &input[pos + 1..]))
+}
+
+/// Splits `<type> <length>\0<content>` object bytes, checking the type is
+/// `expected` and that exactly `length` bytes of content follow the header.
+fn split_object(input: &[u8], expected: ObjectType) -> Result<(u32, &[u8])> {
+    let header_len = input
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or_else(|| malformed("Object has no NUL ending its header."))?;
+    let (object_type, length) = split_at_byte(&input[..header_len], b' ')
+        .ok_or_else(|| malformed("Object header has no length."))?;
+    if !bytes_to_object_type(object_type).is_ok_and(|x| x == expected) {
+        return Err(malformed(format!("Object is not of type {}.", expected)));
+    }
+    let length: u32 = std::str::from_utf8(length)
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| malformed("Object header has an invalid length."))?;
+
+    let content = &input[header_len + 1..];
+    if content.len() != length as usize {
+        return Err(malformed(format!(
+            "Object is truncated: its header declares {} bytes but {} follow.",
+            length,
+            content.len()
+        )));
+    }
+    Ok((length, content))
+}
+
+fn malformed(reason: impl Into<String>) -> GitError {
+    GitError::MalformedObject(reason.into())
+}
+
+/// Puts tree entries in git's tree order: names compare as bytes, with a
+/// subtree's name compared as if it ended in `/`, so `a.txt` sorts before
+/// the directory `a` but after the file `a`.
+pub fn sort_tree_entries(entries: &mut [(String, String, Vec<u8>)]) {
+    entries.sort_by_cached_key(|(mode, name, _)| match mode.as_str() {
+        "40000" => format!("{}/", name).into_bytes(),
+        _ => name.clone().into_bytes(),
+    });
+}
+
+/// Serializes tree entries as `<mode> <name>\0<raw id>` records. The
+/// `tree <length>` header added when storing counts these bytes, whatever
+/// the names are encoded as.
+///
+/// Entries must already be in git's tree order, see [`sort_tree_entries`].
+pub fn serialize_tree(entries: &[(String, String, Vec<u8>)]) -> Vec<u8> {
+    let mut content = Vec::new();
+    for (mode, name, hash) in entries {
+        content.extend_from_slice(mode.as_bytes());
+        content.push(b' ');
+        content.extend_from_slice(name.as_bytes());
+        content.push(0);
+        content.extend_from_slice(hash);
+    }
+    content
+}
+
+/// Tree ids already written by this process, keyed by git directory and content.
+type TreeCache = HashMap<(PathBuf, Vec<u8>), String>;
+
+/// Tree writes that were answered from the cache, and that had to be stored.
+static TREE_CACHE_HITS: AtomicUsize = AtomicUsize::new(0);
+static TREE_CACHE_MISSES: AtomicUsize = AtomicUsize::new(0);
+
+/// Serializes and stores a tree, returning its id.
+///
+/// Bulk rewrites and imports write the same unchanged directories over and
+/// over, so trees already written by this process are remembered by content
+/// and skip hashing, compression and the store entirely.
+pub fn write_tree(git_dir: &Path, entries: &[(String, String, Vec<u8>)]) -> Result<String> {
+    static TREES: OnceLock<Mutex<TreeCache>> = OnceLock::new();
+
+    let content = serialize_tree(entries);
+    let key = (git_dir.to_path_buf(), content);
+    let cache = TREES.get_or_init(Default::default);
+    if let Some(id) = cache.lock().unwrap().get(&key) {
+        TREE_CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+        return Ok(id.clone());
+    }
+
+    let id = Repository::open(git_dir).write(ObjectType::Tree, &key.1)?;
+    TREE_CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
+    cache.lock().unwrap().insert(key, id.clone());
+    Ok(id)
+}
+
+/// How many `write_tree` calls were cache hits and misses so far.
+pub fn tree_cache_stats() -> (usize, usize) {
+    (
+        TREE_CACHE_HITS.load(Ordering::Relaxed),
+        TREE_CACHE_MISSES.load(Ordering::Relaxed),
+    )
+}
+
+impl CommitObject {
+    /// Parses commit content (without the object header).
+    pub fn from_content(content: &[u8]) -> Result<CommitObject> {
+        let text = String::from_utf8_lossy(content);
+        let (headers, message) = text.split_once("\n\n").unwrap_or((&text, ""));
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut encoding = None;
+        for line in headers.lines() {
+            let Some((key, value)) = line.split_once(' ') else {
+                continue;
+            };
+            match key {
+                "tree" => tree = Some(value.to_string()),
+                "parent" => parents.push(value.to_string()),
+                "author" => author = Some(value.to_string()),
+                "committer" => committer = Some(value.to_string()),
+                "encoding" => encoding = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        Ok(CommitObject {
+            tree: tree.ok_or_else(|| malformed("Commit has no tree."))?,
+            parents,
+            author: author.ok_or_else(|| malformed("Commit has no author."))?,
+            committer: committer.ok_or_else(|| malformed("Commit has no committer."))?,
+            encoding,
+            message: message.to_string(),
+        })
+    }
+}
+
+impl TagObject {
+    /// Parses tag content (without the object header).
+    pub fn from_content(content: &[u8]) -> Result<TagObject> {
+        let text = String::from_utf8_lossy(content);
+        let (headers, message) = text.split_once("\n\n").unwrap_or((&text, ""));
+
+        let mut object = None;
+        let mut object_type = None;
+        let mut tag = None;
+        let mut tagger = None;
+        for line in headers.lines() {
+            let Some((key, value)) = line.split_once(' ') else {
+                continue;
+            };
+            match key {
+                "object" => object = Some(value.to_string()),
+                "type" => object_type = Some(bytes_to_object_type(value.as_bytes())?),
+                "tag" => tag = Some(value.to_string()),
+                "tagger" => tagger = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        Ok(TagObject {
+            object: object.ok_or_else(|| malformed("Tag has no object."))?,
+            object_type: object_type.ok_or_else(|| malformed("Tag has no type."))?,
+            tag: tag.ok_or_else(|| malformed("Tag has no name."))?,
+            tagger,
+            message: message.to_string(),
+        })
+    }
+
+    /// Serializes the tag as git stores it, the inverse of `from_content`.
+    pub fn to_content(&self) -> Vec<u8> {
+        let mut content = format!(
+            "object {}\ntype {}\ntag {}\n",
+            self.object, self.object_type, self.tag
+        );
+        if let Some(tagger) = &self.tagger {
+            content.push_str(&format!("tagger {}\n", tagger));
+        }
+        content.push('\n');
+        content.push_str(&self.message);
+        content.into_bytes()
+    }
+}
+
+impl TreeElement {
+    pub fn from_bytes(input: &[u8], format: ObjectFormat) -> Result<TreeElement> {
+        let (mode_bytes, rest) = split_at_byte(input, b' ')
+            .ok_or_else(|| malformed("Tree entry has no space after its mode."))?;
+        let (name_bytes, hash) = split_at_byte(rest, 0)
+            .ok_or_else(|| malformed("Tree entry has no NUL after its name."))?;
+        if hash.len() != format.raw_len() {
+            return Err(malformed(format!(
+                "Tree entry id is {} bytes long instead of {}.",
+                hash.len(),
+                format.raw_len()
+            )));
+        }
+
+        let mode = String::from_utf8(mode_bytes.to_vec())
+            .map_err(|_| malformed("Tree entry mode is not UTF-8."))?;
+        let name = String::from_utf8(name_bytes.to_vec())
+            .map_err(|_| malformed("Tree entry name is not UTF-8."))?;
+        let object_type = ObjectType::from_mode(&mode);
+
+        Ok(TreeElement {
+            mode,
+            object_type,
+            hash: hash.to_vec(),
+            name,
+        })
+    }
+}
+
+/// The mode git stores for an entry of mode `mode`: `120000` for symlinks,
+/// `40000` for directories, `160000` for gitlinks, and for anything else a
+/// regular file, `100755` when the owner may execute it and `100644`
+/// otherwise. Old git versions wrote modes like `100664`, which git reads
+/// back this way too.
+pub fn canonical_mode(mode: u32) -> u32 {
+    match mode & 0o170000 {
+        0o120000 => 0o120000,
+        0o040000 => 0o40000,
+        0o160000 => 0o160000,
+        _ if mode & 0o100 != 0 => 0o100755,
+        _ => 0o100644,
+    }
+}
+
+impl ObjectType {
+    /// The type of object a tree entry with this mode points at. Trees record
+    /// only the mode: `40000` is a subtree, `160000` a submodule commit
+    /// (gitlink), and anything else, symlinks (`120000`) included, a blob.
+    pub fn from_mode(mode: &str) -> ObjectType {
+        match mode {
+            "40000" => ObjectType::Tree,
+            "160000" => ObjectType::Commit,
+            _ => ObjectType::Blob,
+        }
+    }
+}
+
+pub fn bytes_to_object_type(object_type_bytes: &[u8]) -> Result<ObjectType> {
+    let object_type = match object_type_bytes {
+        b"blob" => ObjectType::Blob,
+        b"tree" => ObjectType::Tree,
+        b"commit" => ObjectType::Commit,
+        b"tag" => ObjectType::Tag,
+        _ => {
+            return Err(GitError::InvalidObjectType(
+                String::from_utf8_lossy(object_type_bytes).to_string(),
+            ))
+        }
+    };
+
+    Ok(object_type)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectType {
+    Blob,
+    Tree,
+    Commit,
+    Tag,
+}
+
+impl std::fmt::Display for ObjectType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            ObjectType::Blob => "blob",
+            ObjectType::Tree => "tree",
+            ObjectType::Commit => "commit",
+            ObjectType::Tag => "tag",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub enum Object {
+    Blob(BlobObject),
+    Tree(TreeObject),
+    Commit(CommitObject),
+    Tag(TagObject),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes a hex id spelled out in a test.
+    fn id(hex: &str) -> Vec<u8> {
+        hex::decode(hex).unwrap()
+    }
+
+    #[test]
+    fn tree_elements_know_subdirectories_from_files() {
+        let content = serialize_tree(&[
+            (
+                "100644".to_string(),
+                "a".to_string(),
+                id("45b983be36b73c0788dc9cbcb76cbb80fc7bb057"),
+            ),
+            (
+                "40000".to_string(),
+                "d".to_string(),
+                id("2b4c1d0c6f3c005f72eb2ecd2eb2a25edecf9a50"),
+            ),
+        ]);
+        let tree =
+            TreeObject::from_bytes(&encode_object("tree", &content), ObjectFormat::Sha1).unwrap();
+        assert_eq!(tree.elements.len(), 2);
+        assert_eq!(tree.elements[0].object_type, ObjectType::Blob);
+        assert_eq!(tree.elements[1].name, "d");
+        assert_eq!(tree.elements[1].object_type, ObjectType::Tree);
+    }
+
+    #[test]
+    fn every_mode_has_its_object_type() {
+        for (mode, object_type) in [
+            ("100644", ObjectType::Blob),
+            ("100755", ObjectType::Blob),
+            ("120000", ObjectType::Blob),
+            ("160000", ObjectType::Commit),
+            ("40000", ObjectType::Tree),
+        ] {
+            assert_eq!(ObjectType::from_mode(mode), object_type, "{}", mode);
+            let entry = [mode.as_bytes(), b" x\0", &[0; 20]].concat();
+            let element = TreeElement::from_bytes(&entry, ObjectFormat::Sha1).unwrap();
+            assert_eq!(element.object_type, object_type, "{}", mode);
+        }
+    }
+
+    #[test]
+    fn modes_are_stored_the_way_git_stores_them() {
+        assert_eq!(canonical_mode(0o100644), 0o100644);
+        assert_eq!(canonical_mode(0o100664), 0o100644);
+        assert_eq!(canonical_mode(0o100755), 0o100755);
+        assert_eq!(canonical_mode(0o100744), 0o100755);
+        assert_eq!(canonical_mode(0o120777), 0o120000);
+        assert_eq!(canonical_mode(0o160000), 0o160000);
+        assert_eq!(canonical_mode(0o040755), 0o40000);
+    }
+
+    #[test]
+    fn truncated_objects_are_errors() {
+        let blob = encode_object("blob", b"hello\n");
+        for len in 0..blob.len() {
+            assert!(BlobObject::from_bytes(&blob[..len]).is_err(), "{}", len);
+        }
+
+        let content = serialize_tree(&[
+            ("100644".to_string(), "a".to_string(), vec![1; 20]),
+            ("40000".to_string(), "d".to_string(), vec![2; 20]),
+        ]);
+        let tree = encode_object("tree", &content);
+        for len in 0..tree.len() {
+            let truncated = &tree[..len];
+            assert!(
+                TreeObject::from_bytes(truncated, ObjectFormat::Sha1).is_err(),
+                "{}",
+                len
+            );
+        }
+        // Entries cut short under a header that agrees with their length,
+        // except where the cut falls between two entries.
+        let first_entry = "100644 a\0".len() + 20;
+        for len in (1..content.len()).filter(|&x| x != first_entry) {
+            let truncated = encode_object("tree", &content[..len]);
+            assert!(
+                TreeObject::from_bytes(&truncated, ObjectFormat::Sha1).is_err(),
+                "{}",
+                len
+            );
+        }
+
+        for input in [&b""[..], b" x\0", b"100644 a", b"100644 a\0\x01"] {
+            assert!(TreeElement::from_bytes(input, ObjectFormat::Sha1).is_err());
+        }
+        // A commit cut off anywhere before it names its committer.
+        let commit = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            author A <a@b> 1 +0000\ncommitter C <c@d> 1 +0000\n\nmessage\n";
+        let committer = commit.windows(10).position(|x| x == b"committer ").unwrap() + 10;
+        assert!(CommitObject::from_content(commit).is_ok());
+        for len in 0..committer {
+            assert!(
+                CommitObject::from_content(&commit[..len]).is_err(),
+                "{}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn serializes_trees_like_git() {
+        // A root tree git wrote, given here out of order: `d-x` sorts before
+        // the directory `d`, and `naïve.txt` takes more bytes than chars.
+        let mut entries: Vec<(String, String, Vec<u8>)> = [
+            ("160000", "sub", "4b825dc642cb6eb9a060e54bf8d69288fbee4904"),
+            ("40000", "d", "2b4c1d0c6f3c005f72eb2ecd2eb2a25edecf9a50"),
+            (
+                "100755",
+                "run.sh",
+                "1a2485251c33a70432394c93fb89330ef214bfc9",
+            ),
+            (
+                "100644",
+                "naïve.txt",
+                "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391",
+            ),
+            ("120000", "link", "2e65efe2a145dda7ee51d1741299f848e5bf752e"),
+            ("100644", "d-x", "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"),
+            ("100644", "a", "45b983be36b73c0788dc9cbcb76cbb80fc7bb057"),
+        ]
+        .iter()
+        .map(|(mode, name, hash)| (mode.to_string(), name.to_string(), id(hash)))
+        .collect();
+        sort_tree_entries(&mut entries);
+        let names: Vec<&str> = entries.iter().map(|x| x.1.as_str()).collect();
+        assert_eq!(
+            names,
+            ["a", "d-x", "d", "link", "naïve.txt", "run.sh", "sub"]
+        );
+
+        let content = serialize_tree(&entries);
+        assert_eq!(content.len(), 223);
+        assert!(encode_object("tree", &content).starts_with(b"tree 223\0"));
+        assert_eq!(
+            ObjectFormat::Sha1.hash_object("tree", &content),
+            "731996f4fdf4b2c93a4ea2f52a712522216f3590"
+        );
+    }
+
+    #[test]
+    fn parses_sha256_tree_with_32_byte_ids() {
+        // The root tree git writes in a SHA-256 repository holding `a` and `d/b`.
+        let blob = id("96c18f0297e38d01f4b2dacddea4259aea6b2961eb0822bd2c0c3f6029030045");
+        let subtree = id("d26a796750d0dd6d051ea57f118091d8132e179973e8cf3b217950628c6d65a6");
+        let content = serialize_tree(&[
+            ("100644".to_string(), "a".to_string(), blob.clone()),
+            ("40000".to_string(), "d".to_string(), subtree.clone()),
+        ]);
+        assert_eq!(
+            ObjectFormat::Sha256.hash_object("tree", &content),
+            "d2571402fa000446dd69a508490d87df647efeb4a6fe7591c78b275873b0d7e0"
+        );
+
+        let tree =
+            TreeObject::from_bytes(&encode_object("tree", &content), ObjectFormat::Sha256).unwrap();
+        assert_eq!(tree.elements.len(), 2);
+        assert_eq!(tree.elements[0].name, "a");
+        assert_eq!(tree.elements[0].hash, blob);
+        assert_eq!(tree.elements[1].name, "d");
+        assert_eq!(tree.elements[1].hash, subtree);
+        assert_eq!(tree.elements[1].object_type, ObjectType::Tree);
+
+        // Read as SHA-1, the same bytes don't split into whole entries.
+        assert!(
+            TreeObject::from_bytes(&encode_object("tree", &content), ObjectFormat::Sha1).is_err()
+        );
+    }
+}
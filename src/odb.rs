@@ -0,0 +1,1118 @@
+//! The object database: objects read, written and iterated through a
+//! [`Repository`], over the loose and packed objects of a git directory or a
+//! map held in memory. Only this module knows where objects live on disk.
+
+use crate::error::{GitError, Result};
+use crate::object::{
+    bytes_to_object_type, encode_object, header_field, object_header, stream_object, BlobObject,
+    CommitObject, Object, ObjectFormat, ObjectType, TagObject, TreeObject, MIN_ABBREV_LEN,
+};
+use crate::{git_path, pack};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Longest `<type> <length>` header a valid object can have.
+const MAX_HEADER_LEN: usize = 32;
+
+/// How many objects `peel_to` follows before giving up.
+const MAX_PEEL_DEPTH: usize = 32;
+
+/// How much of two streamed objects is compared at a time.
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// Where a repository keeps its objects.
+pub trait RepositoryBackend {
+    /// Reads an object's type and content (without the header).
+    fn read(&self, object_id: &str) -> Result<(ObjectType, Vec<u8>)>;
+
+    /// Stores an object and returns its id.
+    fn write(&mut self, object_type: ObjectType, content: &[u8]) -> Result<String>;
+
+    fn exists(&self, object_id: &str) -> Result<bool>;
+
+    /// An object's type and content size, or `None` if it isn't stored.
+    /// Backends should answer this without reading the whole content.
+    fn stat(&self, object_id: &str) -> Result<Option<(ObjectType, u64)>>;
+
+    /// Ids of every stored object, sorted.
+    fn object_ids(&self) -> Result<Vec<String>>;
+
+    /// The hash function naming the objects.
+    fn object_format(&self) -> Result<ObjectFormat> {
+        Ok(ObjectFormat::Sha1)
+    }
+}
+
+/// The object store of a git directory: loose objects, falling back to packs.
+pub struct FsBackend {
+    git_dir: PathBuf,
+}
+
+impl FsBackend {
+    pub fn new(git_dir: &Path) -> FsBackend {
+        FsBackend {
+            git_dir: git_dir.to_path_buf(),
+        }
+    }
+}
+
+impl RepositoryBackend for FsBackend {
+    fn read(&self, object_id: &str) -> Result<(ObjectType, Vec<u8>)> {
+        read_object(&self.git_dir, object_id)
+    }
+
+    fn write(&mut self, object_type: ObjectType, content: &[u8]) -> Result<String> {
+        write_object(&self.git_dir, &object_type.to_string(), content)
+    }
+
+    fn exists(&self, object_id: &str) -> Result<bool> {
+        object_exists(&self.git_dir, object_id)
+    }
+
+    fn stat(&self, object_id: &str) -> Result<Option<(ObjectType, u64)>> {
+        Ok(stat_object(&self.git_dir, object_id)?
+            .map(|(object_type, size)| (object_type, size as u64)))
+    }
+
+    fn object_ids(&self) -> Result<Vec<String>> {
+        let mut ids: BTreeSet<String> = loose_object_ids(&self.git_dir)?.into_iter().collect();
+        for (_, idx_path) in pack::list_packs(&git_path(&self.git_dir, "objects"))? {
+            ids.extend(pack::index_object_ids(&idx_path)?);
+        }
+        Ok(ids.into_iter().collect())
+    }
+
+    fn object_format(&self) -> Result<ObjectFormat> {
+        ObjectFormat::of(&self.git_dir)
+    }
+}
+
+/// Objects kept in a map, for tests and for embedding without a git directory.
+#[derive(Default)]
+pub struct MemoryBackend {
+    objects: HashMap<String, (ObjectType, Vec<u8>)>,
+    format: ObjectFormat,
+}
+
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        MemoryBackend::default()
+    }
+
+    /// An empty store naming its objects with `format`'s hash.
+    pub fn with_format(format: ObjectFormat) -> MemoryBackend {
+        MemoryBackend {
+            objects: HashMap::new(),
+            format,
+        }
+    }
+}
+
+impl RepositoryBackend for MemoryBackend {
+    fn read(&self, object_id: &str) -> Result<(ObjectType, Vec<u8>)> {
+        self.objects
+            .get(object_id)
+            .cloned()
+            .ok_or_else(|| GitError::ObjectNotFound(object_id.to_string()))
+    }
+
+    fn write(&mut self, object_type: ObjectType, content: &[u8]) -> Result<String> {
+        let object_id = self.format.hash_object(&object_type.to_string(), content);
+        self.objects
+            .entry(object_id.clone())
+            .or_insert_with(|| (object_type, content.to_vec()));
+        Ok(object_id)
+    }
+
+    fn exists(&self, object_id: &str) -> Result<bool> {
+        Ok(self.objects.contains_key(object_id))
+    }
+
+    fn stat(&self, object_id: &str) -> Result<Option<(ObjectType, u64)>> {
+        Ok(self
+            .objects
+            .get(object_id)
+            .map(|(object_type, content)| (*object_type, content.len() as u64)))
+    }
+
+    fn object_ids(&self) -> Result<Vec<String>> {
+        let mut ids: Vec<String> = self.objects.keys().cloned().collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn object_format(&self) -> Result<ObjectFormat> {
+        Ok(self.format)
+    }
+}
+
+/// A repository's objects, read and written through its backend.
+pub struct Repository<B: RepositoryBackend> {
+    backend: B,
+}
+
+impl Repository<FsBackend> {
+    /// The repository stored in a git directory.
+    pub fn open(git_dir: &Path) -> Repository<FsBackend> {
+        Repository::new(FsBackend::new(git_dir))
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.backend.git_dir
+    }
+
+    /// Path of a loose object, whether or not it is stored there.
+    pub fn loose_path(&self, object_id: &str) -> PathBuf {
+        object_path(self.git_dir(), object_id)
+    }
+
+    /// Ids of the objects stored loose, sorted; packed ones are left out.
+    pub fn loose_object_ids(&self) -> Result<Vec<String>> {
+        loose_object_ids(self.git_dir())
+    }
+
+    /// Opens an object for streaming: parses the header and returns the
+    /// type, the content size and a reader positioned at the start of the
+    /// content. Dropping the reader early stops inflating, so callers pay
+    /// only for what they read. Packed objects are read whole, since deltas
+    /// need their base anyway.
+    pub fn open_object(&self, object_id: &str) -> Result<(ObjectType, usize, Box<dyn Read>)> {
+        open_object(self.git_dir(), object_id)
+    }
+
+    /// Like `write`, for `size` bytes of content read from `reader`. The
+    /// content is hashed and compressed a chunk at a time into a temporary
+    /// file that is renamed into place, so objects larger than memory can be
+    /// stored.
+    pub fn write_stream(
+        &mut self,
+        object_type: ObjectType,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> Result<String> {
+        write_object_stream(self.git_dir(), &object_type.to_string(), size, reader)
+    }
+
+    /// Stores `compressed`, an object already in loose form, as is: the
+    /// caller has checked it inflates to the object named `object_id`.
+    pub fn write_compressed(&mut self, object_id: &str, compressed: &[u8]) -> Result<()> {
+        let objects_dir = git_path(self.git_dir(), "objects");
+        fs::create_dir_all(&objects_dir)?;
+        let (temp, mut file) = TempFile::create(&objects_dir)?;
+        file.write_all(compressed)?;
+        temp.store_as(&self.loose_path(object_id))
+    }
+
+    /// Reads and inflates an object, loose or packed, returning its full
+    /// `<type> <length>\0<content>` bytes.
+    pub fn read_raw(&self, object_id: &str) -> Result<Vec<u8>> {
+        read_object_bytes(self.git_dir(), object_id)
+    }
+
+    /// Reads an object and checks that its `<type> <length>\0<content>`
+    /// bytes hash to `object_id`, catching bit rot and bad writes that reads
+    /// trust.
+    pub fn verify(&self, object_id: &str) -> Result<()> {
+        let buffer = self.read_raw(object_id)?;
+        let actual = hex::encode(ObjectFormat::of(self.git_dir())?.digest(&buffer));
+        if actual != object_id {
+            return Err(GitError::corrupt(
+                object_id,
+                format!("hash mismatch, its content hashes to {}.", actual),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Expands an abbreviated object id to the one stored object it's a
+    /// prefix of, looking at loose objects and every pack.
+    ///
+    /// Fails if the prefix is shorter than `MIN_ABBREV_LEN`, isn't hex, or
+    /// matches no object or more than one.
+    pub fn expand_id(&self, prefix: &str) -> Result<String> {
+        expand_object_id(self.git_dir(), prefix)
+    }
+}
+
+impl Repository<MemoryBackend> {
+    /// An empty repository that only lives in memory.
+    pub fn in_memory() -> Repository<MemoryBackend> {
+        Repository::new(MemoryBackend::new())
+    }
+}
+
+impl<B: RepositoryBackend> Repository<B> {
+    pub fn new(backend: B) -> Repository<B> {
+        Repository { backend }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn read(&self, object_id: &str) -> Result<(ObjectType, Vec<u8>)> {
+        self.backend.read(object_id)
+    }
+
+    pub fn write(&mut self, object_type: ObjectType, content: &[u8]) -> Result<String> {
+        self.backend.write(object_type, content)
+    }
+
+    pub fn exists(&self, object_id: &str) -> Result<bool> {
+        self.backend.exists(object_id)
+    }
+
+    /// Type and content size of an object in one lookup, or `None` if it's
+    /// missing. Only the header is read, so this is cheap even for big blobs.
+    pub fn stat_object(&self, object_id: &str) -> Result<Option<(ObjectType, u64)>> {
+        self.backend.stat(object_id)
+    }
+
+    /// Like `stat_object`, but a missing object is an error.
+    pub fn read_header(&self, object_id: &str) -> Result<(ObjectType, u64)> {
+        self.stat_object(object_id)?
+            .ok_or_else(|| GitError::ObjectNotFound(object_id.to_string()))
+    }
+
+    pub fn object_ids(&self) -> Result<Vec<String>> {
+        self.backend.object_ids()
+    }
+
+    /// Reads and parses an object of any type; content that doesn't parse
+    /// as its type says is reported as corrupt.
+    pub fn object(&self, object_id: &str) -> Result<Object> {
+        let (object_type, content) = self.read(object_id)?;
+        let object = match object_type {
+            ObjectType::Blob => Ok(Object::Blob(BlobObject {
+                length: content.len() as u32,
+                content,
+            })),
+            ObjectType::Tree => {
+                TreeObject::from_content(&content, self.backend.object_format()?).map(Object::Tree)
+            }
+            ObjectType::Commit => CommitObject::from_content(&content).map(Object::Commit),
+            ObjectType::Tag => TagObject::from_content(&content).map(Object::Tag),
+        };
+        object.map_err(|err| GitError::corrupt(object_id, err.to_string()))
+    }
+
+    /// Reads and parses a tree.
+    pub fn tree(&self, object_id: &str) -> Result<TreeObject> {
+        match self.read(object_id)? {
+            (ObjectType::Tree, content) => {
+                TreeObject::from_content(&content, self.backend.object_format()?)
+            }
+            (object_type, _) => Err(GitError::WrongObjectType {
+                id: object_id.to_string(),
+                actual: object_type,
+                expected: ObjectType::Tree,
+            }),
+        }
+    }
+
+    /// Reads and parses a commit.
+    pub fn commit(&self, object_id: &str) -> Result<CommitObject> {
+        match self.read(object_id)? {
+            (ObjectType::Commit, content) => CommitObject::from_content(&content),
+            (object_type, _) => Err(GitError::WrongObjectType {
+                id: object_id.to_string(),
+                actual: object_type,
+                expected: ObjectType::Commit,
+            }),
+        }
+    }
+
+    /// Reads and parses an annotated tag.
+    pub fn tag(&self, object_id: &str) -> Result<TagObject> {
+        match self.read(object_id)? {
+            (ObjectType::Tag, content) => TagObject::from_content(&content),
+            (object_type, _) => Err(GitError::WrongObjectType {
+                id: object_id.to_string(),
+                actual: object_type,
+                expected: ObjectType::Tag,
+            }),
+        }
+    }
+
+    /// Ids an object refers to: a commit's tree and parents, a tree's
+    /// entries (except submodule commits) and a tag's target.
+    pub fn linked_objects(&self, object_type: ObjectType, content: &[u8]) -> Result<Vec<String>> {
+        match object_type {
+            ObjectType::Blob => Ok(Vec::new()),
+            ObjectType::Tree => Ok(TreeObject::from_content(
+                content,
+                self.backend.object_format()?,
+            )?
+            .elements
+            .into_iter()
+            .filter(|x| x.mode != "160000")
+            .map(|x| hex::encode(x.hash))
+            .collect()),
+            ObjectType::Commit => {
+                let commit = CommitObject::from_content(content)?;
+                let mut ids = vec![commit.tree];
+                ids.extend(commit.parents);
+                Ok(ids)
+            }
+            ObjectType::Tag => Ok(header_field(content, "object").into_iter().collect()),
+        }
+    }
+
+    /// Dereferences an object until one of the `target` type is reached.
+    ///
+    /// Tags are followed to the object they point at, and commits to their
+    /// tree, so asking for the tree of a commit, a tag or a tree itself all
+    /// work.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn peel_to(&self, object_id: &str, target: ObjectType) -> Result<String> {
+        let mut object_id = object_id.to_string();
+
+        let mut seen = HashSet::new();
+
+        // Tags may point at tags; bound the chain so a corrupt cycle can't hang us.
+        for _ in 0..MAX_PEEL_DEPTH {
+            if !seen.insert(object_id.clone()) {
+                return Err(GitError::TagCycle(object_id));
+            }
+            let (object_type, content) = self.read(&object_id)?;
+            if object_type == target {
+                return Ok(object_id);
+            }
+
+            let next = match object_type {
+                ObjectType::Tag => header_field(&content, "object"),
+                ObjectType::Commit if target == ObjectType::Tree => header_field(&content, "tree"),
+                _ => None,
+            };
+
+            match next {
+                Some(next) => object_id = next,
+                None => {
+                    return Err(GitError::WrongObjectType {
+                        id: object_id,
+                        actual: object_type,
+                        expected: target,
+                    })
+                }
+            }
+        }
+
+        Err(GitError::TooManyTags(object_id))
+    }
+
+    /// Follows tags until reaching an object that isn't one.
+    pub fn peel_tags(&self, object_id: &str) -> Result<String> {
+        let mut object_id = object_id.to_string();
+        let mut seen = HashSet::new();
+
+        for _ in 0..MAX_PEEL_DEPTH {
+            if !seen.insert(object_id.clone()) {
+                return Err(GitError::TagCycle(object_id));
+            }
+            let (object_type, content) = self.read(&object_id)?;
+            if object_type != ObjectType::Tag {
+                return Ok(object_id);
+            }
+            object_id = header_field(&content, "object")
+                .ok_or_else(|| GitError::corrupt(&object_id, "tag has no object line."))?;
+        }
+
+        Err(GitError::TooManyTags(object_id))
+    }
+}
+
+/// Name of a loose object under `objects/`: `<first two hex chars>/<the
+/// rest>`, which is also where dumb HTTP servers serve it.
+pub fn loose_name(object_id: &str) -> String {
+    let folder: String = object_id.chars().take(2).collect();
+    let file_name: String = object_id.chars().skip(2).collect();
+    format!("{}/{}", folder, file_name)
+}
+
+fn object_path(git_dir: &Path, object_id: &str) -> PathBuf {
+    git_path(git_dir, "objects").join(loose_name(object_id))
+}
+
+/// Ids of the loose objects in `git_dir`, from the files named like one.
+fn loose_object_ids(git_dir: &Path) -> Result<Vec<String>> {
+    let objects_dir = git_path(git_dir, "objects");
+    let loose_len = ObjectFormat::of(git_dir)?.hex_len() - 2;
+    let mut ids = BTreeSet::new();
+
+    for dir in fs::read_dir(&objects_dir)? {
+        let dir = dir?;
+        let dir_name = dir.file_name().to_string_lossy().to_string();
+        if dir_name.len() != 2 || !dir_name.chars().all(|x| x.is_ascii_hexdigit()) {
+            continue;
+        }
+        for file in fs::read_dir(dir.path())? {
+            let file_name = file?.file_name().to_string_lossy().to_string();
+            if file_name.len() == loose_len && file_name.chars().all(|x| x.is_ascii_hexdigit()) {
+                ids.insert(format!("{}{}", dir_name, file_name));
+            }
+        }
+    }
+    Ok(ids.into_iter().collect())
+}
+
+/// Stores an object in the loose object store and returns its id.
+///
+/// Objects are content-addressed, so an object that already exists, loose
+/// or packed, is neither compressed nor written again, but only after
+/// checking the stored bytes really are the ones being written: a SHA-1
+/// collision must not be mistaken for a duplicate. New objects are written
+/// to a temporary file that is renamed into place, so a crash never leaves
+/// a truncated object, and are made read-only as git makes them.
+#[tracing::instrument(level = "debug", skip(git_dir, content), fields(size = content.len()))]
+fn write_object(git_dir: &Path, object_type: &str, content: &[u8]) -> Result<String> {
+    let header = object_header(object_type, content);
+    let sha_hash = ObjectFormat::of(git_dir)?.hash_object(object_type, content);
+
+    if object_exists(git_dir, &sha_hash)? {
+        let stored = read_object_bytes(git_dir, &sha_hash)?;
+        if stored.strip_prefix(&header[..]) != Some(content) {
+            return Err(GitError::HashCollision(sha_hash));
+        }
+        tracing::trace!(id = %sha_hash, "object already stored");
+        return Ok(sha_hash);
+    }
+
+    let objects_dir = git_path(git_dir, "objects");
+    fs::create_dir_all(&objects_dir)?;
+    let (temp, file) = TempFile::create(&objects_dir)?;
+    let mut encoder = ZlibEncoder::new(io::BufWriter::new(file), Compression::default());
+    encoder.write_all(&header)?;
+    encoder.write_all(content)?;
+    encoder.finish()?.flush()?;
+    temp.store_as(&object_path(git_dir, &sha_hash))?;
+    tracing::debug!(id = %sha_hash, "wrote loose object");
+
+    Ok(sha_hash)
+}
+
+#[tracing::instrument(level = "debug", skip(git_dir, reader))]
+fn write_object_stream(
+    git_dir: &Path,
+    object_type: &str,
+    size: u64,
+    reader: &mut dyn Read,
+) -> Result<String> {
+    let objects_dir = git_path(git_dir, "objects");
+    fs::create_dir_all(&objects_dir)?;
+    let (temp, file) = TempFile::create(&objects_dir)?;
+
+    let mut encoder = ZlibEncoder::new(io::BufWriter::new(file), Compression::default());
+    let sha_hash = stream_object(
+        ObjectFormat::of(git_dir)?,
+        object_type,
+        size,
+        reader,
+        |chunk| encoder.write_all(chunk),
+    )?;
+    encoder.finish()?.flush()?;
+
+    if object_exists(git_dir, &sha_hash)? {
+        let (_, _, mut stored) = open_object(git_dir, &sha_hash)?;
+        let mut written = ZlibDecoder::new(BufReader::new(fs::File::open(&temp.path)?));
+        // Skip the header, which matches if the ids do.
+        io::copy(
+            &mut (&mut written).take(object_header_len(object_type, size)),
+            &mut io::sink(),
+        )?;
+        if !same_content(&mut stored, &mut written)? {
+            return Err(GitError::HashCollision(sha_hash));
+        }
+        tracing::trace!(id = %sha_hash, "object already stored");
+        return Ok(sha_hash);
+    }
+
+    temp.store_as(&object_path(git_dir, &sha_hash))?;
+    tracing::debug!(id = %sha_hash, "wrote loose object");
+    Ok(sha_hash)
+}
+
+fn object_header_len(object_type: &str, size: u64) -> u64 {
+    format!("{} {}\0", object_type, size).len() as u64
+}
+
+/// Whether two readers yield the same bytes, compared a chunk at a time.
+fn same_content(a: &mut dyn Read, b: &mut dyn Read) -> io::Result<bool> {
+    let mut a_buffer = vec![0u8; CHUNK_LEN];
+    let mut b_buffer = vec![0u8; CHUNK_LEN];
+    loop {
+        let a_len = read_full(a, &mut a_buffer)?;
+        let b_len = read_full(b, &mut b_buffer)?;
+        if a_buffer[..a_len] != b_buffer[..b_len] {
+            return Ok(false);
+        }
+        if a_len < CHUNK_LEN {
+            return Ok(true);
+        }
+    }
+}
+
+/// Fills `buffer` as far as the reader goes, returning how much it did.
+fn read_full(reader: &mut dyn Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+/// A file in `objects/` that is deleted unless renamed away first.
+struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    fn create(dir: &Path) -> Result<(TempFile, fs::File)> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let name = format!(
+                "tmp_obj_{}_{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = dir.join(name);
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => return Ok((TempFile { path }, file)),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+impl TempFile {
+    /// Makes the file read-only, as objects never change, and renames it to
+    /// `path`.
+    fn store_as(self, path: &Path) -> Result<()> {
+        let mut permissions = fs::metadata(&self.path)?.permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&self.path, permissions)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&self.path, path)?;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Whether the object is stored, loose or in a pack.
+fn object_exists(git_dir: &Path, object_id: &str) -> Result<bool> {
+    Ok(object_path(git_dir, object_id).exists() || pack::has_packed_object(git_dir, object_id)?)
+}
+
+fn expand_object_id(git_dir: &Path, prefix: &str) -> Result<String> {
+    let hex_len = ObjectFormat::of(git_dir)?.hex_len();
+    if prefix.len() < MIN_ABBREV_LEN
+        || prefix.len() > hex_len
+        || !prefix.chars().all(|x| x.is_ascii_hexdigit())
+    {
+        return Err(GitError::InvalidObjectId(prefix.to_string()));
+    }
+    let prefix = prefix.to_lowercase();
+
+    let mut matches = HashSet::new();
+    let loose_dir = git_path(git_dir, "objects").join(&prefix[..2]);
+    if let Ok(entries) = fs::read_dir(&loose_dir) {
+        for entry in entries {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            if name.len() == hex_len - 2 && name.starts_with(&prefix[2..]) {
+                matches.insert(format!("{}{}", &prefix[..2], name));
+            }
+        }
+    }
+    for (_, idx_path) in pack::list_packs(&git_path(git_dir, "objects"))? {
+        let ids = pack::index_object_ids(&idx_path)?;
+        matches.extend(ids.into_iter().filter(|x| x.starts_with(&prefix)));
+    }
+
+    let mut matches: Vec<String> = matches.into_iter().collect();
+    match matches.len() {
+        0 => Err(GitError::ObjectNotFound(prefix)),
+        1 => Ok(matches.remove(0)),
+        _ => {
+            matches.sort();
+            Err(GitError::AmbiguousObjectId {
+                prefix,
+                candidates: matches,
+            })
+        }
+    }
+}
+
+/// Opens a loose object file, or returns `None` if the object isn't stored loose.
+fn open_loose(git_dir: &Path, object_id: &str) -> Result<Option<fs::File>> {
+    match fs::File::open(object_path(git_dir, object_id)) {
+        Ok(file) => Ok(Some(file)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Reads an object out of the packs, failing if no pack holds it either.
+fn read_packed(git_dir: &Path, object_id: &str) -> Result<(ObjectType, Vec<u8>)> {
+    let object = pack::read_packed_object(git_dir, object_id)?
+        .ok_or_else(|| GitError::ObjectNotFound(object_id.to_string()))?;
+    tracing::trace!(size = object.1.len(), "read packed object");
+    Ok(object)
+}
+
+/// A loose object that doesn't inflate, or whose content isn't as long as its
+/// header says, is reported as corrupt rather than with the raw zlib error.
+#[tracing::instrument(level = "debug", skip(git_dir))]
+fn read_object_bytes(git_dir: &Path, object_id: &str) -> Result<Vec<u8>> {
+    let Some(file) = open_loose(git_dir, object_id)? else {
+        let (object_type, content) = read_packed(git_dir, object_id)?;
+        return Ok(encode_object(&object_type.to_string(), &content));
+    };
+    let reader = BufReader::new(file);
+
+    let mut decoder = ZlibDecoder::new(reader);
+    let mut buffer: Vec<u8> = Vec::new();
+    decoder
+        .read_to_end(&mut buffer)
+        .map_err(|err| invalid_zlib(object_id, err))?;
+    tracing::trace!(inflated = buffer.len(), "read loose object");
+
+    let (_, header_len) = parse_header(object_id, &buffer)?;
+    let declared = parse_size(object_id, &buffer[..header_len])?;
+    let stored = buffer.len() - header_len - 1;
+    if stored != declared {
+        return Err(GitError::corrupt(
+            object_id,
+            format!(
+                "its header declares {} bytes but {} are stored.",
+                declared, stored
+            ),
+        ));
+    }
+
+    Ok(buffer)
+}
+
+fn invalid_zlib(object_id: &str, err: io::Error) -> GitError {
+    GitError::corrupt(object_id, format!("not valid zlib data: {}.", err))
+}
+
+/// The error reading the content of a loose object failed with: what
+/// [`SizedReader`] found wrong with it, or else bad zlib data.
+fn read_error(object_id: &str, err: io::Error) -> GitError {
+    if !err.get_ref().is_some_and(|x| x.is::<GitError>()) {
+        return invalid_zlib(object_id, err);
+    }
+    let err = err.into_inner().expect("checked to hold an error");
+    *err.downcast::<GitError>()
+        .expect("checked to be a GitError")
+}
+
+/// Finds the `<type> <length>` header of inflated object bytes, returning the
+/// type and the position of the NUL ending the header.
+fn parse_header(object_id: &str, buffer: &[u8]) -> Result<(ObjectType, usize)> {
+    let header_len = buffer
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or_else(|| GitError::corrupt(object_id, "no header found."))?;
+    let object_type_bytes = buffer[..header_len]
+        .split(|&byte| byte == 0x20)
+        .next()
+        .unwrap_or_default();
+    let object_type = bytes_to_object_type(object_type_bytes)
+        .map_err(|err| GitError::corrupt(object_id, format!("invalid header: {}", err)))?;
+    Ok((object_type, header_len))
+}
+
+/// Parses the length out of a `<type> <length>` header.
+fn parse_size(object_id: &str, header: &[u8]) -> Result<usize> {
+    header
+        .split(|&byte| byte == 0x20)
+        .nth(1)
+        .and_then(|x| std::str::from_utf8(x).ok())
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| GitError::corrupt(object_id, "invalid header length."))
+}
+
+#[tracing::instrument(level = "debug", skip(git_dir))]
+fn open_object(git_dir: &Path, object_id: &str) -> Result<(ObjectType, usize, Box<dyn Read>)> {
+    let Some(file) = open_loose(git_dir, object_id)? else {
+        let (object_type, content) = read_packed(git_dir, object_id)?;
+        return Ok((
+            object_type,
+            content.len(),
+            Box::new(io::Cursor::new(content)),
+        ));
+    };
+    let mut decoder = ZlibDecoder::new(BufReader::new(file));
+
+    let mut header = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        decoder
+            .read_exact(&mut byte)
+            .map_err(|err| invalid_zlib(object_id, err))?;
+        header.push(byte[0]);
+        if byte[0] == 0 {
+            break;
+        }
+        if header.len() > MAX_HEADER_LEN {
+            return Err(GitError::corrupt(object_id, "no header found."));
+        }
+    }
+
+    let (object_type, header_len) = parse_header(object_id, &header)?;
+    let size = parse_size(object_id, &header[..header_len])?;
+    tracing::trace!(%object_type, size, "opened object");
+
+    let reader = SizedReader {
+        inner: decoder,
+        object_id: object_id.to_string(),
+        size,
+        remaining: size,
+        ended: false,
+    };
+    Ok((object_type, size, Box::new(reader)))
+}
+
+/// Reads the content of a loose object, and fails rather than ending
+/// quietly if fewer or more bytes are stored than its header declares, or
+/// the stream after them is cut short.
+struct SizedReader<R> {
+    inner: R,
+    object_id: String,
+    size: usize,
+    remaining: usize,
+    ended: bool,
+}
+
+impl<R: Read> Read for SizedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.remaining);
+        if len == 0 {
+            if !buf.is_empty() && !self.ended {
+                // Reading past the content checks the stream ends there.
+                if self.inner.read(&mut [0])? != 0 {
+                    let reason = format!(
+                        "it holds more than the {} bytes its header declares.",
+                        self.size
+                    );
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        GitError::corrupt(&self.object_id, reason),
+                    ));
+                }
+                self.ended = true;
+            }
+            return Ok(0);
+        }
+        let read = self.inner.read(&mut buf[..len])?;
+        if read == 0 {
+            let reason = format!(
+                "its header declares {} bytes but {} are stored.",
+                self.size,
+                self.size - self.remaining
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                GitError::corrupt(&self.object_id, reason),
+            ));
+        }
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+
+/// Reads only the header of an object, returning its type and content size
+/// without inflating the rest of it, or `None` if the object isn't stored.
+///
+/// Packed objects are answered from their entry header, and deltas by
+/// inflating just the start of the delta.
+fn stat_object(git_dir: &Path, object_id: &str) -> Result<Option<(ObjectType, usize)>> {
+    if !object_path(git_dir, object_id).exists() {
+        return Ok(pack::read_packed_header(git_dir, object_id)?);
+    }
+    let (object_type, size, _) = open_object(git_dir, object_id)?;
+    Ok(Some((object_type, size)))
+}
+
+/// Reads an object and returns its type and content, without the header.
+///
+/// Loose objects are inflated straight into a buffer of the declared size,
+/// so the content is never copied out of a larger one.
+fn read_object(git_dir: &Path, object_id: &str) -> Result<(ObjectType, Vec<u8>)> {
+    if !object_path(git_dir, object_id).exists() {
+        return read_packed(git_dir, object_id);
+    }
+    let (object_type, size, mut reader) = open_object(git_dir, object_id)?;
+
+    let mut content = Vec::with_capacity(size);
+    reader
+        .read_to_end(&mut content)
+        .map_err(|err| read_error(object_id, err))?;
+    Ok((object_type, content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{hash_object, serialize_tree};
+    use crate::pack::PackWriter;
+
+    /// A git directory with just the object store, removed on drop.
+    struct TempGitDir(PathBuf);
+
+    impl TempGitDir {
+        fn new(name: &str) -> TempGitDir {
+            let dir = std::env::temp_dir().join(format!(
+                "codecrafters-git-odb-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("objects/pack")).unwrap();
+            TempGitDir(dir)
+        }
+    }
+
+    impl Drop for TempGitDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn stat_object_answers_for_loose_and_packed_objects() {
+        let git_dir = TempGitDir::new("stat");
+        let mut repository = Repository::open(&git_dir.0);
+
+        let loose = repository.write(ObjectType::Blob, b"loose\n").unwrap();
+        assert_eq!(
+            repository.stat_object(&loose).unwrap(),
+            Some((ObjectType::Blob, 6))
+        );
+
+        let tree = serialize_tree(&[(
+            "100644".to_string(),
+            "a".to_string(),
+            hex::decode(&loose).unwrap(),
+        )]);
+        let mut data = Vec::new();
+        let mut writer = PackWriter::new(&mut data, 2).unwrap();
+        writer.add(ObjectType::Blob, b"packed content\n").unwrap();
+        writer.add(ObjectType::Tree, &tree).unwrap();
+        writer.finish().unwrap();
+        pack::store_pack(&git_dir.0, &data).unwrap();
+
+        let packed = hash_object("blob", b"packed content\n");
+        let packed_tree = hash_object("tree", &tree);
+        assert!(!repository.loose_path(&packed).exists());
+        assert_eq!(
+            repository.stat_object(&packed).unwrap(),
+            Some((ObjectType::Blob, 15))
+        );
+        assert_eq!(
+            repository.stat_object(&packed_tree).unwrap(),
+            Some((ObjectType::Tree, tree.len() as u64))
+        );
+        assert_eq!(
+            repository
+                .stat_object(&hash_object("blob", b"missing"))
+                .unwrap(),
+            None
+        );
+        assert_eq!(repository.object_ids().unwrap().len(), 3);
+        assert_eq!(repository.loose_object_ids().unwrap(), vec![loose]);
+    }
+
+    /// Writes a blob, a tree holding it and a commit of the tree, returning
+    /// their ids.
+    fn write_commit<B: RepositoryBackend>(repository: &mut Repository<B>) -> [String; 3] {
+        let blob = repository.write(ObjectType::Blob, b"hi\n").unwrap();
+        let tree = serialize_tree(&[(
+            "100644".to_string(),
+            "a".to_string(),
+            hex::decode(&blob).unwrap(),
+        )]);
+        let tree = repository.write(ObjectType::Tree, &tree).unwrap();
+        let commit = format!(
+            "tree {}\nauthor A <a@b> 1 +0000\ncommitter A <a@b> 1 +0000\n\nfirst\n",
+            tree
+        );
+        let commit = repository
+            .write(ObjectType::Commit, commit.as_bytes())
+            .unwrap();
+        [blob, tree, commit]
+    }
+
+    #[test]
+    fn memory_repositories_read_back_what_they_store() {
+        let mut repository = Repository::in_memory();
+        let [blob, tree, commit] = write_commit(&mut repository);
+        assert_eq!(blob, "45b983be36b73c0788dc9cbcb76cbb80fc7bb057");
+
+        assert_eq!(
+            repository.read(&blob).unwrap(),
+            (ObjectType::Blob, b"hi\n".to_vec())
+        );
+        assert_eq!(repository.commit(&commit).unwrap().tree, tree);
+        let elements = repository.tree(&tree).unwrap().elements;
+        assert_eq!(elements.len(), 1);
+        assert_eq!(hex::encode(&elements[0].hash), blob);
+        assert_eq!(
+            repository.stat_object(&tree).unwrap(),
+            Some((ObjectType::Tree, 29))
+        );
+
+        assert!(matches!(
+            repository.tree(&commit),
+            Err(GitError::WrongObjectType { .. })
+        ));
+        assert!(matches!(
+            repository.tag(&blob),
+            Err(GitError::WrongObjectType { .. })
+        ));
+        let missing = "0000000000000000000000000000000000000000";
+        assert!(matches!(
+            repository.read(missing),
+            Err(GitError::ObjectNotFound(_))
+        ));
+        assert!(!repository.exists(missing).unwrap());
+        assert_eq!(repository.stat_object(missing).unwrap(), None);
+
+        // Writing an object again stores nothing new.
+        assert_eq!(repository.write(ObjectType::Blob, b"hi\n").unwrap(), blob);
+        let mut ids = vec![blob, tree, commit];
+        ids.sort();
+        assert_eq!(repository.object_ids().unwrap(), ids);
+    }
+
+    #[test]
+    fn memory_repositories_match_the_repository_on_disk() {
+        let git_dir = TempGitDir::new("parity");
+        let mut on_disk = Repository::open(&git_dir.0);
+        let mut in_memory = Repository::in_memory();
+
+        let ids = write_commit(&mut on_disk);
+        assert_eq!(write_commit(&mut in_memory), ids);
+        for id in &ids {
+            assert_eq!(on_disk.read(id).unwrap(), in_memory.read(id).unwrap());
+            assert_eq!(
+                on_disk.stat_object(id).unwrap(),
+                in_memory.stat_object(id).unwrap()
+            );
+        }
+        assert_eq!(
+            on_disk.object_ids().unwrap(),
+            in_memory.object_ids().unwrap()
+        );
+    }
+
+    #[test]
+    fn objects_parse_and_peel_through_any_backend() {
+        let mut repository = Repository::in_memory();
+        let [blob, tree, commit] = write_commit(&mut repository);
+        let tag = format!(
+            "object {}\ntype commit\ntag v1\ntagger A <a@b> 1 +0000\n\nv1\n",
+            commit
+        );
+        let tag = repository.write(ObjectType::Tag, tag.as_bytes()).unwrap();
+
+        assert_eq!(repository.peel_tags(&tag).unwrap(), commit);
+        assert_eq!(repository.peel_tags(&blob).unwrap(), blob);
+        assert_eq!(repository.peel_to(&tag, ObjectType::Tree).unwrap(), tree);
+        assert!(matches!(
+            repository.peel_to(&blob, ObjectType::Commit),
+            Err(GitError::WrongObjectType { .. })
+        ));
+        assert_eq!(
+            repository.read_header(&tree).unwrap(),
+            (ObjectType::Tree, 29)
+        );
+
+        assert!(matches!(
+            repository.object(&blob).unwrap(),
+            Object::Blob(x) if x.content == b"hi\n"
+        ));
+        assert!(matches!(
+            repository.object(&tag).unwrap(),
+            Object::Tag(x) if x.object == commit
+        ));
+        let bad = repository.write(ObjectType::Tree, b"100644 a").unwrap();
+        assert!(matches!(
+            repository.object(&bad),
+            Err(GitError::CorruptObject { .. })
+        ));
+    }
+
+    #[test]
+    fn compressed_objects_are_stored_as_they_are() {
+        let git_dir = TempGitDir::new("compressed");
+        let mut repository = Repository::open(&git_dir.0);
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&encode_object("blob", b"hi\n")).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let id = hash_object("blob", b"hi\n");
+        repository.write_compressed(&id, &compressed).unwrap();
+
+        assert_eq!(fs::read(repository.loose_path(&id)).unwrap(), compressed);
+        assert_eq!(
+            repository.loose_path(&id),
+            git_dir.0.join("objects").join(loose_name(&id))
+        );
+        assert_eq!(
+            repository.read(&id).unwrap(),
+            (ObjectType::Blob, b"hi\n".to_vec())
+        );
+        repository.verify(&id).unwrap();
+        assert_eq!(repository.expand_id(&id[..7]).unwrap(), id);
+    }
+
+    #[test]
+    fn memory_repositories_can_name_objects_with_sha256() {
+        let mut repository = Repository::new(MemoryBackend::with_format(ObjectFormat::Sha256));
+        let [blob, tree, commit] = write_commit(&mut repository);
+        assert_eq!(blob.len(), 64);
+        assert_eq!(blob, ObjectFormat::Sha256.hash_object("blob", b"hi\n"));
+        let elements = repository.tree(&tree).unwrap().elements;
+        assert_eq!(hex::encode(&elements[0].hash), blob);
+        assert_eq!(repository.commit(&commit).unwrap().tree, tree);
+
+        let (_, content) = repository.read(&tree).unwrap();
+        assert_eq!(
+            repository
+                .linked_objects(ObjectType::Tree, &content)
+                .unwrap(),
+            vec![blob]
+        );
+        let (_, content) = repository.read(&commit).unwrap();
+        assert_eq!(
+            repository
+                .linked_objects(ObjectType::Commit, &content)
+                .unwrap(),
+            vec![tree]
+        );
+    }
+}
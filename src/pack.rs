@@ -0,0 +1,940 @@
+use crate::git_path;
+use crate::object::{ObjectFormat, ObjectType};
+use crate::odb::Repository;
+use crate::progress::Progress;
+use anyhow::{anyhow, Context};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+const IDX_V2_MAGIC: &[u8] = b"\xfftOc";
+const FANOUT_SIZE: usize = 256 * 4;
+
+/// Deltas may be based on deltas; bound the chain so a corrupt pack can't recurse forever.
+const MAX_DELTA_DEPTH: usize = 64;
+
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+/// The object ids of a pack and where each one starts in the `.pack` file.
+pub struct PackIndex {
+    /// `(object id, offset)`, sorted by id.
+    entries: Vec<([u8; 20], u64)>,
+}
+
+impl PackIndex {
+    pub fn open(idx_path: &Path) -> anyhow::Result<PackIndex> {
+        let data = fs::read(idx_path)?;
+        PackIndex::from_bytes(&data)
+            .with_context(|| format!("Read pack index {}.", idx_path.display()))
+    }
+
+    /// Parses a pack index, version 1 or 2.
+    pub fn from_bytes(data: &[u8]) -> anyhow::Result<PackIndex> {
+        // Version 2 starts with a magic number and version; version 1 starts
+        // straight with the fanout table and stores `<offset><id>` records.
+        let v2 = data.starts_with(IDX_V2_MAGIC);
+        let fanout_start = if v2 { 8 } else { 0 };
+        let truncated = || anyhow!("Pack index is truncated.");
+
+        let fanout = data
+            .get(fanout_start..fanout_start + FANOUT_SIZE)
+            .ok_or_else(truncated)?;
+        let count = u32::from_be_bytes(fanout[FANOUT_SIZE - 4..].try_into()?) as usize;
+        let records_start = fanout_start + FANOUT_SIZE;
+
+        let mut entries = Vec::with_capacity(count);
+        if !v2 {
+            let records = data
+                .get(records_start..records_start + count * 24)
+                .ok_or_else(truncated)?;
+            for record in records.chunks_exact(24) {
+                let offset = u32::from_be_bytes(record[..4].try_into()?) as u64;
+                entries.push((record[4..].try_into()?, offset));
+            }
+            return Ok(PackIndex { entries });
+        }
+
+        // Ids, then a CRC32 per object, then 31-bit offsets whose high bit
+        // points into a table of 64-bit offsets for packs over 2 GiB.
+        let offsets_start = records_start + count * 24;
+        let large_offsets_start = offsets_start + count * 4;
+        let ids = data
+            .get(records_start..records_start + count * 20)
+            .ok_or_else(truncated)?;
+        let offsets = data
+            .get(offsets_start..large_offsets_start)
+            .ok_or_else(truncated)?;
+
+        for (id, offset) in ids.chunks_exact(20).zip(offsets.chunks_exact(4)) {
+            let offset = u32::from_be_bytes(offset.try_into()?);
+            let offset = if offset & 0x8000_0000 == 0 {
+                offset as u64
+            } else {
+                let start = large_offsets_start + (offset & 0x7fff_ffff) as usize * 8;
+                let large = data.get(start..start + 8).ok_or_else(truncated)?;
+                u64::from_be_bytes(large.try_into()?)
+            };
+            entries.push((id.try_into()?, offset));
+        }
+
+        Ok(PackIndex { entries })
+    }
+
+    /// Offset of an object in the pack, if the pack holds it.
+    pub fn find(&self, object_id: &str) -> Option<u64> {
+        let id: [u8; 20] = hex::decode(object_id).ok()?.try_into().ok()?;
+        let pos = self.entries.binary_search_by(|x| x.0.cmp(&id)).ok()?;
+        Some(self.entries[pos].1)
+    }
+
+    /// The ids of every object in the pack, in sorted order.
+    pub fn object_ids(&self) -> impl Iterator<Item = String> + '_ {
+        self.entries.iter().map(|x| hex::encode(x.0))
+    }
+}
+
+/// A pack loaded in memory together with its index.
+struct Pack {
+    index: PackIndex,
+    data: Vec<u8>,
+}
+
+/// Lists the `.pack` files in `objects/pack`, each with its `.idx` path.
+pub fn list_packs(objects_dir: &Path) -> anyhow::Result<Vec<(PathBuf, PathBuf)>> {
+    let Some(entries) = fs::read_dir(objects_dir.join("pack")).ok() else {
+        return Ok(Vec::new());
+    };
+
+    let mut packs = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|x| x == "pack") {
+            let idx = path.with_extension("idx");
+            packs.push((path, idx));
+        }
+    }
+    packs.sort();
+    Ok(packs)
+}
+
+/// Reads the object ids listed in a pack index (version 1 or 2), in sorted order.
+pub fn index_object_ids(idx_path: &Path) -> anyhow::Result<Vec<String>> {
+    Ok(PackIndex::open(idx_path)?.object_ids().collect())
+}
+
+/// Whether any pack of the repository holds the object.
+pub fn has_packed_object(git_dir: &Path, object_id: &str) -> anyhow::Result<bool> {
+    Ok(find_packed_object(git_dir, object_id)?.is_some())
+}
+
+/// The pack file holding the object and the offset of its entry in it, if any pack does.
+pub fn find_packed_object(
+    git_dir: &Path,
+    object_id: &str,
+) -> anyhow::Result<Option<(PathBuf, u64)>> {
+    for (pack_path, idx_path) in list_packs(&git_path(git_dir, "objects"))? {
+        if let Some(offset) = load_pack(&pack_path, &idx_path)?.index.find(object_id) {
+            return Ok(Some((pack_path, offset)));
+        }
+    }
+    Ok(None)
+}
+
+/// Reads an object out of the repository's packs, resolving deltas, and
+/// returns its type and content, or `None` if no pack holds it.
+pub fn read_packed_object(
+    git_dir: &Path,
+    object_id: &str,
+) -> anyhow::Result<Option<(ObjectType, Vec<u8>)>> {
+    read_packed_object_at_depth(git_dir, object_id, 0)
+}
+
+fn read_packed_object_at_depth(
+    git_dir: &Path,
+    object_id: &str,
+    depth: usize,
+) -> anyhow::Result<Option<(ObjectType, Vec<u8>)>> {
+    for (pack_path, idx_path) in list_packs(&git_path(git_dir, "objects"))? {
+        let pack = load_pack(&pack_path, &idx_path)?;
+        if let Some(offset) = pack.index.find(object_id) {
+            let object = read_at(git_dir, &pack, offset, depth)
+                .with_context(|| format!("Read {} from {}.", object_id, pack_path.display()))?;
+            return Ok(Some(object));
+        }
+    }
+    Ok(None)
+}
+
+/// Packs are immutable once written, so each one is read and indexed once per process.
+fn load_pack(pack_path: &Path, idx_path: &Path) -> anyhow::Result<Arc<Pack>> {
+    static PACKS: OnceLock<Mutex<HashMap<PathBuf, Arc<Pack>>>> = OnceLock::new();
+    let mut packs = PACKS.get_or_init(Default::default).lock().unwrap();
+
+    if let Some(pack) = packs.get(pack_path) {
+        return Ok(pack.clone());
+    }
+
+    let data = fs::read(pack_path).with_context(|| format!("Read {}.", pack_path.display()))?;
+    if !data.starts_with(b"PACK") {
+        return Err(anyhow!("{} is not a pack file.", pack_path.display()));
+    }
+    let pack = Arc::new(Pack {
+        index: PackIndex::open(idx_path)?,
+        data,
+    });
+    packs.insert(pack_path.to_path_buf(), pack.clone());
+    Ok(pack)
+}
+
+/// The header of a pack entry: its type, its size once inflated, where its
+/// compressed data starts and, for deltas, where to find the base.
+struct EntryHeader {
+    kind: u8,
+    size: usize,
+    data_start: usize,
+    base: Option<DeltaBase>,
+}
+
+enum DeltaBase {
+    /// Offset of the base in the same pack.
+    Offset(u64),
+    Id(String),
+}
+
+fn read_entry_header(data: &[u8], offset: u64) -> anyhow::Result<EntryHeader> {
+    let truncated = || anyhow!("Pack is truncated.");
+    let mut pos = offset as usize;
+    let mut next_byte = || -> anyhow::Result<u8> {
+        let byte = *data.get(pos).ok_or_else(truncated)?;
+        pos += 1;
+        Ok(byte)
+    };
+
+    // Type in bits 4-6 of the first byte; the size follows as a little-endian
+    // varint starting with that byte's low four bits.
+    let mut byte = next_byte()?;
+    let kind = (byte >> 4) & 0x7;
+    let mut size = (byte & 0x0f) as usize;
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        byte = next_byte()?;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+    }
+
+    let base = match kind {
+        OBJ_OFS_DELTA => {
+            // Distance back to the base, in git's offset varint encoding.
+            byte = next_byte()?;
+            let mut distance = (byte & 0x7f) as u64;
+            while byte & 0x80 != 0 {
+                byte = next_byte()?;
+                distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+            }
+            let base_offset = offset
+                .checked_sub(distance)
+                .ok_or_else(|| anyhow!("Delta base lies before the start of the pack."))?;
+            Some(DeltaBase::Offset(base_offset))
+        }
+        OBJ_REF_DELTA => {
+            let base_id = hex::encode(data.get(pos..pos + 20).ok_or_else(truncated)?);
+            pos += 20;
+            Some(DeltaBase::Id(base_id))
+        }
+        _ => None,
+    };
+
+    Ok(EntryHeader {
+        kind,
+        size,
+        data_start: pos,
+        base,
+    })
+}
+
+/// Reads the object starting at `offset`, applying deltas against their bases.
+fn read_at(
+    git_dir: &Path,
+    pack: &Pack,
+    offset: u64,
+    depth: usize,
+) -> anyhow::Result<(ObjectType, Vec<u8>)> {
+    if depth > MAX_DELTA_DEPTH {
+        return Err(anyhow!("Delta chain is too deep."));
+    }
+
+    let header = read_entry_header(&pack.data, offset)?;
+    let base = match &header.base {
+        Some(DeltaBase::Offset(base_offset)) => {
+            Some(read_at(git_dir, pack, *base_offset, depth + 1)?)
+        }
+        // The base may be in this pack, another one, or loose (thin packs).
+        Some(DeltaBase::Id(base_id)) => Some(match pack.index.find(base_id) {
+            Some(base_offset) => read_at(git_dir, pack, base_offset, depth + 1)?,
+            None => match read_packed_object_at_depth(git_dir, base_id, depth + 1)? {
+                Some(base) => base,
+                None => Repository::open(git_dir).read(base_id)?,
+            },
+        }),
+        None => None,
+    };
+
+    let mut data = Vec::with_capacity(header.size);
+    ZlibDecoder::new(
+        pack.data
+            .get(header.data_start..)
+            .ok_or_else(|| anyhow!("Pack is truncated."))?,
+    )
+    .read_to_end(&mut data)
+    .context("Inflate packed object.")?;
+    if data.len() != header.size {
+        return Err(anyhow!("Packed object has the wrong size."));
+    }
+
+    match base {
+        Some((base_type, base_content)) => Ok((base_type, apply_delta(&base_content, &data)?)),
+        None => Ok((object_type(header.kind)?, data)),
+    }
+}
+
+/// Reads the type and size of the object at `offset` without inflating it.
+///
+/// A delta's size is the first varints of its (compressed) instructions, so
+/// only those first bytes are inflated; its type is that of its base.
+fn header_at(
+    git_dir: &Path,
+    pack: &Pack,
+    offset: u64,
+    depth: usize,
+) -> anyhow::Result<(ObjectType, usize)> {
+    if depth > MAX_DELTA_DEPTH {
+        return Err(anyhow!("Delta chain is too deep."));
+    }
+
+    let header = read_entry_header(&pack.data, offset)?;
+    let base_type = match &header.base {
+        None => return Ok((object_type(header.kind)?, header.size)),
+        Some(DeltaBase::Offset(base_offset)) => {
+            header_at(git_dir, pack, *base_offset, depth + 1)?.0
+        }
+        Some(DeltaBase::Id(base_id)) => match pack.index.find(base_id) {
+            Some(base_offset) => header_at(git_dir, pack, base_offset, depth + 1)?.0,
+            None => match read_packed_header_at_depth(git_dir, base_id, depth + 1)? {
+                Some((base_type, _)) => base_type,
+                None => Repository::open(git_dir).read_header(base_id)?.0,
+            },
+        },
+    };
+
+    // Two varints of at most ten bytes each: the base size, then the result size.
+    let mut delta_header = Vec::new();
+    ZlibDecoder::new(
+        pack.data
+            .get(header.data_start..)
+            .ok_or_else(|| anyhow!("Pack is truncated."))?,
+    )
+    .take(20)
+    .read_to_end(&mut delta_header)
+    .context("Inflate delta header.")?;
+    let mut delta_header = &delta_header[..];
+    delta_size(&mut delta_header)?;
+    Ok((base_type, delta_size(&mut delta_header)?))
+}
+
+/// Reads an object's type and size out of the repository's packs without
+/// inflating its content, or returns `None` if no pack holds it.
+pub fn read_packed_header(
+    git_dir: &Path,
+    object_id: &str,
+) -> anyhow::Result<Option<(ObjectType, usize)>> {
+    read_packed_header_at_depth(git_dir, object_id, 0)
+}
+
+fn read_packed_header_at_depth(
+    git_dir: &Path,
+    object_id: &str,
+    depth: usize,
+) -> anyhow::Result<Option<(ObjectType, usize)>> {
+    for (pack_path, idx_path) in list_packs(&git_path(git_dir, "objects"))? {
+        let pack = load_pack(&pack_path, &idx_path)?;
+        if let Some(offset) = pack.index.find(object_id) {
+            let header = header_at(git_dir, &pack, offset, depth)
+                .with_context(|| format!("Read {} from {}.", object_id, pack_path.display()))?;
+            return Ok(Some(header));
+        }
+    }
+    Ok(None)
+}
+
+fn object_type(kind: u8) -> anyhow::Result<ObjectType> {
+    match kind {
+        1 => Ok(ObjectType::Commit),
+        2 => Ok(ObjectType::Tree),
+        3 => Ok(ObjectType::Blob),
+        4 => Ok(ObjectType::Tag),
+        _ => Err(anyhow!("Invalid packed object type {}.", kind)),
+    }
+}
+
+fn type_code(object_type: ObjectType) -> u8 {
+    match object_type {
+        ObjectType::Commit => 1,
+        ObjectType::Tree => 2,
+        ObjectType::Blob => 3,
+        ObjectType::Tag => 4,
+    }
+}
+
+/// Writes a version 2 pack: the `PACK` header with the object count, each
+/// object as a type/size varint followed by its zlib-compressed content, and
+/// a trailing SHA-1 over everything before it.
+///
+/// Objects are stored whole, without deltas, which every reader accepts.
+pub struct PackWriter<W: Write> {
+    out: W,
+    hasher: Sha1,
+    remaining: u32,
+    /// Bytes written so far, which is where the next entry starts.
+    offset: u64,
+}
+
+impl<W: Write> PackWriter<W> {
+    /// Starts a pack that will hold exactly `count` objects.
+    pub fn new(out: W, count: u32) -> anyhow::Result<PackWriter<W>> {
+        let mut writer = PackWriter {
+            out,
+            hasher: Sha1::new(),
+            remaining: count,
+            offset: 0,
+        };
+        let mut header = b"PACK".to_vec();
+        header.extend_from_slice(&2u32.to_be_bytes());
+        header.extend_from_slice(&count.to_be_bytes());
+        writer.write(&header)?;
+        Ok(writer)
+    }
+
+    /// Appends an object and returns the offset of its entry, as a pack index records it.
+    pub fn add(&mut self, object_type: ObjectType, content: &[u8]) -> anyhow::Result<u64> {
+        if self.remaining == 0 {
+            return Err(anyhow!(
+                "Pack already holds the announced number of objects."
+            ));
+        }
+        self.remaining -= 1;
+
+        // Type in bits 4-6 of the first byte, then the size as a little-endian
+        // varint: four bits in the first byte, seven in each following one.
+        let mut size = content.len();
+        let mut header = vec![(type_code(object_type) << 4) | (size & 0x0f) as u8];
+        size >>= 4;
+        while size > 0 {
+            *header.last_mut().unwrap() |= 0x80;
+            header.push((size & 0x7f) as u8);
+            size >>= 7;
+        }
+
+        let offset = self.offset;
+        self.write(&header)?;
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content)?;
+        self.write(&encoder.finish()?)?;
+        Ok(offset)
+    }
+
+    /// Writes the trailing checksum and returns it, hex encoded; packs are
+    /// conventionally named `pack-<checksum>.pack`.
+    pub fn finish(mut self) -> anyhow::Result<String> {
+        if self.remaining != 0 {
+            return Err(anyhow!(
+                "Pack is missing {} of its announced objects.",
+                self.remaining
+            ));
+        }
+        let checksum = self.hasher.finalize();
+        self.out.write_all(&checksum)?;
+        self.out.flush()?;
+        Ok(hex::encode(checksum))
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        self.hasher.update(bytes);
+        self.out.write_all(bytes)?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+/// Fails unless the repository at `git_dir` names its objects with SHA-1,
+/// the only hash packs and their indexes are read and written with here.
+/// Commands that make or store packs check it before doing anything else.
+pub fn require_sha1(git_dir: &Path) -> anyhow::Result<()> {
+    match ObjectFormat::of(git_dir)? {
+        ObjectFormat::Sha1 => Ok(()),
+        format => Err(anyhow!(
+            "Packs are only supported in sha1 repositories, not {} ones.",
+            format
+        )),
+    }
+}
+
+/// Writes the given objects of the repository to `out` as a pack and returns its checksum.
+///
+/// The pack doesn't depend on the order of `object_ids` (duplicates are
+/// dropped): objects are written commits first, then tags, trees and blobs,
+/// commits newest first by committer date, with ties broken by id. The same
+/// set of objects therefore always yields a byte-identical pack.
+pub fn write_pack(
+    git_dir: &Path,
+    object_ids: &[String],
+    out: impl Write,
+) -> anyhow::Result<String> {
+    let mut object_ids: Vec<&String> = object_ids.iter().collect();
+    object_ids.sort();
+    object_ids.dedup();
+
+    let mut objects = Vec::with_capacity(object_ids.len());
+    for object_id in object_ids {
+        let (object_type, content) = Repository::open(git_dir).read(object_id)?;
+        let time = match object_type {
+            ObjectType::Commit => crate::object::CommitObject::from_content(&content)?
+                .committer
+                .rsplit(' ')
+                .nth(1)
+                .and_then(|x| x.parse::<i64>().ok())
+                .unwrap_or_default(),
+            _ => 0,
+        };
+        objects.push((object_type, time, object_id, content));
+    }
+
+    let rank = |object_type: ObjectType| match object_type {
+        ObjectType::Commit => 0,
+        ObjectType::Tag => 1,
+        ObjectType::Tree => 2,
+        ObjectType::Blob => 3,
+    };
+    objects.sort_by(|a, b| {
+        (rank(a.0), std::cmp::Reverse(a.1), a.2).cmp(&(rank(b.0), std::cmp::Reverse(b.1), b.2))
+    });
+
+    let mut writer = PackWriter::new(out, objects.len().try_into()?)?;
+    for (object_type, _, _, content) in &objects {
+        writer.add(*object_type, content)?;
+    }
+    writer.finish()
+}
+
+/// An object read out of a pack file by [`parse_pack`], deltas applied.
+pub struct PackedObject {
+    pub id: [u8; 20],
+    pub object_type: ObjectType,
+    pub content: Vec<u8>,
+    /// Where the object's entry starts in the pack.
+    pub offset: u64,
+    /// CRC32 of the entry's raw bytes, as pack indexes record it.
+    pub crc: u32,
+    /// The size of the entry's data once inflated: for a delta, the
+    /// delta's own size rather than the object's.
+    pub stored_size: usize,
+    /// How many bytes the entry takes in the pack, header included.
+    pub packed_size: u64,
+    /// The object a delta is based on.
+    pub base: Option<[u8; 20]>,
+}
+
+/// Reads every object of a pack received whole, in pack order: checks the
+/// header and trailing checksum, inflates each entry and resolves its
+/// deltas to learn its id.
+///
+/// Deltas are normally based on objects in the same pack; a thin pack's
+/// missing bases are asked of `external_base`, which returns `None` for
+/// objects it doesn't have either.
+pub fn parse_pack<F>(data: &[u8], external_base: F) -> anyhow::Result<Vec<PackedObject>>
+where
+    F: Fn(&str) -> anyhow::Result<Option<(ObjectType, Vec<u8>)>>,
+{
+    if data.len() < 12 + 20 || !data.starts_with(b"PACK") {
+        return Err(anyhow!("Not a pack file."));
+    }
+    let (content, checksum) = data.split_at(data.len() - 20);
+    if Sha1::digest(content)[..] != checksum[..] {
+        return Err(anyhow!("Pack checksum mismatch: the pack is corrupt."));
+    }
+    let version = u32::from_be_bytes(data[4..8].try_into()?);
+    if version != 2 && version != 3 {
+        return Err(anyhow!("Unsupported pack version {}.", version));
+    }
+    let count = u32::from_be_bytes(data[8..12].try_into()?) as usize;
+
+    // Inflate every entry, noting where it ends for the next one and its
+    // CRC32 over the raw entry bytes.
+    let mut entries = Vec::with_capacity(count);
+    let mut offset = 12;
+    let mut progress = Progress::new("Indexing objects", Some(count as u64));
+    for _ in 0..count {
+        let header = read_entry_header(content, offset as u64)?;
+        let mut decoder = flate2::bufread::ZlibDecoder::new(
+            content
+                .get(header.data_start..)
+                .ok_or_else(|| anyhow!("Pack is truncated."))?,
+        );
+        let mut inflated = Vec::with_capacity(header.size);
+        decoder
+            .read_to_end(&mut inflated)
+            .with_context(|| format!("Inflate the pack entry at offset {}.", offset))?;
+        if inflated.len() != header.size {
+            return Err(anyhow!(
+                "Pack entry at offset {} has the wrong size.",
+                offset
+            ));
+        }
+        let end = header.data_start + decoder.total_in() as usize;
+        let mut crc = flate2::Crc::new();
+        crc.update(&content[offset..end]);
+        entries.push((offset as u64, header, inflated, crc.sum(), end));
+        offset = end;
+        progress.tick();
+    }
+    progress.finish();
+    if offset != content.len() {
+        return Err(anyhow!("Pack has trailing data after its last entry."));
+    }
+
+    // Resolve deltas. An offset delta's base comes earlier in the pack, but a
+    // ref delta may name a base further on, so repeat until nothing changes;
+    // only then are bases looked for outside the pack.
+    let positions: HashMap<u64, usize> = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| (entry.0, i))
+        .collect();
+    let mut objects: Vec<Option<(ObjectType, Vec<u8>)>> = vec![None; count];
+    let mut ids: Vec<[u8; 20]> = vec![[0; 20]; count];
+    let mut by_id: HashMap<String, usize> = HashMap::new();
+    let mut external: HashMap<String, (ObjectType, Vec<u8>)> = HashMap::new();
+    let mut resolved = 0;
+    let mut look_outside = false;
+    let deltas = entries.iter().filter(|x| x.1.base.is_some()).count();
+    let mut progress = Progress::new("Resolving deltas", Some(deltas as u64));
+    while resolved < count {
+        let before = resolved;
+        for i in 0..count {
+            if objects[i].is_some() {
+                continue;
+            }
+            let (_, header, inflated, _, _) = &entries[i];
+            let object = match &header.base {
+                None => (object_type(header.kind)?, inflated.clone()),
+                Some(DeltaBase::Offset(base_offset)) => {
+                    let base = *positions
+                        .get(base_offset)
+                        .ok_or_else(|| anyhow!("Delta base at {} isn't an entry.", base_offset))?;
+                    match &objects[base] {
+                        Some((base_type, base)) => (*base_type, apply_delta(base, inflated)?),
+                        None => continue,
+                    }
+                }
+                Some(DeltaBase::Id(base_id)) => {
+                    let base = match by_id.get(base_id) {
+                        Some(&base) => objects[base].as_ref(),
+                        None if look_outside => {
+                            if !external.contains_key(base_id) {
+                                let Some(base) = external_base(base_id)? else {
+                                    return Err(anyhow!(
+                                        "Delta base {} is neither in the pack nor stored.",
+                                        base_id
+                                    ));
+                                };
+                                external.insert(base_id.clone(), base);
+                            }
+                            external.get(base_id)
+                        }
+                        None => None,
+                    };
+                    match base {
+                        Some((base_type, base)) => (*base_type, apply_delta(base, inflated)?),
+                        None => continue,
+                    }
+                }
+            };
+
+            let id: [u8; 20] = Sha1::digest(crate::object::encode_object(
+                &object.0.to_string(),
+                &object.1,
+            ))
+            .into();
+            by_id.insert(hex::encode(id), i);
+            ids[i] = id;
+            if header.base.is_some() {
+                progress.tick();
+            }
+            objects[i] = Some(object);
+            resolved += 1;
+        }
+        if resolved == before {
+            if look_outside {
+                return Err(anyhow!("Pack has deltas whose bases never resolve."));
+            }
+            look_outside = true;
+        }
+    }
+    if deltas > 0 {
+        progress.finish();
+    }
+
+    let bases: Vec<Option<[u8; 20]>> = entries
+        .iter()
+        .map(|(_, header, _, _, _)| match &header.base {
+            Some(DeltaBase::Offset(base_offset)) => Some(ids[positions[base_offset]]),
+            Some(DeltaBase::Id(base_id)) => hex::decode(base_id).ok()?.try_into().ok(),
+            None => None,
+        })
+        .collect();
+    Ok(entries
+        .into_iter()
+        .zip(ids)
+        .zip(objects)
+        .zip(bases)
+        .map(|((((offset, header, _, crc, end), id), object), base)| {
+            let (object_type, content) = object.unwrap_or((ObjectType::Blob, Vec::new()));
+            PackedObject {
+                id,
+                object_type,
+                content,
+                offset,
+                crc,
+                stored_size: header.size,
+                packed_size: end as u64 - offset,
+                base,
+            }
+        })
+        .collect())
+}
+
+/// Builds the version 2 index of a pack received whole, as `git index-pack`
+/// does, and returns its bytes. Thin packs aren't completed, so every delta
+/// base must be in the pack.
+pub fn index_pack(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let objects = parse_pack(data, |_| Ok(None))?;
+    Ok(write_index(&objects, &data[data.len() - 20..]))
+}
+
+/// The version 2 index of the pack holding `objects`, whose checksum is
+/// `pack_checksum`.
+fn write_index(objects: &[PackedObject], pack_checksum: &[u8]) -> Vec<u8> {
+    let mut records: Vec<([u8; 20], u32, u64)> =
+        objects.iter().map(|x| (x.id, x.crc, x.offset)).collect();
+    records.sort();
+
+    let mut idx = IDX_V2_MAGIC.to_vec();
+    idx.extend_from_slice(&2u32.to_be_bytes());
+    for byte in 0..=255u8 {
+        let below = records.partition_point(|x| x.0[0] <= byte) as u32;
+        idx.extend_from_slice(&below.to_be_bytes());
+    }
+    for (id, _, _) in &records {
+        idx.extend_from_slice(id);
+    }
+    for (_, crc, _) in &records {
+        idx.extend_from_slice(&crc.to_be_bytes());
+    }
+    // Offsets that don't fit in 31 bits go in a table of 64-bit offsets.
+    let mut large_offsets = Vec::new();
+    for (_, _, offset) in &records {
+        let small = match u32::try_from(*offset) {
+            Result::Ok(small) if small & 0x8000_0000 == 0 => small,
+            _ => {
+                large_offsets.extend_from_slice(&offset.to_be_bytes());
+                0x8000_0000 | (large_offsets.len() / 8 - 1) as u32
+            }
+        };
+        idx.extend_from_slice(&small.to_be_bytes());
+    }
+    idx.extend_from_slice(&large_offsets);
+    idx.extend_from_slice(pack_checksum);
+    let idx_checksum = Sha1::digest(&idx);
+    idx.extend_from_slice(&idx_checksum);
+    idx
+}
+
+/// Checks a pack against its index, as `git verify-pack` does: the pack's
+/// checksum and every entry in it, the index's own checksum, and that the
+/// index lists each object at its offset and names the pack. A version 2
+/// index must also carry each entry's CRC32. Returns the objects in pack
+/// order.
+pub fn verify_pack(pack_path: &Path, idx_path: &Path) -> anyhow::Result<Vec<PackedObject>> {
+    let data = fs::read(pack_path).with_context(|| format!("Read {}.", pack_path.display()))?;
+    let idx = fs::read(idx_path).with_context(|| format!("Read {}.", idx_path.display()))?;
+    let objects = parse_pack(&data, |_| Ok(None))?;
+
+    if idx.len() < 40 {
+        return Err(anyhow!("Pack index is truncated."));
+    }
+    let (idx_content, idx_checksum) = idx.split_at(idx.len() - 20);
+    if Sha1::digest(idx_content)[..] != idx_checksum[..] {
+        return Err(anyhow!(
+            "Pack index checksum mismatch: the index is corrupt."
+        ));
+    }
+    if idx_content[idx_content.len() - 20..] != data[data.len() - 20..] {
+        return Err(anyhow!("The index is for another pack."));
+    }
+    let index = PackIndex::from_bytes(&idx)?;
+    if index.entries.len() != objects.len() {
+        return Err(anyhow!(
+            "The index lists {} objects but the pack holds {}.",
+            index.entries.len(),
+            objects.len()
+        ));
+    }
+    for object in &objects {
+        let id = hex::encode(object.id);
+        if index.find(&id) != Some(object.offset) {
+            return Err(anyhow!(
+                "The index doesn't list {} at offset {}.",
+                id,
+                object.offset
+            ));
+        }
+    }
+    if idx.starts_with(IDX_V2_MAGIC) && write_index(&objects, &data[data.len() - 20..]) != idx {
+        return Err(anyhow!(
+            "The index's CRC32s don't match the pack's entries."
+        ));
+    }
+    Ok(objects)
+}
+
+/// Indexes a pack received whole and stores it in `objects/pack` as
+/// `pack-<checksum>.pack` next to its index, and returns that name.
+pub fn store_pack(git_dir: &Path, data: &[u8]) -> anyhow::Result<String> {
+    let idx = index_pack(data)?;
+    let name = format!("pack-{}", hex::encode(&data[data.len() - 20..]));
+    // Write the index last: packs are only picked up once their index exists.
+    let pack_dir = git_path(git_dir, "objects/pack");
+    fs::create_dir_all(&pack_dir)?;
+    fs::write(pack_dir.join(format!("{}.pack", name)), data)?;
+    fs::write(pack_dir.join(format!("{}.idx", name)), &idx)?;
+    Ok(name)
+}
+
+/// Writes every object of a pack as a loose object, as
+/// `git unpack-objects` does, and returns how many there were. A thin
+/// pack's missing delta bases are read from the repository.
+pub fn unpack_objects(git_dir: &Path, data: &[u8]) -> anyhow::Result<usize> {
+    let mut repository = Repository::open(git_dir);
+    let external_base = |id: &str| match repository.exists(id)? {
+        true => Ok(Some(repository.read(id)?)),
+        false => Ok(None),
+    };
+    let objects = parse_pack(data, external_base)?;
+    for object in &objects {
+        repository.write(object.object_type, &object.content)?;
+    }
+    Ok(objects.len())
+}
+
+/// Reads one of the little-endian size varints a delta starts with.
+fn delta_size(delta: &mut &[u8]) -> anyhow::Result<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = delta
+            .split_first()
+            .ok_or_else(|| anyhow!("Invalid delta."))?;
+        *delta = rest;
+        if shift >= usize::BITS {
+            return Err(anyhow!("Invalid delta."));
+        }
+        value |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+/// Rebuilds an object from its base and a delta: two size varints followed by
+/// instructions that either copy a range of the base or insert literal bytes.
+fn apply_delta(base: &[u8], mut delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let invalid = || anyhow!("Invalid delta.");
+    let next_byte = |delta: &mut &[u8]| -> anyhow::Result<u8> {
+        let (&byte, rest) = delta.split_first().ok_or_else(invalid)?;
+        *delta = rest;
+        Ok(byte)
+    };
+
+    let base_size = delta_size(&mut delta)?;
+    let result_size = delta_size(&mut delta)?;
+    if base_size != base.len() {
+        return Err(anyhow!("Delta base has the wrong size."));
+    }
+
+    // The sizes are only claims, so a corrupt delta can't make this reserve
+    // more than it could plausibly produce; the result grows past that if
+    // it really needs to, but never past the size claimed.
+    let mut result = Vec::with_capacity(result_size.min(base.len() + delta.len()));
+    while !delta.is_empty() {
+        let instruction = next_byte(&mut delta)?;
+        if instruction & 0x80 != 0 {
+            // Bits 0-3 say which offset bytes follow, bits 4-6 which size bytes.
+            let mut fields = [0usize; 7];
+            for (bit, field) in fields.iter_mut().enumerate() {
+                if instruction & (1 << bit) != 0 {
+                    *field = next_byte(&mut delta)? as usize;
+                }
+            }
+            let offset = fields[0] | fields[1] << 8 | fields[2] << 16 | fields[3] << 24;
+            let size = match fields[4] | fields[5] << 8 | fields[6] << 16 {
+                0 => 0x10000,
+                size => size,
+            };
+            result.extend_from_slice(base.get(offset..offset + size).ok_or_else(invalid)?);
+        } else if instruction != 0 {
+            let size = instruction as usize;
+            result.extend_from_slice(delta.get(..size).ok_or_else(invalid)?);
+            delta = &delta[size..];
+        } else {
+            return Err(invalid());
+        }
+        if result.len() > result_size {
+            return Err(anyhow!("Delta produced the wrong size."));
+        }
+    }
+
+    if result.len() != result_size {
+        return Err(anyhow!("Delta produced the wrong size."));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deltas_claiming_huge_results_fail_without_reserving_them() {
+        // Base size 3, result size 2^63, then one literal "abc".
+        let mut delta = vec![3];
+        delta.extend([0xff; 8]);
+        delta.extend([0x7f, 3]);
+        delta.extend(b"abc");
+        assert!(apply_delta(b"xyz", &delta).is_err());
+
+        assert_eq!(
+            apply_delta(b"xyz", &[3, 3, 3, b'a', b'b', b'c']).unwrap(),
+            b"abc"
+        );
+        assert!(apply_delta(b"xyz", &[3, 2, 3, b'a', b'b', b'c']).is_err());
+        assert!(apply_delta(b"xyz", &[0x80; 12]).is_err());
+    }
+}
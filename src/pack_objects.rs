@@ -0,0 +1,122 @@
+use crate::{pack, resolve_rev, shallow, Repository};
+use crate::{CommitObject, ObjectType, Progress};
+use anyhow::{anyhow, Context};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Packs the objects listed in `input`, one id per line, like
+/// `git pack-objects`. With `revs`, each line is instead a revision whose
+/// reachable objects are packed, or `^<rev>` for one whose reachable objects
+/// are left out.
+///
+/// The pack goes to `out` when `base_name` is `None`; otherwise it is written
+/// as `<base_name>-<checksum>.pack` next to its index, and the checksum is
+/// printed on `out`.
+pub fn pack_objects(
+    git_dir: &Path,
+    base_name: Option<&Path>,
+    revs: bool,
+    input: &str,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    pack::require_sha1(git_dir)?;
+    // git lets each id be followed by the path it was found at.
+    let lines = input
+        .lines()
+        .filter_map(|x| x.split_whitespace().next())
+        .filter(|x| !x.is_empty());
+    let objects = match revs {
+        false => {
+            let mut objects = Vec::new();
+            for id in lines {
+                let id = resolve_rev(git_dir, id)?;
+                if !Repository::open(git_dir).exists(&id)? {
+                    return Err(anyhow!("Object {} doesn't exist.", id));
+                }
+                objects.push(id);
+            }
+            objects
+        }
+        true => {
+            let (mut tips, mut excluded) = (Vec::new(), Vec::new());
+            for rev in lines {
+                match rev.strip_prefix('^') {
+                    Some(rev) => excluded.push(resolve_rev(git_dir, rev)?),
+                    None => tips.push(resolve_rev(git_dir, rev)?),
+                }
+            }
+            missing_objects(git_dir, &tips, &excluded)?
+        }
+    };
+
+    let Some(base_name) = base_name else {
+        pack::write_pack(git_dir, &objects, out)?;
+        return Ok(());
+    };
+    let mut data = Vec::new();
+    let checksum = pack::write_pack(git_dir, &objects, &mut data)?;
+    let idx = pack::index_pack(&data)?;
+    let prefix = format!("{}-{}", base_name.display(), checksum);
+    fs::write(format!("{}.pack", prefix), &data)
+        .with_context(|| format!("Write {}.pack", prefix))?;
+    fs::write(format!("{}.idx", prefix), &idx).with_context(|| format!("Write {}.idx", prefix))?;
+    writeln!(out, "{}", checksum)?;
+    Ok(())
+}
+
+/// Every object reachable from `tips` but not from `known`, the ones a
+/// repository that has `known` already holds, counted on a meter.
+pub fn missing_objects(
+    git_dir: &Path,
+    tips: &[String],
+    known: &[String],
+) -> anyhow::Result<Vec<String>> {
+    let no_grafts = HashSet::new();
+    let known = reachable(git_dir, known, &HashSet::new(), &no_grafts)?;
+    let mut progress = Progress::new("Counting objects", None);
+    let objects = walk(git_dir, tips, &known, &no_grafts, Some(&mut progress))?;
+    progress.finish();
+    Ok(objects.into_iter().collect())
+}
+
+/// The objects reachable from `tips`, without descending into `excluded`,
+/// nor into the parents of `shallow` commits or the repository's own.
+pub fn reachable(
+    git_dir: &Path,
+    tips: &[String],
+    excluded: &HashSet<String>,
+    shallow: &HashSet<String>,
+) -> anyhow::Result<HashSet<String>> {
+    walk(git_dir, tips, excluded, shallow, None)
+}
+
+fn walk(
+    git_dir: &Path,
+    tips: &[String],
+    excluded: &HashSet<String>,
+    shallow: &HashSet<String>,
+    mut progress: Option<&mut Progress>,
+) -> anyhow::Result<HashSet<String>> {
+    let mut shallow = shallow.clone();
+    shallow.extend(shallow::read(git_dir)?);
+    let mut objects = HashSet::new();
+    let mut pending: Vec<String> = tips.to_vec();
+    let repository = Repository::open(git_dir);
+    while let Some(id) = pending.pop() {
+        if excluded.contains(&id) || !objects.insert(id.clone()) {
+            continue;
+        }
+        if let Some(progress) = progress.as_mut() {
+            progress.tick();
+        }
+        let (object_type, content) = repository.read(&id)?;
+        match object_type == ObjectType::Commit && shallow.contains(&id) {
+            // The parents of the shallow edge were never fetched.
+            true => pending.push(CommitObject::from_content(&content)?.tree),
+            false => pending.extend(repository.linked_objects(object_type, &content)?),
+        }
+    }
+    Ok(objects)
+}
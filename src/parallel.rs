@@ -0,0 +1,63 @@
+//! Running independent pieces of work, like hashing the files of a big work
+//! tree, on several threads at once.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Below this many items the work runs on the calling thread: starting
+/// threads would cost more than it saves.
+const MIN_PARALLEL_ITEMS: usize = 16;
+
+/// Calls `f` on every item, on as many threads as there are cores, and
+/// returns the results in the order of the items, so what is built from
+/// them comes out the same as when run one at a time.
+///
+/// Threads take the next item as they finish one, so a few big files don't
+/// hold up the rest.
+pub fn map<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let threads = thread::available_parallelism()
+        .map_or(1, |x| x.get())
+        .min(items.len());
+    if threads <= 1 || items.len() < MIN_PARALLEL_ITEMS {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<R>> = Vec::with_capacity(items.len());
+    results.resize_with(items.len(), || None);
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            return done;
+                        };
+                        done.push((i, f(item)));
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            // A panic in `f` is passed on to the caller, as it would be if
+            // the items were worked through in turn.
+            let done = worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            for (i, result) in done {
+                results[i] = Some(result);
+            }
+        }
+    });
+    results
+        .into_iter()
+        .map(|x| x.expect("every item is worked on"))
+        .collect()
+}
@@ -0,0 +1,169 @@
+//! git's pkt-line framing, which every smart protocol exchange is made of:
+//! each packet is its length as four hex digits, the length itself
+//! included, followed by that many bytes. The lengths 0000, 0001 and 0002,
+//! which no data packet can have, are the flush, delimiter and response-end
+//! packets.
+
+use anyhow::{anyhow, Context};
+use std::io::Read;
+
+/// Longest packet git sends or accepts, length included.
+pub const MAX_PACKET_LEN: usize = 65520;
+
+pub enum Packet<'a> {
+    Data(&'a [u8]),
+    Flush,
+    Delim,
+    ResponseEnd,
+}
+
+/// Reads the packets of a buffered response one at a time.
+pub struct PktLines<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> PktLines<'a> {
+    pub fn new(data: &'a [u8]) -> PktLines<'a> {
+        PktLines { data }
+    }
+
+    /// The next packet, or `None` at the end of the data.
+    pub fn next(&mut self) -> anyhow::Result<Option<Packet<'a>>> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+        let length = self
+            .data
+            .get(..4)
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| usize::from_str_radix(x, 16).ok())
+            .ok_or_else(|| anyhow!("Invalid pkt-line length."))?;
+
+        let packet = match length {
+            0 => Packet::Flush,
+            1 => Packet::Delim,
+            2 => Packet::ResponseEnd,
+            3 => return Err(anyhow!("Invalid pkt-line length.")),
+            _ => Packet::Data(
+                self.data
+                    .get(4..length)
+                    .ok_or_else(|| anyhow!("Truncated pkt-line."))?,
+            ),
+        };
+        self.data = &self.data[length.max(4)..];
+        Ok(Some(packet))
+    }
+
+    /// The data after the packets read so far.
+    pub fn rest(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+/// Reads packets from a stream, like a child process's output, up to and
+/// including the flush that ends a message, or up to the end of the stream,
+/// returning their raw bytes.
+pub fn read_message(reader: &mut impl Read) -> anyhow::Result<Vec<u8>> {
+    let mut message = Vec::new();
+    loop {
+        let mut header = [0; 4];
+        match reader.read_exact(&mut header) {
+            Result::Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(message),
+            Err(err) => return Err(err).context("Read from the remote."),
+        }
+        let length = std::str::from_utf8(&header)
+            .ok()
+            .and_then(|x| usize::from_str_radix(x, 16).ok())
+            .ok_or_else(|| anyhow!("Invalid pkt-line length."))?;
+        message.extend_from_slice(&header);
+        match length {
+            0 => return Ok(message),
+            1 | 2 => continue,
+            3 => return Err(anyhow!("Invalid pkt-line length.")),
+            _ => {}
+        }
+        let start = message.len();
+        message.resize(start + length - 4, 0);
+        reader
+            .read_exact(&mut message[start..])
+            .context("Truncated pkt-line.")?;
+    }
+}
+
+/// Builds a request out of packets.
+#[derive(Default)]
+pub struct PktWriter {
+    buffer: Vec<u8>,
+}
+
+impl PktWriter {
+    pub fn new() -> PktWriter {
+        PktWriter::default()
+    }
+
+    /// Appends `line` and a newline as a packet.
+    pub fn line(&mut self, line: &str) -> anyhow::Result<()> {
+        self.data(format!("{}\n", line).as_bytes())
+    }
+
+    /// Appends `data` as a packet, which must fit in one.
+    pub fn data(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        let length = data.len() + 4;
+        if length > MAX_PACKET_LEN {
+            return Err(anyhow!(
+                "A packet of {} bytes is too long for a pkt-line.",
+                data.len()
+            ));
+        }
+        self.buffer
+            .extend_from_slice(format!("{:04x}", length).as_bytes());
+        self.buffer.extend_from_slice(data);
+        Ok(())
+    }
+
+    pub fn flush(&mut self) {
+        self.buffer.extend_from_slice(b"0000");
+    }
+
+    pub fn delim(&mut self) {
+        self.buffer.extend_from_slice(b"0001");
+    }
+
+    /// Appends bytes that follow the packets unframed, like a pushed pack.
+    pub fn raw(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+/// Splits a side-band-64k packet by the band its first byte names: band 1
+/// carries data, appended to `data`, band 2 progress messages, handed to
+/// `progress`, and band 3 an error the remote failed with.
+pub fn demux_sideband(
+    packet: &[u8],
+    data: &mut Vec<u8>,
+    mut progress: impl FnMut(&str),
+) -> anyhow::Result<()> {
+    match packet.split_first() {
+        Some((1, bytes)) => data.extend_from_slice(bytes),
+        Some((2, message)) => {
+            for line in String::from_utf8_lossy(message).split(['\n', '\r']) {
+                if !line.is_empty() {
+                    progress(line);
+                }
+            }
+        }
+        Some((3, message)) => return Err(anyhow!("The remote failed: {}", text(message))),
+        _ => return Err(anyhow!("Invalid side-band packet.")),
+    }
+    Ok(())
+}
+
+/// A text packet without its trailing newline.
+pub fn text(line: &[u8]) -> String {
+    String::from_utf8_lossy(line.strip_suffix(b"\n").unwrap_or(line)).to_string()
+}
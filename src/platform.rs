@@ -0,0 +1,292 @@
+use crate::Config;
+use anyhow::anyhow;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// What the filesystem under a work tree can record, as git stores it in
+/// `core.fileMode`, `core.symlinks` and `core.ignoreCase`: `init` probes for
+/// it, and every other command reads it back rather than assume Unix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Filesystem {
+    /// Whether the executable bit can be trusted; without it a file keeps
+    /// the mode it is staged with.
+    pub file_mode: bool,
+    /// Whether symbolic links can be made; without them a link is checked
+    /// out as a file holding its target.
+    pub symlinks: bool,
+    /// Whether `README` and `readme` name the same file.
+    pub ignore_case: bool,
+}
+
+impl Default for Filesystem {
+    /// What git assumes when the config says nothing.
+    fn default() -> Filesystem {
+        Filesystem {
+            file_mode: cfg!(unix),
+            symlinks: cfg!(unix),
+            ignore_case: false,
+        }
+    }
+}
+
+impl Filesystem {
+    /// The filesystem the config of `git_dir` describes, read once per
+    /// repository.
+    pub fn of(git_dir: &Path) -> Filesystem {
+        static FILESYSTEMS: OnceLock<Mutex<HashMap<PathBuf, Filesystem>>> = OnceLock::new();
+
+        let filesystems = FILESYSTEMS.get_or_init(Default::default);
+        if let Some(filesystem) = filesystems.lock().unwrap().get(git_dir) {
+            return *filesystem;
+        }
+        let default = Filesystem::default();
+        let filesystem = match Config::load_all(git_dir) {
+            Result::Ok(config) => {
+                let flag = |name: &str, default: bool| {
+                    config.get_bool(name).ok().flatten().unwrap_or(default)
+                };
+                Filesystem {
+                    file_mode: flag("core.filemode", default.file_mode),
+                    symlinks: flag("core.symlinks", default.symlinks),
+                    ignore_case: flag("core.ignorecase", default.ignore_case),
+                }
+            }
+            Err(_) => default,
+        };
+        filesystems
+            .lock()
+            .unwrap()
+            .insert(git_dir.to_path_buf(), filesystem);
+        filesystem
+    }
+
+    /// Tries out the filesystem `git_dir` is on, like `git init` does:
+    /// setting the executable bit of a file, making a symlink, and opening a
+    /// file under its name in another case.
+    pub fn probe(git_dir: &Path) -> Filesystem {
+        let probe = git_dir.join("probe.file");
+        let _ = fs::remove_file(&probe);
+        let written = fs::write(&probe, "").is_ok();
+        let file_mode = written && can_set_executable(&probe);
+        let ignore_case = written && git_dir.join("PrObE.FiLe").exists();
+        let _ = fs::remove_file(&probe);
+
+        let link = git_dir.join("probe.link");
+        let symlinks = make_symlink(Path::new("probe.file"), &link).is_ok();
+        let _ = fs::remove_file(&link);
+
+        Filesystem {
+            file_mode,
+            symlinks,
+            ignore_case,
+        }
+    }
+
+    /// The `core` settings a new repository's config records: the
+    /// executable bit always, the others only when they differ from what
+    /// git assumes.
+    pub fn config_lines(&self) -> String {
+        let mut lines = format!("\tfilemode = {}\n", self.file_mode);
+        if self.symlinks != Filesystem::default().symlinks {
+            lines.push_str(&format!("\tsymlinks = {}\n", self.symlinks));
+        }
+        if self.ignore_case {
+            lines.push_str("\tignorecase = true\n");
+        }
+        lines
+    }
+
+    /// Whether two paths name the same file here.
+    pub fn same_path(&self, a: &str, b: &str) -> bool {
+        match self.ignore_case {
+            true => a.eq_ignore_ascii_case(b),
+            false => a == b,
+        }
+    }
+
+    /// `path` as a key that compares like the filesystem compares names.
+    pub fn path_key(&self, path: &str) -> String {
+        match self.ignore_case {
+            true => path.to_ascii_lowercase(),
+            false => path.to_string(),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn can_set_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    let Result::Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+    let mode = metadata.permissions().mode();
+    fs::set_permissions(path, fs::Permissions::from_mode(mode ^ 0o100)).is_ok()
+        && fs::metadata(path).is_ok_and(|x| x.permissions().mode() != mode)
+}
+
+#[cfg(not(unix))]
+fn can_set_executable(_path: &Path) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn make_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Windows needs a privilege, or developer mode, to make symlinks at all.
+#[cfg(windows)]
+fn make_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn make_symlink(_target: &Path, _link: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// The mode a work tree file would be stored with: a symlink, or a regular
+/// file that is executable if any execute bit is set. Where the filesystem
+/// can't tell, the mode the file is `staged` with stands: its executable
+/// bit, or that a file standing in for a symlink is one.
+pub fn work_tree_mode(filesystem: Filesystem, metadata: &fs::Metadata, staged: Option<u32>) -> u32 {
+    if metadata.file_type().is_symlink() {
+        return 0o120000;
+    }
+    if !filesystem.symlinks && staged == Some(0o120000) {
+        return 0o120000;
+    }
+    if !filesystem.file_mode {
+        return match staged {
+            Some(0o100755) => 0o100755,
+            _ => 0o100644,
+        };
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 != 0 {
+            return 0o100755;
+        }
+    }
+    0o100644
+}
+
+/// Writes a file of `mode` at `path`, where nothing is: a symlink to
+/// `content`, or a file created executable by everyone or no one, which the
+/// umask then narrows like it does for git. A filesystem without symlinks
+/// gets a file holding the target instead, like `core.symlinks=false`.
+pub fn write_file(
+    filesystem: Filesystem,
+    path: &Path,
+    mode: u32,
+    content: &[u8],
+) -> anyhow::Result<()> {
+    if mode == 0o120000 && filesystem.symlinks {
+        return Ok(make_symlink(&symlink_path(content), path)?);
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(match mode {
+            0o100755 if filesystem.file_mode => 0o777,
+            _ => 0o666,
+        });
+    }
+    options.open(path)?.write_all(content)?;
+    Ok(())
+}
+
+/// A symlink's target as stored in its blob: the raw bytes on Unix, where
+/// paths needn't be UTF-8, and with `/` separators elsewhere.
+pub fn symlink_target(target: &Path) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        target.as_os_str().as_bytes().to_vec()
+    }
+    #[cfg(not(unix))]
+    {
+        tree_path(target).into_bytes()
+    }
+}
+
+/// The target a symlink blob's `content` names, as a path of this platform.
+fn symlink_path(content: &[u8]) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        PathBuf::from(OsStr::from_bytes(content))
+    }
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(String::from_utf8_lossy(content).into_owned())
+    }
+}
+
+/// A relative path the way trees, the index and ignore rules spell it:
+/// components joined by `/`, whatever the platform separates them with.
+pub fn tree_path(path: &Path) -> String {
+    let components: Vec<String> = path
+        .components()
+        .map(|x| x.as_os_str().to_string_lossy().to_string())
+        .collect();
+    components.join("/")
+}
+
+/// Where the `/` separated `path` is under `work_tree` on this platform.
+pub fn work_tree_path(work_tree: &Path, path: &str) -> PathBuf {
+    let mut full_path = work_tree.to_path_buf();
+    full_path.extend(path.split('/').filter(|x| !x.is_empty()));
+    full_path
+}
+
+/// Whether `name` can be one component of a tree path: not empty, `.` or
+/// `..`, not a `.git` in any case, since a filesystem ignoring case or a
+/// clone onto one would take it for the git directory, and holding no `/` or
+/// other character that separates or qualifies paths on this platform.
+pub fn is_valid_tree_name(name: &str) -> bool {
+    let reserved: &[char] = match cfg!(windows) {
+        true => &['/', '\\', ':'],
+        false => &['/'],
+    };
+    !matches!(name, "" | "." | "..")
+        && !name.eq_ignore_ascii_case(".git")
+        && !name.contains(reserved)
+}
+
+/// Refuses a tree path that would land outside its place in the work tree
+/// or inside a git directory, one with a component that isn't a valid tree
+/// name.
+pub fn check_tree_path(path: &str) -> anyhow::Result<()> {
+    match path.split('/').all(is_valid_tree_name) {
+        true => Ok(()),
+        false => Err(anyhow!("Invalid path '{}'.", path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn git_directories_are_refused_in_any_case() {
+        for path in [".git/config", ".GIT/hooks/x", "d/.Git", "d/.gIT/HEAD"] {
+            assert!(check_tree_path(path).is_err(), "{}", path);
+        }
+        for path in ["", "a//b", "./a", "a/..", "/a"] {
+            assert!(check_tree_path(path).is_err(), "{}", path);
+        }
+        for path in ["a", "d/b", ".gitignore", "d/.github/x", "git"] {
+            assert!(check_tree_path(path).is_ok(), "{}", path);
+        }
+        assert!(!is_valid_tree_name("a/b"));
+    }
+}
@@ -0,0 +1,199 @@
+//! Progress meters for long operations, drawn on stderr as git draws them:
+//! `Resolving deltas:  45% (450/1000)`, redrawn in place, and finished
+//! with `, done.`.
+
+use std::io::{IsTerminal, Read, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+/// How much the command prints about what it's doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verbosity {
+    /// `--quiet`: no progress.
+    Quiet,
+    /// Progress when stderr is a terminal.
+    Normal,
+    /// `--verbose`: progress even when stderr isn't a terminal.
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(1);
+
+/// Sets how much every meter made from now on shows.
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+fn shown() -> bool {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => false,
+        1 => std::io::stderr().is_terminal(),
+        _ => true,
+    }
+}
+
+/// How often a meter without a total, or counting bytes, is redrawn.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a delayed meter waits before it appears, so quick operations
+/// print nothing.
+const DELAY: Duration = Duration::from_secs(1);
+
+/// A meter counting up to `total` things, if that's known, and the bytes
+/// they took when that's counted too, with the rate they came at.
+pub struct Progress {
+    title: &'static str,
+    total: Option<u64>,
+    count: u64,
+    bytes: Option<u64>,
+    start: Instant,
+    delay: Duration,
+    /// When the meter was last drawn, at what percentage.
+    last_drawn: Option<(Instant, Option<u64>)>,
+    drawn_width: usize,
+    enabled: bool,
+}
+
+impl Progress {
+    pub fn new(title: &'static str, total: Option<u64>) -> Progress {
+        Progress {
+            title,
+            total,
+            count: 0,
+            bytes: None,
+            start: Instant::now(),
+            delay: Duration::ZERO,
+            last_drawn: None,
+            drawn_width: 0,
+            enabled: shown(),
+        }
+    }
+
+    /// A meter that only appears once the operation has run for a while.
+    pub fn delayed(title: &'static str, total: Option<u64>) -> Progress {
+        Progress {
+            delay: DELAY,
+            ..Progress::new(title, total)
+        }
+    }
+
+    /// A meter that never shows, for an operation that has none.
+    pub fn disabled() -> Progress {
+        Progress {
+            enabled: false,
+            ..Progress::new("", None)
+        }
+    }
+
+    /// Counts one more thing done.
+    pub fn tick(&mut self) {
+        self.set(self.count + 1);
+    }
+
+    /// Sets how many things are done.
+    pub fn set(&mut self, count: u64) {
+        self.count = count;
+        self.draw(false);
+    }
+
+    /// Counts `bytes` more bytes, shown with their rate.
+    pub fn add_bytes(&mut self, bytes: u64) {
+        self.bytes = Some(self.bytes.unwrap_or(0) + bytes);
+        self.draw(false);
+    }
+
+    /// Draws the meter a last time with `, done.`, if it was shown at all.
+    pub fn finish(mut self) {
+        self.draw(true);
+    }
+
+    fn percent(&self) -> Option<u64> {
+        self.total
+            .map(|total| self.count * 100 / total.max(1))
+            .map(|x| x.min(100))
+    }
+
+    fn draw(&mut self, done: bool) {
+        if !self.enabled {
+            return;
+        }
+        let now = Instant::now();
+        let percent = self.percent();
+        match self.last_drawn {
+            None if now - self.start < self.delay => return,
+            Some((at, drawn)) if !done && drawn == percent && now - at < REDRAW_INTERVAL => return,
+            _ => {}
+        }
+        self.last_drawn = Some((now, percent));
+
+        let mut line = format!("{}: ", self.title);
+        match (self.total, percent) {
+            (Some(total), Some(percent)) => {
+                line.push_str(&format!("{:3}% ({}/{})", percent, self.count, total))
+            }
+            _ if self.bytes.is_some() && self.count == 0 => {}
+            _ => line.push_str(&self.count.to_string()),
+        }
+        if let Some(bytes) = self.bytes {
+            if !line.ends_with(": ") {
+                line.push_str(", ");
+            }
+            line.push_str(&human_size(bytes as f64));
+            let seconds = (now - self.start).as_secs_f64();
+            if seconds > 0.0 {
+                line.push_str(&format!(" | {}/s", human_size(bytes as f64 / seconds)));
+            }
+        }
+        if done {
+            line.push_str(", done.");
+        }
+        // Padding covers what a longer earlier line left behind.
+        let width = self.drawn_width.max(line.len());
+        self.drawn_width = line.len();
+        let end = if done { "\n" } else { "\r" };
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "{:<width$}{}", line, end, width = width);
+        let _ = stderr.flush();
+    }
+}
+
+/// A size in bytes the way git's meters print it: `512 bytes`, `1.50 KiB`,
+/// `12.00 MiB`.
+fn human_size(bytes: f64) -> String {
+    const UNITS: [&str; 3] = ["KiB", "MiB", "GiB"];
+    if bytes < 1024.0 {
+        return format!("{} bytes", bytes as u64);
+    }
+    let mut size = bytes / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", size, UNITS[unit])
+}
+
+/// A reader that counts what is read through it on a meter.
+pub struct Reader<R> {
+    inner: R,
+    progress: Progress,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R, progress: Progress) -> Reader<R> {
+        Reader { inner, progress }
+    }
+
+    /// Finishes the meter.
+    pub fn finish(self) {
+        self.progress.finish();
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.add_bytes(n as u64);
+        Ok(n)
+    }
+}
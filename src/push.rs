@@ -0,0 +1,358 @@
+use crate::fetch::{self, is_fast_forward};
+use crate::pack_objects::missing_objects;
+use crate::refs::{self, RefLock};
+use crate::smart::{ReceivePack, RefUpdate};
+use crate::{expand_ref, pack, read_symref, record_ref_update, resolve_rev, Repository};
+use crate::{hooks, ssh};
+use crate::{short_id, short_ref_name, Config};
+use anyhow::anyhow;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The old or new id of a ref that doesn't exist.
+const ZERO_ID: &str = "0000000000000000000000000000000000000000";
+
+/// Width of the column summarizing each ref update, like git's.
+const SUMMARY_WIDTH: usize = 17;
+
+/// Pushes to the remote `name` (or a URL) over SSH or smart HTTP. Each refspec
+/// `[+]<src>[:<dst>]` sets the remote ref `dst` to the local `src`, or
+/// deletes it when `src` is empty; without refspecs the current branch is
+/// pushed to the branch of the same name.
+///
+/// The objects the remote lacks go in one pack: those reachable from the
+/// pushed objects but not from the refs the remote already has. Unless
+/// `force` or the refspec's `+` allows it, a remote ref only moves forward
+/// to a commit that descends from it. Remote-tracking refs are updated for
+/// the refs the server accepted, as a fetch would.
+///
+/// With `verify`, the pre-push hook is run first, given the remote's name
+/// and URL and a line per ref to update on its stdin, and can stop the push.
+pub fn push(
+    git_dir: &Path,
+    name: &str,
+    refspecs: &[String],
+    force: bool,
+    verify: bool,
+) -> anyhow::Result<()> {
+    pack::require_sha1(git_dir)?;
+    let config = Config::load_all(git_dir)?;
+    let url = match config.get(&format!("remote.{}.url", name)) {
+        Some(url) => url.to_string(),
+        None if name.contains("://") || ssh::parse_url(name).is_some() => name.to_string(),
+        None => {
+            return Err(anyhow!(
+                "'{}' does not appear to be a git repository.",
+                name
+            ))
+        }
+    };
+    let refspecs = match refspecs {
+        [] => {
+            let branch = read_symref(git_dir, "HEAD")?
+                .ok_or_else(|| anyhow!("You are not currently on a branch."))?;
+            vec![branch]
+        }
+        _ => refspecs.to_vec(),
+    };
+
+    let remote = ReceivePack::connect(&url)?;
+    let remote_refs: HashMap<&str, &str> = remote
+        .refs()
+        .iter()
+        .map(|(name, id)| (name.as_str(), id.as_str()))
+        .collect();
+    let mut commands = Vec::new();
+    for refspec in &refspecs {
+        commands.push(Command::plan(git_dir, refspec, force, &remote_refs)?);
+    }
+
+    let updates: Vec<RefUpdate> = commands
+        .iter()
+        .filter(|x| x.status == Status::Send)
+        .map(|x| RefUpdate {
+            name: x.destination.clone(),
+            old: x.old.clone(),
+            new: x.new.clone(),
+        })
+        .collect();
+    if verify && !updates.is_empty() {
+        let mut input = String::new();
+        for command in commands.iter().filter(|x| x.status == Status::Send) {
+            let local = match command.source.as_str() {
+                "" => "(delete)".to_string(),
+                source => expand_ref(git_dir, source)?.unwrap_or_else(|| source.to_string()),
+            };
+            input.push_str(&format!(
+                "{} {} {} {}\n",
+                local, command.new, command.destination, command.old
+            ));
+        }
+        if !hooks::run(git_dir, "pre-push", &[name, &url], input.as_bytes())? {
+            return Err(anyhow!(
+                "The pre-push hook failed; failed to push some refs to '{}'",
+                url
+            ));
+        }
+    }
+    if !updates.is_empty() {
+        let tips: Vec<String> = updates
+            .iter()
+            .filter(|x| x.new != ZERO_ID)
+            .map(|x| x.new.clone())
+            .collect();
+        let pack = match tips.is_empty() {
+            true => None,
+            false => {
+                let mut known = Vec::new();
+                for id in remote_refs.values() {
+                    if Repository::open(git_dir).exists(id)? {
+                        known.push(id.to_string());
+                    }
+                }
+                let objects = missing_objects(git_dir, &tips, &known)?;
+                let mut pack = Vec::new();
+                pack::write_pack(git_dir, &objects, &mut pack)?;
+                Some(pack)
+            }
+        };
+
+        let statuses: HashMap<String, Result<(), String>> = remote
+            .push(&updates, pack.as_deref())?
+            .into_iter()
+            .collect();
+        for command in commands.iter_mut().filter(|x| x.status == Status::Send) {
+            command.status = match statuses.get(&command.destination) {
+                Some(Ok(())) => Status::Sent,
+                Some(Err(reason)) => Status::RemoteRejected(reason.clone()),
+                None => Status::RemoteRejected("no status reported".to_string()),
+            };
+        }
+    }
+
+    if commands.iter().all(|x| x.status == Status::UpToDate) {
+        info!("Everything up-to-date");
+        return Ok(());
+    }
+    info!("To {}", url);
+    for command in &commands {
+        if let Some(line) = command.summary() {
+            info!("{}", line);
+        }
+    }
+
+    for command in commands.iter().filter(|x| x.status == Status::Sent) {
+        update_tracking_ref(git_dir, &config, name, command)?;
+    }
+    if commands
+        .iter()
+        .any(|x| matches!(x.status, Status::Rejected(_) | Status::RemoteRejected(_)))
+    {
+        return Err(anyhow!("failed to push some refs to '{}'", url));
+    }
+    Ok(())
+}
+
+/// What a refspec asks of one remote ref, and what came of it.
+struct Command {
+    /// The local ref (or revision) pushed; empty for a deletion.
+    source: String,
+    destination: String,
+    old: String,
+    new: String,
+    /// Whether the update loses commits the remote ref had.
+    forced: bool,
+    status: Status,
+}
+
+#[derive(PartialEq)]
+enum Status {
+    UpToDate,
+    /// Refused before sending, with git's reason.
+    Rejected(&'static str),
+    Send,
+    Sent,
+    RemoteRejected(String),
+}
+
+impl Command {
+    fn plan(
+        git_dir: &Path,
+        refspec: &str,
+        force: bool,
+        remote_refs: &HashMap<&str, &str>,
+    ) -> anyhow::Result<Command> {
+        let (force, refspec) = match refspec.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (force, refspec),
+        };
+        let (source, destination) = match refspec.split_once(':') {
+            Some((source, destination)) => (source, Some(destination)),
+            None => (refspec, None),
+        };
+
+        let new = match source.is_empty() {
+            true => ZERO_ID.to_string(),
+            false => resolve_rev(git_dir, source)
+                .map_err(|_| anyhow!("src refspec {} does not match any", source))?,
+        };
+        let source_ref = match source.is_empty() {
+            true => None,
+            false => expand_ref(git_dir, source)?,
+        };
+        let destination = match destination {
+            Some(destination) if destination.starts_with("refs/") => destination.to_string(),
+            Some(destination) => {
+                let existing = [
+                    format!("refs/heads/{}", destination),
+                    format!("refs/tags/{}", destination),
+                ]
+                .into_iter()
+                .find(|x| remote_refs.contains_key(x.as_str()));
+                match (existing, &source_ref) {
+                    (Some(existing), _) => existing,
+                    (None, Some(x)) if x.starts_with("refs/tags/") => {
+                        format!("refs/tags/{}", destination)
+                    }
+                    (None, _) => format!("refs/heads/{}", destination),
+                }
+            }
+            None => source_ref.clone().ok_or_else(|| {
+                anyhow!(
+                    "'{}' isn't a ref, so the remote ref to push it to must be given.",
+                    source
+                )
+            })?,
+        };
+        if !refs::is_valid_ref_name(&destination) {
+            return Err(anyhow!("'{}' is not a valid ref name.", destination));
+        }
+
+        let old = remote_refs
+            .get(destination.as_str())
+            .map_or(ZERO_ID.to_string(), |x| x.to_string());
+
+        let mut forced = false;
+        let status = if old == new {
+            if new == ZERO_ID {
+                return Err(anyhow!(
+                    "Unable to delete '{}': the remote ref doesn't exist.",
+                    destination
+                ));
+            }
+            Status::UpToDate
+        } else if old == ZERO_ID || new == ZERO_ID {
+            Status::Send
+        } else {
+            // Without the old commit, the remote has commits never fetched here.
+            let known = Repository::open(git_dir).exists(&old)?;
+            if known && is_fast_forward(git_dir, &old, &new)? {
+                Status::Send
+            } else if force {
+                forced = true;
+                Status::Send
+            } else if !known {
+                Status::Rejected("fetch first")
+            } else {
+                Status::Rejected("non-fast-forward")
+            }
+        };
+
+        Ok(Command {
+            source: source_ref.unwrap_or_else(|| source.to_string()),
+            destination,
+            old,
+            new,
+            forced,
+            status,
+        })
+    }
+
+    /// The line git prints for the ref, if any.
+    fn summary(&self) -> Option<String> {
+        let names = match self.new == ZERO_ID {
+            true => short_ref_name(&self.destination).to_string(),
+            false => format!(
+                "{} -> {}",
+                short_ref_name(&self.source),
+                short_ref_name(&self.destination)
+            ),
+        };
+        let (flag, summary, names, note) = match &self.status {
+            Status::UpToDate => return None,
+            Status::Send => unreachable!("every sent ref has a status"),
+            Status::Rejected(reason) => ('!', "[rejected]".to_string(), names, reason.to_string()),
+            Status::RemoteRejected(reason) => {
+                ('!', "[remote rejected]".to_string(), names, reason.clone())
+            }
+            Status::Sent if self.new == ZERO_ID => {
+                ('-', "[deleted]".to_string(), names, String::new())
+            }
+            Status::Sent if self.old == ZERO_ID => {
+                let kind = match self.destination.starts_with("refs/tags/") {
+                    true => "[new tag]",
+                    false if self.destination.starts_with("refs/heads/") => "[new branch]",
+                    false => "[new reference]",
+                };
+                ('*', kind.to_string(), names, String::new())
+            }
+            Status::Sent if self.forced => (
+                '+',
+                format!("{}...{}", short_id(&self.old), short_id(&self.new)),
+                names,
+                "forced update".to_string(),
+            ),
+            Status::Sent => (
+                ' ',
+                format!("{}..{}", short_id(&self.old), short_id(&self.new)),
+                names,
+                String::new(),
+            ),
+        };
+        let mut line = format!(
+            " {} {:<width$} {}",
+            flag,
+            summary,
+            names,
+            width = SUMMARY_WIDTH
+        );
+        if !note.is_empty() {
+            line.push_str(&format!(" ({})", note));
+        }
+        Some(line)
+    }
+}
+
+/// Moves the remote-tracking ref a fetch would store `command`'s
+/// destination in to where the push left it.
+fn update_tracking_ref(
+    git_dir: &Path,
+    config: &Config,
+    name: &str,
+    command: &Command,
+) -> anyhow::Result<()> {
+    let tracking = fetch::fetch_refspecs(config, name)?
+        .iter()
+        .find_map(|x| x.map(&command.destination));
+    let Some(tracking) = tracking else {
+        return Ok(());
+    };
+    match command.new == ZERO_ID {
+        true => {
+            refs::delete_ref(git_dir, &tracking)?;
+        }
+        false => {
+            let lock = RefLock::acquire(git_dir, &tracking)?;
+            let old = refs::resolve_ref(git_dir, &tracking)?;
+            lock.commit(&command.new)?;
+            record_ref_update(
+                git_dir,
+                &tracking,
+                old.as_deref(),
+                &command.new,
+                "update by push",
+            )?;
+        }
+    }
+    Ok(())
+}
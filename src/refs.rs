@@ -0,0 +1,417 @@
+//! Refs: loose files under `refs/`, the `packed-refs` file, or a reftable,
+//! and the symbolic refs like `HEAD` that point at other refs.
+
+use crate::config::Config;
+use crate::lockfile::LockFile;
+use crate::object::ObjectFormat;
+use crate::{git_path, reftable};
+use anyhow::{anyhow, Context};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Reads a ref and follows symbolic refs until an object id is found.
+///
+/// Returns `None` when the ref (or the branch a symbolic ref points to) does not exist yet.
+#[tracing::instrument(level = "debug", skip(git_dir))]
+pub fn resolve_ref(git_dir: &Path, name: &str) -> anyhow::Result<Option<String>> {
+    let mut name = name.to_string();
+    let reftable = reftable::read_refs(git_dir)?;
+
+    // Bound the indirection so a symref loop can't hang us.
+    for _ in 0..5 {
+        if let Some(refs) = &reftable {
+            match refs.get(&name) {
+                Some(reftable::RefValue::Symbolic(target)) => name = target.clone(),
+                Some(reftable::RefValue::Object(object_id)) => return Ok(Some(object_id.clone())),
+                None => return Ok(None),
+            }
+            continue;
+        }
+
+        let Some(content) = fs::read_to_string(git_path(git_dir, &name)).ok() else {
+            tracing::trace!(%name, "no loose ref, trying packed-refs");
+            return read_packed_ref(git_dir, &name);
+        };
+        let content = content.trim_end();
+
+        match content.strip_prefix("ref: ") {
+            Some(target) => {
+                tracing::trace!(%name, %target, "following symbolic ref");
+                name = target.to_string();
+            }
+            None => return checked_object_id(git_dir, &name, content),
+        }
+    }
+
+    Err(anyhow!("Too many levels of symbolic refs: {}", name))
+}
+
+/// The object id a ref holds, or `None` for a broken ref holding anything
+/// else, which git ignores too.
+fn checked_object_id(git_dir: &Path, name: &str, value: &str) -> anyhow::Result<Option<String>> {
+    let hex_len = ObjectFormat::of(git_dir)?.hex_len();
+    if value.len() != hex_len || !value.chars().all(|x| x.is_ascii_hexdigit()) {
+        tracing::warn!(%name, %value, "ignoring broken ref");
+        return Ok(None);
+    }
+    Ok(Some(value.to_string()))
+}
+
+/// Expands a short ref name the way revisions are resolved (as given, then under
+/// `refs/`, `refs/tags/`, `refs/heads/` and `refs/remotes/`) and follows symbolic
+/// refs, returning the full name of the ref holding the object id.
+pub fn expand_ref(git_dir: &Path, name: &str) -> anyhow::Result<Option<String>> {
+    let candidates = [
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+    ];
+
+    for candidate in candidates {
+        if resolve_ref(git_dir, &candidate)?.is_none() {
+            continue;
+        }
+
+        let mut name = candidate;
+        // resolve_ref succeeded, so the chain is known to end within its bound.
+        while let Some(target) = read_symref(git_dir, &name)? {
+            name = target;
+        }
+        return Ok(Some(name));
+    }
+
+    Ok(None)
+}
+
+/// Lists every ref under `refs/`, loose or packed, as `(name, object id)`
+/// sorted by name. Symbolic refs are resolved to the id they point at.
+pub fn list_refs(git_dir: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    let mut refs = std::collections::BTreeMap::new();
+
+    if let Some(table_refs) = reftable::read_refs(git_dir)? {
+        for name in table_refs.keys().filter(|x| x.starts_with("refs/")) {
+            if let Some(object_id) = resolve_ref(git_dir, name)? {
+                refs.insert(name.clone(), object_id);
+            }
+        }
+        return Ok(refs.into_iter().collect());
+    }
+
+    if let Result::Ok(content) = fs::read_to_string(git_path(git_dir, "packed-refs")) {
+        for line in content.lines() {
+            if line.starts_with('#') || line.starts_with('^') {
+                continue;
+            }
+            if let Some((object_id, name)) = line.split_once(' ') {
+                refs.insert(name.to_string(), object_id.to_string());
+            }
+        }
+    }
+
+    let mut pending = vec!["refs".to_string()];
+    while let Some(dir) = pending.pop() {
+        let Some(entries) = fs::read_dir(git_path(git_dir, &dir)).ok() else {
+            continue;
+        };
+        for entry in entries {
+            let entry = entry?;
+            let name = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                pending.push(name);
+            } else if let Some(object_id) = resolve_ref(git_dir, &name)? {
+                refs.insert(name, object_id);
+            }
+        }
+    }
+
+    Ok(refs.into_iter().collect())
+}
+
+/// The ref a symbolic ref points to, or `None` if `name` isn't a symbolic ref.
+pub fn read_symref(git_dir: &Path, name: &str) -> anyhow::Result<Option<String>> {
+    if let Some(refs) = reftable::read_refs(git_dir)? {
+        return Ok(match refs.get(name) {
+            Some(reftable::RefValue::Symbolic(target)) => Some(target.clone()),
+            _ => None,
+        });
+    }
+
+    Ok(fs::read_to_string(git_path(git_dir, name))
+        .ok()
+        .and_then(|x| x.trim_end().strip_prefix("ref: ").map(|x| x.to_string())))
+}
+
+/// Whether `name` is a valid full ref name, by the rules of
+/// `git check-ref-format`: no component may start with `.` or end with
+/// `.lock`, and the name may not contain `..`, `@{`, `//`, control
+/// characters, spaces or any of `~^:?*[\`, nor end with `/` or `.`.
+pub fn is_valid_ref_name(name: &str) -> bool {
+    let bad_char = |x: char| x.is_ascii_control() || " ~^:?*[\\".contains(x);
+    name != "@"
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.ends_with('.')
+        && !name.chars().any(bad_char)
+        && name
+            .split('/')
+            .all(|x| !x.is_empty() && !x.starts_with('.') && !x.ends_with(".lock"))
+}
+
+/// Points a ref at an object, creating its directories as needed.
+pub fn write_ref(git_dir: &Path, name: &str, object_id: &str) -> anyhow::Result<()> {
+    RefLock::acquire(git_dir, name)?.commit(object_id)
+}
+
+/// A ref locked for updating, as git does it, through a [`LockFile`]:
+/// concurrent writers fail instead of racing, and readers never see a torn
+/// file. The lock is released when dropped uncommitted.
+pub struct RefLock {
+    name: String,
+    lock: LockFile,
+}
+
+impl RefLock {
+    pub fn acquire(git_dir: &Path, name: &str) -> anyhow::Result<RefLock> {
+        if reftable::is_reftable(git_dir) {
+            return Err(anyhow!(
+                "Cannot update {}: writing reftables isn't supported.",
+                name
+            ));
+        }
+        let path = git_path(git_dir, name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(RefLock {
+            name: name.to_string(),
+            lock: LockFile::acquire(&path).with_context(|| format!("Unable to lock {}.", name))?,
+        })
+    }
+
+    /// Points the ref at `object_id` and releases the lock.
+    pub fn commit(self, object_id: &str) -> anyhow::Result<()> {
+        self.write(format!("{}\n", object_id).as_bytes())
+    }
+
+    /// Makes the ref a symbolic ref to `target` and releases the lock.
+    pub fn commit_symref(self, target: &str) -> anyhow::Result<()> {
+        self.write(format!("ref: {}\n", target).as_bytes())
+    }
+
+    /// Deletes the ref, loose or packed, and its reflog, and releases the
+    /// lock, returning whether the ref existed. Directories left empty under
+    /// `refs/` and `logs/refs/` are removed, so a ref of their name can be
+    /// made.
+    pub fn delete(self, git_dir: &Path) -> anyhow::Result<bool> {
+        let path = self.lock.path().to_path_buf();
+        let loose = match fs::remove_file(&path) {
+            Result::Ok(()) => true,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => false,
+            Err(err) => return Err(err).with_context(|| format!("Delete ref {}.", self.name)),
+        };
+        let packed = remove_packed_ref(git_dir, &self.name)?;
+        drop(self.lock);
+        remove_empty_dirs(&path, &git_path(git_dir, "refs"));
+
+        let log_path = git_path(git_dir, &format!("logs/{}", self.name));
+        match fs::remove_file(&log_path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                return Err(err).with_context(|| format!("Delete {}.", log_path.display()))
+            }
+            _ => remove_empty_dirs(&log_path, &git_path(git_dir, "logs/refs")),
+        }
+        Ok(loose || packed)
+    }
+
+    fn write(self, content: &[u8]) -> anyhow::Result<()> {
+        self.lock
+            .commit(content)
+            .with_context(|| format!("Write ref {}.", self.name))
+    }
+}
+
+/// Removes the directories above `path` that are empty, up to but not
+/// including `top`.
+fn remove_empty_dirs(path: &Path, top: &Path) {
+    let mut dir = path.parent();
+    while let Some(parent) = dir.filter(|x| x.starts_with(top) && *x != top) {
+        // Only empty directories can be removed.
+        if fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+}
+
+/// Deletes a ref, loose or packed, returning whether it existed.
+pub fn delete_ref(git_dir: &Path, name: &str) -> anyhow::Result<bool> {
+    RefLock::acquire(git_dir, name)?.delete(git_dir)
+}
+
+/// Rewrites `packed-refs` without `name` (and the peeled id line that may
+/// follow it), returning whether it was there.
+fn remove_packed_ref(git_dir: &Path, name: &str) -> anyhow::Result<bool> {
+    let Some(content) = fs::read_to_string(git_path(git_dir, "packed-refs")).ok() else {
+        return Ok(false);
+    };
+
+    let mut kept = String::new();
+    let mut removed = false;
+    let mut skipping = false;
+    for line in content.lines() {
+        if line.starts_with('^') && skipping {
+            continue;
+        }
+        skipping = !line.starts_with('#') && line.split_once(' ').map(|x| x.1) == Some(name);
+        if skipping {
+            removed = true;
+            continue;
+        }
+        kept.push_str(line);
+        kept.push('\n');
+    }
+
+    if removed {
+        RefLock::acquire(git_dir, "packed-refs")?.write(kept.as_bytes())?;
+    }
+    Ok(removed)
+}
+
+/// Looks a ref up in the `packed-refs` file.
+pub fn read_packed_ref(git_dir: &Path, name: &str) -> anyhow::Result<Option<String>> {
+    let Some(content) = fs::read_to_string(git_path(git_dir, "packed-refs")).ok() else {
+        return Ok(None);
+    };
+
+    for line in content.lines() {
+        if line.starts_with('#') || line.starts_with('^') {
+            continue;
+        }
+        if let Some((object_id, ref_name)) = line.split_once(' ') {
+            if ref_name == name {
+                return checked_object_id(git_dir, name, object_id);
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// One update of a ref, as its reflog records it.
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    /// What the ref held before, all zeros if it didn't exist.
+    pub old: String,
+    pub new: String,
+    /// `Name <email> <seconds> <offset>` of who made the update.
+    pub committer: String,
+    pub message: String,
+}
+
+/// Reads the reflog of a ref, oldest entry first; empty if it has none.
+pub fn read_reflog(git_dir: &Path, name: &str) -> anyhow::Result<Vec<ReflogEntry>> {
+    let path = git_path(git_dir, &format!("logs/{}", name));
+    let content = match fs::read_to_string(&path) {
+        Result::Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("Read {}.", path.display())),
+    };
+
+    // Each line is `<old> <new> <committer>\t<message>`.
+    let mut entries = Vec::new();
+    for line in content.lines().filter(|x| !x.is_empty()) {
+        let (header, message) = line.split_once('\t').unwrap_or((line, ""));
+        let mut fields = header.splitn(3, ' ');
+        let (Some(old), Some(new), Some(committer)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(anyhow!(
+                "Invalid reflog entry in {}: {}",
+                path.display(),
+                line
+            ));
+        };
+        entries.push(ReflogEntry {
+            old: old.to_string(),
+            new: new.to_string(),
+            committer: committer.to_string(),
+            message: message.to_string(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Appends an update of `name` from `old` (`None` when it didn't exist) to
+/// `new` to the ref's reflog, if it keeps one.
+///
+/// Like git, a ref whose reflog exists always gets the entry; otherwise
+/// `core.logAllRefUpdates` decides: `always` logs every ref, and `true`, the
+/// default outside bare repositories, logs `HEAD` and the refs under
+/// `refs/heads/`, `refs/remotes/` and `refs/notes/`.
+pub fn append_reflog(
+    git_dir: &Path,
+    name: &str,
+    old: Option<&str>,
+    new: &str,
+    committer: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    let path = git_path(git_dir, &format!("logs/{}", name));
+    if !path.exists() && !logs_updates(git_dir, name)? {
+        return Ok(());
+    }
+
+    let zero = "0".repeat(new.len());
+    // The message must stay on the entry's line.
+    let message: Vec<&str> = message.split_whitespace().collect();
+    let line = format!(
+        "{} {} {}\t{}\n",
+        old.unwrap_or(&zero),
+        new,
+        committer,
+        message.join(" ")
+    );
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut x| x.write_all(line.as_bytes()))
+        .with_context(|| format!("Write {}.", path.display()))
+}
+
+/// Replaces the reflog of `name` with `entries`, oldest first, as when
+/// entries are dropped from it. The file is replaced whole, through a lock.
+pub fn write_reflog(git_dir: &Path, name: &str, entries: &[ReflogEntry]) -> anyhow::Result<()> {
+    let mut content = String::new();
+    for entry in entries {
+        content.push_str(&format!(
+            "{} {} {}\t{}\n",
+            entry.old, entry.new, entry.committer, entry.message
+        ));
+    }
+    let lock = RefLock::acquire(git_dir, &format!("logs/{}", name))?;
+    lock.write(content.as_bytes())
+}
+
+fn logs_updates(git_dir: &Path, name: &str) -> anyhow::Result<bool> {
+    let config = Config::load(git_dir)?;
+    let setting = match config.get("core.logallrefupdates") {
+        Some(value) if value.eq_ignore_ascii_case("always") => return Ok(true),
+        Some(_) => config.get_bool("core.logallrefupdates")?,
+        None => None,
+    };
+    let enabled = match setting {
+        Some(enabled) => enabled,
+        None => !config.get_bool("core.bare")?.unwrap_or(false),
+    };
+    Ok(enabled
+        && (name == "HEAD"
+            || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+                .iter()
+                .any(|x| name.starts_with(x))))
+}
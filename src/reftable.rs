@@ -0,0 +1,205 @@
+//! Reading the reftable format, which repositories made with
+//! `--ref-format=reftable` keep their refs in instead of loose files.
+
+use crate::git_path;
+use anyhow::{anyhow, Context};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8] = b"REFT";
+const HASH_SIZE: usize = 20;
+
+const VALUE_DELETION: u64 = 0;
+const VALUE_OBJECT: u64 = 1;
+const VALUE_PEELED: u64 = 2;
+const VALUE_SYMREF: u64 = 3;
+
+/// What a ref stored in a reftable holds.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefValue {
+    Object(String),
+    Symbolic(String),
+}
+
+/// Whether refs are kept in reftables (`extensions.refStorage=reftable`)
+/// rather than in loose files and `packed-refs`.
+pub fn is_reftable(git_dir: &Path) -> bool {
+    git_path(git_dir, "reftable/tables.list").is_file()
+}
+
+/// Reads every ref of a reftable repository, or returns `None` if the
+/// repository doesn't use reftables.
+///
+/// Tables are listed oldest first in `reftable/tables.list`; a ref in a newer
+/// table replaces (or, as a deletion record, removes) the same ref in older ones.
+pub fn read_refs(git_dir: &Path) -> anyhow::Result<Option<BTreeMap<String, RefValue>>> {
+    if !is_reftable(git_dir) {
+        return Ok(None);
+    }
+
+    let reftable_dir = git_path(git_dir, "reftable");
+    let list = fs::read_to_string(reftable_dir.join("tables.list"))
+        .context("Read reftable/tables.list.")?;
+
+    let mut refs = BTreeMap::new();
+    for name in list.lines().filter(|x| !x.is_empty()) {
+        let data = fs::read(reftable_dir.join(name))
+            .with_context(|| format!("Read reftable {}.", name))?;
+        for (ref_name, value) in
+            read_table(&data).with_context(|| format!("Parse reftable {}.", name))?
+        {
+            match value {
+                Some(value) => refs.insert(ref_name, value),
+                None => refs.remove(&ref_name),
+            };
+        }
+    }
+    Ok(Some(refs))
+}
+
+/// Parses the ref records of one table; deletions come back as `None`.
+/// Object, log and index blocks are skipped, since lookups only need refs.
+fn read_table(data: &[u8]) -> anyhow::Result<Vec<(String, Option<RefValue>)>> {
+    if data.len() < 24 || &data[..4] != MAGIC {
+        return Err(anyhow!("Not a reftable."));
+    }
+    let (header_size, footer_size) = match data[4] {
+        1 => (24, 68),
+        2 if &data[24..28] == b"sha1" => (28, 72),
+        2 => return Err(anyhow!("Only SHA-1 reftables are supported.")),
+        version => return Err(anyhow!("Unsupported reftable version {}.", version)),
+    };
+    let block_size = be24(&data[5..8]);
+
+    if data.len() < header_size + footer_size {
+        return Err(anyhow!("Reftable is truncated."));
+    }
+    let footer_start = data.len() - footer_size;
+    let footer = &data[footer_start..];
+    let (footer, crc) = footer.split_at(footer_size - 4);
+    let mut hasher = flate2::Crc::new();
+    hasher.update(footer);
+    if hasher.sum().to_be_bytes() != crc {
+        return Err(anyhow!("Reftable footer checksum mismatch."));
+    }
+
+    // The footer repeats the header, then gives where the later sections start.
+    let positions = &footer[header_size..];
+    let ref_index_position = be64(&positions[0..8]);
+    let obj_position = be64(&positions[8..16]) >> 5;
+    let log_position = be64(&positions[24..32]);
+    let refs_end = [ref_index_position, obj_position, log_position]
+        .into_iter()
+        .filter(|&x| x > 0)
+        .min()
+        .map_or(footer_start, |x| x as usize);
+
+    let mut records = Vec::new();
+    let mut block_start = 0;
+    // The first block shares its space with the file header.
+    let mut header_offset = header_size;
+    while block_start + header_offset + 4 <= refs_end {
+        let block_header = &data[block_start + header_offset..];
+        if block_header[0] != b'r' {
+            break;
+        }
+        let block_len = be24(&block_header[1..4]);
+        let block = data
+            .get(block_start..block_start + block_len)
+            .ok_or_else(|| anyhow!("Ref block at {} is truncated.", block_start))?;
+        read_ref_block(block, header_offset + 4, &mut records)?;
+
+        block_start += match block_size {
+            0 => block_len,
+            block_size => block_size,
+        };
+        header_offset = 0;
+    }
+    Ok(records)
+}
+
+/// Parses the records of a ref block, which run from `records_start` up to
+/// the restart table at the end of the block.
+fn read_ref_block(
+    block: &[u8],
+    records_start: usize,
+    records: &mut Vec<(String, Option<RefValue>)>,
+) -> anyhow::Result<()> {
+    let truncated = || anyhow!("Ref block is truncated.");
+
+    let restart_count = u16::from_be_bytes(
+        block
+            .get(block.len().saturating_sub(2)..)
+            .and_then(|x| x.try_into().ok())
+            .ok_or_else(truncated)?,
+    ) as usize;
+    let records_end = block
+        .len()
+        .checked_sub(2 + 3 * restart_count)
+        .ok_or_else(truncated)?;
+    let mut buf = block
+        .get(records_start..records_end)
+        .ok_or_else(truncated)?;
+
+    let mut name: Vec<u8> = Vec::new();
+    while !buf.is_empty() {
+        let prefix_len = read_varint(&mut buf)? as usize;
+        let suffix_and_type = read_varint(&mut buf)?;
+        let suffix_len = (suffix_and_type >> 3) as usize;
+        if prefix_len > name.len() {
+            return Err(anyhow!("Ref record shares more than the previous name."));
+        }
+        name.truncate(prefix_len);
+        name.extend_from_slice(take(&mut buf, suffix_len)?);
+        // Update indexes order edits across tables; the table order already does that here.
+        read_varint(&mut buf)?;
+
+        let value = match suffix_and_type & 0x7 {
+            VALUE_DELETION => None,
+            VALUE_OBJECT => Some(RefValue::Object(hex::encode(take(&mut buf, HASH_SIZE)?))),
+            VALUE_PEELED => {
+                let object_id = hex::encode(take(&mut buf, HASH_SIZE)?);
+                take(&mut buf, HASH_SIZE)?;
+                Some(RefValue::Object(object_id))
+            }
+            VALUE_SYMREF => {
+                let len = read_varint(&mut buf)? as usize;
+                let target = String::from_utf8_lossy(take(&mut buf, len)?).to_string();
+                Some(RefValue::Symbolic(target))
+            }
+            value_type => return Err(anyhow!("Unknown ref value type {}.", value_type)),
+        };
+        records.push((String::from_utf8_lossy(&name).to_string(), value));
+    }
+    Ok(())
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> anyhow::Result<&'a [u8]> {
+    if buf.len() < len {
+        return Err(anyhow!("Ref record is truncated."));
+    }
+    let (head, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(head)
+}
+
+/// Reads a varint in the same encoding as pack offsets: each continuation
+/// byte adds one before shifting, so every value has a single encoding.
+fn read_varint(buf: &mut &[u8]) -> anyhow::Result<u64> {
+    let mut byte = take(buf, 1)?[0];
+    let mut value = (byte & 0x7f) as u64;
+    while byte & 0x80 != 0 {
+        byte = take(buf, 1)?[0];
+        value = ((value + 1) << 7) | (byte & 0x7f) as u64;
+    }
+    Ok(value)
+}
+
+fn be24(bytes: &[u8]) -> usize {
+    (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize
+}
+
+fn be64(bytes: &[u8]) -> u64 {
+    u64::from_be_bytes(bytes[..8].try_into().unwrap())
+}
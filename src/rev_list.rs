@@ -0,0 +1,107 @@
+use crate::log::{self, Filter};
+use crate::pack_objects::reachable;
+use crate::{resolve_rev, Object, ObjectType, Repository};
+use anyhow::anyhow;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Prints the ids of the commits reachable from `revs` but not from
+/// `excluded`, newest first, like `git rev-list`. A rev may be `^<rev>`,
+/// excluded too, or a range `<a>..<b>`, which stands for `^<a> <b>`, either
+/// end defaulting to `HEAD`.
+///
+/// With `objects`, the trees and blobs those commits hold follow, each as
+/// `<id> <path>` (a commit's own tree has an empty path), leaving out any
+/// the excluded commits reach; annotated tags among the revs come first,
+/// by the name given.
+pub fn rev_list(
+    git_dir: &Path,
+    revs: &[String],
+    excluded: &[String],
+    filter: &Filter,
+    objects: bool,
+) -> anyhow::Result<()> {
+    let mut starts = Vec::new();
+    let mut hidden = Vec::new();
+    let mut tags = Vec::new();
+    let resolve = |rev: &str| match rev {
+        "" => resolve_rev(git_dir, "HEAD"),
+        rev => resolve_rev(git_dir, rev),
+    };
+    let repository = Repository::open(git_dir);
+    for rev in revs {
+        if let Some((from, to)) = rev.split_once("..") {
+            hidden.push(resolve(from)?);
+            starts.push(resolve(to)?);
+        } else if let Some(rev) = rev.strip_prefix('^') {
+            hidden.push(resolve(rev)?);
+        } else {
+            let id = resolve(rev)?;
+            if repository.read_header(&id)?.0 == ObjectType::Tag {
+                tags.push((id.clone(), rev.clone()));
+            }
+            starts.push(id);
+        }
+    }
+    for rev in excluded {
+        hidden.push(resolve(rev)?);
+    }
+    let to_commit = |id: &String| repository.peel_to(id, ObjectType::Commit);
+    let starts = starts
+        .iter()
+        .map(to_commit)
+        .collect::<Result<Vec<_>, _>>()?;
+    let hidden = hidden
+        .iter()
+        .map(to_commit)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let commits = log::walk_range(git_dir, &starts, &hidden, filter)?;
+    for (id, _) in &commits {
+        println!("{}", id);
+    }
+    if !objects {
+        return Ok(());
+    }
+
+    let mut seen = reachable(git_dir, &hidden, &HashSet::new(), &HashSet::new())?;
+    for (id, name) in tags {
+        if seen.insert(id.clone()) {
+            println!("{} {}", id, name);
+        }
+    }
+    for (_, commit) in &commits {
+        list_tree(git_dir, &commit.tree, "", &mut seen)?;
+    }
+    Ok(())
+}
+
+/// Prints a tree, then what it holds, depth first in the tree's order,
+/// skipping objects already `seen`. Submodule commits aren't listed.
+fn list_tree(
+    git_dir: &Path,
+    tree_id: &str,
+    path: &str,
+    seen: &mut HashSet<String>,
+) -> anyhow::Result<()> {
+    if !seen.insert(tree_id.to_string()) {
+        return Ok(());
+    }
+    println!("{} {}", tree_id, path);
+    let Object::Tree(tree) = Repository::open(git_dir).object(tree_id)? else {
+        return Err(anyhow!("Object {} is not a tree.", tree_id));
+    };
+    for element in tree.elements {
+        let id = hex::encode(&element.hash);
+        let element_path = match path {
+            "" => element.name.clone(),
+            _ => format!("{}/{}", path, element.name),
+        };
+        match element.object_type {
+            ObjectType::Tree => list_tree(git_dir, &id, &element_path, seen)?,
+            ObjectType::Blob if seen.insert(id.clone()) => println!("{} {}", id, element_path),
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,148 @@
+use crate::checkout::{remove_work_tree_file, Entries};
+use crate::index::Index;
+use crate::ObjectType;
+use crate::{git_path, index_path_of, resolve_ref, status, work_tree_relative_path, Repository};
+use anyhow::anyhow;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct RmOptions {
+    /// Only unstage the files, leaving them in the work tree.
+    pub cached: bool,
+    /// Remove everything under directories given.
+    pub recursive: bool,
+    /// Skip the checks that the files' changes are committed.
+    pub force: bool,
+}
+
+/// Removes tracked files from the index and the work tree like `git rm`,
+/// printing `rm '<path>'` for each. Directories left empty go too.
+///
+/// Unless forced, files whose staged content isn't in `HEAD`, or whose work
+/// tree content isn't staged, are refused, so nothing is lost that only the
+/// index or the work tree has. With `cached` only a file whose staged content
+/// differs from both is refused, as the work tree keeps the rest.
+pub fn rm(
+    git_dir: &Path,
+    work_tree: &Path,
+    paths: &[PathBuf],
+    options: RmOptions,
+) -> anyhow::Result<()> {
+    let index_path = git_path(git_dir, "index");
+    let mut index = Index::load(&index_path)?;
+
+    let mut doomed = Vec::new();
+    for file_path in paths {
+        let path = match file_path.is_dir() {
+            true => work_tree_relative_path(work_tree, file_path)
+                .ok_or_else(|| anyhow!("{}: is outside repository.", file_path.display()))?,
+            false => index_path_of(work_tree, file_path)?,
+        };
+        let dir = format!("{}/", path);
+        let matched: Vec<String> = index
+            .entries
+            .iter()
+            .filter(|x| path.is_empty() || x.path == path || x.path.starts_with(&dir))
+            .map(|x| x.path.clone())
+            .collect();
+        if matched.is_empty() {
+            return Err(anyhow!("pathspec '{}' did not match any files", path));
+        }
+        if !options.recursive && !matched.contains(&path) {
+            return Err(anyhow!("not removing '{}' recursively without -r", path));
+        }
+        for path in matched {
+            if !doomed.contains(&path) {
+                doomed.push(path);
+            }
+        }
+    }
+
+    if !options.force {
+        check_changes(git_dir, work_tree, &index, &doomed, options.cached)?;
+    }
+
+    for path in &doomed {
+        println!("rm '{}'", path);
+        index.remove(path);
+        if !options.cached {
+            remove_work_tree_file(work_tree, path)?;
+        }
+    }
+    index.write(&index_path)
+}
+
+/// Refuses to remove files whose changes would be lost, listing them the way
+/// git does.
+fn check_changes(
+    git_dir: &Path,
+    work_tree: &Path,
+    index: &Index,
+    paths: &[String],
+    cached: bool,
+) -> anyhow::Result<()> {
+    let mut head = Entries::new();
+    if let Some(head_id) = resolve_ref(git_dir, "HEAD")? {
+        let head_tree = Repository::open(git_dir).peel_to(&head_id, ObjectType::Tree)?;
+        status::flatten_tree(git_dir, &head_tree, "", &mut head)?;
+    }
+
+    let mut staged_and_local = Vec::new();
+    let mut staged = Vec::new();
+    let mut local = Vec::new();
+    for entry in index.entries.iter().filter(|x| paths.contains(&x.path)) {
+        // A file already gone from the work tree has nothing left to lose there.
+        if fs::symlink_metadata(work_tree.join(&entry.path)).is_err() {
+            continue;
+        }
+        let staged_changes = head.get(&entry.path) != Some(&(entry.mode, entry.hash.clone()));
+        let local_changes = status::work_tree_differs(git_dir, work_tree, entry)?;
+        if staged_changes && local_changes {
+            staged_and_local.push(entry.path.as_str());
+        } else if !cached && staged_changes {
+            staged.push(entry.path.as_str());
+        } else if !cached && local_changes {
+            local.push(entry.path.as_str());
+        }
+    }
+
+    let mut errors = Vec::new();
+    let groups = [
+        (
+            staged_and_local,
+            "staged content different from both the\nfile and the HEAD:",
+            "(use -f to force removal)",
+        ),
+        (
+            staged,
+            "changes staged in the index:",
+            "(use --cached to keep the file, or -f to force removal)",
+        ),
+        (
+            local,
+            "local modifications:",
+            "(use --cached to keep the file, or -f to force removal)",
+        ),
+    ];
+    for (paths, problem, hint) in groups {
+        if paths.is_empty() {
+            continue;
+        }
+        let subject = match paths.len() {
+            1 => "file has",
+            _ => "files have",
+        };
+        let list: Vec<String> = paths.iter().map(|x| format!("    {}", x)).collect();
+        errors.push(format!(
+            "the following {} {}\n{}\n{}",
+            subject,
+            problem,
+            list.join("\n"),
+            hint
+        ));
+    }
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(anyhow!(errors.join("\n"))),
+    }
+}
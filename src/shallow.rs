@@ -0,0 +1,40 @@
+use crate::{git_path, LockFile};
+use anyhow::Context;
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// The commits at the edge of a shallow clone's history, listed in
+/// `.git/shallow`, one id per line. Their parents weren't fetched, so walks
+/// of the history treat them as roots.
+pub fn read(git_dir: &Path) -> anyhow::Result<HashSet<String>> {
+    let path = git_path(git_dir, "shallow");
+    let content = match fs::read_to_string(&path) {
+        Result::Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(err) => return Err(err).with_context(|| format!("Read {}.", path.display())),
+    };
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect())
+}
+
+/// Replaces the shallow commits with `commits`, sorted as git keeps them.
+/// The file is removed once none is left, as the history is then complete.
+pub fn write(git_dir: &Path, commits: &HashSet<String>) -> anyhow::Result<()> {
+    let path = git_path(git_dir, "shallow");
+    if commits.is_empty() {
+        return match fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("Delete {}.", path.display()))
+            }
+            _ => Ok(()),
+        };
+    }
+    let sorted: BTreeSet<&String> = commits.iter().collect();
+    let content: String = sorted.into_iter().map(|x| format!("{}\n", x)).collect();
+    LockFile::acquire(&path)?.commit(content.as_bytes())
+}
@@ -0,0 +1,103 @@
+use crate::{git_path, ObjectFormat, Repository};
+use anyhow::anyhow;
+use flate2::read::ZlibDecoder;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Checks that every loose object lives at `objects/<first 2 hex>/<rest>`
+/// of its own id, reporting (or with `fix`, moving) the ones that don't.
+///
+/// Looks at hex-named files directly under `objects/` as well as inside the
+/// shard directories, since both are where a misbehaving tool ends up writing.
+/// Objects elsewhere are invisible to lookups by id.
+pub fn check_sharding(git_dir: &Path, fix: bool) -> anyhow::Result<()> {
+    let objects_dir = git_path(git_dir, "objects");
+    let format = ObjectFormat::of(git_dir)?;
+    let repository = Repository::open(git_dir);
+
+    let mut misplaced = 0;
+    for path in loose_candidates(&objects_dir, format.hex_len())? {
+        let Some(object_id) = stored_object_id(&path, format) else {
+            println!("unreadable: {}", path.display());
+            continue;
+        };
+        let expected = repository.loose_path(&object_id);
+        if expected == path {
+            continue;
+        }
+
+        if !fix {
+            println!(
+                "misplaced: {} holds {}, expected at {}",
+                path.display(),
+                object_id,
+                expected.display()
+            );
+            misplaced += 1;
+        } else if expected.exists() {
+            // Same id, same content: the misplaced copy is redundant.
+            fs::remove_file(&path)?;
+            println!(
+                "removed: {} (already at {})",
+                path.display(),
+                expected.display()
+            );
+        } else {
+            if let Some(parent) = expected.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&path, &expected)?;
+            println!("moved: {} to {}", path.display(), expected.display());
+        }
+    }
+
+    if misplaced > 0 {
+        return Err(anyhow!(
+            "{} loose objects are misplaced; run with --fix to move them.",
+            misplaced
+        ));
+    }
+    Ok(())
+}
+
+/// Files named like a loose object: all but 2 of an id's `hex_len` hex
+/// characters in a 2 hex character directory, or a full id straight in
+/// `objects/`.
+fn loose_candidates(objects_dir: &Path, hex_len: usize) -> anyhow::Result<Vec<PathBuf>> {
+    let is_hex = |x: &str, len: usize| x.len() == len && x.chars().all(|x| x.is_ascii_hexdigit());
+
+    let mut candidates = Vec::new();
+    for entry in fs::read_dir(objects_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type()?.is_file() && is_hex(&name, hex_len) {
+            candidates.push(entry.path());
+        }
+        if !entry.file_type()?.is_dir() || !is_hex(&name, 2) {
+            continue;
+        }
+
+        for file in fs::read_dir(entry.path())? {
+            let file = file?;
+            if is_hex(&file.file_name().to_string_lossy(), hex_len - 2) {
+                candidates.push(file.path());
+            }
+        }
+    }
+    candidates.sort();
+    Ok(candidates)
+}
+
+/// The id of the loose object stored in a file, from hashing its inflated
+/// content in the repository's `format`, or `None` if it doesn't inflate to
+/// something object-shaped.
+fn stored_object_id(path: &Path, format: ObjectFormat) -> Option<String> {
+    let compressed = fs::read(path).ok()?;
+    let mut content = Vec::new();
+    ZlibDecoder::new(&compressed[..])
+        .read_to_end(&mut content)
+        .ok()?;
+    content.iter().position(|&x| x == 0)?;
+    Some(hex::encode(format.digest(&content)))
+}
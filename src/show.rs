@@ -0,0 +1,84 @@
+use crate::{diff, log, CommitObject, ObjectFormat, ObjectType, Repository, TagObject};
+use crate::{encode_object, TreeObject};
+use std::io::Write;
+use std::path::Path;
+
+/// Shows an object the way `git show` does: a commit's log entry followed by
+/// its patch against its first parent, a tree's entry names, a blob's content,
+/// and a tag's header and message followed by the object it tags.
+///
+/// `name` is the revision the object was asked for by, which trees are
+/// headed with. Merges are shown without a patch, as git's combined diff of
+/// a clean merge is empty.
+///
+/// With `stat`, a commit's patch is replaced by its `--stat` summary, which
+/// for a merge is against its first parent, as git shows it.
+pub fn show(git_dir: &Path, name: &str, object_id: &str, stat: bool) -> anyhow::Result<()> {
+    let (object_type, content) = Repository::open(git_dir).read(object_id)?;
+    match object_type {
+        ObjectType::Blob => std::io::stdout().write_all(&content)?,
+        ObjectType::Tree => {
+            println!("tree {}", name);
+            println!();
+            let tree = TreeObject::from_bytes(
+                &encode_object("tree", &content),
+                ObjectFormat::of(git_dir)?,
+            )?;
+            for element in &tree.elements {
+                match element.object_type {
+                    ObjectType::Tree => println!("{}/", element.name),
+                    _ => println!("{}", element.name),
+                }
+            }
+        }
+        ObjectType::Commit => {
+            let commit = CommitObject::from_content(&content)?;
+            show_commit(git_dir, object_id, commit, stat)?;
+        }
+        ObjectType::Tag => {
+            let tag = TagObject::from_content(&content)?;
+            println!("tag {}", tag.tag);
+            if let Some(tagger) = &tag.tagger {
+                log::print_signature("Tagger", tagger);
+            }
+            println!();
+            println!("{}", tag.message.trim_end_matches('\n'));
+            println!();
+            show(git_dir, name, &tag.object, stat)?;
+        }
+    }
+    Ok(())
+}
+
+fn show_commit(
+    git_dir: &Path,
+    object_id: &str,
+    commit: CommitObject,
+    stat: bool,
+) -> anyhow::Result<()> {
+    let old_tree = match commit.parents.as_slice() {
+        [] => None,
+        [parent] => Some(log::read_commit(git_dir, parent)?.tree),
+        [parent, ..] if stat => Some(log::read_commit(git_dir, parent)?.tree),
+        _ => {
+            log::print_log(&[(object_id.to_string(), commit)]);
+            // git still separates the empty combined diff.
+            println!();
+            return Ok(());
+        }
+    };
+    let changes = diff::diff_trees(git_dir, old_tree.as_deref(), Some(&commit.tree))?;
+    let changes = diff::detect_renames(git_dir, changes, Some(50))?;
+
+    log::print_log(&[(object_id.to_string(), commit)]);
+    if !changes.is_empty() {
+        println!();
+        let pending = diff::PendingBlobs::new();
+        let options = diff::DiffOptions::default();
+        match stat {
+            true => diff::print_stat(git_dir, &changes, &pending, options)?,
+            false => diff::print_patch(git_dir, &changes, &pending, options)?,
+        }
+    }
+    Ok(())
+}
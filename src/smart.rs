@@ -0,0 +1,503 @@
+use crate::pktline::{self, Packet, PktLines, PktWriter};
+use crate::transport::{self, FetchStats, Transport};
+use crate::{log, pack, shallow, Repository};
+use anyhow::{anyhow, Context};
+use std::collections::{BinaryHeap, HashSet};
+use std::path::Path;
+
+/// How this client introduces itself, in the `agent` capability and as its
+/// HTTP user agent.
+pub const AGENT: &str = concat!("git/codecrafters-git-", env!("CARGO_PKG_VERSION"));
+
+/// How many `have` lines each round of negotiation sends.
+const HAVES_PER_ROUND: usize = 32;
+
+/// How many haves in a row the server may leave unacknowledged before the
+/// client stops negotiating and asks for the pack, like git's limit.
+const MAX_HAVES_IN_VAIN: usize = 256;
+
+/// A repository served by `git-upload-pack` (git's "smart" protocol), over
+/// whichever transport reaches it, spoken in protocol version 2: refs are
+/// listed with `ls-refs` and objects arrive as one pack from `fetch`.
+pub struct UploadPack {
+    transport: Box<dyn Transport>,
+    refs: Vec<(String, String)>,
+    head: Option<String>,
+    /// The features the server's `fetch` offers, like `shallow`.
+    fetch_features: Vec<String>,
+}
+
+impl UploadPack {
+    /// Reads the capabilities the server advertised on `transport` and
+    /// lists its refs.
+    #[tracing::instrument(skip_all)]
+    pub fn new(transport: Box<dyn Transport>) -> anyhow::Result<UploadPack> {
+        let mut lines = PktLines::new(transport.advertisement());
+        let mut capabilities = Vec::new();
+        while let Some(Packet::Data(line)) = lines.next()? {
+            capabilities.push(pktline::text(line));
+        }
+        if capabilities.first().map(String::as_str) != Some("version 2") {
+            return Err(anyhow!(
+                "{} doesn't speak protocol version 2, the only smart protocol supported.",
+                transport.url()
+            ));
+        }
+        for command in ["ls-refs", "fetch"] {
+            let supported = capabilities
+                .iter()
+                .any(|x| x == command || x.starts_with(&format!("{}=", command)));
+            if !supported {
+                return Err(anyhow!("The server doesn't support {}.", command));
+            }
+        }
+
+        let fetch_features = capabilities
+            .iter()
+            .find_map(|x| x.strip_prefix("fetch="))
+            .map(|x| x.split(' ').map(|x| x.to_string()).collect())
+            .unwrap_or_default();
+        let mut remote = UploadPack {
+            transport,
+            refs: Vec::new(),
+            head: None,
+            fetch_features,
+        };
+        remote.ls_refs()?;
+        Ok(remote)
+    }
+
+    /// Lists `HEAD`, branches and tags, remembering which branch `HEAD` is.
+    fn ls_refs(&mut self) -> anyhow::Result<()> {
+        let arguments = [
+            "symrefs",
+            "ref-prefix HEAD",
+            "ref-prefix refs/heads/",
+            "ref-prefix refs/tags/",
+        ];
+        let response = self.command("ls-refs", &arguments)?;
+
+        let mut lines = PktLines::new(&response);
+        while let Some(Packet::Data(line)) = lines.next()? {
+            let line = pktline::text(line);
+            let mut fields = line.split(' ');
+            let (Some(object_id), Some(name)) = (fields.next(), fields.next()) else {
+                return Err(anyhow!("Invalid ls-refs line '{}'.", line));
+            };
+            if name == "HEAD" {
+                self.head = fields
+                    .find_map(|x| x.strip_prefix("symref-target:"))
+                    .map(|x| x.to_string());
+            } else {
+                self.refs.push((name.to_string(), object_id.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// The advertised branches and tags as `(name, object id)`.
+    pub fn refs(&self) -> Vec<(String, String)> {
+        self.refs.clone()
+    }
+
+    /// The branch the remote `HEAD` points to.
+    pub fn head(&self) -> Option<String> {
+        self.head.clone()
+    }
+
+    /// Fetches everything reachable from `wants` as a single pack, which is
+    /// stored in `objects/pack` with an index built for it.
+    ///
+    /// The history of `haves` is offered a round of commits at a time, newest
+    /// first, so the server leaves out what both sides have. Ancestors of a
+    /// commit it acknowledges are common too and aren't offered.
+    ///
+    /// The server is told which local commits are shallow, so it doesn't take
+    /// their missing parents for common. With a `depth` it cuts the history
+    /// that many commits below the wants, even ones already stored, and lists
+    /// the new shallow commits and those whose parents it now sends, which
+    /// `.git/shallow` is updated with.
+    #[tracing::instrument(skip(self, git_dir))]
+    pub fn fetch(
+        &self,
+        git_dir: &Path,
+        wants: &[String],
+        haves: &[String],
+        depth: Option<u32>,
+    ) -> anyhow::Result<FetchStats> {
+        let mut shallow = shallow::read(git_dir)?;
+        if (depth.is_some() || !shallow.is_empty())
+            && !self.fetch_features.iter().any(|x| x == "shallow")
+        {
+            return Err(anyhow!("Server does not support shallow clients"));
+        }
+
+        let mut wanted = Vec::new();
+        let repository = Repository::open(git_dir);
+        for want in wants {
+            if !wanted.contains(want) && (depth.is_some() || !repository.exists(want)?) {
+                wanted.push(want.clone());
+            }
+        }
+        if wanted.is_empty() {
+            return Ok(FetchStats::default());
+        }
+
+        let mut base_arguments = vec!["ofs-delta".to_string(), "no-progress".to_string()];
+        base_arguments.extend(wanted.iter().map(|x| format!("want {}", x)));
+        let mut shallow_commits: Vec<&String> = shallow.iter().collect();
+        shallow_commits.sort();
+        base_arguments.extend(shallow_commits.iter().map(|x| format!("shallow {}", x)));
+        if let Some(depth) = depth {
+            // Tags aren't wanted by a shallow clone, but those pointing into
+            // what it gets are sent along.
+            base_arguments.push(format!("deepen {}", depth));
+            base_arguments.push("include-tag".to_string());
+        }
+
+        let mut candidates = Haves::new(git_dir, haves)?;
+        let mut common: Vec<String> = Vec::new();
+        let mut in_vain = 0;
+        let mut response = loop {
+            let mut round = Vec::new();
+            while round.len() < HAVES_PER_ROUND {
+                match candidates.next()? {
+                    Some(id) => round.push(id),
+                    None => break,
+                }
+            }
+            let done = round.is_empty() || in_vain >= MAX_HAVES_IN_VAIN;
+
+            // Each request stands alone over HTTP, so it repeats the wants
+            // and what is already known to be common.
+            let mut arguments = base_arguments.clone();
+            arguments.extend(common.iter().chain(&round).map(|x| format!("have {}", x)));
+            if done {
+                arguments.push("done".to_string());
+            }
+            let arguments: Vec<&str> = arguments.iter().map(String::as_str).collect();
+            let response = read_fetch_response(&self.command("fetch", &arguments)?)?;
+            if response.pack.is_some() {
+                break response;
+            }
+            if done {
+                return Err(anyhow!("The fetch response has no packfile."));
+            }
+
+            in_vain += round.len();
+            for ack in response.acks {
+                if !common.contains(&ack) {
+                    candidates.mark_common(&ack)?;
+                    common.push(ack);
+                    in_vain = 0;
+                }
+            }
+        };
+        tracing::debug!(common = common.len(), "negotiated");
+        let pack = response.pack.take().unwrap_or_default();
+
+        let name = pack::store_pack(git_dir, &pack).context("Index the fetched pack.")?;
+        info!("Received {}.pack", name);
+
+        if !response.shallow.is_empty() || !response.unshallow.is_empty() {
+            shallow.extend(response.shallow);
+            for id in &response.unshallow {
+                shallow.remove(id);
+            }
+            shallow::write(git_dir, &shallow)?;
+        }
+
+        for want in &wanted {
+            if !repository.exists(want)? {
+                return Err(anyhow!("The fetched pack is missing {}.", want));
+            }
+        }
+        Ok(FetchStats {
+            objects: 0,
+            packs: 1,
+        })
+    }
+
+    /// Runs a protocol v2 command, returning the raw response.
+    #[tracing::instrument(level = "debug", skip(self, arguments))]
+    fn command(&self, command: &str, arguments: &[&str]) -> anyhow::Result<Vec<u8>> {
+        let mut request = PktWriter::new();
+        request.line(&format!("command={}", command))?;
+        request.line(&format!("agent={}", AGENT))?;
+        request.delim();
+        for argument in arguments {
+            request.line(argument)?;
+        }
+        request.flush();
+
+        let response = self.transport.request(&request.into_bytes())?;
+        tracing::trace!(size = response.len(), "received");
+        Ok(response)
+    }
+}
+
+/// A repository served by `git-receive-pack`, which takes pushes. It speaks
+/// the original protocol: refs are advertised as the service starts, and the
+/// one request sends ref update commands and a pack.
+pub struct ReceivePack {
+    transport: Box<dyn Transport>,
+    refs: Vec<(String, String)>,
+    capabilities: Vec<String>,
+}
+
+/// A ref update for the server to make: `old` and `new` are all zeros for a
+/// ref that is created or deleted.
+pub struct RefUpdate {
+    pub name: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl ReceivePack {
+    #[tracing::instrument]
+    pub fn connect(url: &str) -> anyhow::Result<ReceivePack> {
+        let transport = transport::open(url, "git-receive-pack")?.ok_or_else(|| {
+            anyhow!(
+                "{} can't be pushed to: pushing needs a smart HTTP server.",
+                url.trim_end_matches('/')
+            )
+        })?;
+
+        // Each line is `<id> <ref>`; the first also carries the capabilities
+        // after a NUL. A repository without refs advertises a single
+        // `capabilities^{}` line.
+        let mut lines = PktLines::new(transport.advertisement());
+        let mut refs = Vec::new();
+        let mut capabilities = Vec::new();
+        while let Some(Packet::Data(line)) = lines.next()? {
+            let line = pktline::text(line);
+            let (line, caps) = line.split_once('\0').unwrap_or((&line, ""));
+            if refs.is_empty() && capabilities.is_empty() {
+                capabilities = caps.split(' ').map(|x| x.to_string()).collect();
+            }
+            let (object_id, name) = line
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("Invalid ref advertisement '{}'.", line))?;
+            if name != "capabilities^{}" {
+                refs.push((name.to_string(), object_id.to_string()));
+            }
+        }
+
+        Ok(ReceivePack {
+            transport,
+            refs,
+            capabilities,
+        })
+    }
+
+    /// The refs the server has, as `(name, object id)`.
+    pub fn refs(&self) -> &[(String, String)] {
+        &self.refs
+    }
+
+    /// Sends `updates` with the `pack` that holds the objects they need,
+    /// and returns the server's verdict on each ref: `Err` with its reason
+    /// when it refused to update it.
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn push(
+        &self,
+        updates: &[RefUpdate],
+        pack: Option<&[u8]>,
+    ) -> anyhow::Result<Vec<(String, Result<(), String>)>> {
+        let report = self.capabilities.iter().any(|x| x == "report-status");
+        let sideband = self.capabilities.iter().any(|x| x == "side-band-64k");
+        let deletes = updates.iter().any(|x| x.new.bytes().all(|b| b == b'0'));
+        if deletes && !self.capabilities.iter().any(|x| x == "delete-refs") {
+            return Err(anyhow!("The server doesn't allow deleting refs."));
+        }
+
+        let mut request = PktWriter::new();
+        for (i, update) in updates.iter().enumerate() {
+            let mut line = format!("{} {} {}", update.old, update.new, update.name);
+            if i == 0 {
+                let mut capabilities = Vec::new();
+                if report {
+                    capabilities.push("report-status".to_string());
+                }
+                if sideband {
+                    capabilities.push("side-band-64k".to_string());
+                }
+                capabilities.push(format!("agent={}", AGENT));
+                line.push('\0');
+                line.push_str(&capabilities.join(" "));
+            }
+            request.line(&line)?;
+        }
+        request.flush();
+        if let Some(pack) = pack {
+            request.raw(pack);
+        }
+
+        let mut body = self.transport.request(&request.into_bytes())?;
+
+        // With side-band-64k the report arrives on band 1, between the
+        // messages the server's hooks print on band 2.
+        if sideband {
+            let mut lines = PktLines::new(&body);
+            let mut reported = Vec::new();
+            while let Some(Packet::Data(line)) = lines.next()? {
+                pktline::demux_sideband(line, &mut reported, |message| {
+                    info!("remote: {}", message)
+                })?;
+            }
+            body = reported;
+        }
+        if !report {
+            return Ok(updates.iter().map(|x| (x.name.clone(), Ok(()))).collect());
+        }
+        read_report_status(&body)
+    }
+}
+
+/// Reads a `report-status` answer: `unpack ok` (or the reason the pack was
+/// refused), then `ok <ref>` or `ng <ref> <reason>` for each ref.
+fn read_report_status(body: &[u8]) -> anyhow::Result<Vec<(String, Result<(), String>)>> {
+    let mut lines = PktLines::new(body);
+    let mut statuses = Vec::new();
+    while let Some(Packet::Data(line)) = lines.next()? {
+        let line = pktline::text(line);
+        if let Some(status) = line.strip_prefix("unpack ") {
+            if status != "ok" {
+                return Err(anyhow!("The remote failed to unpack the pack: {}", status));
+            }
+        } else if let Some(name) = line.strip_prefix("ok ") {
+            statuses.push((name.to_string(), Ok(())));
+        } else if let Some(rest) = line.strip_prefix("ng ") {
+            let (name, reason) = rest.split_once(' ').unwrap_or((rest, "failed"));
+            statuses.push((name.to_string(), Err(reason.to_string())));
+        }
+    }
+    Ok(statuses)
+}
+
+/// Local commits to offer as `have`s, newest committer date first.
+struct Haves<'a> {
+    git_dir: &'a Path,
+    queue: BinaryHeap<(i64, String)>,
+    seen: HashSet<String>,
+    /// Commits the server has, and so all their ancestors.
+    common: HashSet<String>,
+}
+
+impl<'a> Haves<'a> {
+    fn new(git_dir: &'a Path, tips: &[String]) -> anyhow::Result<Haves<'a>> {
+        let mut haves = Haves {
+            git_dir,
+            queue: BinaryHeap::new(),
+            seen: HashSet::new(),
+            common: HashSet::new(),
+        };
+        for tip in tips {
+            haves.push(tip)?;
+        }
+        Ok(haves)
+    }
+
+    fn push(&mut self, id: &str) -> anyhow::Result<()> {
+        if self.seen.insert(id.to_string()) {
+            let commit = log::read_commit(self.git_dir, id)?;
+            self.queue
+                .push((log::committer_time(&commit), id.to_string()));
+        }
+        Ok(())
+    }
+
+    /// The next commit not known to be common, if any is left.
+    fn next(&mut self) -> anyhow::Result<Option<String>> {
+        while let Some((_, id)) = self.queue.pop() {
+            if self.common.contains(&id) {
+                continue;
+            }
+            for parent in log::read_commit(self.git_dir, &id)?.parents {
+                self.push(&parent)?;
+            }
+            return Ok(Some(id));
+        }
+        Ok(None)
+    }
+
+    fn mark_common(&mut self, id: &str) -> anyhow::Result<()> {
+        let mut pending = vec![id.to_string()];
+        while let Some(id) = pending.pop() {
+            if self.common.insert(id.clone()) {
+                pending.extend(log::read_commit(self.git_dir, &id)?.parents);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The server's answer to a round of `fetch`: the haves it acknowledged,
+/// and the pack, once it sends one, with the commits that became shallow
+/// or stopped being so.
+struct FetchResponse {
+    acks: Vec<String>,
+    shallow: Vec<String>,
+    unshallow: Vec<String>,
+    pack: Option<Vec<u8>>,
+}
+
+/// Reads a `fetch` response: sections start with a header line and end with
+/// a delimiter. `acknowledgments` lists the common commits, `shallow-info`
+/// the changes to the shallow commits, and the last section, `packfile`,
+/// multiplexes the pack (band 1) with progress messages (band 2) and errors
+/// (band 3).
+fn read_fetch_response(response: &[u8]) -> anyhow::Result<FetchResponse> {
+    let mut lines = PktLines::new(response);
+    let mut section = None;
+    let mut acks = Vec::new();
+    let mut shallow = Vec::new();
+    let mut unshallow = Vec::new();
+    let mut pack = Vec::new();
+    let mut has_pack = false;
+
+    while let Some(packet) = lines.next()? {
+        match packet {
+            Packet::Data(line) if section.is_none() => {
+                if let Some(error) = line.strip_prefix(b"ERR ") {
+                    return Err(anyhow!("The remote failed: {}", pktline::text(error)));
+                }
+                let header = pktline::text(line);
+                has_pack |= header == "packfile";
+                section = Some(header);
+            }
+            Packet::Data(line) if section.as_deref() == Some("acknowledgments") => {
+                if let Some(id) = pktline::text(line).strip_prefix("ACK ") {
+                    acks.push(id.to_string());
+                }
+            }
+            Packet::Data(line) if section.as_deref() == Some("shallow-info") => {
+                let line = pktline::text(line);
+                if let Some(id) = line.strip_prefix("shallow ") {
+                    shallow.push(id.to_string());
+                } else if let Some(id) = line.strip_prefix("unshallow ") {
+                    unshallow.push(id.to_string());
+                }
+            }
+            Packet::Data(line) if section.as_deref() == Some("packfile") => {
+                pktline::demux_sideband(line, &mut pack, |message| {
+                    tracing::debug!(message, "remote")
+                })?;
+            }
+            Packet::Data(line) => {
+                if let Some(error) = line.strip_prefix(b"ERR ") {
+                    return Err(anyhow!("The remote failed: {}", pktline::text(error)));
+                }
+            }
+            Packet::Delim => section = None,
+            Packet::Flush | Packet::ResponseEnd => break,
+        }
+    }
+
+    Ok(FetchResponse {
+        acks,
+        shallow,
+        unshallow,
+        pack: has_pack.then_some(pack),
+    })
+}
@@ -0,0 +1,129 @@
+use anyhow::anyhow;
+use std::process::Command;
+
+/// Where an SSH URL points: `ssh://[user@]host[:port]/path`, or the
+/// scp-like `[user@]host:path`, whose path is relative to the home directory.
+#[derive(Debug, PartialEq)]
+pub struct SshUrl {
+    /// The host, with the user to log in as if one is given.
+    pub host: String,
+    pub port: Option<String>,
+    pub path: String,
+}
+
+/// Parses `url` if it's an SSH URL. Like git, a `host:path` without `://`
+/// is one when no `/` comes before the colon, so local paths aren't taken
+/// for hosts.
+pub fn parse_url(url: &str) -> Option<SshUrl> {
+    if let Some(rest) = url
+        .strip_prefix("ssh://")
+        .or_else(|| url.strip_prefix("git+ssh://"))
+        .or_else(|| url.strip_prefix("ssh+git://"))
+    {
+        let (authority, path) = rest.split_at(rest.find('/')?);
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.is_empty() => (host, Some(port.to_string())),
+            Some((host, _)) => (host, None),
+            None => (authority, None),
+        };
+        // `ssh://host/~user/repo` is relative to a home directory too.
+        let path = match path.strip_prefix("/~") {
+            Some(home) => format!("~{}", home),
+            None => path.to_string(),
+        };
+        return Some(SshUrl {
+            host: host.to_string(),
+            port,
+            path,
+        });
+    }
+    if url.contains("://") {
+        return None;
+    }
+    let (host, path) = url.split_once(':')?;
+    if host.is_empty() || host.contains('/') || path.is_empty() {
+        return None;
+    }
+    Some(SshUrl {
+        host: host.to_string(),
+        port: None,
+        path: path.to_string(),
+    })
+}
+
+/// The command that runs `service` on the host `url` names: `GIT_SSH_COMMAND`,
+/// run by the shell, or else the program `GIT_SSH`, or `ssh`. Protocol
+/// version 2 is asked for in `GIT_PROTOCOL`, which ssh is told to pass on.
+///
+/// Like git, a host, port or path starting with `-` is refused, so a URL
+/// can't smuggle in an option of ssh's, and `--` ends the options anyway.
+pub fn command(url: &SshUrl, service: &str) -> anyhow::Result<Command> {
+    let strange = [
+        ("hostname", Some(&url.host)),
+        ("port", url.port.as_ref()),
+        ("pathname", Some(&url.path)),
+    ];
+    for (what, value) in strange {
+        if let Some(value) = value.filter(|x| x.starts_with('-')) {
+            return Err(anyhow!("strange {} '{}' blocked", what, value));
+        }
+    }
+    let mut command = match (
+        std::env::var_os("GIT_SSH_COMMAND"),
+        std::env::var_os("GIT_SSH"),
+    ) {
+        (Some(ssh_command), _) => {
+            let mut script = ssh_command;
+            script.push(" \"$@\"");
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(script).arg("ssh");
+            command
+        }
+        (None, Some(program)) => Command::new(program),
+        (None, None) => Command::new("ssh"),
+    };
+    if service == "git-upload-pack" {
+        command
+            .args(["-o", "SendEnv=GIT_PROTOCOL"])
+            .env("GIT_PROTOCOL", "version=2");
+    }
+    if let Some(port) = &url.port {
+        command.args(["-p", port]);
+    }
+    command
+        .arg("--")
+        .arg(&url.host)
+        .arg(format!("{} {}", service, shell_quote(&url.path)));
+    Ok(command)
+}
+
+/// Quotes `text` for the remote shell, as git does: in single quotes, with
+/// each single quote written `'\''`.
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_cannot_pass_options_to_ssh() {
+        for url in [
+            "ssh://-oProxyCommand=touch%20x/repo",
+            "-oProxyCommand=touch x:repo",
+            "ssh://host:-1/repo",
+            "host:-repo",
+        ] {
+            let url = parse_url(url).unwrap();
+            assert!(command(&url, "git-upload-pack").is_err(), "{:?}", url);
+        }
+
+        let command = command(&parse_url("git@host:repo").unwrap(), "git-upload-pack").unwrap();
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(
+            args[args.len() - 3..],
+            ["--", "git@host", "git-upload-pack 'repo'"]
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use crate::checkout::{self, ResetMode};
+use crate::index::{Index, IndexEntry};
+use crate::platform::{self, Filesystem};
+use crate::refs::{self, RefLock};
+use crate::{
+    commit_tree, git_path, log, merge, read_symref, record_ref_update, resolve_ref, short_id,
+    status, ObjectType, Repository,
+};
+use anyhow::anyhow;
+use std::fs;
+use std::path::Path;
+
+/// The ref whose reflog is the stack of stashes, newest first.
+const STASH_REF: &str = "refs/stash";
+
+/// Saves the staged and unstaged changes to tracked files, like `git stash
+/// push`, and resets the index and work tree to `HEAD`. Untracked files stay.
+///
+/// A stash is a commit of the work tree whose parents are `HEAD` and a commit
+/// of the index. `refs/stash` points at the newest, and its reflog holds the
+/// rest, `stash@{0}` being the newest.
+pub fn push(git_dir: &Path, work_tree: &Path, message: Option<&str>) -> anyhow::Result<()> {
+    let head = resolve_ref(git_dir, "HEAD")?
+        .ok_or_else(|| anyhow!("You do not have the initial commit yet."))?;
+    let mut repository = Repository::open(git_dir);
+    let head = repository.peel_to(&head, ObjectType::Commit)?;
+    let head_commit = log::read_commit(git_dir, &head)?;
+
+    let index_path = git_path(git_dir, "index");
+    let mut index = Index::load(&index_path)?;
+    if index.entries.iter().any(|x| x.stage() != 0) {
+        return Err(anyhow!(
+            "Cannot save the current index state: you have unmerged files."
+        ));
+    }
+    let index_tree = index.write_tree(git_dir)?;
+    index.write(&index_path)?;
+
+    // The work tree's version of every tracked file, or none once it's deleted.
+    let mut work = Index::load(&index_path)?;
+    for entry in &index.entries {
+        let full_path = work_tree.join(&entry.path);
+        let Result::Ok(metadata) = fs::symlink_metadata(&full_path) else {
+            work.remove(&entry.path);
+            continue;
+        };
+        if metadata.is_dir() || !status::work_tree_differs(git_dir, work_tree, entry)? {
+            continue;
+        }
+        let content = status::work_tree_content(git_dir, work_tree, &entry.path, &metadata)?;
+        let blob = repository.write(ObjectType::Blob, &content)?;
+        work.add(IndexEntry::new(
+            &entry.path,
+            platform::work_tree_mode(Filesystem::of(git_dir), &metadata, Some(entry.mode)),
+            hex::decode(blob)?,
+            0,
+        ))?;
+    }
+    let work_tree_tree = work.write_tree(git_dir)?;
+
+    if index_tree == head_commit.tree && work_tree_tree == head_commit.tree {
+        println!("No local changes to save");
+        return Ok(());
+    }
+
+    let branch = match read_symref(git_dir, "HEAD")? {
+        Some(branch) => branch.trim_start_matches("refs/heads/").to_string(),
+        None => "(no branch)".to_string(),
+    };
+    let subject = head_commit.message.lines().next().unwrap_or_default();
+    let on = format!("{}: {} {}", branch, short_id(&head), subject);
+    let message = match message {
+        Some(message) => format!("On {}: {}", branch, message),
+        None => format!("WIP on {}", on),
+    };
+    let index_commit = commit_tree(
+        git_dir,
+        &index_tree,
+        std::slice::from_ref(&head),
+        None,
+        &format!("index on {}", on),
+    )?;
+    let stash = commit_tree(
+        git_dir,
+        &work_tree_tree,
+        &[head, index_commit],
+        None,
+        &message,
+    )?;
+
+    // The stash's reflog is kept whatever `core.logAllRefUpdates` says.
+    let log_path = git_path(git_dir, &format!("logs/{}", STASH_REF));
+    if let Some(parent) = log_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)?;
+    let old = resolve_ref(git_dir, STASH_REF)?;
+    RefLock::acquire(git_dir, STASH_REF)?.commit(&stash)?;
+    record_ref_update(git_dir, STASH_REF, old.as_deref(), &stash, &message)?;
+
+    println!("Saved working directory and index state {}", message);
+    checkout::reset(git_dir, work_tree, "HEAD", ResetMode::Hard, true)
+}
+
+/// Prints each stash as `stash@{<n>}: <message>`, newest first.
+pub fn list(git_dir: &Path) -> anyhow::Result<()> {
+    let entries = refs::read_reflog(git_dir, STASH_REF)?;
+    for (n, entry) in entries.iter().rev().enumerate() {
+        println!("stash@{{{}}}: {}", n, entry.message);
+    }
+    Ok(())
+}
+
+/// Reapplies a stash, `stash@{0}` unless `rev` names another, and then
+/// shows the status, like `git stash apply`. Returns whether it applied
+/// cleanly.
+pub fn apply(git_dir: &Path, work_tree: &Path, rev: Option<&str>) -> anyhow::Result<bool> {
+    let (_, stash) = find_stash(git_dir, rev)?;
+    let clean = merge::apply_stash(git_dir, work_tree, &stash)?;
+    status::status(git_dir, work_tree, false, false)?;
+    if !clean {
+        println!("The stash entry is kept in case you need it again.");
+    }
+    Ok(clean)
+}
+
+/// Applies a stash and drops it if that went cleanly, like `git stash pop`.
+pub fn pop(git_dir: &Path, work_tree: &Path, rev: Option<&str>) -> anyhow::Result<bool> {
+    let clean = apply(git_dir, work_tree, rev)?;
+    if clean {
+        drop(git_dir, rev)?;
+    }
+    Ok(clean)
+}
+
+/// Removes a stash from the stack, `stash@{0}` unless `rev` names another.
+/// Those above it move down a place.
+pub fn drop(git_dir: &Path, rev: Option<&str>) -> anyhow::Result<()> {
+    let (n, stash) = find_stash(git_dir, rev)?;
+    let mut entries = refs::read_reflog(git_dir, STASH_REF)?;
+    entries.remove(entries.len() - 1 - n);
+    // Each entry starts where the one before it left the ref.
+    let mut old = "0".repeat(stash.len());
+    for entry in &mut entries {
+        entry.old = old;
+        old = entry.new.clone();
+    }
+
+    match entries.last() {
+        Some(newest) => {
+            let newest = newest.new.clone();
+            refs::write_reflog(git_dir, STASH_REF, &entries)?;
+            RefLock::acquire(git_dir, STASH_REF)?.commit(&newest)?;
+        }
+        None => {
+            refs::delete_ref(git_dir, STASH_REF)?;
+        }
+    }
+    let shown = match rev {
+        Some(rev) => rev.to_string(),
+        None => format!("{}@{{0}}", STASH_REF),
+    };
+    println!("Dropped {} ({})", shown, stash);
+    Ok(())
+}
+
+/// The position of the stash `rev` names in the stack, and its commit. A
+/// bare number `n` stands for `stash@{n}`.
+fn find_stash(git_dir: &Path, rev: Option<&str>) -> anyhow::Result<(usize, String)> {
+    let entries = refs::read_reflog(git_dir, STASH_REF)?;
+    if entries.is_empty() {
+        return Err(anyhow!("No stash entries found."));
+    }
+    let rev = rev.unwrap_or("stash@{0}");
+    let n = match rev.parse::<usize>() {
+        Result::Ok(n) => n,
+        Err(_) => rev
+            .strip_prefix("stash@{")
+            .or_else(|| rev.strip_prefix("refs/stash@{"))
+            .and_then(|x| x.strip_suffix('}'))
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| anyhow!("'{}' is not a stash reference.", rev))?,
+    };
+    if n >= entries.len() {
+        return Err(anyhow!("{} is not a valid reference.", rev));
+    }
+    Ok((n, entries[entries.len() - 1 - n].new.clone()))
+}
@@ -0,0 +1,535 @@
+use crate::ignore::IgnoreRules;
+use crate::platform::{self, Filesystem};
+use crate::{
+    attributes, canonical_mode, diff, encode_object, git_dir_at, git_path, index,
+    leads_through_symlink, parallel, read_symref, resolve_ref, short_id, Object, ObjectFormat,
+    ObjectType, Repository,
+};
+use anyhow::anyhow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// Prints the branch `HEAD` is on, then the changes staged for the next
+/// commit (the index against `HEAD`), unmerged paths, the changes not staged
+/// (the work tree against the index) and the untracked files, laid out like
+/// `git status` with `advice.statusHints` off. With `recurse_submodules`,
+/// where each submodule recorded in the index stands is reported after the
+/// branch.
+///
+/// With `porcelain`, prints the `XY path` lines of `git status --porcelain`
+/// instead, untracked files last as `?? path`.
+pub fn status(
+    git_dir: &Path,
+    work_tree: &Path,
+    recurse_submodules: bool,
+    porcelain: bool,
+) -> anyhow::Result<()> {
+    // Like git, status saves what it learns about unchanged files, so the
+    // next run needn't read them again. That is only an optimization: when
+    // the index can't be written, say while another command holds its lock,
+    // the comparisons below still hash what they must.
+    let _ = refresh_index(git_dir, work_tree);
+    let index = index::Index::load(&git_path(git_dir, "index"))?;
+    // Like git, staged additions and deletions are paired into renames.
+    let staged = diff::detect_renames(git_dir, diff::diff_head_to_index(git_dir)?, Some(50))?;
+    let unstaged = diff::diff_index_to_work_tree(git_dir, work_tree, &mut Default::default())?;
+    let unmerged = unmerged_paths(&index);
+    let untracked = untracked_files(git_dir, work_tree, &index);
+
+    if porcelain {
+        let mut lines: BTreeMap<&str, (char, char, String)> = BTreeMap::new();
+        for change in &staged {
+            let shown = match &change.rename {
+                Some(rename) => format!("{} -> {}", rename.from, change.path),
+                None => change.path.clone(),
+            };
+            lines.insert(&change.path, (change_code(change), ' ', shown));
+        }
+        for change in &unstaged {
+            lines
+                .entry(&change.path)
+                .or_insert_with(|| (' ', ' ', change.path.clone()))
+                .1 = change_code(change);
+        }
+        for (path, code) in &unmerged {
+            let mut code = code.chars();
+            let (x, y) = (code.next().unwrap_or(' '), code.next().unwrap_or(' '));
+            lines.insert(path, (x, y, path.clone()));
+        }
+        for (x, y, shown) in lines.values() {
+            println!("{}{} {}", x, y, shown);
+        }
+        for path in &untracked {
+            println!("?? {}", path);
+        }
+        return Ok(());
+    }
+
+    match read_symref(git_dir, "HEAD")? {
+        Some(target) => println!(
+            "On branch {}",
+            target.strip_prefix("refs/heads/").unwrap_or(&target)
+        ),
+        None => {
+            let head = resolve_ref(git_dir, "HEAD")?.unwrap_or_default();
+            println!("HEAD detached at {}", short_id(&head));
+        }
+    }
+    if git_path(git_dir, "MERGE_HEAD").exists() {
+        match unmerged.is_empty() {
+            true => println!("All conflicts fixed but you are still merging."),
+            false => println!("You have unmerged paths."),
+        }
+        println!();
+    }
+    if recurse_submodules {
+        submodule_status(git_dir, work_tree)?;
+    }
+    let unborn = resolve_ref(git_dir, "HEAD")?.is_none();
+    if unborn {
+        println!();
+        println!("No commits yet");
+        println!();
+    }
+
+    let change_line = |change: &diff::FileChange| {
+        let shown = match &change.rename {
+            Some(rename) => format!("{} -> {}", rename.from, change.path),
+            None => change.path.clone(),
+        };
+        let label = match change_code(change) {
+            'A' => "new file:",
+            'D' => "deleted:",
+            'R' => "renamed:",
+            'T' => "typechange:",
+            _ => "modified:",
+        };
+        format!("{:<12}{}", label, shown)
+    };
+    print_section("Changes to be committed", staged.iter().map(change_line));
+    print_section(
+        "Unmerged paths",
+        unmerged.iter().map(|(path, code)| {
+            let label = match code.as_str() {
+                "DD" => "both deleted:",
+                "AU" => "added by us:",
+                "UD" => "deleted by them:",
+                "UA" => "added by them:",
+                "DU" => "deleted by us:",
+                "AA" => "both added:",
+                _ => "both modified:",
+            };
+            format!("{:<17}{}", label, path)
+        }),
+    );
+    print_section(
+        "Changes not staged for commit",
+        unstaged.iter().map(change_line),
+    );
+    print_section("Untracked files", untracked.iter().cloned());
+
+    if staged.is_empty() {
+        if !unstaged.is_empty() || !unmerged.is_empty() {
+            println!("no changes added to commit");
+        } else if !untracked.is_empty() {
+            println!("nothing added to commit but untracked files present");
+        } else if unborn {
+            println!("nothing to commit");
+        } else {
+            println!("nothing to commit, working tree clean");
+        }
+    }
+    Ok(())
+}
+
+fn print_section(title: &str, lines: impl Iterator<Item = String>) {
+    let mut lines = lines.peekable();
+    if lines.peek().is_none() {
+        return;
+    }
+    println!("{}:", title);
+    for line in lines {
+        println!("\t{}", line);
+    }
+    println!();
+}
+
+/// The status letter of a change: `T` when a file became a symlink or
+/// gitlink or the other way around, `M` for other changes to a path.
+pub fn change_code(change: &diff::FileChange) -> char {
+    if change.rename.is_some() {
+        return 'R';
+    }
+    let kind = |mode: &str| match mode {
+        "120000" | "160000" => mode.to_string(),
+        _ => "file".to_string(),
+    };
+    match (&change.old, &change.new) {
+        (None, Some(_)) => 'A',
+        (Some(_), None) => 'D',
+        (Some(old), Some(new)) if kind(&old.mode) != kind(&new.mode) => 'T',
+        _ => 'M',
+    }
+}
+
+/// The unmerged paths of the index with the two-letter code `git status`
+/// gives each, from which of the base (1), ours (2) and theirs (3) stages
+/// it has.
+fn unmerged_paths(index: &index::Index) -> Vec<(String, String)> {
+    let mut stages: BTreeMap<&str, u8> = BTreeMap::new();
+    for entry in index.entries.iter().filter(|x| x.stage() != 0) {
+        *stages.entry(&entry.path).or_default() |= 1 << (entry.stage() - 1);
+    }
+    stages
+        .into_iter()
+        .map(|(path, stages)| {
+            let code = match stages {
+                0b001 => "DD",
+                0b010 => "AU",
+                0b011 => "UD",
+                0b100 => "UA",
+                0b101 => "DU",
+                0b110 => "AA",
+                _ => "UU",
+            };
+            (path.to_string(), code.to_string())
+        })
+        .collect()
+}
+
+/// Work tree files that aren't in the index and aren't ignored, sorted. A
+/// directory holding no tracked files is listed once as `dir/` instead of
+/// file by file, as is a repository nested in the work tree.
+pub fn untracked_files(git_dir: &Path, work_tree: &Path, index: &index::Index) -> Vec<String> {
+    let ignore = IgnoreRules::load(work_tree, git_dir);
+    other_files(git_dir, work_tree, index, Some(&ignore), true)
+}
+
+/// Work tree files that aren't in the index, sorted, like `ls-files
+/// --others`: leaving out those `ignore` matches, when given, and with
+/// `collapse`, listing a directory without tracked files as `dir/`. A
+/// repository nested in the work tree is always listed as `dir/`.
+///
+/// Where the filesystem ignores case, a file whose name differs from a
+/// tracked one only in case is that file, not an untracked one.
+pub fn other_files(
+    git_dir: &Path,
+    work_tree: &Path,
+    index: &index::Index,
+    ignore: Option<&IgnoreRules>,
+    collapse: bool,
+) -> Vec<String> {
+    let filesystem = Filesystem::of(git_dir);
+    let tracked: BTreeSet<String> = index
+        .entries
+        .iter()
+        .map(|x| filesystem.path_key(&x.path))
+        .collect();
+    let mut untracked = Vec::new();
+    let walk = Walk {
+        work_tree,
+        filesystem,
+        tracked: &tracked,
+        collapse,
+    };
+    collect_untracked(&walk, "", ignore, &mut untracked);
+    untracked.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+    untracked
+}
+
+/// What stays the same through a walk for untracked files.
+struct Walk<'a> {
+    work_tree: &'a Path,
+    filesystem: Filesystem,
+    /// The tracked paths, as `Filesystem::path_key` spells them.
+    tracked: &'a BTreeSet<String>,
+    collapse: bool,
+}
+
+fn collect_untracked(
+    walk: &Walk,
+    dir: &str,
+    ignore: Option<&IgnoreRules>,
+    untracked: &mut Vec<String>,
+) {
+    let work_tree = walk.work_tree;
+    let Result::Ok(entries) = fs::read_dir(platform::work_tree_path(work_tree, dir)) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = format!("{}{}", dir, name);
+        if walk.filesystem.same_path(&name, ".git")
+            || walk.tracked.contains(&walk.filesystem.path_key(&path))
+        {
+            continue;
+        }
+        let is_dir = entry.file_type().is_ok_and(|x| x.is_dir());
+        if ignore.is_some_and(|x| x.is_ignored(&path, is_dir)) {
+            continue;
+        }
+        if !is_dir {
+            untracked.push(path);
+            continue;
+        }
+
+        let subdir = format!("{}/", path);
+        if entry.path().join(".git").exists() {
+            untracked.push(subdir);
+            continue;
+        }
+        let mut below = Vec::new();
+        let rules = ignore.map(|x| x.for_subdir(work_tree, &subdir));
+        collect_untracked(walk, &subdir, rules.as_ref(), &mut below);
+        let key = walk.filesystem.path_key(&subdir);
+        let has_tracked = walk
+            .tracked
+            .range(key.clone()..)
+            .next()
+            .is_some_and(|x| x.starts_with(&key));
+        match !walk.collapse || has_tracked || below.is_empty() {
+            true => untracked.extend(below),
+            false => untracked.push(subdir),
+        }
+    }
+}
+
+/// Reports, for each gitlink in the index, whether the submodule is checked
+/// out and whether its `HEAD` is at the recorded commit. Submodules are only
+/// read, never fetched or updated.
+fn submodule_status(git_dir: &Path, work_tree: &Path) -> anyhow::Result<()> {
+    let index = index::Index::load(&git_path(git_dir, "index"))?;
+
+    for entry in index.entries.iter().filter(|x| x.mode == 0o160000) {
+        let expected = hex::encode(&entry.hash);
+        let sub_git_dir = match git_dir_at(&work_tree.join(&entry.path)) {
+            Result::Ok(sub_git_dir) => sub_git_dir,
+            Err(_) => {
+                println!(
+                    "submodule {} is not checked out, expected {}",
+                    entry.path, expected
+                );
+                continue;
+            }
+        };
+
+        match resolve_ref(&sub_git_dir, "HEAD")? {
+            Some(actual) if actual == expected => {
+                println!("submodule {} is at {}", entry.path, actual)
+            }
+            Some(actual) => println!(
+                "submodule {} is at {}, expected {}",
+                entry.path, actual, expected
+            ),
+            None => println!(
+                "submodule {} has no commits, expected {}",
+                entry.path, expected
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Whether the index differs from `HEAD` or a tracked file in the work tree
+/// differs from the index. Untracked files don't count, as for `describe --dirty`.
+///
+/// Files whose size and modification time still match their index entry are
+/// taken to be unchanged without being hashed again.
+pub fn is_dirty(git_dir: &Path, work_tree: &Path) -> anyhow::Result<bool> {
+    let index = index::Index::load(&git_path(git_dir, "index"))?;
+
+    let mut head_entries = BTreeMap::new();
+    if let Some(head) = resolve_ref(git_dir, "HEAD")? {
+        let tree = Repository::open(git_dir).peel_to(&head, ObjectType::Tree)?;
+        flatten_tree(git_dir, &tree, "", &mut head_entries)?;
+    }
+    if index.entries.len() != head_entries.len()
+        || index
+            .entries
+            .iter()
+            .any(|entry| head_entries.get(&entry.path) != Some(&(entry.mode, entry.hash.clone())))
+    {
+        return Ok(true);
+    }
+
+    for entry in &index.entries {
+        if entry.mode == 0o160000 {
+            continue;
+        }
+        if work_tree_differs(git_dir, work_tree, entry)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Brings the stat data of index entries up to date with the work tree.
+///
+/// Entries whose stat data changed are hashed again; those
+/// whose content still matches get fresh stat data, so later comparisons can
+/// skip them. Returns the paths that really changed (or went missing) along
+/// with the paths that are unmerged, which are left alone.
+pub fn refresh_index(git_dir: &Path, work_tree: &Path) -> anyhow::Result<Refresh> {
+    let index_path = git_path(git_dir, "index");
+    let mut index = index::Index::load(&index_path)?;
+    let mut refresh = Refresh::default();
+
+    // The files whose stat data changed are hashed on several threads.
+    let states = parallel::map(&index.entries, |entry| {
+        match entry.stage() != 0 || entry.mode == 0o160000 {
+            true => Ok(WorkTreeState::Unchanged),
+            false => work_tree_state(git_dir, work_tree, entry),
+        }
+    });
+    for (entry, state) in index.entries.iter_mut().zip(states) {
+        if entry.stage() != 0 {
+            if refresh.unmerged.last() != Some(&entry.path) {
+                refresh.unmerged.push(entry.path.clone());
+            }
+            continue;
+        }
+        match state? {
+            WorkTreeState::Unchanged => {}
+            WorkTreeState::Modified => refresh.modified.push(entry.path.clone()),
+            WorkTreeState::Restat(metadata) => {
+                let mut current = index::IndexEntry::from_metadata(
+                    &entry.path,
+                    entry.mode,
+                    entry.hash.clone(),
+                    &metadata,
+                );
+                current.flags = entry.flags;
+                current.extended_flags = entry.extended_flags;
+                *entry = current;
+                refresh.refreshed += 1;
+            }
+        }
+    }
+
+    if refresh.refreshed > 0 {
+        index.write(&index_path)?;
+    }
+    Ok(refresh)
+}
+
+/// What `refresh_index` refreshed, and what it couldn't.
+#[derive(Debug, Default)]
+pub struct Refresh {
+    pub refreshed: usize,
+    pub modified: Vec<String>,
+    pub unmerged: Vec<String>,
+}
+
+enum WorkTreeState {
+    /// The stat data matches the index entry.
+    Unchanged,
+    /// The stat data changed but the content didn't.
+    Restat(fs::Metadata),
+    Modified,
+}
+
+/// Whether the work tree file of `entry` has content or a mode other than
+/// what is staged, or is gone.
+pub fn work_tree_differs(
+    git_dir: &Path,
+    work_tree: &Path,
+    entry: &index::IndexEntry,
+) -> anyhow::Result<bool> {
+    Ok(matches!(
+        work_tree_state(git_dir, work_tree, entry)?,
+        WorkTreeState::Modified
+    ))
+}
+
+fn work_tree_state(
+    git_dir: &Path,
+    work_tree: &Path,
+    entry: &index::IndexEntry,
+) -> anyhow::Result<WorkTreeState> {
+    // A file behind a symlinked directory isn't the tracked file anymore.
+    if leads_through_symlink(work_tree, &entry.path) {
+        return Ok(WorkTreeState::Modified);
+    }
+    let path = platform::work_tree_path(work_tree, &entry.path);
+    let Some(metadata) = fs::symlink_metadata(&path).ok().filter(|x| !x.is_dir()) else {
+        return Ok(WorkTreeState::Modified);
+    };
+    let filesystem = Filesystem::of(git_dir);
+    if platform::work_tree_mode(filesystem, &metadata, Some(entry.mode)) != entry.mode {
+        return Ok(WorkTreeState::Modified);
+    }
+    if stat_matches(entry, &metadata) {
+        return Ok(WorkTreeState::Unchanged);
+    }
+
+    let content = work_tree_content(git_dir, work_tree, &entry.path, &metadata)?;
+    match ObjectFormat::of(git_dir)?.digest(&encode_object("blob", &content)) == entry.hash {
+        true => Ok(WorkTreeState::Restat(metadata)),
+        false => Ok(WorkTreeState::Modified),
+    }
+}
+
+/// Whether the stat data in `metadata` still matches the index entry, in
+/// which case the file is taken to be unchanged without reading it: its
+/// size, modification and change times, inode and owner, which git checks
+/// by default (`core.checkStat`). The device isn't, as it can change
+/// across reboots for network filesystems.
+pub fn stat_matches(entry: &index::IndexEntry, metadata: &fs::Metadata) -> bool {
+    let current = index::IndexEntry::from_metadata(&entry.path, entry.mode, Vec::new(), metadata);
+    current.size == entry.size
+        && current.mtime_seconds == entry.mtime_seconds
+        && current.mtime_nanoseconds == entry.mtime_nanoseconds
+        && current.ctime_seconds == entry.ctime_seconds
+        && current.ctime_nanoseconds == entry.ctime_nanoseconds
+        && current.ino == entry.ino
+        && current.uid == entry.uid
+        && current.gid == entry.gid
+}
+
+/// The blob content a work tree file would be stored as: a symlink's target,
+/// or the file's content after the `.gitattributes` conversions.
+pub fn work_tree_content(
+    git_dir: &Path,
+    work_tree: &Path,
+    path: &str,
+    metadata: &fs::Metadata,
+) -> anyhow::Result<Vec<u8>> {
+    let full_path = platform::work_tree_path(work_tree, path);
+    if metadata.file_type().is_symlink() {
+        return Ok(platform::symlink_target(&fs::read_link(&full_path)?));
+    }
+    let attributes = attributes::attributes_for(work_tree, git_dir, path);
+    Ok(attributes::convert_to_git(
+        &attributes,
+        fs::read(&full_path)?,
+    ))
+}
+
+/// Collects `path -> (mode, id)` for every non-tree entry under a tree.
+pub fn flatten_tree(
+    git_dir: &Path,
+    tree_id: &str,
+    prefix: &str,
+    entries: &mut BTreeMap<String, (u32, Vec<u8>)>,
+) -> anyhow::Result<()> {
+    let Object::Tree(tree) = Repository::open(git_dir).object(tree_id)? else {
+        return Err(anyhow!("Object {} is not a tree.", tree_id));
+    };
+
+    for element in tree.elements {
+        let path = format!("{}{}", prefix, element.name);
+        if element.mode == "40000" {
+            flatten_tree(
+                git_dir,
+                &hex::encode(&element.hash),
+                &format!("{}/", path),
+                entries,
+            )?;
+            continue;
+        }
+        let mode = u32::from_str_radix(&element.mode, 8)?;
+        entries.insert(path, (canonical_mode(mode), element.hash));
+    }
+    Ok(())
+}
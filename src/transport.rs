@@ -0,0 +1,295 @@
+use crate::bundle::{self, Bundle};
+use crate::http::{DumbHttp, HttpTransport};
+use crate::local::{self, LocalRepository};
+use crate::pktline::{self, Packet, PktLines};
+use crate::smart::UploadPack;
+use crate::{progress, ssh, Progress};
+use anyhow::{anyhow, Context};
+use std::cell::RefCell;
+use std::env;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// A connection to a smart protocol service, `git-upload-pack` or
+/// `git-receive-pack`, on which the protocol's messages are exchanged, so
+/// fetching and pushing speak the same way over every transport.
+pub trait Transport {
+    /// Where the service runs, for messages.
+    fn url(&self) -> &str;
+
+    /// The packets the service started with: its capabilities, and for the
+    /// original protocol its refs.
+    fn advertisement(&self) -> &[u8];
+
+    /// Sends a request and returns the server's response, up to the flush
+    /// that ends it.
+    fn request(&self, request: &[u8]) -> anyhow::Result<Vec<u8>>;
+}
+
+/// Starts `service` on the repository at `url`: over SSH for `ssh://` and
+/// `[user@]host:path` URLs, over HTTP for `http(s)://` ones, or `None` when
+/// the HTTP server only serves plain files. For a `file://` URL this
+/// program's own `upload-pack` serves fetches, as git runs its own.
+pub fn open(url: &str, service: &str) -> anyhow::Result<Option<Box<dyn Transport>>> {
+    if let Some(ssh_url) = ssh::parse_url(url) {
+        let transport = ProcessTransport::spawn(url, service, ssh::command(&ssh_url, service)?)?;
+        return Ok(Some(Box::new(transport)));
+    }
+    if let Some(path) = url.strip_prefix("file://") {
+        if service != "git-upload-pack" {
+            return Err(anyhow!("Pushing to a local repository isn't supported."));
+        }
+        let mut command = Command::new(env::current_exe()?);
+        command
+            .arg("upload-pack")
+            .arg(path)
+            .env("GIT_PROTOCOL", "version=2");
+        let transport = ProcessTransport::spawn(url, service, command)?;
+        return Ok(Some(Box::new(transport)));
+    }
+    if url.starts_with("http://") || url.starts_with("https://") {
+        let transport = HttpTransport::connect(url, service)?;
+        return Ok(transport.map(|x| Box::new(x) as Box<dyn Transport>));
+    }
+    match url.contains("://") {
+        true => Err(anyhow!(
+            "'{}' isn't a URL this client can reach: it speaks HTTP(S) and SSH.",
+            url
+        )),
+        false => Err(anyhow!("repository '{}' does not exist", url)),
+    }
+}
+
+/// A service run as a child process, by ssh on another host or on this one,
+/// speaking over the process's stdin and stdout. Its stderr is left to the
+/// terminal, where ssh asks for passwords and the remote's errors show.
+pub struct ProcessTransport {
+    url: String,
+    service: String,
+    child: Child,
+    stdin: RefCell<Option<ChildStdin>>,
+    stdout: RefCell<ChildStdout>,
+    advertisement: Vec<u8>,
+}
+
+impl ProcessTransport {
+    #[tracing::instrument(skip(command))]
+    pub fn spawn(
+        url: &str,
+        service: &str,
+        mut command: Command,
+    ) -> anyhow::Result<ProcessTransport> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Run {}.", command.get_program().to_string_lossy()))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let advertisement = pktline::read_message(&mut stdout)?;
+        if advertisement.is_empty() {
+            return Err(anyhow!(
+                "Could not read from remote repository {}: the remote end hung up.",
+                url
+            ));
+        }
+        if let Some(Packet::Data(line)) = PktLines::new(&advertisement).next()? {
+            if let Some(error) = line.strip_prefix(b"ERR ") {
+                return Err(anyhow!("The remote failed: {}", pktline::text(error)));
+            }
+        }
+        Ok(ProcessTransport {
+            url: url.to_string(),
+            service: service.to_string(),
+            child,
+            stdin: RefCell::new(Some(stdin)),
+            stdout: RefCell::new(stdout),
+            advertisement,
+        })
+    }
+}
+
+impl Transport for ProcessTransport {
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn advertisement(&self) -> &[u8] {
+        &self.advertisement
+    }
+
+    fn request(&self, request: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut stdin = self.stdin.borrow_mut();
+        let stdin = stdin
+            .as_mut()
+            .ok_or_else(|| anyhow!("The connection to {} is closed.", self.url))?;
+        stdin
+            .write_all(request)
+            .and_then(|_| stdin.flush())
+            .with_context(|| format!("Send to {}.", self.url))?;
+        let mut stdout = self.stdout.borrow_mut();
+        let response = match self.service.as_str() {
+            "git-upload-pack" => {
+                let progress = Progress::delayed("Receiving objects", None);
+                let mut reader = progress::Reader::new(&mut *stdout, progress);
+                let response = pktline::read_message(&mut reader)?;
+                reader.finish();
+                response
+            }
+            _ => pktline::read_message(&mut *stdout)?,
+        };
+        if response.is_empty() {
+            return Err(anyhow!("The remote end hung up unexpectedly."));
+        }
+        Ok(response)
+    }
+}
+
+impl Drop for ProcessTransport {
+    /// Ends the session with a flush, which services take as the end of
+    /// the client's requests, and waits for the process to exit.
+    fn drop(&mut self) {
+        if let Some(mut stdin) = self.stdin.get_mut().take() {
+            let _ = stdin.write_all(b"0000");
+        }
+        let _ = self.child.wait();
+    }
+}
+
+/// A repository fetched from, by whichever protocol its server speaks.
+pub trait Remote {
+    /// The advertised branches and tags as `(name, object id)`.
+    fn refs(&self) -> anyhow::Result<Vec<(String, String)>>;
+
+    /// The branch the remote `HEAD` points to, if it's a symbolic ref.
+    fn head(&self) -> anyhow::Result<Option<String>>;
+
+    /// Downloads every object reachable from `wants` into `git_dir`, except
+    /// those reachable from `haves`, local commits whose history is complete.
+    /// With a `depth`, only that many commits of each want's history are
+    /// fetched, and the commits at the cut are recorded as shallow.
+    fn fetch(
+        &self,
+        git_dir: &Path,
+        wants: &[String],
+        haves: &[String],
+        depth: Option<u32>,
+    ) -> anyhow::Result<FetchStats>;
+}
+
+/// Connects to the repository at `url`: a local one is read directly, as is
+/// a bundle file, a remote one over the smart protocol when the server
+/// offers it, as plain files otherwise.
+pub fn connect(url: &str) -> anyhow::Result<Box<dyn Remote>> {
+    if let Some(path) = local::local_path(url) {
+        if bundle::is_bundle(&path) {
+            return Ok(Box::new(Bundle::open(&path)?));
+        }
+        return Ok(Box::new(LocalRepository::open(&path)?));
+    }
+    match open(url, "git-upload-pack")? {
+        Some(transport) => Ok(Box::new(UploadPack::new(transport)?)),
+        None => Ok(Box::new(DumbHttp::new(url))),
+    }
+}
+
+impl Remote for DumbHttp {
+    fn refs(&self) -> anyhow::Result<Vec<(String, String)>> {
+        DumbHttp::refs(self)
+    }
+
+    fn head(&self) -> anyhow::Result<Option<String>> {
+        DumbHttp::head(self)
+    }
+
+    fn fetch(
+        &self,
+        git_dir: &Path,
+        wants: &[String],
+        haves: &[String],
+        depth: Option<u32>,
+    ) -> anyhow::Result<FetchStats> {
+        if depth.is_some() {
+            return Err(anyhow!(
+                "dumb http transport does not support shallow capabilities"
+            ));
+        }
+        DumbHttp::fetch(self, git_dir, wants, haves)
+    }
+}
+
+impl Remote for LocalRepository {
+    fn refs(&self) -> anyhow::Result<Vec<(String, String)>> {
+        LocalRepository::refs(self)
+    }
+
+    fn head(&self) -> anyhow::Result<Option<String>> {
+        LocalRepository::head(self)
+    }
+
+    fn fetch(
+        &self,
+        git_dir: &Path,
+        wants: &[String],
+        haves: &[String],
+        depth: Option<u32>,
+    ) -> anyhow::Result<FetchStats> {
+        if depth.is_some() {
+            return Err(anyhow!(
+                "Shallow fetches from a local repository aren't supported."
+            ));
+        }
+        LocalRepository::fetch(self, git_dir, wants, haves)
+    }
+}
+
+impl Remote for Bundle {
+    fn refs(&self) -> anyhow::Result<Vec<(String, String)>> {
+        Ok(Bundle::refs(self))
+    }
+
+    fn head(&self) -> anyhow::Result<Option<String>> {
+        Ok(Bundle::head(self))
+    }
+
+    fn fetch(
+        &self,
+        git_dir: &Path,
+        wants: &[String],
+        _haves: &[String],
+        depth: Option<u32>,
+    ) -> anyhow::Result<FetchStats> {
+        if depth.is_some() {
+            return Err(anyhow!("A bundle's history can't be cut short."));
+        }
+        Bundle::fetch(self, git_dir, wants)
+    }
+}
+
+impl Remote for UploadPack {
+    fn refs(&self) -> anyhow::Result<Vec<(String, String)>> {
+        Ok(UploadPack::refs(self))
+    }
+
+    fn head(&self) -> anyhow::Result<Option<String>> {
+        Ok(UploadPack::head(self))
+    }
+
+    fn fetch(
+        &self,
+        git_dir: &Path,
+        wants: &[String],
+        haves: &[String],
+        depth: Option<u32>,
+    ) -> anyhow::Result<FetchStats> {
+        UploadPack::fetch(self, git_dir, wants, haves, depth)
+    }
+}
+
+/// What a fetch had to download.
+#[derive(Debug, Default)]
+pub struct FetchStats {
+    pub objects: usize,
+    pub packs: usize,
+}
@@ -0,0 +1,333 @@
+use crate::pack_objects::reachable;
+use crate::pktline::{self, Packet, PktLines, PktWriter, MAX_PACKET_LEN};
+use crate::smart::AGENT;
+use crate::{header_field, list_refs, log, pack, shallow, Repository};
+use crate::{read_symref, resolve_ref, ObjectType};
+use anyhow::{anyhow, Context};
+use std::collections::{HashSet, VecDeque};
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Serves fetches from the repository at `git_dir`, like `git upload-pack`:
+/// the server end of protocol version 2, whose requests arrive on `input`
+/// and responses go to `output`, as when run by ssh or a local `clone`.
+///
+/// The capabilities are advertised first, then each request runs a command,
+/// `ls-refs` or `fetch`, until the client sends a lone flush or hangs up.
+/// A failure is sent to the client as an `ERR` packet too.
+pub fn upload_pack(
+    git_dir: &Path,
+    mut input: impl Read,
+    mut output: impl Write,
+) -> anyhow::Result<()> {
+    // The original protocol starts with the refs, which this server doesn't
+    // speak, so it says why instead.
+    let version_2 =
+        std::env::var("GIT_PROTOCOL").is_ok_and(|x| x.split(':').any(|x| x == "version=2"));
+    if !version_2 {
+        let message = "upload-pack: only protocol version 2 is supported";
+        send_error(&mut output, message)?;
+        return Err(anyhow!(message));
+    }
+    if let Err(err) = pack::require_sha1(git_dir) {
+        send_error(&mut output, &err.to_string())?;
+        return Err(err);
+    }
+
+    let mut advertisement = PktWriter::new();
+    for capability in [
+        "version 2",
+        &format!("agent={}", AGENT),
+        "ls-refs",
+        "fetch=shallow",
+        "object-format=sha1",
+    ] {
+        advertisement.line(capability)?;
+    }
+    advertisement.flush();
+    send(&mut output, &advertisement.into_bytes())?;
+
+    loop {
+        let message = pktline::read_message(&mut input)?;
+        let request = match Request::parse(&message)? {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+        let response = match request.command.as_str() {
+            "ls-refs" => ls_refs(git_dir, &request.arguments),
+            "fetch" => fetch(git_dir, &request.arguments),
+            command => Err(anyhow!("unknown command '{}'", command)),
+        };
+        match response {
+            Result::Ok(response) => send(&mut output, &response)?,
+            Err(err) => {
+                send_error(&mut output, &format!("upload-pack: {:#}", err))?;
+                return Err(err);
+            }
+        }
+    }
+}
+
+/// A client's request: `command=<name>` and capabilities, a delimiter, and
+/// the command's arguments, up to a flush.
+struct Request {
+    command: String,
+    arguments: Vec<String>,
+}
+
+impl Request {
+    /// Parses a request, or returns `None` for the flush or end of input
+    /// that ends the session.
+    fn parse(message: &[u8]) -> anyhow::Result<Option<Request>> {
+        let mut lines = PktLines::new(message);
+        let mut command = None;
+        let mut arguments = Vec::new();
+        let mut in_arguments = false;
+        while let Some(packet) = lines.next()? {
+            match packet {
+                Packet::Data(line) if in_arguments => arguments.push(pktline::text(line)),
+                Packet::Data(line) => {
+                    if let Some(name) = pktline::text(line).strip_prefix("command=") {
+                        command = Some(name.to_string());
+                    }
+                }
+                Packet::Delim => in_arguments = true,
+                Packet::Flush | Packet::ResponseEnd => break,
+            }
+        }
+        match command {
+            Some(command) => Ok(Some(Request { command, arguments })),
+            None if in_arguments || !arguments.is_empty() => {
+                Err(anyhow!("The request names no command."))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Lists `HEAD` and the refs under the prefixes asked for, or all of them:
+/// `<id> <name>`, with `symref-target:<ref>` for `HEAD` when `symrefs` is
+/// asked for, and `peeled:<id>` for annotated tags with `peel`.
+fn ls_refs(git_dir: &Path, arguments: &[String]) -> anyhow::Result<Vec<u8>> {
+    let symrefs = arguments.iter().any(|x| x == "symrefs");
+    let peel = arguments.iter().any(|x| x == "peel");
+    let prefixes: Vec<&str> = arguments
+        .iter()
+        .filter_map(|x| x.strip_prefix("ref-prefix "))
+        .collect();
+    let wanted = |name: &str| prefixes.is_empty() || prefixes.iter().any(|x| name.starts_with(x));
+
+    let mut response = PktWriter::new();
+    if wanted("HEAD") {
+        if let Some(head) = resolve_ref(git_dir, "HEAD")? {
+            let mut line = format!("{} HEAD", head);
+            if let Some(target) = read_symref(git_dir, "HEAD")?.filter(|_| symrefs) {
+                line.push_str(&format!(" symref-target:{}", target));
+            }
+            response.line(&line)?;
+        }
+    }
+    for (name, object_id) in list_refs(git_dir)? {
+        if !wanted(&name) {
+            continue;
+        }
+        let mut line = format!("{} {}", object_id, name);
+        if peel {
+            if let (ObjectType::Tag, content) = Repository::open(git_dir).read(&object_id)? {
+                if let Some(target) = header_field(&content, "object") {
+                    line.push_str(&format!(" peeled:{}", target));
+                }
+            }
+        }
+        response.line(&line)?;
+    }
+    response.flush();
+    Ok(response.into_bytes())
+}
+
+/// Answers a round of `fetch` negotiation. Until the client is `done`, the
+/// haves this repository has are acknowledged, and once any is common the
+/// server is `ready` and sends the pack: everything reachable from the
+/// wants but not from the common commits.
+///
+/// With `deepen <n>` the history sent stops `n` commits below the wants,
+/// and `shallow-info` lists the commits at the cut, along with the client's
+/// shallow commits whose parents now come too. The client's shallow commits
+/// are treated as roots when working out what it has.
+fn fetch(git_dir: &Path, arguments: &[String]) -> anyhow::Result<Vec<u8>> {
+    let repository = Repository::open(git_dir);
+    let mut wants = Vec::new();
+    let mut haves = Vec::new();
+    let mut client_shallow = HashSet::new();
+    let mut depth = None;
+    let mut done = false;
+    let mut include_tag = false;
+    for argument in arguments {
+        let (name, value) = argument.split_once(' ').unwrap_or((argument, ""));
+        match name {
+            "want" => wants.push(value.to_string()),
+            "have" => haves.push(value.to_string()),
+            "shallow" => {
+                client_shallow.insert(value.to_string());
+            }
+            "deepen" => {
+                let n: usize = value
+                    .parse()
+                    .ok()
+                    .filter(|x| *x > 0)
+                    .ok_or_else(|| anyhow!("invalid deepen: {}", value))?;
+                depth = Some(n);
+            }
+            "done" => done = true,
+            "include-tag" => include_tag = true,
+            "ofs-delta" | "no-progress" | "thin-pack" | "wait-for-done" => {}
+            _ => return Err(anyhow!("unexpected line: '{}'", argument)),
+        }
+    }
+    for want in &wants {
+        if !repository.exists(want)? {
+            return Err(anyhow!("not our ref {}", want));
+        }
+    }
+
+    let mut common = Vec::new();
+    for have in haves {
+        if !common.contains(&have) && repository.exists(&have)? {
+            common.push(have);
+        }
+    }
+    let mut response = PktWriter::new();
+    if !done {
+        response.line("acknowledgments")?;
+        if common.is_empty() {
+            response.line("NAK")?;
+            response.flush();
+            return Ok(response.into_bytes());
+        }
+        for id in &common {
+            response.line(&format!("ACK {}", id))?;
+        }
+        response.line("ready")?;
+        response.delim();
+    }
+
+    // What the client has stops at its shallow commits.
+    let known = reachable(git_dir, &common, &HashSet::new(), &client_shallow)?;
+    let (mut tips, cut, unshallow) = match depth {
+        Some(depth) => {
+            let (commits, cut) = deepen(git_dir, &wants, depth)?;
+            let mut tips = wants.clone();
+            let mut unshallow = Vec::new();
+            // Commits no longer at the cut need their parents sent.
+            for id in client_shallow.iter().filter(|x| commits.contains(*x)) {
+                let parents = log::read_commit(git_dir, id)?.parents;
+                if !cut.contains(id) && !parents.is_empty() {
+                    tips.extend(parents);
+                    unshallow.push(id.clone());
+                }
+            }
+            (tips, cut, unshallow)
+        }
+        None => (wants.clone(), client_shallow.clone(), Vec::new()),
+    };
+    tips.sort();
+    tips.dedup();
+    let mut objects = reachable(git_dir, &tips, &known, &cut)?;
+    if include_tag {
+        for (name, object_id) in list_refs(git_dir)? {
+            if !name.starts_with("refs/tags/") || objects.contains(&object_id) {
+                continue;
+            }
+            if let (ObjectType::Tag, content) = repository.read(&object_id)? {
+                if header_field(&content, "object").is_some_and(|x| objects.contains(&x)) {
+                    objects.insert(object_id);
+                }
+            }
+        }
+    }
+
+    // The repository's own shallow commits are shallow for the client too.
+    let repository_shallow = shallow::read(git_dir)?;
+    let mut new_shallow: Vec<&String> = cut
+        .iter()
+        .chain(repository_shallow.iter().filter(|x| objects.contains(*x)))
+        .filter(|x| !client_shallow.contains(*x))
+        .collect();
+    new_shallow.sort();
+    new_shallow.dedup();
+    let mut unshallow = unshallow;
+    unshallow.sort();
+    if depth.is_some() || !client_shallow.is_empty() || !repository_shallow.is_empty() {
+        response.line("shallow-info")?;
+        for id in new_shallow {
+            response.line(&format!("shallow {}", id))?;
+        }
+        for id in unshallow {
+            response.line(&format!("unshallow {}", id))?;
+        }
+        response.delim();
+    }
+
+    let objects: Vec<String> = objects.into_iter().collect();
+    let mut data = Vec::new();
+    pack::write_pack(git_dir, &objects, &mut data).context("Write the pack.")?;
+    response.line("packfile")?;
+    for chunk in data.chunks(MAX_PACKET_LEN - 5) {
+        let mut packet = Vec::with_capacity(chunk.len() + 1);
+        packet.push(1);
+        packet.extend_from_slice(chunk);
+        response.data(&packet)?;
+    }
+    response.flush();
+    Ok(response.into_bytes())
+}
+
+/// The commits at most `depth` commits below `wants`, the wants being the
+/// first, and of those the ones at the cut: `depth` below, with parents
+/// that aren't sent.
+fn deepen(
+    git_dir: &Path,
+    wants: &[String],
+    depth: usize,
+) -> anyhow::Result<(HashSet<String>, HashSet<String>)> {
+    let mut commits = HashSet::new();
+    let mut cut = HashSet::new();
+    let mut queue = VecDeque::new();
+    for want in wants {
+        // Tags and other objects count from the commit they name.
+        if let Result::Ok(commit) = Repository::open(git_dir).peel_to(want, ObjectType::Commit) {
+            if commits.insert(commit.clone()) {
+                queue.push_back((commit, 1));
+            }
+        }
+    }
+    // Breadth first, so each commit is reached by its shortest path.
+    while let Some((id, level)) = queue.pop_front() {
+        let parents = log::read_commit(git_dir, &id)?.parents;
+        if level == depth {
+            if !parents.is_empty() {
+                cut.insert(id);
+            }
+            continue;
+        }
+        for parent in parents {
+            if commits.insert(parent.clone()) {
+                queue.push_back((parent, level + 1));
+            }
+        }
+    }
+    Ok((commits, cut))
+}
+
+fn send(output: &mut impl Write, data: &[u8]) -> anyhow::Result<()> {
+    output
+        .write_all(data)
+        .and_then(|_| output.flush())
+        .context("Write to the client.")
+}
+
+fn send_error(output: &mut impl Write, message: &str) -> anyhow::Result<()> {
+    let mut packet = PktWriter::new();
+    packet.line(&format!("ERR {}", message))?;
+    send(output, &packet.into_bytes())
+}
@@ -0,0 +1,63 @@
+use crate::pack;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Checks the pack `path` names, by its `.idx`, its `.pack` or neither
+/// extension, against its index, like `git verify-pack`.
+///
+/// With `verbose`, each object is listed in pack order as `<id> <type>
+/// <size> <size in pack> <offset>`, with the chain depth and base of
+/// deltas, whose size is the delta's; a histogram of delta chain lengths
+/// and `<pack>: ok` follow. `stat_only` prints just the histogram.
+pub fn verify_pack(path: &Path, verbose: bool, stat_only: bool) -> anyhow::Result<()> {
+    let pack_path = path.with_extension("pack");
+    let objects = pack::verify_pack(&pack_path, &path.with_extension("idx"))?;
+    if !verbose && !stat_only {
+        return Ok(());
+    }
+
+    let bases: HashMap<[u8; 20], [u8; 20]> = objects
+        .iter()
+        .filter_map(|x| Some((x.id, x.base?)))
+        .collect();
+    let depth = |mut id: [u8; 20]| {
+        let mut depth = 0;
+        while let Some(base) = bases.get(&id) {
+            depth += 1;
+            id = *base;
+        }
+        depth
+    };
+    let mut chains = BTreeMap::new();
+    for object in &objects {
+        let depth = depth(object.id);
+        *chains.entry(depth).or_insert(0) += 1;
+        if stat_only {
+            continue;
+        }
+        let mut line = format!(
+            "{} {:<6} {} {} {}",
+            hex::encode(object.id),
+            object.object_type.to_string(),
+            object.stored_size,
+            object.packed_size,
+            object.offset
+        );
+        if let Some(base) = object.base {
+            line.push_str(&format!(" {} {}", depth, hex::encode(base)));
+        }
+        println!("{}", line);
+    }
+
+    let plural = |count: usize| if count == 1 { "object" } else { "objects" };
+    for (depth, count) in chains {
+        match depth {
+            0 => println!("non delta: {} {}", count, plural(count)),
+            _ => println!("chain length = {}: {} {}", depth, count, plural(count)),
+        }
+    }
+    if !stat_only {
+        println!("{}: ok", pack_path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,86 @@
+/// Matches `text` against a git glob pattern, as used by gitignore and gitattributes.
+///
+/// `*` and `?` never match a `/`, while `**` matches across directories; a
+/// `**/` can also match no directories at all (`a/**/b` matches `a/b`).
+/// Character classes (`[abc]`, `[a-z]`, `[!abc]`) and `\` escapes are supported.
+pub fn wildmatch(pattern: &str, text: &str) -> bool {
+    matches(pattern.as_bytes(), text.as_bytes())
+}
+
+fn matches(pattern: &[u8], text: &[u8]) -> bool {
+    let Some((&first, rest)) = pattern.split_first() else {
+        return text.is_empty();
+    };
+
+    match first {
+        b'*' if rest.first() == Some(&b'*') => {
+            let rest = &rest[1..];
+            // `**/` may stand for zero directories.
+            if rest.first() == Some(&b'/') && matches(&rest[1..], text) {
+                return true;
+            }
+            (0..=text.len()).any(|i| matches(rest, &text[i..]))
+        }
+        b'*' => {
+            let limit = text.iter().position(|&x| x == b'/').unwrap_or(text.len());
+            (0..=limit).any(|i| matches(rest, &text[i..]))
+        }
+        b'?' => match text.split_first() {
+            Some((&c, text)) if c != b'/' => matches(rest, text),
+            _ => false,
+        },
+        b'[' => {
+            let Some((&c, text)) = text.split_first() else {
+                return false;
+            };
+            match match_class(rest, c) {
+                Some((true, rest)) if c != b'/' => matches(rest, text),
+                Some(_) => false,
+                // An unterminated class matches a literal `[`.
+                None => c == b'[' && matches(rest, text),
+            }
+        }
+        b'\\' if !rest.is_empty() => match text.split_first() {
+            Some((&c, text)) if c == rest[0] => matches(&rest[1..], text),
+            _ => false,
+        },
+        _ => match text.split_first() {
+            Some((&c, text)) if c == first => matches(rest, text),
+            _ => false,
+        },
+    }
+}
+
+/// Matches `c` against a class body (the part after `[`), returning whether it
+/// matched and the pattern after the closing `]`.
+fn match_class(class: &[u8], c: u8) -> Option<(bool, &[u8])> {
+    let (negated, mut class) = match class.first() {
+        Some(b'!') | Some(b'^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let (&start, rest) = class.split_first()?;
+        // A `]` right after the opening bracket is a literal.
+        if start == b']' && !first {
+            return Some((matched != negated, rest));
+        }
+        first = false;
+
+        let (start, rest) = match (start, rest.split_first()) {
+            (b'\\', Some((&escaped, rest))) => (escaped, rest),
+            _ => (start, rest),
+        };
+
+        if rest.len() >= 2 && rest[0] == b'-' && rest[1] != b']' {
+            let end = rest[1];
+            matched |= start <= c && c <= end;
+            class = &rest[2..];
+        } else {
+            matched |= start == c;
+            class = rest;
+        }
+    }
+}
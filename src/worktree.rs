@@ -0,0 +1,215 @@
+use crate::checkout::checkout_tree;
+use crate::RefLock;
+use crate::{common_dir, create_branch, diff, index, read_symref, record_ref_update, Repository};
+use crate::{git_path, resolve_ref, resolve_rev, short_id, status};
+use crate::{CommitObject, ObjectType};
+use anyhow::{anyhow, Context};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The worktrees of the repository `git_dir` belongs to, as `(path, git
+/// directory)`: the main one, the directory containing the common git
+/// directory, then every linked one registered under `worktrees/`, by name.
+fn worktrees(git_dir: &Path) -> anyhow::Result<Vec<(PathBuf, PathBuf)>> {
+    let common = common_dir(git_dir);
+    let common = common.canonicalize().unwrap_or(common);
+
+    let mut worktrees: Vec<(PathBuf, PathBuf)> = Vec::new();
+    if let Some(parent) = common.parent() {
+        worktrees.push((parent.to_path_buf(), common.clone()));
+    }
+
+    if let Result::Ok(entries) = fs::read_dir(common.join("worktrees")) {
+        let mut entries: Vec<PathBuf> = entries.filter_map(|x| x.ok()).map(|x| x.path()).collect();
+        entries.sort();
+
+        for worktree_git_dir in entries {
+            let gitdir_file = fs::read_to_string(worktree_git_dir.join("gitdir"))
+                .context("Read worktree gitdir file.")?;
+            let dot_git = PathBuf::from(gitdir_file.trim_end());
+            let path = dot_git.parent().map(Path::to_path_buf).unwrap_or(dot_git);
+            worktrees.push((path, worktree_git_dir));
+        }
+    }
+    Ok(worktrees)
+}
+
+pub fn list(git_dir: &Path) -> anyhow::Result<()> {
+    let worktrees = worktrees(git_dir)?;
+    let width = worktrees
+        .iter()
+        .map(|(path, _)| path.display().to_string().len())
+        .max()
+        .unwrap_or(0);
+
+    for (path, worktree_git_dir) in worktrees {
+        let head = fs::read_to_string(worktree_git_dir.join("HEAD")).unwrap_or_default();
+        let head = head.trim_end();
+        let object_id = resolve_ref(&worktree_git_dir, "HEAD")?.unwrap_or_default();
+        let short_id: String = object_id.chars().take(7).collect();
+
+        let branch = match head.strip_prefix("ref: refs/heads/") {
+            Some(branch) => format!("[{}]", branch),
+            None => "(detached HEAD)".to_string(),
+        };
+
+        println!(
+            "{:<width$} {:<7} {}",
+            path.display().to_string(),
+            short_id,
+            branch,
+            width = width + 1
+        );
+    }
+
+    Ok(())
+}
+
+/// How `add` sets up the new worktree's `HEAD`.
+pub enum NewHead<'a> {
+    /// On a new branch of this name, started at the commit.
+    NewBranch(&'a str),
+    /// Detached at the commit, even if a branch was named.
+    Detach,
+    /// On the branch named, if it is one, or else detached.
+    Checkout,
+}
+
+/// Creates a linked worktree at `path`, like `git worktree add`: its `.git`
+/// is a file pointing to `worktrees/<name>` in the common git directory,
+/// which holds the worktree's own `HEAD`, index and reflog, while objects,
+/// refs and config stay shared. `rev` defaults to `HEAD`; without one, a
+/// new branch named after the directory is checked out.
+///
+/// A branch can only be checked out in one worktree at a time.
+pub fn add(git_dir: &Path, path: &Path, rev: Option<&str>, head: NewHead) -> anyhow::Result<()> {
+    if path.exists() && !fs::read_dir(path).is_ok_and(|mut x| x.next().is_none()) {
+        return Err(anyhow!("'{}' already exists.", path.display()));
+    }
+    let base_name = path
+        .file_name()
+        .and_then(|x| x.to_str())
+        .ok_or_else(|| anyhow!("'{}' names no directory.", path.display()))?;
+    let head = match (rev, head) {
+        (None, NewHead::Checkout) => NewHead::NewBranch(base_name),
+        (_, head) => head,
+    };
+    let rev = rev.unwrap_or("HEAD");
+    let repository = Repository::open(git_dir);
+    let commit_id = resolve_rev(git_dir, rev)
+        .and_then(|x| Ok(repository.peel_to(&x, ObjectType::Commit)?))
+        .with_context(|| format!("Invalid reference: {}", rev))?;
+    let branch = match head {
+        NewHead::NewBranch(name) => {
+            create_branch(git_dir, name, Some(rev))?;
+            info!("Preparing worktree (new branch '{}')", name);
+            Some(format!("refs/heads/{}", name))
+        }
+        NewHead::Checkout if resolve_ref(git_dir, &format!("refs/heads/{}", rev))?.is_some() => {
+            let branch = format!("refs/heads/{}", rev);
+            for (other, other_git_dir) in worktrees(git_dir)? {
+                if read_symref(&other_git_dir, "HEAD")?.as_deref() == Some(branch.as_str()) {
+                    return Err(anyhow!(
+                        "'{}' is already checked out at '{}'",
+                        rev,
+                        other.display()
+                    ));
+                }
+            }
+            info!("Preparing worktree (checking out '{}')", rev);
+            Some(branch)
+        }
+        _ => {
+            info!(
+                "Preparing worktree (detached HEAD {})",
+                short_id(&commit_id)
+            );
+            None
+        }
+    };
+
+    // Like git, a name already taken gets a number appended.
+    let worktrees_dir = git_path(git_dir, "worktrees");
+    let mut name = base_name.to_string();
+    let mut n = 1;
+    while worktrees_dir.join(&name).exists() {
+        name = format!("{}{}", base_name, n);
+        n += 1;
+    }
+    let worktree_git_dir = worktrees_dir.join(&name);
+    fs::create_dir_all(&worktree_git_dir)
+        .with_context(|| format!("Create {}.", worktree_git_dir.display()))?;
+    fs::create_dir_all(path).with_context(|| format!("Create {}.", path.display()))?;
+    let path = path.canonicalize()?;
+    let worktree_git_dir = worktree_git_dir.canonicalize()?;
+    fs::write(
+        path.join(".git"),
+        format!("gitdir: {}\n", worktree_git_dir.display()),
+    )?;
+    fs::write(worktree_git_dir.join("commondir"), "../..\n")?;
+    fs::write(
+        worktree_git_dir.join("gitdir"),
+        format!("{}\n", path.join(".git").display()),
+    )?;
+
+    let head_lock = RefLock::acquire(&worktree_git_dir, "HEAD")?;
+    match &branch {
+        Some(branch) => head_lock.commit_symref(branch)?,
+        None => head_lock.commit(&commit_id)?,
+    }
+    record_ref_update(
+        &worktree_git_dir,
+        "HEAD",
+        None,
+        &commit_id,
+        &format!("worktree add: {}", rev),
+    )?;
+    let (_, content) = repository.read(&commit_id)?;
+    let commit = CommitObject::from_content(&content)?;
+    checkout_tree(&worktree_git_dir, &path, &commit.tree)?;
+    let subject = commit.message.lines().next().unwrap_or_default();
+    info!("HEAD is now at {} {}", short_id(&commit_id), subject);
+    Ok(())
+}
+
+/// Deletes the linked worktree at `path` and its administrative files, like
+/// `git worktree remove`. Unless `force`d, one with changes, staged or not,
+/// or untracked files is kept, as they would be lost.
+pub fn remove(git_dir: &Path, path: &Path, force: bool) -> anyhow::Result<()> {
+    let target = path.canonicalize().ok();
+    let mut worktrees = worktrees(git_dir)?.into_iter();
+    let (main, _) = worktrees
+        .next()
+        .ok_or_else(|| anyhow!("The repository has no worktree."))?;
+    if target.as_ref() == Some(&main) {
+        return Err(anyhow!("'{}' is a main working tree", path.display()));
+    }
+    let Some((work_tree, worktree_git_dir)) = worktrees.find(|(x, _)| target.as_ref() == Some(x))
+    else {
+        return Err(anyhow!("'{}' is not a working tree", path.display()));
+    };
+
+    if !force && work_tree.exists() {
+        let index = index::Index::load(&git_path(&worktree_git_dir, "index"))?;
+        let dirty = !diff::diff_head_to_index(&worktree_git_dir)?.is_empty()
+            || !diff::diff_index_to_work_tree(
+                &worktree_git_dir,
+                &work_tree,
+                &mut Default::default(),
+            )?
+            .is_empty()
+            || !status::untracked_files(&worktree_git_dir, &work_tree, &index).is_empty();
+        if dirty {
+            return Err(anyhow!(
+                "'{}' contains modified or untracked files, use --force to delete it",
+                path.display()
+            ));
+        }
+    }
+    if work_tree.exists() {
+        fs::remove_dir_all(&work_tree)
+            .with_context(|| format!("Remove {}.", work_tree.display()))?;
+    }
+    fs::remove_dir_all(&worktree_git_dir)
+        .with_context(|| format!("Remove {}.", worktree_git_dir.display()))
+}
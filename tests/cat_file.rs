@@ -0,0 +1,140 @@
+mod common;
+
+use common::{text, Scratch};
+
+/// Replaces the loose object `id` with `bytes`.
+fn overwrite_object(scratch: &Scratch, id: &str, bytes: &[u8]) {
+    let path = scratch.path(&format!(".git/objects/{}/{}", &id[..2], &id[2..]));
+    let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    std::fs::set_permissions(&path, permissions).unwrap();
+    std::fs::write(path, bytes).unwrap();
+}
+
+#[test]
+fn truncated_objects_fail_cleanly() {
+    let scratch = Scratch::new("cat-file-truncated");
+    scratch.run(&["init", "-q", "."]);
+    scratch.write("a", "hello world\n");
+    let id = text(scratch.run(&["hash-object", "-w", "a"]));
+    let id = id.trim_end();
+    let stored =
+        std::fs::read(scratch.path(&format!(".git/objects/{}/{}", &id[..2], &id[2..]))).unwrap();
+
+    for len in [0, 1, 2, 10, stored.len() - 1] {
+        overwrite_object(&scratch, id, &stored[..len]);
+        for args in [["cat-file", "-p", id], ["ls-tree", id, "a"]] {
+            let output = scratch.output_in("", &args);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(!output.status.success(), "{:?} on {} bytes", args, len);
+            assert!(!stderr.contains("panicked"), "{:?}: {}", args, stderr);
+        }
+    }
+}
+
+#[test]
+fn objects_shorter_than_their_header_fail() {
+    let scratch = Scratch::new("cat-file-short");
+    scratch.run(&["init", "-q", "."]);
+    scratch.write("a", "hello world\n");
+    let id = text(scratch.run(&["hash-object", "-w", "a"]));
+    let id = id.trim_end();
+
+    // A complete zlib stream, holding less than the header says.
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), Default::default());
+    std::io::Write::write_all(&mut encoder, b"blob 12\0hello").unwrap();
+    overwrite_object(&scratch, id, &encoder.finish().unwrap());
+
+    let output = scratch.output_in("", &["cat-file", "-p", id]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("its header declares 12 bytes but 5 are stored"));
+}
+
+#[test]
+fn binary_blobs_round_trip_byte_for_byte() {
+    let scratch = Scratch::new("cat-file-binary");
+    scratch.run(&["init", "-q", "."]);
+    let content: Vec<u8> = [&[0xff, 0xfe, 0x00, b'\n', 0xff][..], &[0x80; 100]].concat();
+    scratch.write("bin", &content);
+
+    let id = text(scratch.run(&["hash-object", "-w", "bin"]));
+    assert_eq!(id, text(scratch.git(&["hash-object", "bin"])));
+    let id = id.trim_end();
+    assert_eq!(scratch.run(&["cat-file", "-p", id]), content);
+    assert_eq!(scratch.git(&["cat-file", "-p", id]), content);
+    assert_eq!(text(scratch.run(&["cat-file", "-s", id])), "105\n");
+
+    // Read back out of a pack too.
+    scratch.git(&["add", "bin"]);
+    scratch.git(&["commit", "-q", "-m", "bin"]);
+    scratch.git(&["repack", "-a", "-d", "-q"]);
+    scratch.git(&["prune-packed"]);
+    assert_eq!(scratch.run(&["cat-file", "-p", id]), content);
+}
+
+#[test]
+fn types_and_sizes_match_git_for_deltified_objects() {
+    let scratch = Scratch::new("cat-file-stat");
+    scratch.run(&["init", "-q", "."]);
+    let base: String = (0..200).map(|x| format!("line {}\n", x)).collect();
+    for i in 0..3 {
+        scratch.write("f", format!("{}edit {}\n", base, i));
+        scratch.git(&["add", "f"]);
+        scratch.git(&["commit", "-q", "-m", &format!("edit {}", i)]);
+    }
+    scratch.git(&["repack", "-a", "-d", "-q", "--depth=5", "--window=10"]);
+    scratch.git(&["prune-packed"]);
+    let verified = text(scratch.git(&["verify-pack", "-v", &pack_index(&scratch)]));
+    assert!(
+        verified.contains("chain length = "),
+        "no deltas: {}",
+        verified
+    );
+
+    let objects: String = text(scratch.git(&["rev-list", "--objects", "--all"]))
+        .lines()
+        .map(|x| format!("{}\n", &x[..40]))
+        .collect();
+    let batch = ["cat-file", "--batch-check"];
+    let git = text(scratch.git_with_input_in("", &batch, objects.as_bytes()));
+    assert_eq!(
+        text(scratch.run_with_input_in("", &batch, objects.as_bytes())),
+        git
+    );
+    for line in git.lines() {
+        let fields: Vec<&str> = line.split(' ').collect();
+        assert_eq!(
+            text(scratch.run(&["cat-file", "-t", fields[0]])),
+            format!("{}\n", fields[1])
+        );
+        assert_eq!(
+            text(scratch.run(&["cat-file", "-s", fields[0]])),
+            format!("{}\n", fields[2])
+        );
+    }
+}
+
+/// The index of the only pack in the repository.
+fn pack_index(scratch: &Scratch) -> String {
+    let pack_dir = scratch.path(".git/objects/pack");
+    let idx = std::fs::read_dir(&pack_dir)
+        .unwrap()
+        .map(|x| x.unwrap().path())
+        .find(|x| x.extension().is_some_and(|x| x == "idx"))
+        .unwrap();
+    idx.to_str().unwrap().to_string()
+}
+
+#[test]
+fn hash_object_takes_stdin_or_files_not_both() {
+    let scratch = Scratch::new("hash-object-stdin");
+    scratch.run(&["init", "-q", "."]);
+    scratch.write("a", "hello world\n");
+
+    let ours = scratch.run_with_input_in("", &["hash-object", "--stdin"], b"hello world\n");
+    assert_eq!(text(ours), text(scratch.git(&["hash-object", "a"])));
+    let output = scratch.output_in("", &["hash-object", "--stdin", "a"]);
+    assert!(!output.status.success());
+}
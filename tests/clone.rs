@@ -0,0 +1,22 @@
+mod common;
+
+use common::{text, Scratch};
+
+#[test]
+fn a_failed_clone_leaves_nothing_behind() {
+    let scratch = Scratch::new("clone-failed");
+    let missing = scratch.path("missing");
+    let missing = missing.to_str().unwrap();
+
+    for _ in 0..2 {
+        let output = scratch.output_in("", &["clone", missing, "dd"]);
+        assert!(!output.status.success());
+        assert!(!text(output.stderr).contains("already exists"));
+        assert!(!scratch.path("dd").exists());
+    }
+
+    std::fs::create_dir(scratch.path("empty")).unwrap();
+    let output = scratch.output_in("", &["clone", missing, "empty"]);
+    assert!(!output.status.success());
+    assert_eq!(std::fs::read_dir(scratch.path("empty")).unwrap().count(), 0);
+}
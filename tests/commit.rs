@@ -0,0 +1,19 @@
+mod common;
+
+use common::{text, Scratch};
+
+#[test]
+fn quiet_commits_print_no_summary() {
+    let scratch = Scratch::new("commit-quiet");
+    scratch.run(&["init", "-q", "."]);
+    scratch.write("a", "hi\n");
+    scratch.run(&["add", "a"]);
+    let summary = text(scratch.run(&["commit", "-m", "first"]));
+    assert!(summary.starts_with("[main (root-commit) "), "{}", summary);
+    assert!(summary.ends_with("] first\n"), "{}", summary);
+
+    scratch.write("a", "again\n");
+    scratch.run(&["add", "a"]);
+    assert_eq!(text(scratch.run(&["commit", "-q", "-m", "second"])), "");
+    assert_eq!(text(scratch.run(&["log", "--oneline"])).lines().count(), 2);
+}
@@ -0,0 +1,199 @@
+//! Helpers for the integration tests, which run the built binary and the
+//! system `git` side by side in throwaway directories.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// How long any one command may run.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A throwaway directory in the system temp directory, removed on drop.
+pub struct Scratch {
+    pub dir: PathBuf,
+}
+
+impl Scratch {
+    pub fn new(name: &str) -> Scratch {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "codecrafters-git-test-{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Scratch { dir }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    pub fn write(&self, name: &str, content: impl AsRef<[u8]>) {
+        let path = self.path(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    /// Runs the binary in `dir` under the scratch directory, failing the
+    /// test unless it succeeds, and returns its stdout.
+    pub fn run_in(&self, dir: &str, args: &[&str]) -> Vec<u8> {
+        let output = self.output_in(dir, args);
+        assert!(
+            output.status.success(),
+            "{:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output.stdout
+    }
+
+    pub fn run(&self, args: &[&str]) -> Vec<u8> {
+        self.run_in("", args)
+    }
+
+    /// Runs the binary in `dir` with `input` on its stdin, failing the test
+    /// unless it succeeds, and returns its stdout.
+    pub fn run_with_input_in(&self, dir: &str, args: &[&str], input: &[u8]) -> Vec<u8> {
+        let output = command_with_input(
+            env!("CARGO_BIN_EXE_codecrafters-git"),
+            &self.dir.join(dir),
+            args,
+            input,
+        );
+        assert!(
+            output.status.success(),
+            "{:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output.stdout
+    }
+
+    /// Runs the binary however it ends.
+    pub fn output_in(&self, dir: &str, args: &[&str]) -> Output {
+        command(
+            env!("CARGO_BIN_EXE_codecrafters-git"),
+            &self.dir.join(dir),
+            args,
+        )
+    }
+
+    /// Runs the binary in `dir` with `input` on its stdin, however it ends.
+    pub fn output_with_input_in(&self, dir: &str, args: &[&str], input: &[u8]) -> Output {
+        command_with_input(
+            env!("CARGO_BIN_EXE_codecrafters-git"),
+            &self.dir.join(dir),
+            args,
+            input,
+        )
+    }
+
+    /// Runs the system `git`, failing the test unless it succeeds.
+    pub fn git_in(&self, dir: &str, args: &[&str]) -> Vec<u8> {
+        let output = command("git", &self.dir.join(dir), args);
+        assert!(
+            output.status.success(),
+            "git {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output.stdout
+    }
+
+    pub fn git(&self, args: &[&str]) -> Vec<u8> {
+        self.git_in("", args)
+    }
+
+    /// Runs the system `git` with `input` on its stdin, failing the test
+    /// unless it succeeds.
+    pub fn git_with_input_in(&self, dir: &str, args: &[&str], input: &[u8]) -> Vec<u8> {
+        let output = command_with_input("git", &self.dir.join(dir), args, input);
+        assert!(
+            output.status.success(),
+            "git {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output.stdout
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A command that reads no user or system config and has a fixed identity
+/// and clock, so runs are repeatable.
+fn command(program: &str, dir: &Path, args: &[&str]) -> Output {
+    command_with_input(program, dir, args, b"")
+}
+
+/// Like [`command`], with `input` on its stdin.
+fn command_with_input(program: &str, dir: &Path, args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(program)
+        .args(args)
+        .current_dir(dir)
+        .env_remove("GIT_DIR")
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_AUTHOR_NAME", "A U Thor")
+        .env("GIT_AUTHOR_EMAIL", "author@example.com")
+        .env("GIT_AUTHOR_DATE", "1112911993 -0700")
+        .env("GIT_COMMITTER_NAME", "C O Mitter")
+        .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+        .env("GIT_COMMITTER_DATE", "1112911993 -0700")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Fed and drained from other threads, so a command writing a lot before
+    // it has read all its input can't block on a full pipe.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || {
+        let _ = stdin.write_all(&input);
+    });
+    let drain = |mut pipe: Box<dyn Read + Send>| {
+        std::thread::spawn(move || {
+            let mut bytes = Vec::new();
+            pipe.read_to_end(&mut bytes).unwrap();
+            bytes
+        })
+    };
+    let stdout = drain(Box::new(child.stdout.take().unwrap()));
+    let stderr = drain(Box::new(child.stderr.take().unwrap()));
+
+    // A command that loops forever fails its test rather than hanging it.
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            panic!("{} {:?} didn't finish within {:?}", program, args, TIMEOUT);
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    };
+    writer.join().unwrap();
+    Output {
+        status,
+        stdout: stdout.join().unwrap(),
+        stderr: stderr.join().unwrap(),
+    }
+}
+
+pub fn text(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes).unwrap()
+}
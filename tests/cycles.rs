@@ -0,0 +1,91 @@
+mod common;
+
+use common::{text, Scratch};
+use std::io::Write;
+use std::process::Output;
+
+const A: &str = "1111111111111111111111111111111111111111";
+const B: &str = "2222222222222222222222222222222222222222";
+const TAG: &str = "3333333333333333333333333333333333333333";
+const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+/// Stores `content` as a loose object under `id`, which can't be its real
+/// id: that is how a corrupt repository gets an object naming itself.
+fn forge(scratch: &Scratch, id: &str, object_type: &str, content: &str) {
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), Default::default());
+    write!(encoder, "{} {}\0{}", object_type, content.len(), content).unwrap();
+    let path = format!(".git/objects/{}/{}", &id[..2], &id[2..]);
+    scratch.write(&path, encoder.finish().unwrap());
+}
+
+fn commit(parent: &str) -> String {
+    format!(
+        "tree {}\nparent {}\nauthor A <a@b> 1 +0000\ncommitter A <a@b> 1 +0000\n\nloop\n",
+        EMPTY_TREE, parent
+    )
+}
+
+/// Runs the binary, which must finish, as the helpers check, and not crash.
+fn run(scratch: &Scratch, args: &[&str]) -> Output {
+    let output = scratch.output_in("", args);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        !stderr.contains("panicked") && !stderr.contains("overflow"),
+        "{}",
+        stderr
+    );
+    output
+}
+
+fn cycle_error(scratch: &Scratch, args: &[&str]) -> String {
+    let output = run(scratch, args);
+    assert!(!output.status.success(), "{:?} succeeded", args);
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+#[test]
+fn self_referential_commits_fail_gracefully() {
+    let scratch = Scratch::new("cycles-commit");
+    scratch.run(&["init", "-q", "."]);
+    scratch.run(&["write-tree"]);
+    forge(&scratch, A, "commit", &commit(A));
+
+    for args in [&["log", A][..], &["log", "--oneline", A], &["rev-list", A]] {
+        let stderr = cycle_error(&scratch, args);
+        assert!(stderr.contains("Cycle detected"), "{:?}: {}", args, stderr);
+    }
+    scratch.write("a", "hi\n");
+    scratch.run(&["add", "a"]);
+    scratch.run(&["commit", "-q", "-m", "real"]);
+    run(&scratch, &["merge-base", A, "HEAD"]);
+}
+
+#[test]
+fn commits_that_are_each_others_parents_fail_gracefully() {
+    let scratch = Scratch::new("cycles-two-commits");
+    scratch.run(&["init", "-q", "."]);
+    scratch.run(&["write-tree"]);
+    forge(&scratch, A, "commit", &commit(B));
+    forge(&scratch, B, "commit", &commit(A));
+
+    let stderr = cycle_error(&scratch, &["rev-list", A]);
+    assert!(stderr.contains("Cycle detected"), "{}", stderr);
+}
+
+#[test]
+fn tags_that_tag_themselves_fail_gracefully() {
+    let scratch = Scratch::new("cycles-tag");
+    scratch.run(&["init", "-q", "."]);
+    let tag = format!(
+        "object {}\ntype tag\ntag t\ntagger A <a@b> 1 +0000\n\nloop\n",
+        TAG
+    );
+    forge(&scratch, TAG, "tag", &tag);
+
+    let peeled = format!("{}^{{commit}}", TAG);
+    for args in [&["rev-parse", &peeled][..], &["log", TAG]] {
+        let stderr = cycle_error(&scratch, args);
+        assert!(stderr.contains("Cycle detected"), "{:?}: {}", args, stderr);
+    }
+    assert_eq!(text(scratch.run(&["cat-file", "-t", TAG])), "tag\n");
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use common::{text, Scratch};
+
+#[test]
+fn symmetric_ranges_diff_from_the_merge_base() {
+    let scratch = Scratch::new("diff-merge-base");
+    scratch.run(&["init", "-q", "."]);
+    scratch.write("f", "base\n");
+    scratch.git(&["add", "f"]);
+    scratch.git(&["commit", "-q", "-m", "base"]);
+    scratch.git(&["checkout", "-q", "-b", "side"]);
+    scratch.write("g", "side\n");
+    scratch.git(&["add", "g"]);
+    scratch.git(&["commit", "-q", "-m", "side"]);
+    scratch.git(&["checkout", "-q", "main"]);
+    scratch.write("f", "main\n");
+    scratch.git(&["commit", "-q", "-a", "-m", "main"]);
+
+    for range in ["main...side", "side...main", "...side", "main..side"] {
+        let ours = text(scratch.run(&["diff", range]));
+        assert_eq!(ours, text(scratch.git(&["diff", range])), "{}", range);
+    }
+    // Only what the side branch added, not main's change to `f`.
+    let diff = text(scratch.run(&["diff", "--stat", "main...side"]));
+    assert!(diff.contains(" g ") && !diff.contains(" f "), "{}", diff);
+
+    scratch.git(&["checkout", "-q", "--orphan", "lone"]);
+    scratch.git(&["commit", "-q", "-m", "lone"]);
+    let output = scratch.output_in("", &["diff", "lone...main"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no merge base"));
+}
+
+#[test]
+fn show_stat_summarizes_commits_like_git() {
+    let scratch = Scratch::new("diff-show-stat");
+    scratch.run(&["init", "-q", "."]);
+    scratch.write("f", "base\n");
+    scratch.git(&["add", "f"]);
+    scratch.git(&["commit", "-q", "-m", "base"]);
+    scratch.git(&["checkout", "-q", "-b", "side"]);
+    scratch.write("g", "side\n");
+    scratch.write("bin", b"\0\x01\x02");
+    scratch.git(&["add", "g", "bin"]);
+    scratch.git(&["commit", "-q", "-m", "side"]);
+    scratch.git(&["checkout", "-q", "main"]);
+    scratch.write("f", "main\nmore\n");
+    scratch.git(&["commit", "-q", "-a", "-m", "main"]);
+    scratch.git(&["merge", "-q", "--no-edit", "side"]);
+    scratch.git(&["tag", "-a", "t", "-m", "tagged", "HEAD^"]);
+
+    for rev in ["HEAD", "HEAD^", "HEAD^2", "main~2", "t"] {
+        let ours = text(scratch.run(&["show", "--stat", rev]));
+        assert_eq!(ours, text(scratch.git(&["show", "--stat", rev])), "{}", rev);
+    }
+}
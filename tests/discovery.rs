@@ -0,0 +1,69 @@
+mod common;
+
+use common::{text, Scratch};
+
+/// Makes a repository in `elsewhere` with one commit, for work trees whose
+/// `.git` is a file pointing at it.
+fn repository(scratch: &Scratch) -> String {
+    scratch.git(&["init", "-q", "elsewhere"]);
+    scratch.write("elsewhere/a", "hi\n");
+    scratch.write("elsewhere/d/b", "x\n");
+    scratch.git_in("elsewhere", &["add", "a", "d"]);
+    scratch.git_in("elsewhere", &["commit", "-q", "-m", "first"]);
+    text(scratch.git_in("elsewhere", &["rev-parse", "HEAD"]))
+}
+
+#[test]
+fn git_files_point_at_the_git_directory() {
+    let scratch = Scratch::new("discovery-git-file");
+    let head = repository(&scratch);
+    scratch.write("work/a", "hi\n");
+    scratch.write("work/d/b", "changed\n");
+    let git_dir = scratch.path("elsewhere/.git");
+    scratch.write("work/.git", format!("gitdir: {}\n", git_dir.display()));
+    let work = scratch.path("work");
+    scratch.git_in(
+        "elsewhere",
+        &["config", "core.worktree", work.to_str().unwrap()],
+    );
+
+    assert_eq!(text(scratch.run_in("work", &["rev-parse", "HEAD"])), head);
+    assert_eq!(text(scratch.run_in("work/d", &["rev-parse", "HEAD"])), head);
+    assert_eq!(
+        text(scratch.run_in("work", &["status", "--porcelain"])),
+        " M d/b\n"
+    );
+}
+
+#[test]
+fn relative_git_files_are_read_from_where_they_are() {
+    let scratch = Scratch::new("discovery-relative-git-file");
+    let head = repository(&scratch);
+    scratch.write("nested/work/.git", "gitdir: ../../elsewhere/.git\n");
+
+    assert_eq!(
+        text(scratch.run_in("nested/work", &["rev-parse", "HEAD"])),
+        head
+    );
+    let tree = text(scratch.run_in("nested/work", &["cat-file", "-p", "HEAD^{tree}"]));
+    assert!(tree.contains("\ta\n") && tree.contains("\td\n"), "{}", tree);
+}
+
+#[test]
+fn separate_git_dirs_made_by_git_are_found() {
+    let scratch = Scratch::new("discovery-separate-git-dir");
+    scratch.git(&["init", "-q", "--separate-git-dir", "store.git", "work"]);
+    scratch.write("work/a", "hi\n");
+    scratch.run_in("work", &["add", "a"]);
+    scratch.run_in("work", &["commit", "-q", "-m", "first"]);
+
+    assert!(scratch.path("work/.git").is_file());
+    assert_eq!(
+        text(scratch.git_in("work", &["log", "--format=%s"])),
+        "first\n"
+    );
+    assert_eq!(
+        text(scratch.git(&["--git-dir", "store.git", "ls-files"])),
+        "a\n"
+    );
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::{text, Scratch};
+
+/// A stream with one commit on `main` holding an empty file at `path`,
+/// quoted as fast-export writes it.
+fn stream(path: &str) -> String {
+    format!(
+        "commit refs/heads/main\n\
+         committer A <a@b> 1 +0000\n\
+         data 5\nfirst\n\
+         M 644 inline {}\n\
+         data 0\n\n",
+        path
+    )
+}
+
+#[test]
+fn quoted_paths_unescape_like_git() {
+    let scratch = Scratch::new("fast-import-quoted");
+    scratch.run(&["init", "-q", "ours"]);
+    scratch.git(&["init", "-q", "theirs"]);
+    let input = stream(r#""caf\303\251""#);
+    scratch.run_with_input_in("ours", &["fast-import"], input.as_bytes());
+    scratch.git_with_input_in("theirs", &["fast-import", "--quiet"], input.as_bytes());
+
+    let ours = text(scratch.run_in("ours", &["rev-parse", "main^{tree}"]));
+    let theirs = text(scratch.git_in("theirs", &["rev-parse", "main^{tree}"]));
+    assert_eq!(ours, theirs);
+}
+
+#[test]
+fn malformed_octal_escapes_fail_cleanly() {
+    let scratch = Scratch::new("fast-import-octal");
+    scratch.run(&["init", "-q", "."]);
+    for path in [r#""a\18""#, r#""a\7""#, r#""a\777""#] {
+        let input = stream(path);
+        let output = scratch.output_with_input_in("", &["fast-import"], input.as_bytes());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{}", path);
+        assert!(!stderr.contains("panicked"), "{}: {}", path, stderr);
+    }
+}
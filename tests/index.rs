@@ -0,0 +1,124 @@
+mod common;
+
+use common::{text, Scratch};
+
+/// Stages a file and a file in a subdirectory, and checks both this tool and
+/// git read back the index it wrote, in a repository of `format`.
+fn add_then_ls_files(format: &str) {
+    let scratch = Scratch::new(&format!("index-{}", format));
+    scratch.run(&["init", "-q", &format!("--object-format={}", format), "."]);
+    scratch.write("a", "hi\n");
+    scratch.write("d/b", "x\n");
+    scratch.run(&["add", "a", "d"]);
+
+    let ours = text(scratch.run(&["ls-files", "--stage"]));
+    let git = text(scratch.git(&["ls-files", "--stage"]));
+    assert_eq!(ours, git);
+    assert_eq!(ours.lines().count(), 2);
+    let id_len = ours.split_whitespace().nth(1).unwrap().len();
+    assert_eq!(id_len, if format == "sha256" { 64 } else { 40 });
+    assert_eq!(
+        text(scratch.run(&["status", "--porcelain"])),
+        "A  a\nA  d/b\n"
+    );
+
+    // An index git wrote, with its TREE extension, reads back too.
+    scratch.git(&["write-tree"]);
+    assert_eq!(text(scratch.run(&["ls-files", "--stage"])), git);
+}
+
+#[test]
+fn index_round_trips_in_sha1_repositories() {
+    add_then_ls_files("sha1");
+}
+
+#[test]
+fn index_round_trips_in_sha256_repositories() {
+    add_then_ls_files("sha256");
+}
+
+#[test]
+fn reads_version_4_indexes_git_wrote() {
+    let scratch = Scratch::new("index-v4");
+    scratch.git(&["init", "-q", "."]);
+    for path in [
+        "src/main.rs",
+        "src/main_test.rs",
+        "src/lib.rs",
+        "src/a/b/c.rs",
+        "tests/x.rs",
+        "z",
+    ] {
+        scratch.write(path, path);
+    }
+    scratch.git(&["add", "."]);
+    scratch.git(&["update-index", "--index-version", "4"]);
+    let version =
+        |scratch: &Scratch| std::fs::read(scratch.path(".git/index")).unwrap()[4..8].to_vec();
+    assert_eq!(version(&scratch), [0, 0, 0, 4]);
+
+    let git = text(scratch.git(&["ls-files", "--stage"]));
+    assert_eq!(text(scratch.run(&["ls-files", "--stage"])), git);
+    assert_eq!(
+        text(scratch.run(&["status", "--porcelain"]))
+            .lines()
+            .count(),
+        6
+    );
+
+    // Written back after a change, it is still version 4 and git reads it.
+    scratch.write("src/main_x.rs", "new\n");
+    scratch.run(&["add", "src/main_x.rs"]);
+    assert_eq!(version(&scratch), [0, 0, 0, 4]);
+    assert_eq!(
+        text(scratch.git(&["ls-files"])),
+        "src/a/b/c.rs\nsrc/lib.rs\nsrc/main.rs\nsrc/main_test.rs\nsrc/main_x.rs\ntests/x.rs\nz\n"
+    );
+    assert_eq!(
+        text(scratch.run(&["ls-files", "--stage"])),
+        text(scratch.git(&["ls-files", "--stage"]))
+    );
+}
+
+#[test]
+fn a_path_cannot_be_staged_as_both_file_and_directory() {
+    let scratch = Scratch::new("index-file-directory");
+    scratch.run(&["init", "-q", "."]);
+    scratch.write("d", "file\n");
+    scratch.write("d-x", "sibling\n");
+    scratch.write("e/f", "nested\n");
+    scratch.run(&["add", "d", "d-x", "e"]);
+
+    std::fs::remove_file(scratch.path("d")).unwrap();
+    scratch.write("d/a", "now a directory\n");
+    let output = scratch.output_in("", &["update-index", "--add", "d/a"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("'d/a' appears as both a file and as a directory (conflicts with 'd')."));
+
+    std::fs::remove_dir_all(scratch.path("e")).unwrap();
+    scratch.write("e", "now a file\n");
+    let output = scratch.output_in("", &["update-index", "--add", "e"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("'e' appears as both a file and as a directory (conflicts with 'e/f')."));
+
+    assert_eq!(text(scratch.run(&["ls-files"])), "d\nd-x\ne/f\n");
+}
+
+#[test]
+fn add_no_filters_stages_files_as_on_disk() {
+    let scratch = Scratch::new("index-no-filters");
+    scratch.run(&["init", "-q", "."]);
+    scratch.write(".gitattributes", "*.txt text\n");
+    scratch.write("a.txt", "one\r\ntwo\r\n");
+
+    scratch.run(&["add", "a.txt"]);
+    let filtered = text(scratch.git(&["hash-object", "a.txt"]));
+    assert!(text(scratch.run(&["ls-files", "--stage"])).contains(filtered.trim_end()));
+
+    scratch.run(&["add", "--no-filters", "a.txt"]);
+    let raw = text(scratch.git(&["hash-object", "--no-filters", "a.txt"]));
+    assert_ne!(raw, filtered);
+    assert!(text(scratch.run(&["ls-files", "--stage"])).contains(raw.trim_end()));
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use common::{text, Scratch};
+use std::collections::BTreeSet;
+
+/// Makes a repository with two commits, a subdirectory, an executable and
+/// an annotated tag, and returns the ids of every object in it, one per line.
+fn repository(scratch: &Scratch) -> String {
+    scratch.run(&["init", "-q", "."]);
+    scratch.write("a", "hi\n");
+    scratch.write("d/b", "x\n");
+    scratch.git(&["add", "a", "d"]);
+    scratch.git(&["commit", "-q", "-m", "first"]);
+    scratch.write("d/b", "y\n");
+    scratch.write("run.sh", "#!/bin/sh\n");
+    scratch.git(&["update-index", "--add", "--chmod=+x", "run.sh"]);
+    scratch.git(&["commit", "-q", "-a", "-m", "second"]);
+    scratch.git(&["tag", "-a", "-m", "v1", "v1"]);
+
+    let mut objects = text(scratch.git(&["rev-list", "--objects", "--all"]))
+        .lines()
+        .map(|x| format!("{}\n", x.split(' ').next().unwrap()))
+        .collect::<String>();
+    objects.push_str(&text(scratch.git(&["rev-parse", "v1"])));
+    objects
+}
+
+/// The ids of the objects a pack index lists, as `git verify-pack` reads it.
+fn packed_ids(scratch: &Scratch, idx: &str) -> BTreeSet<String> {
+    text(scratch.git(&["verify-pack", "-v", idx]))
+        .lines()
+        .filter(|x| x.len() > 41 && x.as_bytes()[40] == b' ')
+        .map(|x| x[..40].to_string())
+        .collect()
+}
+
+fn ids(lines: &str) -> BTreeSet<String> {
+    lines.lines().map(str::to_string).collect()
+}
+
+#[test]
+fn packs_pass_git_index_pack_strict() {
+    let scratch = Scratch::new("pack-objects-stdout");
+    let objects = repository(&scratch);
+
+    let pack = scratch.run_with_input_in("", &["pack-objects", "--stdout"], objects.as_bytes());
+    scratch.write("out.pack", &pack);
+    scratch.git(&["index-pack", "--strict", "-o", "out.idx", "out.pack"]);
+    assert_eq!(packed_ids(&scratch, "out.idx"), ids(&objects));
+}
+
+#[test]
+fn packs_written_with_their_index_verify() {
+    let scratch = Scratch::new("pack-objects-base-name");
+    let objects = repository(&scratch);
+
+    let checksum =
+        text(scratch.run_with_input_in("", &["pack-objects", "out"], objects.as_bytes()));
+    let prefix = format!("out-{}", checksum.trim_end());
+    scratch.git(&[
+        "index-pack",
+        "--strict",
+        "-o",
+        "git.idx",
+        &format!("{}.pack", prefix),
+    ]);
+    // Our index is the same file git makes for the pack.
+    assert_eq!(
+        std::fs::read(scratch.path(&format!("{}.idx", prefix))).unwrap(),
+        std::fs::read(scratch.path("git.idx")).unwrap()
+    );
+    assert_eq!(
+        packed_ids(&scratch, &format!("{}.idx", prefix)),
+        ids(&objects)
+    );
+}
+
+#[test]
+fn revs_pack_what_is_reachable() {
+    let scratch = Scratch::new("pack-objects-revs");
+    repository(&scratch);
+
+    let input = b"v1\n^HEAD~1\n";
+    let pack = scratch.run_with_input_in("", &["pack-objects", "--stdout", "--revs"], input);
+    scratch.write("out.pack", &pack);
+    scratch.git(&["index-pack", "--strict", "-o", "out.idx", "out.pack"]);
+    let expected = text(scratch.git(&["rev-list", "--objects", "v1", "^HEAD~1"]))
+        .lines()
+        .map(|x| format!("{}\n", x.split(' ').next().unwrap()))
+        .collect::<String>();
+    assert_eq!(packed_ids(&scratch, "out.idx"), ids(&expected));
+}
+
+#[test]
+fn the_same_objects_always_make_the_same_pack() {
+    let scratch = Scratch::new("pack-objects-deterministic");
+    let objects = repository(&scratch);
+
+    let first = scratch.run_with_input_in("", &["pack-objects", "--stdout"], objects.as_bytes());
+    let second = scratch.run_with_input_in("", &["pack-objects", "--stdout"], objects.as_bytes());
+    assert_eq!(first, second);
+
+    // Nor does the order the ids come in, or repeating one, matter.
+    let mut shuffled: Vec<&str> = objects.lines().rev().collect();
+    shuffled.push(shuffled[0]);
+    let shuffled = shuffled.join("\n");
+    let third = scratch.run_with_input_in("", &["pack-objects", "--stdout"], shuffled.as_bytes());
+    assert_eq!(first, third);
+}
+
+#[test]
+fn sha256_repositories_walk_their_trees_but_refuse_packs() {
+    let scratch = Scratch::new("pack-objects-sha256");
+    scratch.run(&["init", "-q", "--object-format=sha256", "."]);
+    scratch.write("a", "hi\n");
+    scratch.write("d/b", "x\n");
+    scratch.run(&["add", "a", "d"]);
+    scratch.run(&["commit", "-q", "-m", "first"]);
+
+    let ours = ids(&text(scratch.run(&["rev-list", "--objects", "HEAD"])));
+    let git = ids(&text(scratch.git(&["rev-list", "--objects", "HEAD"])));
+    assert_eq!(ours, git);
+    assert_eq!(ours.len(), 5);
+
+    for args in [&["gc"][..], &["pack-objects", "--revs", "out"]] {
+        let output = scratch.output_in("", args);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(
+            String::from_utf8_lossy(&output.stderr)
+                .contains("Packs are only supported in sha1 repositories, not sha256 ones."),
+            "{:?}",
+            args
+        );
+    }
+}
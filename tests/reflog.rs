@@ -0,0 +1,26 @@
+mod common;
+
+use common::{text, Scratch};
+
+#[test]
+fn reflog_shows_malformed_entries_without_panicking() {
+    let scratch = Scratch::new("reflog-malformed");
+    scratch.run(&["init", "-q", "."]);
+    scratch.write("a", "hi\n");
+    scratch.run(&["add", "a"]);
+    scratch.run(&["commit", "-q", "-m", "first"]);
+    let head = text(scratch.run(&["rev-parse", "HEAD"]));
+
+    let log = scratch.path(".git/logs/HEAD");
+    let mut content = std::fs::read_to_string(&log).unwrap();
+    content.push_str(&format!(
+        "{} abc A <a@b> 1 +0000\tbroken\n",
+        head.trim_end()
+    ));
+    std::fs::write(&log, content).unwrap();
+
+    let reflog = text(scratch.run(&["reflog", "HEAD"]));
+    let lines: Vec<&str> = reflog.lines().collect();
+    assert_eq!(lines[0], "abc HEAD@{0}: broken");
+    assert!(lines[1].starts_with(&format!("{} HEAD@{{1}}: ", &head[..7])));
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use common::{text, Scratch};
+
+#[test]
+fn broken_refs_are_ignored_rather_than_sliced() {
+    let scratch = Scratch::new("refs-broken");
+    scratch.run(&["init", "-q", "."]);
+    scratch.write("a", "hi\n");
+    scratch.run(&["add", "a"]);
+    scratch.run(&["commit", "-q", "-m", "first"]);
+    scratch.write(".git/refs/heads/feat", "abc\n");
+    scratch.write(".git/refs/tags/v1", "abc\n");
+
+    for args in [
+        &["branch", "-D", "feat"][..],
+        &["tag", "-d", "v1"],
+        &["log", "feat"],
+    ] {
+        let output = scratch.output_in("", args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{:?} succeeded", args);
+        assert!(!stderr.contains("panicked"), "{:?}: {}", args, stderr);
+    }
+    assert_eq!(text(scratch.run(&["branch"])), "* main\n");
+
+    // Ids in packed-refs are checked the same way.
+    scratch.write(".git/packed-refs", "abc refs/heads/packed\n");
+    assert!(!scratch
+        .output_in("", &["rev-parse", "packed"])
+        .status
+        .success());
+}
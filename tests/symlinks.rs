@@ -0,0 +1,52 @@
+//! Work tree walks must treat symlinks as entries and never follow them,
+//! or a link to itself or to a parent directory would loop forever.
+#![cfg(unix)]
+
+mod common;
+
+use common::{text, Scratch};
+use std::os::unix::fs::symlink;
+
+#[test]
+fn self_referential_symlinks_are_entries() {
+    let scratch = Scratch::new("symlinks-loop");
+    scratch.run(&["init", "-q", "."]);
+    scratch.write("a", "hi\n");
+    scratch.run(&["add", "a"]);
+    scratch.run(&["commit", "-q", "-m", "a"]);
+    scratch.run(&["branch", "plain"]);
+
+    symlink("loop", scratch.path("loop")).unwrap();
+    symlink(".", scratch.path("self")).unwrap();
+    std::fs::create_dir(scratch.path("d")).unwrap();
+    symlink("../d", scratch.path("d/up")).unwrap();
+
+    assert_eq!(
+        text(scratch.run(&["status", "--porcelain"])),
+        "?? d/\n?? loop\n?? self\n"
+    );
+    scratch.run(&["add", "."]);
+    let staged = text(scratch.run(&["ls-files", "--stage"]));
+    assert_eq!(staged, text(scratch.git(&["ls-files", "--stage"])));
+    assert_eq!(staged.matches("120000 ").count(), 3);
+    scratch.run(&["commit", "-q", "-m", "links"]);
+    assert_eq!(text(scratch.run(&["status", "--porcelain"])), "");
+
+    // Checked out again, they come back as links, still unfollowed.
+    scratch.run(&["checkout", "-q", "plain"]);
+    assert!(!scratch.path("self").is_symlink() && !scratch.path("loop").is_symlink());
+    scratch.run(&["checkout", "-q", "main"]);
+    assert_eq!(
+        std::fs::read_link(scratch.path("loop")).unwrap().to_str(),
+        Some("loop")
+    );
+    assert_eq!(
+        std::fs::read_link(scratch.path("self")).unwrap().to_str(),
+        Some(".")
+    );
+    assert_eq!(
+        std::fs::read_link(scratch.path("d/up")).unwrap().to_str(),
+        Some("../d")
+    );
+    assert_eq!(text(scratch.run(&["status", "--porcelain"])), "");
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use common::{text, Scratch};
+
+/// Symlinks and the executable bit need a Unix work tree.
+#[cfg(unix)]
+#[test]
+fn write_tree_stores_every_mode_like_git() {
+    let scratch = Scratch::new("write-tree-modes");
+    scratch.run(&["init", "-q", "."]);
+    scratch.write("a", "hi\n");
+    scratch.write("d/b", "x\n");
+    scratch.write("run.sh", "#!/bin/sh\n");
+    std::os::unix::fs::symlink("a", scratch.path("link")).unwrap();
+    let executable = std::os::unix::fs::PermissionsExt::from_mode(0o755);
+    std::fs::set_permissions(scratch.path("run.sh"), executable).unwrap();
+    scratch.run(&["add", "a", "d", "link", "run.sh"]);
+    scratch.git(&[
+        "update-index",
+        "--add",
+        "--cacheinfo",
+        "160000,4b825dc642cb6eb9a060e54bf8d69288fbee4904,sub",
+    ]);
+
+    let tree = text(scratch.run(&["write-tree"]));
+    assert_eq!(tree, text(scratch.git(&["write-tree"])));
+    assert_eq!(
+        text(scratch.run(&["ls-tree", tree.trim_end()])),
+        "100644 blob 45b983be36b73c0788dc9cbcb76cbb80fc7bb057\ta\n\
+         040000 tree 2b4c1d0c6f3c005f72eb2ecd2eb2a25edecf9a50\td\n\
+         120000 blob 2e65efe2a145dda7ee51d1741299f848e5bf752e\tlink\n\
+         100755 blob 1a2485251c33a70432394c93fb89330ef214bfc9\trun.sh\n\
+         160000 commit 4b825dc642cb6eb9a060e54bf8d69288fbee4904\tsub\n"
+    );
+    // A symlink's blob holds its target.
+    assert_eq!(
+        scratch.run(&["cat-file", "-p", "2e65efe2a145dda7ee51d1741299f848e5bf752e"]),
+        b"a"
+    );
+}